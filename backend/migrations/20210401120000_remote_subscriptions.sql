@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS remote_subscriptions (
+    username VARCHAR(24) NOT NULL,
+    host VARCHAR(259) NOT NULL,
+    community VARCHAR(24) NOT NULL,
+    -- authority used to reach the remote, may include a port unlike remotes.host
+    community_host VARCHAR(259) NOT NULL,
+
+    FOREIGN KEY (username, host) REFERENCES users(username, host) ON DELETE CASCADE,
+
+    PRIMARY KEY(username, host, community, community_host)
+);
@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS outbox (
+    id UUID NOT NULL PRIMARY KEY,
+    host VARCHAR(259) NOT NULL,
+    delivery JSONB NOT NULL,
+    -- "pending" or "dead", delivered items are removed
+    status VARCHAR(8) NOT NULL,
+    attempts INTEGER NOT NULL,
+    next_attempt BIGINT NOT NULL,
+    last_error TEXT,
+    created BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS outbox_pending_idx ON outbox (next_attempt) WHERE status = 'pending';
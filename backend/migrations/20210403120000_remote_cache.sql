@@ -0,0 +1,29 @@
+CREATE TABLE IF NOT EXISTS remote_communities (
+    id TEXT NOT NULL,
+    host VARCHAR(259) NOT NULL,
+
+    title TEXT NOT NULL,
+    description TEXT NOT NULL,
+    moderators JSONB NOT NULL,
+    last_synced BIGINT NOT NULL,
+
+    PRIMARY KEY(id, host)
+);
+
+CREATE TABLE IF NOT EXISTS remote_posts (
+    id UUID NOT NULL,
+    host VARCHAR(259) NOT NULL,
+
+    community TEXT NOT NULL,
+    parent UUID,
+    author_username TEXT NOT NULL,
+    author_host TEXT NOT NULL,
+
+    title TEXT NOT NULL,
+    content JSONB NOT NULL,
+    created BIGINT NOT NULL,
+    modified BIGINT NOT NULL,
+
+    PRIMARY KEY(id, host),
+    FOREIGN KEY (community, host) REFERENCES remote_communities(id, host) ON DELETE CASCADE
+);
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS remote_users (
+    username TEXT NOT NULL,
+    host VARCHAR(259) NOT NULL,
+
+    about TEXT NOT NULL,
+    avatar_url TEXT,
+    posts JSONB NOT NULL,
+    fetched BIGINT NOT NULL,
+
+    PRIMARY KEY(username, host)
+);
@@ -0,0 +1,3 @@
+ALTER TABLE local_users ADD COLUMN about TEXT NOT NULL DEFAULT '';
+ALTER TABLE local_users ADD COLUMN display_name VARCHAR(64);
+ALTER TABLE remote_users ADD COLUMN display_name TEXT;
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS federation_policies (
+    host VARCHAR(259) NOT NULL PRIMARY KEY,
+
+    mode VARCHAR(8) NOT NULL CHECK (mode IN ('block', 'silence', 'allow')),
+    reason TEXT NOT NULL,
+    created BIGINT NOT NULL
+);
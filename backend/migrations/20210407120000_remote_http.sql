@@ -0,0 +1,2 @@
+-- whether the remote may be reached over plain HTTP when HTTPS is unavailable
+ALTER TABLE remotes ADD COLUMN IF NOT EXISTS allow_http BOOLEAN NOT NULL DEFAULT TRUE;
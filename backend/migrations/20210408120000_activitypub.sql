@@ -0,0 +1,26 @@
+CREATE TABLE IF NOT EXISTS activitypub_actors (
+    uri TEXT NOT NULL PRIMARY KEY,
+
+    username VARCHAR(24) NOT NULL,
+    host VARCHAR(259) NOT NULL,
+    document JSONB NOT NULL,
+    fetched BIGINT NOT NULL,
+
+    FOREIGN KEY (username, host) REFERENCES users(username, host) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS activitypub_objects (
+    uri TEXT NOT NULL PRIMARY KEY,
+    post UUID NOT NULL UNIQUE,
+
+    FOREIGN KEY (post) REFERENCES posts(id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS activitypub_followers (
+    community VARCHAR(24) NOT NULL,
+    actor TEXT NOT NULL,
+    inbox TEXT NOT NULL,
+
+    PRIMARY KEY(community, actor),
+    FOREIGN KEY (community) REFERENCES communities(id) ON DELETE CASCADE
+);
@@ -0,0 +1,5 @@
+-- time at which a post was deleted, leaving a tombstone in its place until it is purged
+ALTER TABLE posts ADD COLUMN IF NOT EXISTS deleted BIGINT;
+ALTER TABLE remote_posts ADD COLUMN IF NOT EXISTS deleted BIGINT;
+
+CREATE INDEX IF NOT EXISTS posts_deleted_idx ON posts (deleted) WHERE deleted IS NOT NULL;
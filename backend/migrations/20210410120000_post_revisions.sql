@@ -0,0 +1,16 @@
+-- previous versions of edited posts, numbered from 1 in the order they were replaced
+CREATE TABLE IF NOT EXISTS post_revisions (
+    post UUID NOT NULL,
+    revision INTEGER NOT NULL,
+
+    -- the editor may be a remote user, and is kept if they are later removed
+    editor_username VARCHAR(24) NOT NULL,
+    editor_host VARCHAR(259) NOT NULL,
+    edited BIGINT NOT NULL,
+
+    title TEXT NOT NULL,
+    content JSONB NOT NULL,
+
+    PRIMARY KEY (post, revision),
+    FOREIGN KEY (post) REFERENCES posts(id) ON DELETE CASCADE
+);
@@ -0,0 +1,52 @@
+-- time of the latest reply anywhere beneath a top-level post, so that the feed can be ordered by
+-- activity in the database rather than by walking every thread
+ALTER TABLE posts ADD COLUMN IF NOT EXISTS last_reply BIGINT;
+ALTER TABLE remote_posts ADD COLUMN IF NOT EXISTS last_reply BIGINT;
+
+WITH RECURSIVE tree AS (
+    SELECT id AS root, id, created, deleted FROM posts
+    WHERE parent IS NULL
+    UNION ALL
+    SELECT tree.root, posts.id, posts.created, posts.deleted FROM posts
+    INNER JOIN tree ON posts.parent = tree.id
+)
+UPDATE posts
+SET last_reply = replies.last_reply
+FROM (
+    SELECT root, MAX(created) AS last_reply FROM tree
+    WHERE id <> root
+    AND deleted IS NULL
+    GROUP BY root
+) AS replies
+WHERE posts.id = replies.root;
+
+WITH RECURSIVE tree AS (
+    SELECT host, id AS root, id, created, deleted FROM remote_posts
+    WHERE parent IS NULL
+    UNION ALL
+    SELECT tree.host, tree.root, remote_posts.id, remote_posts.created, remote_posts.deleted
+    FROM remote_posts
+    INNER JOIN tree ON remote_posts.parent = tree.id AND remote_posts.host = tree.host
+)
+UPDATE remote_posts
+SET last_reply = replies.last_reply
+FROM (
+    SELECT host, root, MAX(created) AS last_reply FROM tree
+    WHERE id <> root
+    AND deleted IS NULL
+    GROUP BY host, root
+) AS replies
+WHERE remote_posts.host = replies.host
+AND remote_posts.id = replies.root;
+
+-- keyset pagination of the feed by each sort that is ranked in the database
+CREATE INDEX IF NOT EXISTS posts_feed_new_idx ON posts (created, id)
+    WHERE parent IS NULL AND deleted IS NULL;
+CREATE INDEX IF NOT EXISTS posts_feed_active_idx ON posts ((COALESCE(last_reply, created)), created, id)
+    WHERE parent IS NULL AND deleted IS NULL;
+
+CREATE INDEX IF NOT EXISTS remote_posts_feed_new_idx ON remote_posts (created, id)
+    WHERE parent IS NULL AND deleted IS NULL;
+CREATE INDEX IF NOT EXISTS remote_posts_feed_active_idx
+    ON remote_posts ((COALESCE(last_reply, created)), created, id)
+    WHERE parent IS NULL AND deleted IS NULL;
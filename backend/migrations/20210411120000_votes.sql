@@ -0,0 +1,24 @@
+-- up and down votes on posts by local and remote users, one per user and post
+CREATE TABLE IF NOT EXISTS votes (
+    post UUID NOT NULL,
+
+    -- the voter may be a remote user, and their vote is kept if they are later removed
+    voter_username VARCHAR(24) NOT NULL,
+    voter_host VARCHAR(259) NOT NULL,
+    value SMALLINT NOT NULL CHECK (value IN (-1, 1)),
+    created BIGINT NOT NULL,
+
+    PRIMARY KEY (post, voter_username, voter_host),
+    FOREIGN KEY (post) REFERENCES posts(id) ON DELETE CASCADE
+);
+
+-- sum of the votes on each post, kept up to date alongside them so that posts can be ranked by it
+ALTER TABLE posts ADD COLUMN IF NOT EXISTS score BIGINT NOT NULL DEFAULT 0;
+-- score of each cached remote post as last reported by its host
+ALTER TABLE remote_posts ADD COLUMN IF NOT EXISTS score BIGINT NOT NULL DEFAULT 0;
+
+-- keyset pagination of the feed by score, alongside the indexes of the other sorts
+CREATE INDEX IF NOT EXISTS posts_feed_top_idx ON posts (score, created, id)
+    WHERE parent IS NULL AND deleted IS NULL;
+CREATE INDEX IF NOT EXISTS remote_posts_feed_top_idx ON remote_posts (score, created, id)
+    WHERE parent IS NULL AND deleted IS NULL;
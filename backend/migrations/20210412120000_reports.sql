@@ -0,0 +1,34 @@
+-- reports of posts and messages, handled by the moderators of the post's community or by admins
+CREATE TABLE IF NOT EXISTS reports (
+    id UUID NOT NULL PRIMARY KEY,
+
+    -- the reporter may be a remote user whose report was forwarded to this server
+    reporter_username VARCHAR(24) NOT NULL,
+    reporter_host VARCHAR(259) NOT NULL,
+
+    -- exactly one of a local post or a message received by the reporter is reported
+    post UUID,
+    message UUID,
+    -- community of the reported post, absent for messages, which only admins handle
+    community VARCHAR(24),
+    -- author of the reported post or sender of the reported message
+    author_username VARCHAR(24) NOT NULL,
+    author_host VARCHAR(259) NOT NULL,
+
+    reason TEXT NOT NULL,
+    created BIGINT NOT NULL,
+
+    -- "open", "resolved" or "dismissed"
+    status VARCHAR(9) NOT NULL,
+    handler_username VARCHAR(24),
+    handler_host VARCHAR(259),
+    handled BIGINT,
+
+    CHECK ((post IS NULL) <> (message IS NULL)),
+
+    FOREIGN KEY (post) REFERENCES posts(id) ON DELETE CASCADE,
+    FOREIGN KEY (message) REFERENCES messages(id) ON DELETE CASCADE,
+    FOREIGN KEY (community) REFERENCES communities(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS reports_open_idx ON reports (community, created) WHERE status = 'open';
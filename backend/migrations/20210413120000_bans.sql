@@ -0,0 +1,51 @@
+-- local and remote users banned from posting in a community, until the ban expires if it does
+CREATE TABLE IF NOT EXISTS community_bans (
+    community VARCHAR(24) NOT NULL,
+
+    -- the banned user need not have interacted with the server yet
+    username VARCHAR(24) NOT NULL,
+    host VARCHAR(259) NOT NULL,
+
+    reason TEXT NOT NULL,
+    created BIGINT NOT NULL,
+    expires BIGINT,
+    moderator_username VARCHAR(24) NOT NULL,
+    moderator_host VARCHAR(259) NOT NULL,
+
+    PRIMARY KEY (community, username, host),
+    FOREIGN KEY (community) REFERENCES communities(id) ON DELETE CASCADE
+);
+
+-- local and remote users suspended from the whole server, until the suspension expires if it does
+CREATE TABLE IF NOT EXISTS suspensions (
+    username VARCHAR(24) NOT NULL,
+    host VARCHAR(259) NOT NULL,
+
+    reason TEXT NOT NULL,
+    created BIGINT NOT NULL,
+    expires BIGINT,
+    moderator_username VARCHAR(24) NOT NULL,
+    moderator_host VARCHAR(259) NOT NULL,
+
+    PRIMARY KEY (username, host)
+);
+
+-- every ban and suspension and the lifting of them, kept after the community or users are removed
+CREATE TABLE IF NOT EXISTS moderation_log (
+    id UUID NOT NULL PRIMARY KEY,
+
+    -- "ban", "unban", "suspend" or "unsuspend"
+    action VARCHAR(9) NOT NULL,
+    -- community of bans, absent for suspensions
+    community VARCHAR(24),
+    username VARCHAR(24) NOT NULL,
+    host VARCHAR(259) NOT NULL,
+    moderator_username VARCHAR(24) NOT NULL,
+    moderator_host VARCHAR(259) NOT NULL,
+
+    reason TEXT NOT NULL,
+    expires BIGINT,
+    created BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS moderation_log_community_idx ON moderation_log (community, created);
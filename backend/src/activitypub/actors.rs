@@ -0,0 +1,345 @@
+use {
+    super::{community_url, create_activity, note, user_url, ACTIVITY_JSON, OUTBOX_SIZE},
+    crate::{
+        models::{
+            activitypub::{
+                Activity, Actor, OrderedCollection, PublicKey, ACTIVITYSTREAMS, SECURITY,
+            },
+            database,
+        },
+        AppData, Error,
+    },
+    actix_web::{get, web, HttpResponse, Responder, Result},
+    rsa::{PublicKeyPemEncoding, RSAPublicKey},
+    serde_json::json,
+    uuid::Uuid,
+};
+
+/// Builds an actor using this server's key
+fn actor(
+    data: &AppData,
+    id: String,
+    kind: &str,
+    preferred_username: String,
+    name: Option<String>,
+    summary: Option<String>,
+) -> Result<Actor, Error> {
+    let pem = RSAPublicKey::from(&data.privkey)
+        .to_pem_pkcs8()
+        .map_err(|e| Error::Parse(e.into()))?;
+
+    Ok(Actor {
+        context: json!([ACTIVITYSTREAMS, SECURITY]),
+        inbox: format!("{}/inbox", id),
+        outbox: Some(format!("{}/outbox", id)),
+        followers: Some(format!("{}/followers", id)),
+        public_key: PublicKey {
+            id: format!("{}#main-key", id),
+            owner: id.clone(),
+            public_key_pem: pem,
+        },
+        id,
+        kind: kind.to_owned(),
+        preferred_username,
+        name,
+        summary,
+    })
+}
+
+/// Builds an outbox of Create activities for the supplied posts
+async fn outbox(
+    data: &AppData,
+    id: String,
+    total_items: i64,
+    posts: Vec<database::Post>,
+) -> Result<OrderedCollection<Activity>, Error> {
+    let mut ordered_items = vec![];
+    for post in posts {
+        let mut activity = create_activity(post, &data.host, &data.pool).await?;
+        activity.context = serde_json::Value::Null;
+        ordered_items.push(activity);
+    }
+
+    Ok(OrderedCollection {
+        context: json!(ACTIVITYSTREAMS),
+        id,
+        kind: "OrderedCollection".to_owned(),
+        total_items,
+        ordered_items,
+    })
+}
+
+/// Gets the Person actor of a local user
+#[get("/ap/users/{id}")]
+pub(crate) async fn get_user_actor(
+    data: web::Data<AppData>,
+    web::Path(id): web::Path<String>,
+) -> Result<impl Responder, Error> {
+    let user = match sqlx::query!(
+        r#"
+            SELECT username, display_name, about FROM local_users
+            WHERE username = $1
+        "#,
+        id
+    )
+    .fetch_optional(&data.pool)
+    .await?
+    {
+        Some(user) => user,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    let mut person = actor(
+        &data,
+        user_url(&data.host, &user.username),
+        "Person",
+        user.username,
+        user.display_name,
+        user.about.filter(|about| !about.is_empty()),
+    )?;
+    // users receive activities through the shared inbox
+    person.inbox = format!("https://{}/ap/inbox", data.host);
+
+    Ok(HttpResponse::Ok().content_type(ACTIVITY_JSON).json(person))
+}
+
+/// Gets the outbox of a local user, listing their most recent posts
+#[get("/ap/users/{id}/outbox")]
+pub(crate) async fn get_user_outbox(
+    data: web::Data<AppData>,
+    web::Path(id): web::Path<String>,
+) -> Result<impl Responder, Error> {
+    let total = sqlx::query!(
+        r#"
+            SELECT
+                EXISTS(SELECT 1 FROM local_users WHERE username = $1) AS "exists!",
+                (SELECT COUNT(*) FROM posts
+                    WHERE author_username = $1 AND author_host = $2 AND deleted IS NULL)
+                    AS "count!"
+        "#,
+        id,
+        data.host
+    )
+    .fetch_one(&data.pool)
+    .await?;
+
+    if !total.exists {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    let posts = sqlx::query_as!(
+        database::Post,
+        r#"
+            SELECT * FROM posts
+            WHERE author_username = $1
+            AND author_host = $2
+            AND deleted IS NULL
+            ORDER BY created DESC
+            LIMIT $3
+        "#,
+        id,
+        data.host,
+        OUTBOX_SIZE
+    )
+    .fetch_all(&data.pool)
+    .await?;
+
+    let outbox = outbox(
+        &data,
+        format!("{}/outbox", user_url(&data.host, &id)),
+        total.count,
+        posts,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().content_type(ACTIVITY_JSON).json(outbox))
+}
+
+/// Gets the Group actor of a community
+#[get("/ap/communities/{id}")]
+pub(crate) async fn get_community_actor(
+    data: web::Data<AppData>,
+    web::Path(id): web::Path<String>,
+) -> Result<impl Responder, Error> {
+    let community = match sqlx::query!(
+        r#"
+            SELECT id, title, description FROM communities
+            WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_optional(&data.pool)
+    .await?
+    {
+        Some(community) => community,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    let group = actor(
+        &data,
+        community_url(&data.host, &community.id),
+        "Group",
+        community.id,
+        Some(community.title),
+        Some(community.description),
+    )?;
+
+    Ok(HttpResponse::Ok().content_type(ACTIVITY_JSON).json(group))
+}
+
+/// Gets the outbox of a community, listing its most recent posts
+#[get("/ap/communities/{id}/outbox")]
+pub(crate) async fn get_community_outbox(
+    data: web::Data<AppData>,
+    web::Path(id): web::Path<String>,
+) -> Result<impl Responder, Error> {
+    let total = sqlx::query!(
+        r#"
+            SELECT
+                EXISTS(SELECT 1 FROM communities WHERE id = $1) AS "exists!",
+                (SELECT COUNT(*) FROM posts WHERE community = $1 AND deleted IS NULL)
+                    AS "count!"
+        "#,
+        id
+    )
+    .fetch_one(&data.pool)
+    .await?;
+
+    if !total.exists {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    let posts = sqlx::query_as!(
+        database::Post,
+        r#"
+            SELECT * FROM posts
+            WHERE community = $1
+            AND deleted IS NULL
+            ORDER BY created DESC
+            LIMIT $2
+        "#,
+        id,
+        OUTBOX_SIZE
+    )
+    .fetch_all(&data.pool)
+    .await?;
+
+    let outbox = outbox(
+        &data,
+        format!("{}/outbox", community_url(&data.host, &id)),
+        total.count,
+        posts,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().content_type(ACTIVITY_JSON).json(outbox))
+}
+
+/// Gets the followers collection of a community, which only discloses its size
+#[get("/ap/communities/{id}/followers")]
+pub(crate) async fn get_community_followers(
+    data: web::Data<AppData>,
+    web::Path(id): web::Path<String>,
+) -> Result<impl Responder, Error> {
+    let total = sqlx::query!(
+        r#"
+            SELECT
+                EXISTS(SELECT 1 FROM communities WHERE id = $1) AS "exists!",
+                (SELECT COUNT(*) FROM activitypub_followers WHERE community = $1) AS "count!"
+        "#,
+        id
+    )
+    .fetch_one(&data.pool)
+    .await?;
+
+    if !total.exists {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ACTIVITY_JSON)
+        .json(OrderedCollection::<String> {
+            context: json!(ACTIVITYSTREAMS),
+            id: format!("{}/followers", community_url(&data.host, &id)),
+            kind: "OrderedCollection".to_owned(),
+            total_items: total.count,
+            ordered_items: vec![],
+        }))
+}
+
+/// Gets the Note of a post
+#[get("/ap/posts/{id}")]
+pub(crate) async fn get_post_object(
+    data: web::Data<AppData>,
+    web::Path(id): web::Path<Uuid>,
+) -> Result<impl Responder, Error> {
+    let post = match sqlx::query_as!(
+        database::Post,
+        r#"
+            SELECT * FROM posts
+            WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_optional(&data.pool)
+    .await?
+    {
+        Some(post) if post.deleted.is_some() => return Ok(HttpResponse::Gone().finish()),
+        Some(post) => post,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ACTIVITY_JSON)
+        .json(note(post, &data.host, &data.pool).await?))
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        crate::{
+            activitypub::community_url,
+            models::activitypub::Actor,
+            test::{connect, new_user_login, ADDR, FQDN},
+        },
+        actix_web::http::{header::CONTENT_TYPE, StatusCode},
+        rsa::{PublicKeyPemEncoding, RSAPublicKey},
+    };
+
+    #[actix_rt::test]
+    async fn get_community_actor_success() {
+        let (_, privkey) = connect().await;
+        let (client, _, cookie) = new_user_login().await;
+
+        let res = client
+            .post(format!("{}/internal/communities", *ADDR))
+            .cookie(cookie)
+            .header(CONTENT_TYPE, "application/json")
+            .send_body(r#"{"id": "apgroup", "title": "Group", "description": "A group"}"#)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let mut res = client
+            .get(format!("{}/ap/communities/apgroup", *ADDR))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let actor: Actor = res.json().await.unwrap();
+        assert_eq!(actor.id, community_url(&FQDN, "apgroup"));
+        assert_eq!(actor.kind, "Group");
+        assert_eq!(actor.name.as_deref(), Some("Group"));
+        assert_eq!(
+            actor.public_key.public_key_pem,
+            RSAPublicKey::from(&privkey).to_pem_pkcs8().unwrap()
+        );
+
+        let res = client
+            .get(format!("{}/ap/communities/missing", *ADDR))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+}
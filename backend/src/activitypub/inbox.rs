@@ -0,0 +1,845 @@
+use {
+    super::{
+        activity_url, base_url, community_url, parse_community_url,
+        signature::{inbox_host, verify_request},
+        strip_html,
+    },
+    crate::{
+        fed::outbox::{self, Delivery},
+        models::{
+            activitypub::{Activity, Actor, Note, ACTIVITYSTREAMS},
+            database::{MarkdownContent, PostContent, TextContent},
+        },
+        tombstones,
+        util::{
+            ensure_federates, is_banned, is_banned_from_post, is_suspended, record_reply,
+            record_revision,
+        },
+        AppData, Error,
+    },
+    actix_web::{post, web, HttpRequest, HttpResponse, Responder, Result},
+    anyhow::anyhow,
+    log::debug,
+    serde_json::json,
+    sqlx::{Pool, Postgres},
+    uuid::Uuid,
+};
+
+/// Receives activities addressed to a community
+#[post("/ap/communities/{id}/inbox")]
+pub(crate) async fn community_inbox(
+    req: HttpRequest,
+    data: web::Data<AppData>,
+    web::Path(id): web::Path<String>,
+    body: web::Bytes,
+) -> Result<impl Responder, Error> {
+    receive(&req, &data, &body, Some(id)).await
+}
+
+/// Receives activities addressed to local users or to communities through their addressing
+#[post("/ap/inbox")]
+pub(crate) async fn shared_inbox(
+    req: HttpRequest,
+    data: web::Data<AppData>,
+    body: web::Bytes,
+) -> Result<impl Responder, Error> {
+    receive(&req, &data, &body, None).await
+}
+
+/// Verifies and applies an activity, `community` being the community whose inbox received it
+async fn receive(
+    req: &HttpRequest,
+    data: &AppData,
+    body: &[u8],
+    community: Option<String>,
+) -> Result<HttpResponse, Error> {
+    let actor = verify_request(req, body, data).await?;
+
+    let activity =
+        serde_json::from_slice::<Activity>(body).map_err(|e| Error::BadRequest(e.into()))?;
+    if activity.actor != actor.id {
+        return Err(Error::BadRequest(anyhow!(
+            "Activity by {} signed by {}",
+            activity.actor,
+            actor.id
+        )));
+    }
+
+    debug!("activitypub: received {} from {}", activity.kind, actor.id);
+
+    match activity.kind.as_str() {
+        "Follow" => follow(data, &actor, activity, community).await,
+        "Undo" => undo(data, &actor, activity).await,
+        "Create" => create(data, &actor, activity, community).await,
+        "Update" => update(data, &actor, activity).await,
+        "Delete" => delete(data, &actor, activity).await,
+        _ => Ok(HttpResponse::Accepted().finish()),
+    }
+}
+
+/// Adds a follower to a community and accepts the follow
+async fn follow(
+    data: &AppData,
+    actor: &Actor,
+    mut activity: Activity,
+    community: Option<String>,
+) -> Result<HttpResponse, Error> {
+    // following local users is not supported
+    let target = match activity
+        .object_id()
+        .and_then(|url| parse_community_url(&data.host, url))
+    {
+        Some(target) => target,
+        None => return Ok(HttpResponse::Accepted().finish()),
+    };
+    if community.map_or(false, |community| community != target) {
+        return Err(Error::BadRequest(anyhow!(
+            "Follow of {} received by another community",
+            target
+        )));
+    }
+
+    if !community_exists(&target, &data.pool).await? {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    // the accept is delivered to the inbox, so it must still be federated with
+    ensure_federates(inbox_host(actor)?, &data.pool).await?;
+
+    sqlx::query!(
+        r#"
+            INSERT INTO activitypub_followers VALUES ($1, $2, $3)
+            ON CONFLICT (community, actor) DO UPDATE
+                SET inbox = $3
+        "#,
+        target,
+        actor.id,
+        actor.inbox
+    )
+    .execute(&data.pool)
+    .await?;
+
+    activity.context = serde_json::Value::Null;
+    let accept = Activity {
+        context: json!(ACTIVITYSTREAMS),
+        id: activity_url(&data.host),
+        kind: "Accept".to_owned(),
+        actor: community_url(&data.host, &target),
+        object: serde_json::to_value(activity)?,
+        to: vec![actor.id.clone()],
+        cc: vec![],
+    };
+
+    outbox::enqueue(
+        &data.pool,
+        &Delivery::Activity {
+            inbox: actor.inbox.clone(),
+            activity: serde_json::to_value(accept)?,
+        },
+    )
+    .await?;
+
+    Ok(HttpResponse::Accepted().finish())
+}
+
+/// Reverts a follow
+async fn undo(data: &AppData, actor: &Actor, activity: Activity) -> Result<HttpResponse, Error> {
+    let object = &activity.object;
+
+    if object.get("type").and_then(serde_json::Value::as_str) == Some("Follow") {
+        let target = object
+            .get("object")
+            .and_then(serde_json::Value::as_str)
+            .and_then(|url| parse_community_url(&data.host, url));
+
+        sqlx::query!(
+            r#"
+                DELETE FROM activitypub_followers
+                WHERE actor = $1
+                AND ($2::VARCHAR IS NULL OR community = $2)
+            "#,
+            actor.id,
+            target
+        )
+        .execute(&data.pool)
+        .await?;
+    }
+
+    Ok(HttpResponse::Accepted().finish())
+}
+
+/// Creates a post from a Note
+async fn create(
+    data: &AppData,
+    actor: &Actor,
+    activity: Activity,
+    community: Option<String>,
+) -> Result<HttpResponse, Error> {
+    let note = parse_note(actor, activity)?;
+
+    // deliveries may be retried, so objects that were already received are ignored
+    if find_object(&note.id, &data.host, &data.pool)
+        .await?
+        .is_some()
+    {
+        return Ok(HttpResponse::Accepted().finish());
+    }
+
+    let parent = match &note.in_reply_to {
+        Some(uri) => Some(
+            find_object(uri, &data.host, &data.pool)
+                .await?
+                .ok_or_else(|| Error::BadRequest(anyhow!("Unknown inReplyTo {}", uri)))?,
+        ),
+        None => None,
+    };
+
+    // replies belong to the community of their parent, other posts to the community they are
+    // addressed to
+    let community = match (&parent, community) {
+        (Some((_, parent_community)), _) => parent_community.clone(),
+        (None, Some(community)) => community,
+        (None, None) => match note
+            .audience
+            .iter()
+            .chain(note.to.iter())
+            .chain(note.cc.iter())
+            .find_map(|url| parse_community_url(&data.host, url))
+        {
+            Some(community) => community,
+            None => {
+                return Err(Error::BadRequest(anyhow!(
+                    "Note is not addressed to a local community"
+                )))
+            }
+        },
+    };
+
+    if !community_exists(&community, &data.pool).await? {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    let author = actor_user(actor, &data.pool).await?;
+    if is_suspended(&author.0, &author.1, &data.pool).await?
+        || is_banned(&author.0, &author.1, &community, &data.pool).await?
+    {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+
+    let id = Uuid::new_v4();
+    let now = chrono::Local::now().timestamp();
+    // remotes may backdate notes, but not date them in the future to pin them atop the feed
+    let created = note
+        .published
+        .as_deref()
+        .and_then(|published| chrono::DateTime::parse_from_rfc3339(published).ok())
+        .map_or(now, |published| published.timestamp().min(now));
+
+    let mut tx = data.pool.begin().await?;
+
+    sqlx::query!(
+        r#"
+            INSERT INTO posts VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        "#,
+        id,
+        community,
+        parent.as_ref().map(|(parent, _)| *parent),
+        author.0,
+        author.1,
+        title(&note),
+        serde_json::to_value(content(&note))?,
+        created,
+        now
+    )
+    .execute(&mut tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+            INSERT INTO activitypub_objects VALUES ($1, $2)
+        "#,
+        note.id,
+        id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    if let Some((parent, _)) = parent {
+        record_reply(parent, created, &data.pool).await?;
+    }
+
+    Ok(HttpResponse::Accepted().finish())
+}
+
+/// Updates the post created from a Note
+async fn update(data: &AppData, actor: &Actor, activity: Activity) -> Result<HttpResponse, Error> {
+    let note = parse_note(actor, activity)?;
+
+    let id = match find_authored(&note.id, actor, &data.pool).await? {
+        Some(id) => id,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let (username, host) = actor_user(actor, &data.pool).await?;
+    if is_suspended(&username, &host, &data.pool).await?
+        || is_banned_from_post(&username, &host, id, &data.pool).await?
+    {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+
+    let mut tx = data.pool.begin().await?;
+
+    if !record_revision(id, username, host, &mut tx).await? {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    sqlx::query!(
+        r#"
+            UPDATE posts
+            SET title = $1, content = $2, modified = $3
+            WHERE id = $4
+        "#,
+        title(&note),
+        serde_json::to_value(content(&note))?,
+        chrono::Local::now().timestamp(),
+        id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Accepted().finish())
+}
+
+/// Deletes the post created from an object, leaving a tombstone in its place
+async fn delete(data: &AppData, actor: &Actor, activity: Activity) -> Result<HttpResponse, Error> {
+    let uri = activity
+        .object_id()
+        .ok_or_else(|| Error::BadRequest(anyhow!("Delete without an object")))?;
+
+    let id = match find_authored(uri, actor, &data.pool).await? {
+        Some(id) => id,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    tombstones::tombstone(id, &data.pool).await?;
+
+    // the object no longer exists, so later activities referring to it are not applied to the
+    // tombstone
+    sqlx::query!(
+        r#"
+            DELETE FROM activitypub_objects
+            WHERE post = $1
+        "#,
+        id
+    )
+    .execute(&data.pool)
+    .await?;
+
+    Ok(HttpResponse::Accepted().finish())
+}
+
+/// Parses the Note embedded in an activity, which must be attributed to the activity's actor
+fn parse_note(actor: &Actor, activity: Activity) -> Result<Note, Error> {
+    let note =
+        serde_json::from_value::<Note>(activity.object).map_err(|e| Error::BadRequest(e.into()))?;
+
+    if note.attributed_to != actor.id {
+        return Err(Error::BadRequest(anyhow!(
+            "Note attributed to {} sent by {}",
+            note.attributed_to,
+            actor.id
+        )));
+    }
+
+    Ok(note)
+}
+
+/// Title of the post created from a Note
+fn title(note: &Note) -> String {
+    note.name
+        .clone()
+        .unwrap_or_default()
+        .chars()
+        .take(100)
+        .collect()
+}
+
+/// Content of the post created from a Note, preferring its Markdown source
+fn content(note: &Note) -> Vec<PostContent> {
+    match &note.source {
+        Some(source) if source.media_type == "text/markdown" => {
+            vec![PostContent::Markdown(MarkdownContent {
+                text: source.content.clone(),
+            })]
+        }
+        _ => vec![PostContent::Text(TextContent {
+            text: strip_html(&note.content),
+        })],
+    }
+}
+
+/// Returns whether a local community exists
+async fn community_exists(id: &str, pool: &Pool<Postgres>) -> Result<bool, Error> {
+    Ok(sqlx::query!(
+        r#"
+            SELECT EXISTS(SELECT 1 FROM communities WHERE id = $1) AS "exists!"
+        "#,
+        id
+    )
+    .fetch_one(pool)
+    .await?
+    .exists)
+}
+
+/// Returns the user a remote actor is mapped to
+async fn actor_user(actor: &Actor, pool: &Pool<Postgres>) -> Result<(String, String), Error> {
+    let row = sqlx::query!(
+        r#"
+            SELECT username, host FROM activitypub_actors
+            WHERE uri = $1
+        "#,
+        actor.id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok((row.username, row.host))
+}
+
+/// Finds the ID and community of the post with the supplied object ID, which is either a post on
+/// this server or one received over ActivityPub
+async fn find_object(
+    uri: &str,
+    host: &str,
+    pool: &Pool<Postgres>,
+) -> Result<Option<(Uuid, String)>, Error> {
+    let local = uri
+        .strip_prefix(&format!("{}/ap/posts/", base_url(host)))
+        .and_then(|id| id.parse::<Uuid>().ok());
+
+    Ok(sqlx::query!(
+        r#"
+            SELECT posts.id, posts.community FROM posts
+            LEFT JOIN activitypub_objects ON activitypub_objects.post = posts.id
+            WHERE activitypub_objects.uri = $1
+            OR posts.id = $2
+        "#,
+        uri,
+        local
+    )
+    .fetch_optional(pool)
+    .await?
+    .map(|row| (row.id, row.community)))
+}
+
+/// Finds the ID of the post created from an object received over ActivityPub, if it was authored by
+/// the supplied actor
+async fn find_authored(
+    uri: &str,
+    actor: &Actor,
+    pool: &Pool<Postgres>,
+) -> Result<Option<Uuid>, Error> {
+    let author = actor_user(actor, pool).await?;
+
+    Ok(sqlx::query!(
+        r#"
+            SELECT posts.id FROM posts
+            INNER JOIN activitypub_objects ON activitypub_objects.post = posts.id
+            WHERE activitypub_objects.uri = $1
+            AND posts.author_username = $2
+            AND posts.author_host = $3
+        "#,
+        uri,
+        author.0,
+        author.1
+    )
+    .fetch_optional(pool)
+    .await?
+    .map(|row| row.id))
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        crate::{
+            activitypub::{community_url, signature::sign_request, ACTIVITY_JSON},
+            fed::outbox::process,
+            models::activitypub::{ACTIVITYSTREAMS, PUBLIC, SECURITY},
+            test::{connect, new_user_login, stand_in, ADDR, FQDN},
+        },
+        actix_rt::time::delay_for,
+        actix_web::{
+            client::Client,
+            http::{header::CONTENT_TYPE, StatusCode, Uri},
+            web, HttpRequest, HttpResponse,
+        },
+        rsa::{PublicKeyPemEncoding, RSAPrivateKey, RSAPublicKey},
+        serde_json::{json, Value},
+        std::{
+            convert::TryFrom,
+            sync::{Arc, Mutex},
+            time::Duration,
+        },
+    };
+
+    /// Posts an activity to an inbox of the backend, signed by the supplied actor
+    async fn deliver(
+        privkey: &RSAPrivateKey,
+        actor: &str,
+        path: &str,
+        activity: Value,
+    ) -> StatusCode {
+        let url = Uri::try_from(format!("{}{}", *ADDR, path)).unwrap();
+        let body = serde_json::to_vec(&activity).unwrap();
+        let headers = sign_request(privkey, actor, &url, &body).unwrap();
+
+        Client::new()
+            .post(url)
+            .header("Date", headers.date)
+            .header("Digest", headers.digest)
+            .header("Signature", headers.signature)
+            .content_type(ACTIVITY_JSON)
+            .send_body(body)
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[actix_rt::test]
+    async fn inbox_success() {
+        let (pool, privkey) = connect().await;
+        let (client, _, cookie) = new_user_login().await;
+
+        let res = client
+            .post(format!("{}/internal/communities", *ADDR))
+            .cookie(cookie.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .send_body(r#"{"id": "apcommunity", "title": "AP", "description": "Bridged"}"#)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // stand-in actor signing with the backend's own key and recording what it receives
+        let received = Arc::new(Mutex::new(Vec::<Value>::new()));
+        let pem = RSAPublicKey::from(&privkey).to_pem_pkcs8().unwrap();
+        let inbox = received.clone();
+        let addr = stand_in(
+            "127.0.0.1",
+            move |cfg: &mut web::ServiceConfig| {
+                let pem = pem.clone();
+                let inbox = inbox.clone();
+                cfg.route(
+                    "/actor",
+                    web::get().to(move |req: HttpRequest| {
+                        let base = format!("http://{}", req.connection_info().host());
+                        let pem = pem.clone();
+                        async move {
+                            HttpResponse::Ok().content_type(ACTIVITY_JSON).json(json!({
+                                "@context": [ACTIVITYSTREAMS, SECURITY],
+                                "id": format!("{}/actor", base),
+                                "type": "Person",
+                                "preferredUsername": "stand_in",
+                                "inbox": format!("{}/inbox", base),
+                                "publicKey": {
+                                    "id": format!("{}/actor#main-key", base),
+                                    "owner": format!("{}/actor", base),
+                                    "publicKeyPem": pem,
+                                },
+                            }))
+                        }
+                    }),
+                )
+                .route(
+                    "/inbox",
+                    web::post().to(move |body: web::Bytes| {
+                        inbox
+                            .lock()
+                            .unwrap()
+                            .push(serde_json::from_slice(&body).unwrap());
+                        async { HttpResponse::Accepted().finish() }
+                    }),
+                );
+            },
+            None,
+        );
+        let actor = format!("http://{}/actor", addr);
+        let community = community_url(&FQDN, "apcommunity");
+
+        // follow the community
+        let status = deliver(
+            &privkey,
+            &actor,
+            "/ap/communities/apcommunity/inbox",
+            json!({
+                "@context": ACTIVITYSTREAMS,
+                "id": format!("{}/follow", actor),
+                "type": "Follow",
+                "actor": actor,
+                "object": community,
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+
+        // posts made locally are announced to followers
+        let res = client
+            .post(format!("{}/internal/posts", *ADDR))
+            .cookie(cookie)
+            .header(CONTENT_TYPE, "application/json")
+            .send_body(
+                r#"{"community": "apcommunity", "title": "Local", "content": [{"text": {"text": "Hi"}}]}"#,
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // the background worker may deliver first
+        let mut kinds = vec![];
+        for _ in 0..10 {
+            process(&FQDN, &pool, &privkey).await.unwrap();
+            kinds = received
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|activity| activity["type"].as_str().unwrap().to_owned())
+                .collect();
+            if kinds.len() == 2 {
+                break;
+            }
+            delay_for(Duration::from_millis(500)).await;
+        }
+        kinds.sort();
+        assert_eq!(kinds, vec!["Accept", "Announce"]);
+        {
+            let received = received.lock().unwrap();
+            let accept = received.iter().find(|a| a["type"] == "Accept").unwrap();
+            assert_eq!(accept["object"]["id"], format!("{}/follow", actor));
+            let announce = received.iter().find(|a| a["type"] == "Announce").unwrap();
+            assert_eq!(announce["actor"], community);
+            assert_eq!(announce["object"]["type"], "Create");
+            assert_eq!(announce["object"]["object"]["name"], "Local");
+        }
+
+        // create a post through the shared inbox, addressed to the community
+        let note = format!("{}/notes/1", actor);
+        let status = deliver(
+            &privkey,
+            &actor,
+            "/ap/inbox",
+            json!({
+                "@context": ACTIVITYSTREAMS,
+                "id": format!("{}/activity", note),
+                "type": "Create",
+                "actor": actor,
+                "to": PUBLIC,
+                "cc": [community],
+                "object": {
+                    "id": note,
+                    "type": "Note",
+                    "attributedTo": actor,
+                    "name": "Hello",
+                    "content": "<p>Hello &amp; welcome</p>",
+                    "published": "2100-01-01T00:00:00Z",
+                    "to": PUBLIC,
+                    "cc": [community],
+                },
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+
+        let post = sqlx::query!(
+            r#"
+                SELECT posts.community, posts.author_username, posts.author_host, posts.title,
+                    posts.content, posts.created
+                FROM posts
+                INNER JOIN activitypub_objects ON activitypub_objects.post = posts.id
+                WHERE activitypub_objects.uri = $1
+            "#,
+            note
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(post.community, "apcommunity");
+        assert_eq!(post.author_username, "stand_in");
+        assert_eq!(post.author_host, addr.to_string());
+        assert_eq!(post.title, "Hello");
+        assert_eq!(post.content, json!([{"text": {"text": "Hello & welcome"}}]));
+        // dates in the future are not taken as they are
+        assert!(post.created <= chrono::Local::now().timestamp());
+
+        // update it from its Markdown source
+        let status = deliver(
+            &privkey,
+            &actor,
+            "/ap/inbox",
+            json!({
+                "@context": ACTIVITYSTREAMS,
+                "id": format!("{}/update", note),
+                "type": "Update",
+                "actor": actor,
+                "object": {
+                    "id": note,
+                    "type": "Note",
+                    "attributedTo": actor,
+                    "name": "Hello again",
+                    "content": "<p><strong>bold</strong></p>",
+                    "source": {"content": "**bold**", "mediaType": "text/markdown"},
+                },
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+
+        let post = sqlx::query!(
+            r#"
+                SELECT posts.title, posts.content FROM posts
+                INNER JOIN activitypub_objects ON activitypub_objects.post = posts.id
+                WHERE activitypub_objects.uri = $1
+            "#,
+            note
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(post.title, "Hello again");
+        assert_eq!(post.content, json!([{"markdown": {"text": "**bold**"}}]));
+
+        // and delete it
+        let status = deliver(
+            &privkey,
+            &actor,
+            "/ap/inbox",
+            json!({
+                "@context": ACTIVITYSTREAMS,
+                "id": format!("{}/delete", note),
+                "type": "Delete",
+                "actor": actor,
+                "object": note,
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+
+        let count = sqlx::query!(
+            r#"
+                SELECT COUNT(*) AS "count!" FROM activitypub_objects
+                WHERE uri = $1
+            "#,
+            note
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap()
+        .count;
+        assert_eq!(count, 0);
+    }
+
+    #[actix_rt::test]
+    async fn inbox_unsigned_fail() {
+        let res = Client::new()
+            .post(format!("{}/ap/inbox", *ADDR))
+            .content_type(ACTIVITY_JSON)
+            .send_body(r#"{"type": "Delete"}"#)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    /// Starts a stand-in remote serving an actor that publishes the supplied key under the key ID
+    /// `actor#<key>`, and whose inbox is the supplied URL or one on its own host
+    fn stand_in_actor(
+        privkey: &RSAPrivateKey,
+        username: &'static str,
+        key: &'static str,
+        inbox: Option<&'static str>,
+    ) -> String {
+        let pem = RSAPublicKey::from(privkey).to_pem_pkcs8().unwrap();
+        let addr = stand_in(
+            "127.0.0.1",
+            move |cfg: &mut web::ServiceConfig| {
+                let pem = pem.clone();
+                cfg.route(
+                    "/actor",
+                    web::get().to(move |req: HttpRequest| {
+                        let base = format!("http://{}", req.connection_info().host());
+                        let pem = pem.clone();
+                        async move {
+                            HttpResponse::Ok().content_type(ACTIVITY_JSON).json(json!({
+                                "@context": [ACTIVITYSTREAMS, SECURITY],
+                                "id": format!("{}/actor", base),
+                                "type": "Person",
+                                "preferredUsername": username,
+                                "inbox": inbox
+                                    .map_or_else(|| format!("{}/inbox", base), str::to_owned),
+                                "publicKey": {
+                                    "id": format!("{}/actor#{}", base, key),
+                                    "owner": format!("{}/actor", base),
+                                    "publicKeyPem": pem,
+                                },
+                            }))
+                        }
+                    }),
+                );
+            },
+            None,
+        );
+
+        format!("http://{}/actor", addr)
+    }
+
+    #[actix_rt::test]
+    async fn inbox_mismatched_key_id_fail() {
+        let (_, privkey) = connect().await;
+
+        // actor publishing the signing key under a different key ID than is signed with
+        let actor = stand_in_actor(&privkey, "mismatched", "other-key", None);
+
+        let status = deliver(
+            &privkey,
+            &actor,
+            "/ap/inbox",
+            json!({
+                "@context": ACTIVITYSTREAMS,
+                "id": format!("{}/delete", actor),
+                "type": "Delete",
+                "actor": actor,
+                "object": format!("{}/notes/1", actor),
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn inbox_foreign_inbox_fail() {
+        let (_, privkey) = connect().await;
+
+        // actor whose inbox would have accepts delivered to another host
+        let actor = stand_in_actor(
+            &privkey,
+            "foreigninbox",
+            "main-key",
+            Some("http://169.254.169.254/inbox"),
+        );
+
+        let status = deliver(
+            &privkey,
+            &actor,
+            "/ap/communities/apcommunity/inbox",
+            json!({
+                "@context": ACTIVITYSTREAMS,
+                "id": format!("{}/follow", actor),
+                "type": "Follow",
+                "actor": actor,
+                "object": format!("{}/ap/communities/apcommunity", *ADDR),
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
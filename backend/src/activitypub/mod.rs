@@ -0,0 +1,332 @@
+//! ActivityPub bridge exposing communities as Group actors and local users as Person actors
+
+mod actors;
+mod inbox;
+pub mod signature;
+mod webfinger;
+
+pub use {actors::*, inbox::*, webfinger::*};
+
+use {
+    crate::{
+        fed::outbox::{self, Delivery},
+        models::{
+            activitypub::{Activity, Note, Source, ACTIVITYSTREAMS, PUBLIC},
+            database::{self, PostContent},
+        },
+        Error,
+    },
+    actix_web::web,
+    chrono::{TimeZone, Utc},
+    regex::Regex,
+    serde_json::json,
+    sqlx::{Pool, Postgres},
+    uuid::Uuid,
+};
+
+/// Content type of ActivityPub documents
+pub const ACTIVITY_JSON: &str = "application/activity+json";
+/// Maximum number of activities listed in an outbox
+const OUTBOX_SIZE: i64 = 20;
+
+/// Registers the ActivityPub routes
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_webfinger)
+        .service(get_user_actor)
+        .service(get_user_outbox)
+        .service(get_community_actor)
+        .service(get_community_outbox)
+        .service(get_community_followers)
+        .service(get_post_object)
+        .service(community_inbox)
+        .service(shared_inbox);
+}
+
+/// Base URL of ActivityPub IDs on the server with the supplied host
+fn base_url(host: &str) -> String {
+    format!("https://{}", host)
+}
+
+/// ID of the Person actor of a local user
+pub(crate) fn user_url<U: AsRef<str>>(host: &str, username: U) -> String {
+    format!("{}/ap/users/{}", base_url(host), username.as_ref())
+}
+
+/// ID of the Group actor of a community
+pub(crate) fn community_url<C: AsRef<str>>(host: &str, community: C) -> String {
+    format!("{}/ap/communities/{}", base_url(host), community.as_ref())
+}
+
+/// ID of the object of a post created on this server
+pub(crate) fn post_url(host: &str, id: Uuid) -> String {
+    format!("{}/ap/posts/{}", base_url(host), id)
+}
+
+/// New unique ID for an activity
+fn activity_url(host: &str) -> String {
+    format!("{}/ap/activities/{}", base_url(host), Uuid::new_v4())
+}
+
+/// Returns the ID of the community whose Group actor has the supplied ID
+pub(crate) fn parse_community_url<U: AsRef<str>>(host: &str, url: U) -> Option<String> {
+    url.as_ref()
+        .strip_prefix(&community_url(host, ""))
+        .filter(|id| !id.is_empty() && !id.contains('/'))
+        .map(str::to_owned)
+}
+
+/// Escapes text for inclusion in HTML
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Converts HTML into plain text, keeping paragraph and line breaks
+fn strip_html(html: &str) -> String {
+    let breaks =
+        Regex::new(r"(?i)<br\s*/?>|</p>\s*<p[^>]*>").expect("Failed to build regular expression");
+    let tags = Regex::new(r"<[^>]*>").expect("Failed to build regular expression");
+
+    let text = breaks.replace_all(html, "\n");
+    tags.replace_all(&text, "")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Renders post content as HTML, along with its Markdown source if it has any
+fn render(content: &[PostContent]) -> (String, Option<Source>) {
+    let mut html = String::new();
+    let mut markdown = vec![];
+
+    for item in content {
+        let text = match item {
+            PostContent::Text(t) => &t.text,
+            PostContent::Markdown(m) => {
+                markdown.push(m.text.clone());
+                &m.text
+            }
+        };
+
+        html.push_str(&format!(
+            "<p>{}</p>",
+            escape_html(text).replace('\n', "<br>")
+        ));
+    }
+
+    let source = if markdown.is_empty() {
+        None
+    } else {
+        Some(Source {
+            content: markdown.join("\n\n"),
+            media_type: "text/markdown".to_owned(),
+        })
+    };
+
+    (html, source)
+}
+
+/// Converts a post into a Note
+pub(crate) async fn note(
+    post: database::Post,
+    host: &str,
+    pool: &Pool<Postgres>,
+) -> Result<Note, Error> {
+    let ids = sqlx::query!(
+        r#"
+            SELECT
+                (SELECT uri FROM activitypub_objects WHERE post = $1) AS uri,
+                (SELECT uri FROM activitypub_objects WHERE post = $2) AS parent_uri,
+                (SELECT uri FROM activitypub_actors WHERE username = $3 AND host = $4) AS actor
+        "#,
+        post.id,
+        post.parent,
+        post.author_username,
+        post.author_host
+    )
+    .fetch_one(pool)
+    .await?;
+
+    // users of the bespoke federation protocol have no actor, so are referenced by their profile
+    let attributed_to = match ids.actor {
+        Some(actor) => actor,
+        None if post.author_host == host => user_url(host, &post.author_username),
+        None => format!(
+            "https://{}/fed/users/{}",
+            post.author_host, post.author_username
+        ),
+    };
+
+    let content = serde_json::from_value::<Vec<PostContent>>(post.content)?;
+    let (html, source) = render(&content);
+
+    Ok(Note {
+        context: json!(ACTIVITYSTREAMS),
+        id: ids.uri.unwrap_or_else(|| post_url(host, post.id)),
+        kind: "Note".to_owned(),
+        attributed_to,
+        name: Some(post.title).filter(|t| !t.is_empty()),
+        content: html,
+        source,
+        in_reply_to: post
+            .parent
+            .map(|parent| ids.parent_uri.unwrap_or_else(|| post_url(host, parent))),
+        audience: Some(community_url(host, &post.community)),
+        to: vec![PUBLIC.to_owned()],
+        cc: vec![community_url(host, &post.community)],
+        published: Some(Utc.timestamp(post.created, 0).to_rfc3339()),
+        updated: Some(Utc.timestamp(post.modified, 0).to_rfc3339()),
+    })
+}
+
+/// Wraps a post in a Create activity by its author
+pub(crate) async fn create_activity(
+    post: database::Post,
+    host: &str,
+    pool: &Pool<Postgres>,
+) -> Result<Activity, Error> {
+    let note = note(post, host, pool).await?;
+
+    Ok(Activity {
+        context: json!(ACTIVITYSTREAMS),
+        id: format!("{}#create", note.id),
+        kind: "Create".to_owned(),
+        actor: note.attributed_to.clone(),
+        to: note.to.clone(),
+        cc: note.cc.clone(),
+        object: serde_json::to_value(Note {
+            context: serde_json::Value::Null,
+            ..note
+        })?,
+    })
+}
+
+/// Queues a Create, Update or Delete activity about a post for delivery to every ActivityPub
+/// follower of its community, announced by the community's Group actor
+///
+/// Delete activities must be published before the post is removed.
+pub(crate) async fn publish(
+    kind: &str,
+    id: Uuid,
+    host: &str,
+    pool: &Pool<Postgres>,
+) -> Result<(), Error> {
+    let post = match sqlx::query_as!(
+        database::Post,
+        r#"
+            SELECT * FROM posts
+            WHERE id = $1
+        "#,
+        id
+    )
+    .fetch_optional(pool)
+    .await?
+    {
+        Some(post) => post,
+        None => return Ok(()),
+    };
+
+    let inboxes = sqlx::query!(
+        r#"
+            SELECT DISTINCT inbox FROM activitypub_followers
+            WHERE community = $1
+        "#,
+        post.community
+    )
+    .fetch_all(pool)
+    .await?;
+
+    if inboxes.is_empty() {
+        return Ok(());
+    }
+
+    let community = community_url(host, &post.community);
+    let mut activity = create_activity(post, host, pool).await?;
+    activity.context = serde_json::Value::Null;
+    activity.id = activity_url(host);
+    activity.kind = kind.to_owned();
+    if kind == "Delete" {
+        activity.object = json!(activity.object_id());
+    }
+
+    let announce = Activity {
+        context: json!(ACTIVITYSTREAMS),
+        id: activity_url(host),
+        kind: "Announce".to_owned(),
+        actor: community.clone(),
+        to: vec![PUBLIC.to_owned()],
+        cc: vec![format!("{}/followers", community)],
+        object: serde_json::to_value(activity)?,
+    };
+
+    for row in inboxes {
+        outbox::enqueue(
+            pool,
+            &Delivery::Activity {
+                inbox: row.inbox,
+                activity: serde_json::to_value(&announce)?,
+            },
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::{escape_html, parse_community_url, render, strip_html},
+        crate::models::database::{MarkdownContent, PostContent, TextContent},
+    };
+
+    #[test]
+    fn render_escapes_html() {
+        assert_eq!(
+            escape_html("<b>\"a\" & b</b>"),
+            "&lt;b&gt;&quot;a&quot; &amp; b&lt;/b&gt;"
+        );
+
+        let (html, source) = render(&[
+            PostContent::Text(TextContent {
+                text: "one\ntwo".to_owned(),
+            }),
+            PostContent::Markdown(MarkdownContent {
+                text: "**three**".to_owned(),
+            }),
+        ]);
+        assert_eq!(html, "<p>one<br>two</p><p>**three**</p>");
+        assert_eq!(source.unwrap().content, "**three**");
+    }
+
+    #[test]
+    fn strip_html_success() {
+        assert_eq!(
+            strip_html("<p>one<br/>two</p><p class=\"x\"><b>&lt;three&gt;</b> &amp; four</p>"),
+            "one\ntwo\n<three> & four"
+        );
+    }
+
+    #[test]
+    fn parse_community_url_success() {
+        assert_eq!(
+            parse_community_url("example.org", "https://example.org/ap/communities/rust"),
+            Some("rust".to_owned())
+        );
+        assert_eq!(
+            parse_community_url(
+                "example.org",
+                "https://example.org/ap/communities/rust/outbox"
+            ),
+            None
+        );
+        assert_eq!(
+            parse_community_url("example.org", "https://other.example/ap/communities/rust"),
+            None
+        );
+    }
+}
@@ -0,0 +1,287 @@
+//! HTTP Signatures as used by ActivityPub implementations, with RSA and SHA-256
+
+use {
+    super::ACTIVITY_JSON,
+    crate::{
+        fed::signature::{decode_pem, request_target, sign_sha256, verify_sha256, SignatureHeader},
+        models::activitypub::Actor,
+        util::ensure_federates,
+        AppData, Error,
+    },
+    actix_web::{
+        http::{header::HttpDate, uri::Authority, Uri},
+        HttpRequest,
+    },
+    anyhow::{anyhow, Context},
+    regex::Regex,
+    rsa::{RSAPrivateKey, RSAPublicKey},
+    sha2::{Digest, Sha256, Sha512},
+    sqlx::{Pool, Postgres},
+    std::{convert::TryFrom, time::SystemTime},
+};
+
+/// Headers every ActivityPub request must sign
+const REQUIRED_HEADERS: &[&str] = &["(request-target)", "host", "date", "digest"];
+/// Seconds for which a fetched remote actor is reused
+const ACTOR_TTL: i64 = 24 * 60 * 60;
+
+/// Values of the Date, Digest and Signature headers of a signed request
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignedHeaders {
+    pub date: String,
+    pub digest: String,
+    pub signature: String,
+}
+
+/// Signs a POST request to the supplied URL with the key of the supplied actor
+pub fn sign_request(
+    privkey: &RSAPrivateKey,
+    actor: &str,
+    url: &Uri,
+    body: &[u8],
+) -> anyhow::Result<SignedHeaders> {
+    let host = url
+        .authority()
+        .ok_or_else(|| anyhow!("URL {} has no authority", url))?
+        .to_string();
+    let date = HttpDate::from(SystemTime::now()).to_string();
+    let digest = format!("SHA-256={}", base64::encode(Sha256::digest(body)));
+
+    let mut signature = SignatureHeader {
+        key_id: format!("{}#main-key", actor),
+        algorithm: Some("rsa-sha256".to_owned()),
+        headers: REQUIRED_HEADERS.iter().map(|h| (*h).to_owned()).collect(),
+        signature: vec![],
+    };
+
+    let input = signature.signing_string("post", request_target(url), |name| match name {
+        "host" => Some(host.clone()),
+        "date" => Some(date.clone()),
+        "digest" => Some(digest.clone()),
+        _ => None,
+    })?;
+    signature.signature = sign_sha256(privkey, &input)?;
+
+    Ok(SignedHeaders {
+        date,
+        digest,
+        signature: signature.to_string(),
+    })
+}
+
+/// Verifies the HTTP signature and digest of an inbox request, returning the signing actor
+pub(crate) async fn verify_request(
+    req: &HttpRequest,
+    body: &[u8],
+    data: &AppData,
+) -> Result<Actor, Error> {
+    let date: SystemTime = header(req, "Date")?
+        .parse::<HttpDate>()
+        .map_err(|e| Error::BadRequest(e.into()))?
+        .into();
+    data.replay_cache
+        .check_date(date, SystemTime::now())
+        .map_err(Error::Replay)?;
+
+    let signature = header(req, "Signature")?
+        .parse::<SignatureHeader>()
+        .map_err(|e| Error::BadRequest(e.into()))?;
+
+    match signature.algorithm.as_deref() {
+        None | Some("rsa-sha256") | Some("hs2019") => {}
+        Some(algorithm) => {
+            return Err(Error::BadRequest(anyhow!(
+                "Unsupported signature algorithm {:?}",
+                algorithm
+            )))
+        }
+    }
+    for name in REQUIRED_HEADERS {
+        if !signature.covers(name) {
+            return Err(Error::BadRequest(anyhow!(
+                "Signature does not cover required header \"{}\"",
+                name
+            )));
+        }
+    }
+
+    // the digest must match the body that was received
+    let digest = header(req, "Digest")?;
+    let expected = base64::encode(Sha256::digest(body));
+    if !digest.split(',').any(|entry| {
+        let mut parts = entry.trim().splitn(2, '=');
+        parts
+            .next()
+            .map_or(false, |algorithm| algorithm.eq_ignore_ascii_case("sha-256"))
+            && parts.next() == Some(expected.as_str())
+    }) {
+        return Err(Error::BadRequest(anyhow!(
+            "Digest header does not contain the SHA-256 digest of the body"
+        )));
+    }
+
+    // the key ID is the actor ID with a fragment identifying the key
+    let actor_id = signature
+        .key_id
+        .split('#')
+        .next()
+        .expect("Splitting always yields at least one item");
+    let host = Uri::try_from(actor_id)
+        .ok()
+        .and_then(|uri| uri.authority().cloned())
+        .ok_or_else(|| Error::BadRequest(anyhow!("Invalid keyId {:?}", signature.key_id)))?;
+    ensure_federates(host.as_str(), &data.pool).await?;
+
+    let actor = fetch_actor(actor_id, data).await?;
+    if actor.public_key.id != signature.key_id || actor.public_key.owner != actor_id {
+        return Err(Error::BadRequest(anyhow!(
+            "Key {} does not belong to {}",
+            signature.key_id,
+            actor.id
+        )));
+    }
+
+    let input = signature
+        .signing_string(req.method().as_str(), request_target(req.uri()), |name| {
+            if name == "digest" {
+                return Some(digest.to_owned());
+            }
+
+            req.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_owned)
+        })
+        .map_err(|e| Error::BadRequest(e.into()))?;
+
+    let pubkey = decode_pem(&actor.public_key.public_key_pem)
+        .map_err(|e| Error::BadRequest(e.into()))
+        .and_then(|der| RSAPublicKey::from_pkcs8(&der).map_err(|e| Error::BadRequest(e.into())))?;
+    verify_sha256(&pubkey, &input, &signature.signature)
+        .context("Verifying signature")
+        .map_err(Error::BadRequest)?;
+
+    data.replay_cache
+        .check_signature(Sha512::digest(&signature.signature).to_vec())
+        .map_err(Error::Replay)?;
+
+    Ok(actor)
+}
+
+/// Returns the value of a header of a request
+fn header<'a>(req: &'a HttpRequest, name: &str) -> Result<&'a str, Error> {
+    req.headers()
+        .get(name)
+        .ok_or_else(|| Error::BadRequest(anyhow!("Missing {} header", name)))?
+        .to_str()
+        .map_err(|e| Error::BadRequest(e.into()))
+}
+
+/// Returns a remote actor, fetching it if it is not cached or the cached copy is stale
+pub(crate) async fn fetch_actor(id: &str, data: &AppData) -> Result<Actor, Error> {
+    let now = chrono::Local::now().timestamp();
+
+    if let Some(row) = sqlx::query!(
+        r#"
+            SELECT document FROM activitypub_actors
+            WHERE uri = $1
+            AND fetched > $2
+        "#,
+        id,
+        now - ACTOR_TTL
+    )
+    .fetch_optional(&data.pool)
+    .await?
+    {
+        return Ok(serde_json::from_value(row.document)?);
+    }
+
+    let actor = crate::Client::new(&data.host, &data.privkey, &data.pool)
+        .get_actor(id)
+        .await
+        .map_err(Error::Client)?;
+
+    if actor.id != id {
+        return Err(Error::BadRequest(anyhow!(
+            "Actor fetched from {} has ID {}",
+            id,
+            actor.id
+        )));
+    }
+
+    store_actor(&actor, &data.pool).await?;
+
+    Ok(actor)
+}
+
+/// Returns the host of an actor's inbox, which activities signed by the backend are delivered to
+/// and must therefore be on the same host as the actor itself
+pub(crate) fn inbox_host(actor: &Actor) -> Result<String, Error> {
+    let authority = |url: &str| {
+        Uri::try_from(url)
+            .ok()
+            .and_then(|uri| uri.authority().map(Authority::to_string))
+    };
+
+    let host = authority(&actor.id)
+        .ok_or_else(|| Error::BadRequest(anyhow!("Invalid actor ID {:?}", actor.id)))?;
+    match authority(&actor.inbox) {
+        Some(inbox) if inbox.eq_ignore_ascii_case(&host) => Ok(host),
+        _ => Err(Error::BadRequest(anyhow!(
+            "Inbox {:?} of actor {} is not on its host",
+            actor.inbox,
+            actor.id
+        ))),
+    }
+}
+
+/// Caches a remote actor, creating the user it is mapped to
+async fn store_actor(actor: &Actor, pool: &Pool<Postgres>) -> Result<(), Error> {
+    if !Regex::new("^[a-zA-Z0-9-_]{1,24}$")
+        .expect("Failed to build regular expression")
+        .is_match(&actor.preferred_username)
+    {
+        return Err(Error::BadRequest(anyhow!(
+            "Unsupported preferredUsername {:?}",
+            actor.preferred_username
+        )));
+    }
+
+    let host = inbox_host(actor)?;
+
+    sqlx::query!(
+        r#"
+            INSERT INTO users VALUES ($1, $2)
+            ON CONFLICT DO NOTHING
+        "#,
+        actor.preferred_username,
+        host
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query!(
+        r#"
+            INSERT INTO activitypub_actors VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (uri) DO UPDATE
+                SET username = $2, host = $3, document = $4, fetched = $5
+        "#,
+        actor.id,
+        actor.preferred_username,
+        host,
+        serde_json::to_value(actor)?,
+        chrono::Local::now().timestamp()
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Accept header value for fetching ActivityPub documents
+pub fn accept() -> String {
+    format!(
+        "{}, application/ld+json; profile=\"https://www.w3.org/ns/activitystreams\"",
+        ACTIVITY_JSON
+    )
+}
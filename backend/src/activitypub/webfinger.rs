@@ -0,0 +1,99 @@
+use {
+    super::{community_url, user_url, ACTIVITY_JSON},
+    crate::{
+        models::activitypub::{WebFinger, WebFingerLink, WebFingerQuery},
+        AppData, Error,
+    },
+    actix_web::{get, web, HttpResponse, Responder, Result},
+};
+
+/// Resolves an "acct:" resource to the actor of a community or local user, preferring communities
+#[get("/.well-known/webfinger")]
+pub(crate) async fn get_webfinger(
+    data: web::Data<AppData>,
+    web::Query(query): web::Query<WebFingerQuery>,
+) -> Result<impl Responder, Error> {
+    let (name, host) = match query.resource.strip_prefix("acct:").and_then(|account| {
+        account
+            .rfind('@')
+            .map(|i| (&account[..i], &account[i + 1..]))
+    }) {
+        Some(account) => account,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    if !host.eq_ignore_ascii_case(&data.host) {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    let row = sqlx::query!(
+        r#"
+            SELECT
+                EXISTS(SELECT 1 FROM communities WHERE id = $1) AS "community!",
+                EXISTS(SELECT 1 FROM local_users WHERE username = $1) AS "user!"
+        "#,
+        name
+    )
+    .fetch_one(&data.pool)
+    .await?;
+
+    let href = if row.community {
+        community_url(&data.host, name)
+    } else if row.user {
+        user_url(&data.host, name)
+    } else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/jrd+json")
+        .json(WebFinger {
+            subject: query.resource.clone(),
+            aliases: vec![href.clone()],
+            links: vec![WebFingerLink {
+                rel: "self".to_owned(),
+                kind: ACTIVITY_JSON.to_owned(),
+                href,
+            }],
+        }))
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        crate::{
+            activitypub::user_url,
+            models::activitypub::WebFinger,
+            test::{new_user_login, ADDR, FQDN},
+        },
+        actix_web::http::StatusCode,
+    };
+
+    #[actix_rt::test]
+    async fn get_webfinger_success() {
+        let (client, username, _) = new_user_login().await;
+
+        let mut res = client
+            .get(format!(
+                "{}/.well-known/webfinger?resource=acct:{}@{}",
+                *ADDR, username, *FQDN
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let webfinger: WebFinger = res.json().await.unwrap();
+        assert_eq!(webfinger.links[0].href, user_url(&FQDN, &username));
+
+        // accounts of other hosts are not resolved
+        let res = client
+            .get(format!(
+                "{}/.well-known/webfinger?resource=acct:{}@other.example",
+                *ADDR, username
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+}
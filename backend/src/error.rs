@@ -1,5 +1,5 @@
 use {
-    crate::fed::client,
+    crate::{fed::client, middleware::fedsec::ReplayError, util::Defederated},
     actix_web::{http::StatusCode, HttpResponse},
     log::error,
     serde::Serialize,
@@ -24,6 +24,14 @@ pub enum Error {
     #[error("Client error: {0:?}")]
     Client(client::Error),
 
+    /// Federation request rejected by replay protection
+    #[error("Replayed federation request: {0}")]
+    Replay(ReplayError),
+
+    /// Federation refused by policy
+    #[error("{0}")]
+    Defederated(Defederated),
+
     /// General error
     #[error("{0:?}")]
     General(anyhow::Error),
@@ -64,6 +72,8 @@ impl From<client::Error> for Error {
 pub struct ErrorBody {
     title: String,
     message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<String>,
 }
 
 impl From<&Error> for ErrorBody {
@@ -74,9 +84,17 @@ impl From<&Error> for ErrorBody {
                 Error::Parse(_) => "Parse".to_owned(),
                 Error::BadRequest(_) => "Bad request".to_owned(),
                 Error::Client(_) => "Client".to_owned(),
+                Error::Replay(_) => "Replay".to_owned(),
+                Error::Defederated(_) => "Defederated".to_owned(),
                 Error::General(_) => "General".to_owned(),
             },
             message: format!("{}", e),
+            code: match e {
+                Error::Replay(e) => Some(e.code().to_owned()),
+                Error::Defederated(_) => Some("host_blocked".to_owned()),
+                Error::Client(client::Error::CircuitOpen(_)) => Some("circuit_open".to_owned()),
+                _ => None,
+            },
         }
     }
 }
@@ -90,6 +108,17 @@ impl actix_web::error::ResponseError for Error {
                 HttpResponse::NotFound().json(ErrorBody::from(self))
             }
             Error::BadRequest(_) => HttpResponse::BadRequest().json(ErrorBody::from(self)),
+            Error::Replay(_) => HttpResponse::Unauthorized().json(ErrorBody::from(self)),
+            Error::Defederated(_) => HttpResponse::Forbidden().json(ErrorBody::from(self)),
+            // pass through rejections from remotes so that clients can tell them apart
+            Error::Client(client::Error::ResponseStatus(status))
+                if *status == StatusCode::FORBIDDEN || *status == StatusCode::NOT_FOUND =>
+            {
+                HttpResponse::build(*status).json(ErrorBody::from(self))
+            }
+            Error::Client(client::Error::CircuitOpen(_)) => {
+                HttpResponse::ServiceUnavailable().json(ErrorBody::from(self))
+            }
             _ => HttpResponse::InternalServerError().json(ErrorBody::from(self)),
         }
     }
@@ -1,85 +1,327 @@
 use {
     crate::{
-        fed::PostFilters,
-        models::fed::{Community, Message, Post, UserId},
+        activitypub,
+        fed::{
+            health,
+            signature::{self, SignatureHeader},
+            PostFilters,
+        },
+        models::{
+            activitypub::Actor,
+            fed::{
+                Community, Message, NewPost, NodeInfo, Post, PostEdit, PostTimestamp, Report,
+                Score, User, UserId, Vote, INCLUDE_SUB_CHILDREN_POSTS, PAGINATION,
+            },
+            internal,
+        },
+        pagination::{self, Page},
     },
     actix_web::{
-        client::{Client as ActixClient, ClientRequest, JsonPayloadError, SendRequestError},
+        client::{
+            Client as ActixClient, ClientBuilder, ClientRequest, ClientResponse, Connector,
+            JsonPayloadError, SendRequestError,
+        },
+        dev::{Decompress, Payload, PayloadStream},
         error::PayloadError,
         http::{
-            header::{Date, ToStrError, LOCATION},
+            header::{Date, ToStrError, ACCEPT, LOCATION},
             uri::{Authority, InvalidUri, Parts, Scheme, Uri},
             StatusCode,
         },
         HttpMessage,
     },
     anyhow::anyhow,
+    futures::future::join_all,
     log::debug,
-    rsa::{hash::Hash, PaddingScheme, RSAPrivateKey, RSAPublicKey},
+    once_cell::sync::Lazy,
+    rsa::{RSAPrivateKey, RSAPublicKey},
+    rustls::ClientConfig,
     serde::{de::DeserializeOwned, Serialize},
     sha2::{Digest, Sha512},
+    sqlx::{Pool, Postgres},
     std::{
-        collections::HashMap,
+        collections::{HashMap, HashSet},
         convert::{TryFrom, TryInto},
-        time::SystemTime,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant, SystemTime},
     },
+    uuid::Uuid,
 };
 
+/// Response to a request sent by the federation client
+type Response = ClientResponse<Decompress<Payload<PayloadStream>>>;
+
+/// Maximum number of redirects followed while resolving the base URL of a remote
+pub const MAX_REDIRECTS: usize = 5;
+/// Duration for which the resolved base URL of a remote is reused
+const BASE_URL_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Resolved base URLs of remotes, keyed by the authority used to reach them
+static BASE_URLS: Lazy<Mutex<HashMap<String, (Scheme, Authority, Instant)>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Duration for which the nodeinfo document of a remote is reused
+const NODE_INFO_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Nodeinfo documents of remotes, None if the remote does not serve one
+static NODE_INFO: Lazy<Mutex<HashMap<String, (Option<NodeInfo>, Instant)>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Forgets the resolved base URL of a remote so that it is resolved again on the next request
+pub fn forget_base_url<H: AsRef<str>>(host: H) {
+    BASE_URLS
+        .lock()
+        .expect("Base URL cache lock poisoned")
+        .remove(host.as_ref());
+}
+
 /// Federation Client
 pub struct Client {
     client: ActixClient,
+    /// "host" value of the server the client sends requests on behalf of
+    host: String,
     privkey: RSAPrivateKey,
+    pool: Pool<Postgres>,
 }
 
 impl Client {
-    /// Creates a new Client with the supplied private key
-    pub fn new(privkey: &RSAPrivateKey) -> Self {
+    /// Creates a new Client for the supplied local host with the supplied private key
+    pub fn new<H: AsRef<str>>(host: H, privkey: &RSAPrivateKey, pool: &Pool<Postgres>) -> Self {
         Self {
             client: ActixClient::default(),
+            host: host.as_ref().to_owned(),
+            privkey: privkey.clone(),
+            pool: pool.clone(),
+        }
+    }
+
+    /// Creates a new Client for the supplied local host with the supplied private key that uses the
+    /// supplied TLS configuration, for example to trust additional root certificates
+    pub fn with_tls_config<H: AsRef<str>>(
+        host: H,
+        privkey: &RSAPrivateKey,
+        pool: &Pool<Postgres>,
+        mut config: ClientConfig,
+    ) -> Self {
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+        Self {
+            client: ClientBuilder::new()
+                .connector(Connector::new().rustls(Arc::new(config)).finish())
+                .finish(),
+            host: host.as_ref().to_owned(),
             privkey: privkey.clone(),
+            pool: pool.clone(),
         }
     }
 
+    /// Returns the base URL of a remote, resolving it if it is not cached
+    ///
+    /// HTTPS is preferred, falling back to HTTP if the remote cannot be reached over HTTPS and is
+    /// not known to require it.
     async fn validate_host<T: AsRef<str>>(&self, host: T) -> Result<Parts, Error> {
+        if health::is_open(host.as_ref()) {
+            return Err(Error::CircuitOpen(host.as_ref().to_owned()));
+        }
+
         let host = Authority::try_from(host.as_ref())?;
 
+        let cached = BASE_URLS
+            .lock()
+            .expect("Base URL cache lock poisoned")
+            .get(host.as_str())
+            .filter(|(_, _, resolved)| resolved.elapsed() < BASE_URL_TTL)
+            .map(|(scheme, authority, _)| (scheme.clone(), authority.clone()));
+
+        let (scheme, authority) = match cached {
+            Some(base) => base,
+            None => {
+                let allow_http = self.allows_http(&host).await?;
+                let started = Instant::now();
+
+                let base = match self.resolve(Scheme::HTTPS, host.clone(), allow_http).await {
+                    Ok(base) => Ok(base),
+                    Err(Error::Send(e)) if allow_http => {
+                        debug!("fed client: {} unreachable over HTTPS: {:?}", host, e);
+                        self.resolve(Scheme::HTTP, host.clone(), allow_http).await
+                    }
+                    Err(e) => Err(e),
+                };
+                let base = match base {
+                    Ok(base) => base,
+                    Err(e) => {
+                        health::record(host.as_str(), started.elapsed(), Some(e.to_string()));
+                        return Err(e);
+                    }
+                };
+
+                debug!("fed client: resolved {} to {}://{}", host, base.0, base.1);
+
+                BASE_URLS
+                    .lock()
+                    .expect("Base URL cache lock poisoned")
+                    .insert(
+                        host.as_str().to_owned(),
+                        (base.0.clone(), base.1.clone(), Instant::now()),
+                    );
+
+                base
+            }
+        };
+
         let mut parts = Parts::default();
-        parts.scheme = Some(Scheme::HTTP);
-        parts.authority = Some(host.clone());
+        parts.scheme = Some(scheme);
+        parts.authority = Some(authority);
         parts.path_and_query = Some(
             "/".try_into()
                 .expect("Parsing index path should always succeed"),
         );
 
-        let response = self.client.get(parts).send().await?;
+        Ok(parts)
+    }
 
-        let mut parts = Parts::default();
-        parts.scheme = Some(Scheme::HTTP);
-        parts.authority = Some(host);
-        parts.path_and_query = Some(
-            "/".try_into()
-                .expect("Parsing index path should always succeed"),
-        );
+    /// Returns whether the remote may be reached over plain HTTP, which is the case unless it was
+    /// added with HTTP disallowed
+    async fn allows_http(&self, host: &Authority) -> Result<bool, Error> {
+        Ok(sqlx::query!(
+            r#"
+                SELECT allow_http FROM remotes
+                WHERE host = $1
+            "#,
+            host.host()
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .map(|row| row.allow_http)
+        .unwrap_or(true))
+    }
 
-        if response.status() == StatusCode::MOVED_PERMANENTLY {
-            let location = response
-                .headers()
-                .get(LOCATION)
-                .ok_or(anyhow!("Missing Location header"))
-                .expect("Got 301 but no location")
-                .to_str()
-                .expect("Location should be valid UTF-8");
-            parts = Parts::from(Uri::try_from(location)?);
+    /// Requests the index of a remote over the supplied scheme, following up to MAX_REDIRECTS
+    /// redirects, and returns the scheme and authority that finally responded
+    async fn resolve(
+        &self,
+        scheme: Scheme,
+        authority: Authority,
+        allow_http: bool,
+    ) -> Result<(Scheme, Authority), Error> {
+        let mut uri = Uri::builder()
+            .scheme(scheme)
+            .authority(authority)
+            .path_and_query("/")
+            .build()
+            .map_err(|e| Error::Construction(e.into()))?;
+
+        for _ in 0..=MAX_REDIRECTS {
+            let response = self
+                .client
+                .get(uri.clone())
+                .timeout(health::DEADLINE)
+                .send()
+                .await?;
+
+            match response.status() {
+                StatusCode::MOVED_PERMANENTLY
+                | StatusCode::FOUND
+                | StatusCode::TEMPORARY_REDIRECT
+                | StatusCode::PERMANENT_REDIRECT => {
+                    let location = response
+                        .headers()
+                        .get(LOCATION)
+                        .ok_or_else(|| {
+                            Error::Redirect(anyhow!(
+                                "Got {} from {} without a Location header",
+                                response.status(),
+                                uri
+                            ))
+                        })?
+                        .to_str()?;
+                    let location = Uri::try_from(location)?;
+
+                    // relative locations keep the current scheme and authority
+                    let mut parts = Parts::from(location.clone());
+                    if parts.authority.is_none() {
+                        parts.scheme = uri.scheme().cloned();
+                        parts.authority = uri.authority().cloned();
+                    }
+
+                    match parts.scheme.as_ref() {
+                        Some(s) if *s == Scheme::HTTPS => {}
+                        Some(s) if *s == Scheme::HTTP && allow_http => {}
+                        _ => {
+                            return Err(Error::Redirect(anyhow!(
+                                "Refusing to follow redirect from {} to {}",
+                                uri,
+                                location
+                            )))
+                        }
+                    }
+
+                    let next = Uri::from_parts(parts).map_err(|e| Error::Redirect(e.into()))?;
+                    debug!("fed client: following redirect from {} to {}", uri, next);
+                    uri = next;
+                }
+                _ => {
+                    let parts = Parts::from(uri);
+                    return Ok((
+                        parts.scheme.expect("Resolved URI should contain a scheme"),
+                        parts
+                            .authority
+                            .expect("Resolved URI should contain an authority"),
+                    ));
+                }
+            }
         }
 
-        Ok(parts)
+        Err(Error::Redirect(anyhow!(
+            "Exceeded {} redirects resolving {}",
+            MAX_REDIRECTS,
+            uri
+        )))
     }
 
-    async fn send_json<A: Serialize, B: DeserializeOwned>(
+    /// Sends a request to a remote within health::DEADLINE and records the outcome in its health,
+    /// refusing to send it while the remote's circuit is open
+    async fn send(
+        &self,
+        host: &str,
+        req: ClientRequest,
+        body: Option<Vec<u8>>,
+    ) -> Result<Response, Error> {
+        if !health::allow(host) {
+            return Err(Error::CircuitOpen(host.to_owned()));
+        }
+
+        let started = Instant::now();
+        let req = req.timeout(health::DEADLINE);
+        let result = match body {
+            Some(body) => req.send_body(body).await,
+            None => req.send().await,
+        };
+
+        // remotes that reject a request are still healthy, remotes that fail to handle it are not
+        health::record(
+            host,
+            started.elapsed(),
+            match &result {
+                Ok(response) if response.status().is_server_error() => {
+                    Some(format!("Received {}", response.status()))
+                }
+                Ok(_) => None,
+                Err(e) => Some(format!("{:?}", e)),
+            },
+        );
+
+        Ok(result?)
+    }
+
+    /// Signs and sends a request with a JSON body to a remote, returning the response if it was
+    /// successful
+    async fn send_signed<A: Serialize>(
         &self,
+        host: &str,
         req: ClientRequest,
         value: &A,
-    ) -> Result<B, Error> {
+    ) -> Result<Response, Error> {
         let body = serde_json::to_string(value)?;
 
         debug!("body: {}", body);
@@ -89,8 +331,8 @@ impl Client {
         debug!("generated digest: {}", digest);
 
         let user_id = match req.headers().get("User-ID") {
-            Some(value) => format!("user-id: {}\n", value.to_str()?),
-            None => "".to_owned(),
+            Some(value) => Some(value.to_str()?.to_owned()),
+            None => None,
         };
 
         let req = req.set(Date(SystemTime::now().into()));
@@ -98,48 +340,52 @@ impl Client {
             .headers()
             .get("Date")
             .expect("Date header should not be missing")
-            .to_str()?;
+            .to_str()?
+            .to_owned();
 
-        let host = req
+        let authority = req
             .get_uri()
             .authority()
-            .expect("URI should contain an authority");
-
-        let signature_input = format!(
-            "(request-target): {} {}\nhost: {}\nclient-host: {}\n{}date: {}\ndigest: SHA-512={}",
-            req.get_method().as_str().to_lowercase(),
-            req.get_uri().path(),
-            host,
-            crate::host!(),
-            user_id,
-            date,
-            digest
-        );
+            .expect("URI should contain an authority")
+            .to_string();
+
+        let digest = format!("SHA-512={}", digest);
+
+        let mut signature = SignatureHeader {
+            key_id: "global".to_owned(),
+            algorithm: Some("rsa-sha512".to_owned()),
+            headers: vec!["(request-target)", "host", "client-host"]
+                .into_iter()
+                .chain(user_id.as_ref().map(|_| "user-id"))
+                .chain(vec!["date", "digest"])
+                .map(str::to_owned)
+                .collect(),
+            signature: vec![],
+        };
 
-        let signature = format!(
-            "keyId=\"global\",algorithm=\"rsa-sha512\",headers=\"(request-target) host client-host {}date digest\",signature={}",
-            if user_id == "" {
-                ""
-            } else {
-                "user-id "
+        let signature_input = signature.signing_string(
+            req.get_method().as_str(),
+            signature::request_target(req.get_uri()),
+            |name| match name {
+                "host" => Some(authority.clone()),
+                "client-host" => Some(self.host.clone()),
+                "user-id" => user_id.clone(),
+                "date" => Some(date.clone()),
+                "digest" => Some(digest.clone()),
+                _ => None,
             },
-            base64::encode(self.privkey.sign(
-                PaddingScheme::PKCS1v15Sign {
-                    hash: Some(Hash::SHA2_512)
-                },
-                Sha512::digest(signature_input.as_bytes()).as_slice(),
-            )?)
-        );
+        )?;
+
+        signature.signature = signature::sign(&self.privkey, &signature_input)?;
 
         debug!("generated signature: {}", signature);
 
-        let mut response = req
-            .header("Client-Host", crate::host!())
-            .header("Digest", format!("sha-512={}", digest))
-            .header("Signature", signature.clone())
-            .content_type("application/json")
-            .send_body(&body)
-            .await?;
+        let req = req
+            .header("Client-Host", self.host.as_str())
+            .header("Digest", digest)
+            .header("Signature", signature.to_string())
+            .content_type("application/json");
+        let mut response = self.send(host, req, Some(body.into_bytes())).await?;
 
         if !response.status().is_success() {
             match response.body().await {
@@ -153,7 +399,17 @@ impl Client {
             return Err(Error::ResponseStatus(response.status()));
         }
 
-        Ok(response.json().await?)
+        Ok(response)
+    }
+
+    /// Signs and sends a request with a JSON body to a remote, deserializing the JSON response
+    async fn send_json<A: Serialize, B: DeserializeOwned>(
+        &self,
+        host: &str,
+        req: ClientRequest,
+        value: &A,
+    ) -> Result<B, Error> {
+        Ok(self.send_signed(host, req, value).await?.json().await?)
     }
 
     /// Gets the public key of a remote host
@@ -162,10 +418,13 @@ impl Client {
         parts.path_and_query = Some("/fed/key".try_into()?);
 
         let mut response = self
-            .client
-            .get(parts)
-            .header("Client-Host", crate::host!())
-            .send()
+            .send(
+                host.as_ref(),
+                self.client
+                    .get(parts)
+                    .header("Client-Host", self.host.as_str()),
+                None,
+            )
             .await?;
 
         if !response.status().is_success() {
@@ -190,18 +449,8 @@ impl Client {
         // Parse body as &str
         let body = core::str::from_utf8(&body_bytes).map_err(|e| Error::Body(e.into()))?;
 
-        // Remove PKCS8 headers
-        let mut der_encoded = body.lines().filter(|line| !line.starts_with("-")).fold(
-            String::new(),
-            |mut data, line| {
-                data.push_str(&line);
-                data
-            },
-        );
-        der_encoded.retain(|c| !c.is_whitespace());
-
-        // Base64 decode DER
-        let der = base64::decode(der_encoded).map_err(|e| Error::Body(e.into()))?;
+        // Remove PKCS8 headers and base64 decode DER
+        let der = signature::decode_pem(body).map_err(|e| Error::Body(e.into()))?;
 
         // Ensure that the key can be parsed
         debug!(
@@ -213,6 +462,132 @@ impl Client {
         Ok(der)
     }
 
+    /// Gets the nodeinfo document of a remote, returning None if it does not serve one
+    pub async fn get_node_info<H: AsRef<str>>(&self, host: H) -> Result<Option<NodeInfo>, Error> {
+        let cached = NODE_INFO
+            .lock()
+            .expect("Nodeinfo cache lock poisoned")
+            .get(host.as_ref())
+            .filter(|(_, fetched)| fetched.elapsed() < NODE_INFO_TTL)
+            .map(|(info, _)| info.clone());
+        if let Some(info) = cached {
+            return Ok(info);
+        }
+
+        let mut parts = self.validate_host(host.as_ref()).await?;
+        parts.path_and_query = Some("/fed/nodeinfo".try_into()?);
+
+        let mut response = self
+            .send(
+                host.as_ref(),
+                self.client
+                    .get(parts)
+                    .header("Client-Host", self.host.as_str()),
+                None,
+            )
+            .await?;
+
+        // remotes that predate nodeinfo are remembered as such rather than asked on every request,
+        // while other failures are not cached so that the next request tries again
+        let info = if response.status().is_success() {
+            Some(response.json::<NodeInfo>().await?)
+        } else if response.status() == StatusCode::NOT_FOUND {
+            debug!("fed client: {} does not serve nodeinfo", host.as_ref());
+            None
+        } else {
+            return Err(Error::ResponseStatus(response.status()));
+        };
+
+        NODE_INFO
+            .lock()
+            .expect("Nodeinfo cache lock poisoned")
+            .insert(host.as_ref().to_owned(), (info.clone(), Instant::now()));
+
+        Ok(info)
+    }
+
+    /// Returns whether a remote advertises the supplied feature, remotes without a nodeinfo
+    /// document are assumed to support none
+    pub async fn supports<H: AsRef<str>, F: AsRef<str>>(
+        &self,
+        host: H,
+        feature: F,
+    ) -> Result<bool, Error> {
+        Ok(self
+            .get_node_info(host)
+            .await?
+            .map(|info| info.supports(feature))
+            .unwrap_or(false))
+    }
+
+    /// Gets an ActivityPub actor by its ID
+    pub async fn get_actor<U: AsRef<str>>(&self, id: U) -> Result<Actor, Error> {
+        let url = Uri::try_from(id.as_ref())?;
+        let host = url
+            .authority()
+            .ok_or_else(|| Error::Construction(anyhow!("Actor ID {} has no authority", url)))?
+            .to_string();
+
+        let mut response = self
+            .send(
+                &host,
+                self.client
+                    .get(url)
+                    .header(ACCEPT, activitypub::signature::accept()),
+                None,
+            )
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(Error::ResponseStatus(response.status()));
+        }
+
+        let actor = response.json().await?;
+
+        debug!("fed client: got actor: {:?}", actor);
+
+        Ok(actor)
+    }
+
+    /// Delivers an ActivityPub activity to an inbox, signed with the key of its actor
+    pub async fn deliver_activity<I: AsRef<str>>(
+        &self,
+        inbox: I,
+        activity: &serde_json::Value,
+    ) -> Result<(), Error> {
+        let url = Uri::try_from(inbox.as_ref())?;
+        let actor = activity
+            .get("actor")
+            .and_then(serde_json::Value::as_str)
+            .ok_or_else(|| Error::Construction(anyhow!("Activity has no actor")))?;
+        let body = serde_json::to_vec(activity)?;
+
+        let headers = activitypub::signature::sign_request(&self.privkey, actor, &url, &body)
+            .map_err(Error::Construction)?;
+
+        let host = url
+            .authority()
+            .ok_or_else(|| Error::Construction(anyhow!("Inbox {} has no authority", url)))?
+            .to_string();
+        let req = self
+            .client
+            .post(url)
+            .header("Date", headers.date)
+            .header("Digest", headers.digest)
+            .header("Signature", headers.signature)
+            .content_type(activitypub::ACTIVITY_JSON);
+
+        let response = self.send(&host, req, Some(body)).await?;
+
+        if !response.status().is_success() {
+            return Err(Error::ResponseStatus(response.status()));
+        }
+
+        debug!("fed client: delivered activity to {}", inbox.as_ref());
+
+        Ok(())
+    }
+
     /// Sends a message to a host
     pub async fn send_message<T: AsRef<str>>(
         &self,
@@ -224,7 +599,8 @@ impl Client {
 
         parts.path_and_query = Some(format!("/fed/users/{}", to.id).try_into()?);
 
-        self.send_json(
+        self.send_signed(
+            &to.host,
             self.client.post(parts).header("User-ID", from.as_ref()),
             msg,
         )
@@ -235,14 +611,126 @@ impl Client {
         Ok(())
     }
 
-    /// Gets a list of the IDs of communities on the server
-    pub async fn get_communities<T: AsRef<str>>(&self, host: T) -> Result<Vec<String>, Error> {
-        let mut parts = self.validate_host(host).await?;
+    /// Forwards a user's report of a post or message to the remote host it came from
+    pub async fn report<F: AsRef<str>, H: AsRef<str>>(
+        &self,
+        from: F,
+        host: H,
+        report: &Report,
+    ) -> Result<(), Error> {
+        let mut parts = self.validate_host(host.as_ref()).await?;
+
+        parts.path_and_query = Some("/fed/reports".try_into()?);
+
+        self.send_signed(
+            host.as_ref(),
+            self.client.post(parts).header("User-ID", from.as_ref()),
+            report,
+        )
+        .await?;
 
-        parts.path_and_query = Some("/fed/communities".try_into()?);
+        debug!("fed client: reported {:?} on {}", report, host.as_ref());
+
+        Ok(())
+    }
+
+    /// Gets a user's profile
+    pub async fn get_user<H: AsRef<str>, U: AsRef<str>>(
+        &self,
+        host: H,
+        user: U,
+    ) -> Result<User, Error> {
+        let mut parts = self.validate_host(host.as_ref()).await?;
+
+        parts.path_and_query = Some(format!("/fed/users/{}", user.as_ref()).try_into()?);
+
+        let user = self
+            .send_json(
+                host.as_ref(),
+                self.client.get(parts),
+                &HashMap::<(), ()>::with_capacity(0),
+            )
+            .await?;
+
+        debug!("fed client: got user: {:?}", user);
+
+        Ok(user)
+    }
+
+    /// Gets every item of a list, walking its pages if the remote supports pagination and
+    /// otherwise fetching the whole list in one request
+    async fn get_all_pages<T: DeserializeOwned>(
+        &self,
+        host: &str,
+        path: &str,
+        mut query: Vec<(&str, String)>,
+    ) -> Result<Vec<T>, Error> {
+        let paginated = self.supports(host, PAGINATION).await?;
+        if paginated {
+            query.push(("limit", pagination::MAX_LIMIT.to_string()));
+        }
+
+        let mut items = vec![];
+        let mut cursor = None;
+
+        loop {
+            let mut parts = self.validate_host(host).await?;
+
+            let mut page_query = query.clone();
+            if let Some(cursor) = cursor.take() {
+                page_query.push(("cursor", cursor));
+            }
+            let page_query =
+                serde_urlencoded::ser::to_string(page_query).map_err(|e| Error::Body(e.into()))?;
+
+            parts.path_and_query = Some(if page_query.is_empty() {
+                path.try_into()?
+            } else {
+                format!("{}?{}", path, page_query).try_into()?
+            });
+
+            // serialising an empty hashmap to get send "{}" as the body of the request to avoid errors from body-parser in Express backends
+            let req = self.client.get(parts);
+            let body = &HashMap::<(), ()>::with_capacity(0);
+
+            if !paginated {
+                return self.send_json(host, req, body).await;
+            }
 
+            let page: Page<T> = self.send_json(host, req, body).await?;
+            items.extend(page.items);
+
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => return Ok(items),
+            }
+        }
+    }
+
+    /// Gets a list of the usernames of users on the server, optionally only those starting with
+    /// the supplied prefix
+    pub async fn get_users<H: AsRef<str>>(
+        &self,
+        host: H,
+        prefix: Option<&str>,
+    ) -> Result<Vec<String>, Error> {
+        let query = prefix
+            .map(|prefix| vec![("prefix", prefix.to_owned())])
+            .unwrap_or_default();
+
+        let usernames = self
+            .get_all_pages(host.as_ref(), "/fed/users", query)
+            .await?;
+
+        debug!("fed client: got usernames: {:?}", usernames);
+
+        Ok(usernames)
+    }
+
+    /// Gets a list of the IDs of communities on the server
+    pub async fn get_communities<T: AsRef<str>>(&self, host: T) -> Result<Vec<String>, Error> {
         let ids = self
-            .send_json(self.client.get(parts), &HashMap::<(), ()>::with_capacity(0))
+            .get_all_pages(host.as_ref(), "/fed/communities", vec![])
             .await?;
 
         debug!("fed client: got community ids: {:?}", ids);
@@ -256,13 +744,17 @@ impl Client {
         host: H,
         community: C,
     ) -> Result<Community, Error> {
-        let mut parts = self.validate_host(host).await?;
+        let mut parts = self.validate_host(host.as_ref()).await?;
 
         parts.path_and_query = Some(format!("/fed/communities/{}", community.as_ref()).try_into()?);
 
         let community = self
             // serialising an empty hashmap to get send "{}" as the body of the request to avoid errors from body-parser in Express backends
-            .send_json(self.client.get(parts), &HashMap::<(), ()>::with_capacity(0))
+            .send_json(
+                host.as_ref(),
+                self.client.get(parts),
+                &HashMap::<(), ()>::with_capacity(0),
+            )
             .await?;
 
         debug!("fed client: got community: {:?}", community);
@@ -270,6 +762,30 @@ impl Client {
         Ok(community)
     }
 
+    /// Gets the last modification timestamps of all posts in a community
+    pub async fn get_community_timestamps<H: AsRef<str>, C: AsRef<str>>(
+        &self,
+        host: H,
+        community: C,
+    ) -> Result<Vec<PostTimestamp>, Error> {
+        let mut parts = self.validate_host(host.as_ref()).await?;
+
+        parts.path_and_query =
+            Some(format!("/fed/communities/{}/timestamps", community.as_ref()).try_into()?);
+
+        let timestamps = self
+            .send_json(
+                host.as_ref(),
+                self.client.get(parts),
+                &HashMap::<(), ()>::with_capacity(0),
+            )
+            .await?;
+
+        debug!("fed client: got post timestamps: {:?}", timestamps);
+
+        Ok(timestamps)
+    }
+
     /// Gets all posts
     pub async fn get_posts<A: AsRef<str>, B: AsRef<str>>(
         &self,
@@ -277,7 +793,7 @@ impl Client {
         filters: PostFilters,
         user: B,
     ) -> Result<Vec<Post>, Error> {
-        let mut parts = self.validate_host(host).await?;
+        let mut parts = self.validate_host(host.as_ref()).await?;
 
         let mut query =
             serde_urlencoded::ser::to_string(filters).map_err(|e| Error::Body(e.into()))?;
@@ -290,6 +806,7 @@ impl Client {
         let posts = self
             // serialising an empty hashmap to get send "{}" as the body of the request to avoid errors from body-parser in Express backends
             .send_json(
+                host.as_ref(),
                 self.client.get(parts).header("User-ID", user.as_ref()),
                 &HashMap::<(), ()>::with_capacity(0),
             )
@@ -299,6 +816,188 @@ impl Client {
 
         Ok(posts)
     }
+
+    /// Gets a post by ID
+    pub async fn get_post<H: AsRef<str>, U: AsRef<str>>(
+        &self,
+        host: H,
+        id: Uuid,
+        user: U,
+    ) -> Result<Post, Error> {
+        let mut parts = self.validate_host(host.as_ref()).await?;
+
+        parts.path_and_query = Some(format!("/fed/posts/{}", id).try_into()?);
+
+        let post = self
+            .send_json(
+                host.as_ref(),
+                self.client.get(parts).header("User-ID", user.as_ref()),
+                &HashMap::<(), ()>::with_capacity(0),
+            )
+            .await?;
+
+        debug!("fed client: got post: {:?}", post);
+
+        Ok(post)
+    }
+
+    /// Creates a post in a remote community
+    pub async fn create_post<H: AsRef<str>, U: AsRef<str>>(
+        &self,
+        host: H,
+        user: U,
+        post: &NewPost,
+    ) -> Result<Post, Error> {
+        let mut parts = self.validate_host(host.as_ref()).await?;
+
+        parts.path_and_query = Some("/fed/posts".try_into()?);
+
+        let post = self
+            .send_json(
+                host.as_ref(),
+                self.client.post(parts).header("User-ID", user.as_ref()),
+                post,
+            )
+            .await?;
+
+        debug!("fed client: created post: {:?}", post);
+
+        Ok(post)
+    }
+
+    /// Edits a post on a remote host
+    pub async fn edit_post<H: AsRef<str>, U: AsRef<str>>(
+        &self,
+        host: H,
+        id: Uuid,
+        user: U,
+        edit: &PostEdit,
+    ) -> Result<(), Error> {
+        let mut parts = self.validate_host(host.as_ref()).await?;
+
+        parts.path_and_query = Some(format!("/fed/posts/{}", id).try_into()?);
+
+        self.send_signed(
+            host.as_ref(),
+            self.client.put(parts).header("User-ID", user.as_ref()),
+            edit,
+        )
+        .await?;
+
+        debug!("fed client: edited post {}", id);
+
+        Ok(())
+    }
+
+    /// Deletes a post on a remote host
+    pub async fn delete_post<H: AsRef<str>, U: AsRef<str>>(
+        &self,
+        host: H,
+        id: Uuid,
+        user: U,
+    ) -> Result<(), Error> {
+        let mut parts = self.validate_host(host.as_ref()).await?;
+
+        parts.path_and_query = Some(format!("/fed/posts/{}", id).try_into()?);
+
+        self.send_signed(
+            host.as_ref(),
+            self.client.delete(parts).header("User-ID", user.as_ref()),
+            &HashMap::<(), ()>::with_capacity(0),
+        )
+        .await?;
+
+        debug!("fed client: deleted post {}", id);
+
+        Ok(())
+    }
+
+    /// Casts, changes or withdraws a user's vote on a post on a remote host, returning its new
+    /// score
+    pub async fn vote_post<H: AsRef<str>, U: AsRef<str>>(
+        &self,
+        host: H,
+        id: Uuid,
+        user: U,
+        vote: Vote,
+    ) -> Result<i64, Error> {
+        let mut parts = self.validate_host(host.as_ref()).await?;
+
+        parts.path_and_query = Some(format!("/fed/posts/{}/vote", id).try_into()?);
+
+        let score: Score = self
+            .send_json(
+                host.as_ref(),
+                self.client.put(parts).header("User-ID", user.as_ref()),
+                &vote,
+            )
+            .await?;
+
+        debug!("fed client: voted {} on post {}", vote.value, id);
+
+        Ok(score.score)
+    }
+
+    /// Gets posts along with all of their descendants, nested into trees
+    ///
+    /// Descendants are fetched in the same request if the remote supports the
+    /// includeSubChildrenPosts filter, otherwise one level at a time.
+    pub async fn get_post_trees<A: AsRef<str>, B: AsRef<str>>(
+        &self,
+        host: A,
+        filters: PostFilters,
+        user: B,
+    ) -> Result<Vec<internal::Post>, Error> {
+        let host = host.as_ref();
+        let user = user.as_ref();
+
+        if self.supports(host, INCLUDE_SUB_CHILDREN_POSTS).await? {
+            let posts = self
+                .get_posts(
+                    host,
+                    PostFilters {
+                        include_sub_children_posts: Some(true),
+                        ..filters
+                    },
+                    user,
+                )
+                .await?
+                .into_iter()
+                .map(|p| internal::Post::from_fed(p, host.to_owned()))
+                .collect();
+
+            return Ok(internal::Post::into_trees(posts));
+        }
+
+        let mut posts = self.get_posts(host, filters, user).await?;
+        let mut seen = posts.iter().map(|p| p.id).collect::<HashSet<_>>();
+        let mut level = posts
+            .iter()
+            .flat_map(|p| p.children.iter().copied())
+            .filter(|id| seen.insert(*id))
+            .collect::<Vec<_>>();
+
+        while !level.is_empty() {
+            let children = join_all(level.iter().map(|id| self.get_post(host, *id, user)))
+                .await
+                .into_iter()
+                .collect::<Result<Vec<_>, _>>()?;
+
+            level = children
+                .iter()
+                .flat_map(|p| p.children.iter().copied())
+                .filter(|id| seen.insert(*id))
+                .collect();
+            posts.extend(children);
+        }
+
+        Ok(internal::Post::into_trees(
+            posts
+                .into_iter()
+                .map(|p| internal::Post::from_fed(p, host.to_owned()))
+                .collect(),
+        ))
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -318,6 +1017,12 @@ pub enum Error {
     #[error("Remote host not found in database")]
     InvalidRemote,
 
+    #[error("Invalid redirect: {0:?}")]
+    Redirect(anyhow::Error),
+
+    #[error("Circuit open for remote {0}, requests are refused until it recovers")]
+    CircuitOpen(String),
+
     #[error("Database error: {0:?}")]
     Database(sqlx::Error),
 
@@ -370,8 +1075,255 @@ impl From<rsa::errors::Error> for Error {
     }
 }
 
+impl From<signature::Error> for Error {
+    fn from(e: signature::Error) -> Self {
+        Self::Construction(e.into())
+    }
+}
+
 impl From<InvalidUri> for Error {
     fn from(e: InvalidUri) -> Self {
         Self::Construction(e.into())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use {
+        super::{Client, Error, BASE_URLS, NODE_INFO},
+        crate::{
+            models::fed::INCLUDE_SUB_CHILDREN_POSTS,
+            test::{connect, stand_in, ADDR, FQDN},
+        },
+        actix_web::{
+            http::{header::LOCATION, uri::Scheme, StatusCode},
+            web, HttpResponse,
+        },
+        rsa::{PublicKeyPemEncoding, RSAPublicKey},
+        rustls::{Certificate, ClientConfig, NoClientAuth, PrivateKey, ServerConfig},
+    };
+
+    /// Generates a self-signed certificate for localhost, returning a server configuration using it
+    /// and a client configuration trusting it
+    fn self_signed() -> (ServerConfig, ClientConfig) {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+        let der = Certificate(cert.serialize_der().unwrap());
+
+        let mut server = ServerConfig::new(NoClientAuth::new());
+        server
+            .set_single_cert(
+                vec![der.clone()],
+                PrivateKey(cert.serialize_private_key_der()),
+            )
+            .unwrap();
+
+        let mut client = ClientConfig::new();
+        client.root_store.add(&der).unwrap();
+
+        (server, client)
+    }
+
+    /// Routes serving the supplied public key
+    fn key_routes(pem: String) -> impl Fn(&mut web::ServiceConfig) + Send + Clone + 'static {
+        move |cfg: &mut web::ServiceConfig| {
+            let pem = pem.clone();
+            cfg.route(
+                "/fed/key",
+                web::get().to(move || {
+                    let pem = pem.clone();
+                    async move {
+                        HttpResponse::Ok()
+                            .content_type("application/x-pem-file")
+                            .body(pem)
+                    }
+                }),
+            );
+        }
+    }
+
+    #[actix_rt::test]
+    async fn node_info_cached_success() {
+        let (pool, privkey) = connect().await;
+        let remote = ADDR.trim_start_matches("http://");
+        let client = Client::new(&*FQDN, &privkey, &pool);
+
+        assert!(client
+            .supports(remote, INCLUDE_SUB_CHILDREN_POSTS)
+            .await
+            .unwrap());
+        assert!(NODE_INFO.lock().unwrap().get(remote).unwrap().0.is_some());
+
+        // remotes without nodeinfo are assumed to support no optional features
+        let addr = stand_in("127.0.0.1", |_: &mut web::ServiceConfig| {}, None);
+        assert!(!client
+            .supports(addr.to_string(), INCLUDE_SUB_CHILDREN_POSTS)
+            .await
+            .unwrap());
+        assert!(NODE_INFO
+            .lock()
+            .unwrap()
+            .get(&addr.to_string())
+            .unwrap()
+            .0
+            .is_none());
+    }
+
+    #[actix_rt::test]
+    async fn node_info_unavailable_fail() {
+        let (pool, privkey) = connect().await;
+        let client = Client::new(&*FQDN, &privkey, &pool);
+
+        // failures other than a missing document are not remembered
+        let addr = stand_in(
+            "127.0.0.1",
+            |cfg: &mut web::ServiceConfig| {
+                cfg.route(
+                    "/fed/nodeinfo",
+                    web::get().to(|| async { HttpResponse::ServiceUnavailable().finish() }),
+                );
+            },
+            None,
+        );
+        let res = client.get_node_info(addr.to_string()).await;
+        assert!(matches!(
+            res,
+            Err(Error::ResponseStatus(StatusCode::SERVICE_UNAVAILABLE))
+        ));
+        assert!(NODE_INFO.lock().unwrap().get(&addr.to_string()).is_none());
+    }
+
+    #[actix_rt::test]
+    async fn https_preferred_success() {
+        let (pool, privkey) = connect().await;
+        let pem = RSAPublicKey::from(&privkey).to_pem_pkcs8().unwrap();
+        let (server, client) = self_signed();
+
+        let addr = stand_in("127.0.0.1", key_routes(pem), Some(server));
+        let host = format!("localhost:{}", addr.port());
+
+        Client::with_tls_config(&*FQDN, &privkey, &pool, client)
+            .get_key(&host)
+            .await
+            .unwrap();
+
+        let cached = BASE_URLS.lock().unwrap().get(&host).cloned().unwrap();
+        assert_eq!(cached.0, Scheme::HTTPS);
+    }
+
+    #[actix_rt::test]
+    async fn redirect_chain_success() {
+        let (pool, privkey) = connect().await;
+        let pem = RSAPublicKey::from(&privkey).to_pem_pkcs8().unwrap();
+        let (server, client) = self_signed();
+
+        let target = stand_in("127.0.0.1", key_routes(pem), Some(server));
+        let target = format!("localhost:{}", target.port());
+
+        // plain HTTP remote that redirects twice before landing on the HTTPS remote
+        let location = format!("https://{}/", target);
+        let origin = stand_in(
+            "127.0.0.1",
+            move |cfg: &mut web::ServiceConfig| {
+                let location = location.clone();
+                cfg.route(
+                    "/",
+                    web::get()
+                        .to(|| async { HttpResponse::Found().header(LOCATION, "/moved").finish() }),
+                )
+                .route(
+                    "/moved",
+                    web::get().to(move || {
+                        let location = location.clone();
+                        async move {
+                            HttpResponse::PermanentRedirect()
+                                .header(LOCATION, location)
+                                .finish()
+                        }
+                    }),
+                );
+            },
+            None,
+        );
+        let origin = format!("localhost:{}", origin.port());
+
+        Client::with_tls_config(&*FQDN, &privkey, &pool, client)
+            .get_key(&origin)
+            .await
+            .unwrap();
+
+        let cached = BASE_URLS.lock().unwrap().get(&origin).cloned().unwrap();
+        assert_eq!(cached.0, Scheme::HTTPS);
+        assert_eq!(cached.1.as_str(), target);
+    }
+
+    #[actix_rt::test]
+    async fn redirect_loop_fail() {
+        let (pool, privkey) = connect().await;
+
+        let addr = stand_in(
+            "127.0.0.1",
+            |cfg: &mut web::ServiceConfig| {
+                cfg.route(
+                    "/",
+                    web::get().to(|| async {
+                        HttpResponse::MovedPermanently()
+                            .header(LOCATION, "/")
+                            .finish()
+                    }),
+                );
+            },
+            None,
+        );
+
+        let res = Client::new(&*FQDN, &privkey, &pool)
+            .get_key(addr.to_string())
+            .await;
+        assert!(matches!(res, Err(Error::Redirect(_))));
+    }
+
+    #[actix_rt::test]
+    async fn missing_location_fail() {
+        let (pool, privkey) = connect().await;
+
+        let addr = stand_in(
+            "127.0.0.1",
+            |cfg: &mut web::ServiceConfig| {
+                cfg.route(
+                    "/",
+                    web::get().to(|| async { HttpResponse::TemporaryRedirect().finish() }),
+                );
+            },
+            None,
+        );
+
+        let res = Client::new(&*FQDN, &privkey, &pool)
+            .get_key(addr.to_string())
+            .await;
+        assert!(matches!(res, Err(Error::Redirect(_))));
+    }
+
+    #[actix_rt::test]
+    async fn http_disallowed_fail() {
+        let (pool, privkey) = connect().await;
+        let pem = RSAPublicKey::from(&privkey).to_pem_pkcs8().unwrap();
+
+        // a separate loopback address, as HTTP is disallowed for every port of the host
+        let addr = stand_in("127.0.0.2", key_routes(pem), None);
+
+        sqlx::query!(
+            r#"
+                INSERT INTO remotes VALUES ('127.0.0.2', '', FALSE)
+                ON CONFLICT (host) DO UPDATE
+                    SET allow_http = FALSE
+            "#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let res = Client::new(&*FQDN, &privkey, &pool)
+            .get_key(addr.to_string())
+            .await;
+        assert!(matches!(res, Err(Error::Send(_))));
+    }
+}
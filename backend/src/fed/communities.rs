@@ -5,26 +5,33 @@ use {
             fed::PostTimestamp,
             fed::{Community, UserId},
         },
+        pagination::Pagination,
         util::get_client_host,
         AppData, Error,
     },
     actix_web::{get, web, HttpRequest, HttpResponse, Responder, Result},
 };
 
-/// Gets a list of the IDs of communities on the server
+/// Gets a list of the IDs of communities on the server, as a page if one was requested
 #[get("/fed/communities")]
 pub(crate) async fn get_communities(
     req: HttpRequest,
     data: web::Data<AppData>,
+    pagination: Pagination,
 ) -> Result<impl Responder, Error> {
     get_client_host(&req)?;
 
-    // Fetch community IDs
+    // Fetch community IDs, all of them unless a page was requested
     let rows: Vec<String> = sqlx::query!(
         r#"
             SELECT id
             FROM communities
+            WHERE ($1::VARCHAR IS NULL OR id > $1)
+            ORDER BY id
+            LIMIT $2
         "#,
+        pagination.after::<String>()?,
+        pagination.requested().then(|| pagination.fetch_limit())
     )
     .fetch_all(&data.pool)
     .await?
@@ -32,8 +39,12 @@ pub(crate) async fn get_communities(
     .map(|r| r.id)
     .collect();
 
-    // Return a successful response containing the IDs in JSON
-    Ok(HttpResponse::Ok().json(rows))
+    // Return a successful response containing the IDs in JSON, enveloped for paginated requests
+    if pagination.requested() {
+        Ok(HttpResponse::Ok().json(pagination.page(rows, |id| id.clone())?))
+    } else {
+        Ok(HttpResponse::Ok().json(rows))
+    }
 }
 
 /// Gets a community by ID
@@ -115,7 +126,7 @@ pub(crate) async fn get_community_timestamps(
     // Execute query
     let rows: Vec<PostTimestamp> = sqlx::query!(
         r#"
-            SELECT id, modified FROM posts
+            SELECT id, modified, deleted, score FROM posts
             WHERE community = $1
         "#,
         id
@@ -126,8 +137,74 @@ pub(crate) async fn get_community_timestamps(
     .map(|row| PostTimestamp {
         id: row.id,
         modified: row.modified,
+        deleted: row.deleted,
+        score: Some(row.score),
     })
     .collect();
 
     Ok(HttpResponse::Ok().json(rows))
 }
+
+#[cfg(test)]
+mod test {
+    use {
+        crate::{
+            pagination::Page,
+            test::{new_user_login, signed_request, ADDR},
+        },
+        actix_web::http::{header::CONTENT_TYPE, Method, StatusCode},
+    };
+
+    #[actix_rt::test]
+    async fn get_communities_pages_success() {
+        let (client, _, cookie) = new_user_login().await;
+        let ids = vec!["fed_paged_a", "fed_paged_b", "fed_paged_c"];
+
+        for id in &ids {
+            let res = client
+                .post(&format!("{}/internal/communities", *ADDR))
+                .cookie(cookie.clone())
+                .header(CONTENT_TYPE, "application/json")
+                .send_body(format!(
+                    r#"{{"id": "{}", "title": "Paged", "description": "Paged communities"}}"#,
+                    id
+                ))
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+
+        // servers that do not ask for a page get the whole list
+        let mut res = signed_request(Method::GET, "/fed/communities", "one.example", None, "")
+            .await
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let all: Vec<String> = res.json().limit(1 << 20).await.unwrap();
+        assert!(ids.iter().all(|id| all.contains(&id.to_string())));
+
+        // pages of one community walk the list in order
+        let mut paged = vec![];
+        let mut path = "/fed/communities?limit=1".to_owned();
+        loop {
+            let mut res = signed_request(Method::GET, &path, "one.example", None, "")
+                .await
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            let page: Page<String> = res.json().await.unwrap();
+            assert_eq!(page.items.len(), 1);
+            paged.extend(page.items);
+
+            match page.next_cursor {
+                Some(cursor) => path = format!("/fed/communities?limit=1&cursor={}", cursor),
+                None => break,
+            }
+        }
+
+        assert!(paged.windows(2).all(|w| w[0] < w[1]));
+        assert!(ids.iter().all(|id| paged.contains(&id.to_string())));
+    }
+}
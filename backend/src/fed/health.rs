@@ -0,0 +1,249 @@
+//! Per-remote health tracking and circuit breaking for the federation client
+//!
+//! Every request the client sends is recorded against the remote it was sent to. Once a remote
+//! has failed FAILURE_THRESHOLD times in a row its circuit opens and requests to it are refused
+//! for OPEN_DURATION, after which a single trial request is let through to decide whether to close
+//! the circuit again.
+
+use {
+    crate::{
+        metrics::{FED_CIRCUIT_OPEN, FED_REQUESTS, FED_REQUEST_HISTOGRAM},
+        models::internal::RemoteHealth,
+    },
+    once_cell::sync::Lazy,
+    std::{
+        collections::HashMap,
+        sync::Mutex,
+        time::{Duration, Instant},
+    },
+};
+
+/// Deadline for each request sent to a remote
+pub const DEADLINE: Duration = Duration::from_secs(5);
+/// Number of consecutive failures after which the circuit of a remote opens
+pub const FAILURE_THRESHOLD: u32 = 5;
+/// Duration for which requests to a remote are refused once its circuit opens
+pub const OPEN_DURATION: Duration = Duration::from_secs(30);
+/// Weight of the latest request in the moving average latency
+const LATENCY_WEIGHT: f64 = 0.2;
+
+/// Health of every remote that has been contacted, keyed by host
+static HEALTH: Lazy<Mutex<HashMap<String, Health>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// State of the circuit breaker of a remote
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Circuit {
+    /// Requests are sent
+    Closed,
+    /// Requests are refused until the instant
+    Open(Instant),
+    /// A trial request is in flight, further requests are refused until it completes or the
+    /// instant passes
+    HalfOpen(Instant),
+}
+
+/// Health of a single remote
+#[derive(Debug, Clone)]
+struct Health {
+    circuit: Circuit,
+    requests: u64,
+    failures: u64,
+    consecutive_failures: u32,
+    latency: Option<Duration>,
+    last_error: Option<String>,
+    last_success: Option<i64>,
+    last_failure: Option<i64>,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self {
+            circuit: Circuit::Closed,
+            requests: 0,
+            failures: 0,
+            consecutive_failures: 0,
+            latency: None,
+            last_error: None,
+            last_success: None,
+            last_failure: None,
+        }
+    }
+}
+
+impl Health {
+    /// Returns whether requests are being refused at `now`
+    fn refusing(&self, now: Instant) -> bool {
+        match self.circuit {
+            Circuit::Closed => false,
+            Circuit::Open(until) | Circuit::HalfOpen(until) => now < until,
+        }
+    }
+
+    /// Returns whether a request may be sent at `now`, making it the trial request if the circuit
+    /// has been open for long enough
+    fn allow(&mut self, now: Instant) -> bool {
+        if self.refusing(now) {
+            return false;
+        }
+
+        if self.circuit != Circuit::Closed {
+            self.circuit = Circuit::HalfOpen(now + OPEN_DURATION);
+        }
+
+        true
+    }
+
+    /// Records the outcome of a request completed at `now`
+    fn record(&mut self, now: Instant, latency: Duration, error: Option<String>) {
+        self.requests += 1;
+        self.latency = Some(match self.latency {
+            Some(average) => {
+                average.mul_f64(1.0 - LATENCY_WEIGHT) + latency.mul_f64(LATENCY_WEIGHT)
+            }
+            None => latency,
+        });
+
+        let timestamp = chrono::Local::now().timestamp();
+        match error {
+            None => {
+                self.circuit = Circuit::Closed;
+                self.consecutive_failures = 0;
+                self.last_success = Some(timestamp);
+            }
+            Some(error) => {
+                self.failures += 1;
+                self.consecutive_failures += 1;
+                self.last_failure = Some(timestamp);
+                self.last_error = Some(error);
+
+                // a failed trial reopens the circuit straight away
+                if matches!(self.circuit, Circuit::HalfOpen(_))
+                    || self.consecutive_failures >= FAILURE_THRESHOLD
+                {
+                    self.circuit = Circuit::Open(now + OPEN_DURATION);
+                }
+            }
+        }
+    }
+}
+
+/// Returns whether requests to a remote are currently being refused
+pub fn is_open(host: &str) -> bool {
+    HEALTH
+        .lock()
+        .expect("Remote health lock poisoned")
+        .get(host)
+        .map_or(false, |health| health.refusing(Instant::now()))
+}
+
+/// Returns whether a request may be sent to a remote, counting refusals
+pub fn allow(host: &str) -> bool {
+    let allowed = HEALTH
+        .lock()
+        .expect("Remote health lock poisoned")
+        .entry(host.to_owned())
+        .or_default()
+        .allow(Instant::now());
+
+    if !allowed {
+        FED_REQUESTS.with_label_values(&[host, "refused"]).inc();
+    }
+
+    allowed
+}
+
+/// Records the outcome of a request to a remote, `error` describing why it failed if it did
+pub fn record(host: &str, latency: Duration, error: Option<String>) {
+    let outcome = if error.is_some() {
+        "failure"
+    } else {
+        "success"
+    };
+    FED_REQUESTS.with_label_values(&[host, outcome]).inc();
+    FED_REQUEST_HISTOGRAM
+        .with_label_values(&[host])
+        .observe(latency.as_secs_f64());
+
+    let mut health = HEALTH.lock().expect("Remote health lock poisoned");
+    let health = health.entry(host.to_owned()).or_default();
+    health.record(Instant::now(), latency, error);
+
+    FED_CIRCUIT_OPEN
+        .with_label_values(&[host])
+        .set(if health.circuit == Circuit::Closed {
+            0.0
+        } else {
+            1.0
+        });
+}
+
+/// Returns the health of every remote that has been contacted, sorted by host
+pub fn snapshot() -> Vec<RemoteHealth> {
+    let mut remotes = HEALTH
+        .lock()
+        .expect("Remote health lock poisoned")
+        .iter()
+        .map(|(host, health)| RemoteHealth {
+            host: host.clone(),
+            circuit: match health.circuit {
+                Circuit::Closed => "closed",
+                Circuit::Open(_) => "open",
+                Circuit::HalfOpen(_) => "halfOpen",
+            }
+            .to_owned(),
+            requests: health.requests,
+            failures: health.failures,
+            consecutive_failures: health.consecutive_failures,
+            average_latency_ms: health.latency.map(|latency| latency.as_millis() as u64),
+            last_error: health.last_error.clone(),
+            last_success: health.last_success,
+            last_failure: health.last_failure,
+        })
+        .collect::<Vec<_>>();
+
+    remotes.sort_by(|a, b| a.host.cmp(&b.host));
+
+    remotes
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::{Circuit, Health, FAILURE_THRESHOLD, OPEN_DURATION},
+        std::time::{Duration, Instant},
+    };
+
+    #[test]
+    fn circuit_trips_and_recovers() {
+        let mut health = Health::default();
+        let start = Instant::now();
+        let latency = Duration::from_millis(10);
+
+        for _ in 0..FAILURE_THRESHOLD - 1 {
+            assert!(health.allow(start));
+            health.record(start, latency, Some("timeout".to_owned()));
+        }
+        assert_eq!(health.circuit, Circuit::Closed);
+
+        health.record(start, latency, Some("timeout".to_owned()));
+        assert_eq!(health.circuit, Circuit::Open(start + OPEN_DURATION));
+        assert!(!health.allow(start + OPEN_DURATION / 2));
+
+        // a single trial is let through once the circuit has been open for long enough
+        let trial = start + OPEN_DURATION;
+        assert!(health.allow(trial));
+        assert!(!health.allow(trial));
+
+        // and reopens the circuit if it fails
+        health.record(trial, latency, Some("timeout".to_owned()));
+        assert_eq!(health.circuit, Circuit::Open(trial + OPEN_DURATION));
+
+        let trial = trial + OPEN_DURATION;
+        assert!(health.allow(trial));
+        health.record(trial, latency, None);
+        assert_eq!(health.circuit, Circuit::Closed);
+        assert_eq!(health.consecutive_failures, 0);
+        assert_eq!(health.failures, u64::from(FAILURE_THRESHOLD) + 1);
+        assert!(health.allow(trial));
+    }
+}
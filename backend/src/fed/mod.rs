@@ -2,8 +2,15 @@
 
 pub mod client;
 mod communities;
+pub mod health;
+#[cfg(test)]
+mod multi_instance;
 mod other;
+pub mod outbox;
 mod posts;
+mod reports;
+pub mod signature;
+pub mod sync;
 mod users;
 
-pub use {communities::*, other::*, posts::*, users::*};
+pub use {communities::*, other::*, posts::*, reports::*, users::*};
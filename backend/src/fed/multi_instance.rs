@@ -0,0 +1,178 @@
+//! Federation tests between separate backend instances
+
+use {
+    crate::{
+        fed::{client::Client, sync},
+        models::internal::{Community, Message, Post},
+        test::{get_all_pages, spawn_instance, Instance},
+    },
+    actix_rt::time::delay_for,
+    actix_web::http::{header::CONTENT_TYPE, StatusCode},
+    std::time::{Duration, Instant},
+};
+
+/// Seconds to wait for queued deliveries to arrive
+const DELIVERY_TIMEOUT: u64 = 10;
+
+/// Starts two instances that know each other's keys
+async fn federated_pair() -> (Instance, Instance) {
+    let a = spawn_instance().await;
+    let b = spawn_instance().await;
+
+    a.add_remote(&b).await;
+    b.add_remote(&a).await;
+
+    (a, b)
+}
+
+/// Creates a community on the instance
+async fn create_community(instance: &Instance, id: &str) {
+    let (client, _, cookie) = instance.new_user_login().await;
+
+    let res = client
+        .post(format!("{}/internal/communities", instance.addr))
+        .header(CONTENT_TYPE, "application/json")
+        .cookie(cookie)
+        .send_body(format!(
+            r#"
+                {{
+                    "id": "{}",
+                    "title": "Federated Community",
+                    "description": "Community on another instance"
+                }}
+            "#,
+            id
+        ))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[actix_rt::test]
+async fn remote_post_success() {
+    let (a, b) = federated_pair().await;
+    create_community(&b, "multiPosts").await;
+
+    let (client, username, cookie) = a.new_user_login().await;
+
+    let mut res = client
+        .post(format!("{}/internal/posts", a.addr))
+        .header(CONTENT_TYPE, "application/json")
+        .cookie(cookie)
+        .send_body(format!(
+            r#"
+                {{
+                    "community": "multiPosts",
+                    "host": "{}",
+                    "title": "Post from another instance",
+                    "content": [
+                        {{
+                            "text": {{
+                                "text": "Hello from A"
+                            }}
+                        }}
+                    ]
+                }}
+            "#,
+            b.fqdn
+        ))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let post = res.json::<Post>().await.unwrap();
+
+    // the post is stored on B, attributed to the user on A
+    let (client, _, cookie) = b.new_user_login().await;
+    let mut res = client
+        .get(format!("{}/internal/posts/{}", b.addr, post.id))
+        .cookie(cookie)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let post = res.json::<Post>().await.unwrap();
+    assert_eq!(post.title, "Post from another instance");
+    assert_eq!(post.community, "multiPosts");
+    assert_eq!(post.author.username, username);
+    assert_eq!(post.author.host, a.fqdn);
+}
+
+#[actix_rt::test]
+async fn remote_message_success() {
+    let (a, b) = federated_pair().await;
+
+    let (a_client, a_user, a_cookie) = a.new_user_login().await;
+    let (b_client, b_user, b_cookie) = b.new_user_login().await;
+
+    let res = a_client
+        .post(format!(
+            "{}/internal/messages/{}@{}",
+            a.addr, b_user, b.fqdn
+        ))
+        .header(CONTENT_TYPE, "application/json")
+        .cookie(a_cookie)
+        .send_body(
+            r#"
+                {
+                    "title": "Across instances",
+                    "content": {
+                        "text": {
+                            "text": "Hello from A"
+                        }
+                    }
+                }
+            "#,
+        )
+        .await
+        .unwrap();
+    // remote messages are queued rather than sent while the request waits
+    assert_eq!(res.status(), StatusCode::ACCEPTED);
+
+    // the message is delivered by A's outbox, so wait for it to arrive on B
+    let start = Instant::now();
+    loop {
+        let messages: Vec<Message> = get_all_pages(
+            &b_client,
+            &format!("{}/internal/messages/{}@{}", b.addr, a_user, a.fqdn),
+            &b_cookie,
+        )
+        .await;
+
+        if let Some(message) = messages.first() {
+            assert_eq!(message.title, "Across instances");
+            assert_eq!(message.sender.username, a_user);
+            break;
+        }
+
+        if start.elapsed().as_secs() >= DELIVERY_TIMEOUT {
+            panic!(
+                "Message was not delivered within {} seconds",
+                DELIVERY_TIMEOUT
+            );
+        }
+        delay_for(Duration::from_millis(250)).await;
+    }
+}
+
+#[actix_rt::test]
+async fn remote_community_listing_success() {
+    let (a, b) = federated_pair().await;
+    create_community(&b, "multiListing").await;
+
+    // synchronise A's cache of B now rather than waiting for the background task
+    sync::sync_remote(&a.pool, &Client::new(&a.fqdn, &a.privkey, &a.pool), &b.fqdn)
+        .await
+        .unwrap();
+
+    let (client, _, cookie) = a.new_user_login().await;
+    let communities: Vec<Community> = get_all_pages(
+        &client,
+        &format!("{}/internal/communities", a.addr),
+        &cookie,
+    )
+    .await;
+
+    assert!(communities
+        .iter()
+        .any(|c| c.id == "multiListing" && c.host == b.fqdn));
+}
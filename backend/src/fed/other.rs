@@ -1,9 +1,34 @@
 use {
-    crate::{AppData, Error},
+    crate::{
+        models::{
+            database::POST_CONTENT_TYPES,
+            fed::{
+                NodeInfo, Software, Usage, COMMUNITY_TIMESTAMPS, INCLUDE_SUB_CHILDREN_POSTS,
+                MESSAGE_REPORTS, PAGINATION, REPORTS, VOTES,
+            },
+        },
+        AppData, Error,
+    },
     actix_web::{get, web, HttpResponse, Responder, Result},
     rsa::{PublicKeyPemEncoding, RSAPublicKey},
 };
 
+/// Federation endpoints served by this server
+const ENDPOINTS: [&str; 12] = [
+    "/fed/key",
+    "/fed/nodeinfo",
+    "/fed/discover",
+    "/fed/communities",
+    "/fed/communities/{id}",
+    "/fed/communities/{id}/timestamps",
+    "/fed/posts",
+    "/fed/posts/{id}",
+    "/fed/posts/{id}/vote",
+    "/fed/reports",
+    "/fed/users",
+    "/fed/users/{id}",
+];
+
 #[get("/fed/key")]
 pub(crate) async fn get_public_key(data: web::Data<AppData>) -> Result<impl Responder, Error> {
     let pubkey = RSAPublicKey::from(&data.privkey);
@@ -16,11 +41,27 @@ pub(crate) async fn get_public_key(data: web::Data<AppData>) -> Result<impl Resp
 
 #[get("/fed/discover")]
 pub(crate) async fn get_known_hosts(data: web::Data<AppData>) -> Result<impl Responder, Error> {
-    // pretty reasonable approximation for all foreign servers this server could know of
+    // pretty reasonable approximation for all foreign servers this server could know of, leaving
+    // out hosts that are blocked or silenced
     let hosts: Vec<String> = sqlx::query!(
         r#"
             SELECT DISTINCT host FROM users
+            WHERE host = $1
+            OR (
+                split_part(host, ':', 1) NOT IN (
+                    SELECT host FROM federation_policies
+                    WHERE mode IN ('block', 'silence')
+                )
+                AND (
+                    NOT EXISTS(SELECT 1 FROM federation_policies WHERE mode = 'allow')
+                    OR split_part(host, ':', 1) IN (
+                        SELECT host FROM federation_policies
+                        WHERE mode = 'allow'
+                    )
+                )
+            )
         "#,
+        data.host
     )
     .fetch_all(&data.pool)
     .await?
@@ -30,3 +71,67 @@ pub(crate) async fn get_known_hosts(data: web::Data<AppData>) -> Result<impl Res
 
     Ok(HttpResponse::Ok().json(hosts))
 }
+
+/// Describes this server's software, supported federation endpoints and features, and usage
+#[get("/fed/nodeinfo")]
+pub(crate) async fn get_node_info(data: web::Data<AppData>) -> Result<impl Responder, Error> {
+    let usage = sqlx::query!(
+        r#"
+            SELECT
+                (SELECT COUNT(*) FROM local_users) AS "users!",
+                (SELECT COUNT(*) FROM communities) AS "communities!",
+                (SELECT COUNT(*) FROM posts WHERE deleted IS NULL) AS "posts!"
+        "#,
+    )
+    .fetch_one(&data.pool)
+    .await?;
+
+    Ok(HttpResponse::Ok().json(NodeInfo {
+        software: Software {
+            name: env!("CARGO_PKG_NAME").to_owned(),
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+        },
+        endpoints: ENDPOINTS.iter().map(|e| (*e).to_owned()).collect(),
+        features: vec![
+            INCLUDE_SUB_CHILDREN_POSTS.to_owned(),
+            COMMUNITY_TIMESTAMPS.to_owned(),
+            PAGINATION.to_owned(),
+            VOTES.to_owned(),
+            REPORTS.to_owned(),
+            MESSAGE_REPORTS.to_owned(),
+        ],
+        post_content_types: POST_CONTENT_TYPES.iter().map(|t| (*t).to_owned()).collect(),
+        usage: Usage {
+            users: usage.users,
+            communities: usage.communities,
+            posts: usage.posts,
+        },
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        crate::{
+            models::fed::{NodeInfo, INCLUDE_SUB_CHILDREN_POSTS},
+            test::ADDR,
+        },
+        actix_web::{client::Client, http::StatusCode},
+    };
+
+    #[actix_rt::test]
+    async fn get_node_info_unsigned_success() {
+        let mut res = Client::new()
+            .get(&format!("{}/fed/nodeinfo", *ADDR))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let info: NodeInfo = res.json().await.unwrap();
+        assert_eq!(info.software.name, "backend");
+        assert!(info.supports(INCLUDE_SUB_CHILDREN_POSTS));
+        assert!(info.endpoints.contains(&"/fed/posts/{id}".to_owned()));
+        assert!(info.post_content_types.contains(&"markdown".to_owned()));
+    }
+}
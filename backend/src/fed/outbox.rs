@@ -0,0 +1,341 @@
+//! Persistent queue of outbound federation deliveries
+
+use {
+    crate::{
+        fed::client::{self, Client},
+        models::fed::{Message, Report, UserId},
+        util::{federation_policy, Defederated, Policy},
+        Error,
+    },
+    actix_rt::time::delay_for,
+    log::{debug, error, warn},
+    rsa::RSAPrivateKey,
+    serde::{Deserialize, Serialize},
+    sqlx::{Pool, Postgres},
+    std::time::Duration,
+    uuid::Uuid,
+};
+
+/// Default interval between passes over the outbox
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(5);
+/// Number of failed attempts after which a delivery is marked as dead
+pub const MAX_ATTEMPTS: i32 = 10;
+/// Delay in seconds before the first retry, doubled on every subsequent failure
+const BASE_DELAY: i64 = 10;
+/// Upper bound on the delay in seconds between retries
+const MAX_DELAY: i64 = 60 * 60;
+/// Seconds a claimed delivery is hidden from other workers while it is being attempted
+const LEASE: i64 = 60;
+/// Maximum number of deliveries attempted per pass
+const BATCH_SIZE: i64 = 32;
+
+/// Status of a pending delivery
+pub const PENDING: &str = "pending";
+/// Status of a delivery that exceeded MAX_ATTEMPTS or whose host was blocked
+pub const DEAD: &str = "dead";
+
+/// Outbound federation request
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Delivery {
+    /// Message from a local user to a remote user
+    #[serde(rename_all = "camelCase")]
+    Message {
+        from: String,
+        to: UserId,
+        message: Message,
+    },
+    /// Report by a local user of a post or message from a remote host
+    #[serde(rename_all = "camelCase")]
+    Report {
+        from: String,
+        host: String,
+        report: Report,
+    },
+    /// ActivityPub activity to a remote inbox
+    #[serde(rename_all = "camelCase")]
+    Activity {
+        inbox: String,
+        activity: serde_json::Value,
+    },
+}
+
+impl Delivery {
+    /// Host the delivery is sent to
+    pub fn host(&self) -> &str {
+        match self {
+            Delivery::Message { to, .. } => &to.host,
+            Delivery::Report { host, .. } => host,
+            Delivery::Activity { inbox, .. } => inbox
+                .split("://")
+                .nth(1)
+                .and_then(|rest| rest.split('/').next())
+                .unwrap_or(inbox),
+        }
+    }
+
+    /// Sends the delivery to its host
+    async fn deliver(&self, client: &Client) -> Result<(), client::Error> {
+        match self {
+            Delivery::Message { from, to, message } => client.send_message(from, to, message).await,
+            Delivery::Report { from, host, report } => client.report(from, host, report).await,
+            Delivery::Activity { inbox, activity } => {
+                client.deliver_activity(inbox, activity).await
+            }
+        }
+    }
+}
+
+/// Adds a delivery to the outbox to be sent by the worker, returning its ID
+pub async fn enqueue(pool: &Pool<Postgres>, delivery: &Delivery) -> Result<Uuid, Error> {
+    let id = Uuid::new_v4();
+    let now = chrono::Local::now().timestamp();
+
+    sqlx::query!(
+        r#"
+            INSERT INTO outbox VALUES ($1, $2, $3, $4, 0, $5, NULL, $5)
+        "#,
+        id,
+        delivery.host(),
+        serde_json::to_value(delivery)?,
+        PENDING,
+        now
+    )
+    .execute(pool)
+    .await?;
+
+    debug!("outbox: queued {} for {}", id, delivery.host());
+
+    Ok(id)
+}
+
+/// Seconds to wait before the next attempt after the supplied number of failed attempts
+pub fn backoff(attempts: i32) -> i64 {
+    let exponent = attempts.saturating_sub(1).clamp(0, 32) as u32;
+    BASE_DELAY
+        .saturating_mul(2i64.saturating_pow(exponent))
+        .min(MAX_DELAY)
+}
+
+/// Attempts every due delivery once, returning the number of deliveries attempted
+pub async fn process(
+    host: &str,
+    pool: &Pool<Postgres>,
+    privkey: &RSAPrivateKey,
+) -> Result<usize, Error> {
+    let now = chrono::Local::now().timestamp();
+
+    // claim due deliveries by pushing their next attempt past the lease so that concurrent workers
+    // skip them
+    let claimed = sqlx::query!(
+        r#"
+            UPDATE outbox
+            SET next_attempt = $1 + $2
+            WHERE id IN (
+                SELECT id FROM outbox
+                WHERE status = $3
+                AND next_attempt <= $1
+                ORDER BY next_attempt
+                LIMIT $4
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, delivery, attempts
+        "#,
+        now,
+        LEASE,
+        PENDING,
+        BATCH_SIZE
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let client = Client::new(host, privkey, pool);
+
+    for row in &claimed {
+        // deliveries to hosts that were blocked after they were queued are given up on immediately
+        let mut blocked = false;
+        let result = match serde_json::from_value::<Delivery>(row.delivery.clone()) {
+            Ok(delivery) => match federation_policy(delivery.host(), pool).await? {
+                Policy::Blocked => {
+                    blocked = true;
+                    Err(Defederated(delivery.host().to_owned()).to_string())
+                }
+                _ => delivery.deliver(&client).await.map_err(|e| e.to_string()),
+            },
+            Err(e) => Err(format!("Failed to parse delivery: {}", e)),
+        };
+
+        match result {
+            Ok(()) => {
+                debug!("outbox: delivered {}", row.id);
+
+                sqlx::query!(
+                    r#"
+                        DELETE FROM outbox
+                        WHERE id = $1
+                    "#,
+                    row.id
+                )
+                .execute(pool)
+                .await?;
+            }
+            Err(e) => {
+                let attempts = row.attempts + 1;
+                let status = if blocked || attempts >= MAX_ATTEMPTS {
+                    warn!(
+                        "outbox: giving up on {} after {} attempts: {}",
+                        row.id, attempts, e
+                    );
+                    DEAD
+                } else {
+                    debug!("outbox: attempt {} of {} failed: {}", attempts, row.id, e);
+                    PENDING
+                };
+
+                sqlx::query!(
+                    r#"
+                        UPDATE outbox
+                        SET status = $1, attempts = $2, next_attempt = $3, last_error = $4
+                        WHERE id = $5
+                    "#,
+                    status,
+                    attempts,
+                    chrono::Local::now().timestamp() + backoff(attempts),
+                    e,
+                    row.id
+                )
+                .execute(pool)
+                .await?;
+            }
+        }
+    }
+
+    Ok(claimed.len())
+}
+
+/// Spawns a task on the current system that drains the outbox every interval
+pub fn spawn_worker(
+    host: String,
+    pool: Pool<Postgres>,
+    privkey: RSAPrivateKey,
+    interval: Duration,
+) {
+    actix_rt::spawn(async move {
+        loop {
+            if let Err(e) = process(&host, &pool, &privkey).await {
+                error!("outbox: failed to process deliveries: {}", e);
+            }
+
+            delay_for(interval).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::{backoff, enqueue, process, Delivery, BASE_DELAY, MAX_DELAY, PENDING},
+        crate::{
+            models::{
+                database::{PostContent, TextContent},
+                fed::{Message, UserId},
+            },
+            test::{add_remote, connect, new_user_login, ADDR, FQDN},
+            Config,
+        },
+        actix_rt::time::delay_for,
+        sqlx::{Pool, Postgres},
+        std::time::Duration,
+        uuid::Uuid,
+    };
+
+    fn message(from: &str, to: &str, host: &str) -> Delivery {
+        Delivery::Message {
+            from: from.to_owned(),
+            to: UserId {
+                id: to.to_owned(),
+                host: host.to_owned(),
+            },
+            message: Message {
+                title: "Queued".to_owned(),
+                content: PostContent::Text(TextContent {
+                    text: "Delivered by the outbox".to_owned(),
+                }),
+            },
+        }
+    }
+
+    /// Returns the attempt count and status of a delivery, or None once it has been delivered
+    async fn state(pool: &Pool<Postgres>, id: Uuid) -> Option<(i32, String)> {
+        sqlx::query!(
+            r#"
+                SELECT attempts, status FROM outbox
+                WHERE id = $1
+            "#,
+            id
+        )
+        .fetch_optional(pool)
+        .await
+        .unwrap()
+        .map(|row| (row.attempts, row.status))
+    }
+
+    #[test]
+    fn backoff_doubles_until_capped() {
+        assert_eq!(backoff(1), BASE_DELAY);
+        assert_eq!(backoff(2), BASE_DELAY * 2);
+        assert_eq!(backoff(3), BASE_DELAY * 4);
+        assert_eq!(backoff(1000), MAX_DELAY);
+    }
+
+    #[actix_rt::test]
+    async fn deliver_success() {
+        let (pool, privkey) = connect().await;
+        let (_, sender, _) = new_user_login().await;
+        let (_, receiver, _) = new_user_login().await;
+
+        // the backend delivers to itself through its listening address, which differs from its
+        // FQDN and is therefore treated as a remote
+        add_remote(&envy::from_env::<Config>().unwrap().fqdn).await;
+        let remote = ADDR.trim_start_matches("http://");
+
+        let id = enqueue(&pool, &message(&sender, &receiver, remote))
+            .await
+            .unwrap();
+
+        // the background worker may claim the delivery first
+        for _ in 0..10 {
+            process(&FQDN, &pool, &privkey).await.unwrap();
+            if state(&pool, id).await.is_none() {
+                return;
+            }
+            delay_for(Duration::from_millis(500)).await;
+        }
+
+        panic!("delivery was not completed");
+    }
+
+    #[actix_rt::test]
+    async fn deliver_unreachable_retried() {
+        let (pool, privkey) = connect().await;
+        let (_, sender, _) = new_user_login().await;
+
+        let id = enqueue(&pool, &message(&sender, "nobody", "127.0.0.1:1"))
+            .await
+            .unwrap();
+
+        for _ in 0..10 {
+            process(&FQDN, &pool, &privkey).await.unwrap();
+            if let Some((attempts, status)) = state(&pool, id).await {
+                if attempts > 0 {
+                    assert_eq!(attempts, 1);
+                    assert_eq!(status, PENDING);
+                    return;
+                }
+            }
+            delay_for(Duration::from_millis(500)).await;
+        }
+
+        panic!("delivery was not attempted");
+    }
+}
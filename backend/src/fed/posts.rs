@@ -1,15 +1,22 @@
 use {
     crate::{
+        activitypub,
         models::{
             database,
-            fed::{NewPost, Post, PostEdit, UserId},
+            fed::{NewPost, Post, PostEdit, Score, UserId, Vote},
+        },
+        tombstones,
+        util::{
+            fetch_child_ids, fetch_descendants, get_client_host, get_user_id, is_banned,
+            is_banned_from_post, is_moderator, is_suspended, record_reply, record_revision,
+            record_vote,
         },
-        util::{get_client_host, get_user_id},
         AppData, Error,
     },
     actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse, Responder, Result},
     serde::{Deserialize, Serialize},
-    std::{borrow::Cow, convert::TryInto},
+    sqlx::{Pool, Postgres},
+    std::{borrow::Cow, collections::HashSet, convert::TryInto},
     uuid::Uuid,
 };
 
@@ -40,7 +47,8 @@ pub(crate) async fn get_posts(
         database::Post,
         r#"
             SELECT * FROM posts
-            WHERE ($2::VARCHAR is null OR community = $2)
+            WHERE deleted IS NULL
+            AND ($2::VARCHAR is null OR community = $2)
             AND ($3::BIGINT is null OR created >= $3)
             AND ($4::UUID is null OR parent = $4)
             AND ($5::VARCHAR is null OR
@@ -60,27 +68,24 @@ pub(crate) async fn get_posts(
     .map(|r| r.try_into())
     .collect::<Result<_, _>>()?;
 
-    // Populate children field for each post
-    for post in &mut posts {
-        let children: Vec<Uuid> = sqlx::query!(
-            r#"
-                SELECT id FROM posts
-                WHERE parent = $1
-            "#,
-            post.id
-        )
-        .fetch_all(&data.pool)
-        .await?
-        .into_iter()
-        .map(|x| x.id)
-        .collect();
+    // Fetch whole subtrees of the matching posts, skipping any that were already matched and
+    // including tombstones so that the subtrees keep their shape
+    if filters.include_sub_children_posts == Some(true) {
+        let mut ids = posts.iter().map(|p| p.id).collect::<HashSet<_>>();
+        let roots = ids.iter().copied().collect::<Vec<_>>();
 
-        post.children = children;
+        for descendant in fetch_descendants(&roots, &data.pool).await? {
+            if ids.insert(descendant.id) {
+                posts.push(descendant.try_into()?);
+            }
+        }
     }
 
-    // Fetch children
-    if filters.include_sub_children_posts == Some(true) {
-        unimplemented!();
+    // Populate children field for each post
+    let ids = posts.iter().map(|p| p.id).collect::<Vec<_>>();
+    let mut children = fetch_child_ids(&ids, &data.pool).await?;
+    for post in &mut posts {
+        post.children = children.remove(&post.id).unwrap_or_default();
     }
 
     // Return a successful response containing the posts in JSON
@@ -99,6 +104,12 @@ pub(crate) async fn create_post(
         host: get_client_host(&req)?.to_owned(),
     };
 
+    if is_suspended(&author.id, &author.host, &data.pool).await?
+        || is_banned(&author.id, &author.host, &body.community, &data.pool).await?
+    {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+
     // ensure that author exists
     match sqlx::query!(
         r#"
@@ -134,6 +145,8 @@ pub(crate) async fn create_post(
         content: body.content,
         created: now,
         modified: now,
+        deleted: None,
+        score: 0,
     };
 
     // Execute query
@@ -154,10 +167,16 @@ pub(crate) async fn create_post(
     .execute(&data.pool)
     .await?;
 
+    if let Some(parent) = p.parent_post {
+        record_reply(parent, p.created, &data.pool).await?;
+    }
+
+    activitypub::publish("Create", p.id, &data.host, &data.pool).await?;
+
     Ok(HttpResponse::Ok().json(p))
 }
 
-/// Gets a post by ID
+/// Gets a post by ID, which may be the tombstone of a deleted post
 #[get("/fed/posts/{id}")]
 pub(crate) async fn get_post_by_id(
     req: HttpRequest,
@@ -198,6 +217,40 @@ pub(crate) async fn get_post_by_id(
     Ok(HttpResponse::Ok().json(post))
 }
 
+/// Returns whether the supplied remote user may edit or delete the post, or None if the post does
+/// not exist or has been deleted
+async fn can_modify_post(
+    id: Uuid,
+    username: &str,
+    host: &str,
+    pool: &Pool<Postgres>,
+) -> Result<Option<bool>, Error> {
+    let post = match sqlx::query!(
+        r#"
+            SELECT community, author_username, author_host FROM posts
+            WHERE id = $1
+            AND deleted IS NULL
+        "#,
+        id
+    )
+    .fetch_optional(pool)
+    .await?
+    {
+        Some(post) => post,
+        None => return Ok(None),
+    };
+
+    // authors may always modify their own posts
+    if post.author_username == username && post.author_host == host {
+        return Ok(Some(true));
+    }
+
+    // otherwise the user must moderate the community the post belongs to
+    Ok(Some(
+        is_moderator(username, host, &post.community, pool).await?,
+    ))
+}
+
 /// Edit a post
 #[put("/fed/posts/{id}")]
 pub(crate) async fn edit_post(
@@ -209,29 +262,46 @@ pub(crate) async fn edit_post(
     let username = get_user_id(&req)?;
     let host = get_client_host(&req)?;
 
+    match can_modify_post(id, username, host, &data.pool).await? {
+        Some(true) => {}
+        Some(false) => return Ok(HttpResponse::Forbidden().finish()),
+        None => return Ok(HttpResponse::NotFound().finish()),
+    }
+
+    if is_suspended(username, host, &data.pool).await?
+        || is_banned_from_post(username, host, id, &data.pool).await?
+    {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+
     let now = chrono::Local::now().timestamp();
+    let mut tx = data.pool.begin().await?;
+
+    // keep the previous version of the post, attributed to the remote user editing it
+    if !record_revision(id, username, host, &mut tx).await? {
+        return Ok(HttpResponse::NotFound().finish());
+    }
 
     // Execute query
     sqlx::query!(
         r#"
             UPDATE posts
             SET content = $1, title = $2, modified = $3
-            FROM users
-            WHERE posts.id = $4
-            AND users.username = $5
-            AND users.host = $6
+            WHERE id = $4
         "#,
         serde_json::to_value(body.content)?,
         body.title,
         now,
-        id,
-        username,
-        host
+        id
     )
-    .execute(&data.pool)
+    .execute(&mut tx)
     .await?;
 
-    Ok(HttpResponse::Ok())
+    tx.commit().await?;
+
+    activitypub::publish("Update", id, &data.host, &data.pool).await?;
+
+    Ok(HttpResponse::Ok().finish())
 }
 
 /// Deletes a post
@@ -244,20 +314,358 @@ pub(crate) async fn delete_post(
     let username = get_user_id(&req)?;
     let host = get_client_host(&req)?;
 
-    sqlx::query!(
-        r#"
-            DELETE FROM posts
-            USING users
-            WHERE posts.id = $1
-            AND users.username = $2
-            AND users.host = $3
-        "#,
-        id,
-        username,
-        host
-    )
-    .execute(&data.pool)
-    .await?;
+    match can_modify_post(id, username, host, &data.pool).await? {
+        Some(true) => {}
+        Some(false) => return Ok(HttpResponse::Forbidden().finish()),
+        None => return Ok(HttpResponse::NotFound().finish()),
+    }
+
+    activitypub::publish("Delete", id, &data.host, &data.pool).await?;
+
+    tombstones::tombstone(id, &data.pool).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Casts, changes or withdraws a remote user's vote on a post, responding with its new score
+#[put("/fed/posts/{id}/vote")]
+pub(crate) async fn vote_post(
+    req: HttpRequest,
+    data: web::Data<AppData>,
+    web::Path(id): web::Path<Uuid>,
+    web::Json(body): web::Json<Vote>,
+) -> Result<impl Responder, Error> {
+    let username = get_user_id(&req)?;
+    let host = get_client_host(&req)?;
+
+    if is_suspended(username, host, &data.pool).await?
+        || is_banned_from_post(username, host, id, &data.pool).await?
+    {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+
+    match record_vote(id, username, host, body, &data.pool).await? {
+        Some(score) => Ok(HttpResponse::Ok().json(Score { score })),
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        crate::{
+            models::fed::{Post, Score},
+            test::{connect, make_moderator, new_user_login, signed_request, ADDR},
+        },
+        actix_web::http::{header::CONTENT_TYPE, Method, StatusCode},
+        uuid::Uuid,
+    };
+
+    /// Creates a local community and returns its ID
+    async fn create_community(id: &str) -> String {
+        let (client, _, cookie) = new_user_login().await;
+
+        let res = client
+            .post(&format!("{}/internal/communities", *ADDR))
+            .cookie(cookie)
+            .header(CONTENT_TYPE, "application/json")
+            .send_body(format!(
+                r#"{{"id": "{}", "title": "Federated", "description": "Federated posts"}}"#,
+                id
+            ))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        id.to_owned()
+    }
+
+    /// Creates a post as the supplied remote user
+    async fn create_remote_post(community: &str, user: &str, host: &str) -> Post {
+        create_remote_reply(community, None, user, host).await
+    }
+
+    /// Creates a post as the supplied remote user, optionally as a reply to another post
+    async fn create_remote_reply(
+        community: &str,
+        parent: Option<Uuid>,
+        user: &str,
+        host: &str,
+    ) -> Post {
+        let body = format!(
+            r#"{{"community": "{}", "parentPost": {}, "title": "Remote post", "content": [{{"text": {{"text": "Hello from {}"}}}}]}}"#,
+            community,
+            parent.map_or("null".to_owned(), |id| format!("\"{}\"", id)),
+            host
+        );
+
+        let mut res = signed_request(Method::POST, "/fed/posts", host, Some(user), &body)
+            .await
+            .send_body(body)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        res.json().await.unwrap()
+    }
+
+    const EDIT: &str = r#"{"title": "Edited", "content": [{"text": {"text": "Edited content"}}]}"#;
+
+    #[actix_rt::test]
+    async fn author_edit_delete_success() {
+        let community = create_community("fed_author_edit").await;
+        let post = create_remote_post(&community, "alice", "one.example").await;
 
-    Ok(HttpResponse::Ok())
+        let res = signed_request(
+            Method::PUT,
+            &format!("/fed/posts/{}", post.id),
+            "one.example",
+            Some("alice"),
+            EDIT,
+        )
+        .await
+        .send_body(EDIT)
+        .await
+        .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = signed_request(
+            Method::DELETE,
+            &format!("/fed/posts/{}", post.id),
+            "one.example",
+            Some("alice"),
+            "",
+        )
+        .await
+        .send_body("")
+        .await
+        .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn other_user_edit_delete_fail() {
+        let community = create_community("fed_other_edit").await;
+        let post = create_remote_post(&community, "alice", "one.example").await;
+        // make bob known to the server
+        create_remote_post(&community, "bob", "one.example").await;
+
+        // same host, different user
+        let res = signed_request(
+            Method::PUT,
+            &format!("/fed/posts/{}", post.id),
+            "one.example",
+            Some("bob"),
+            EDIT,
+        )
+        .await
+        .send_body(EDIT)
+        .await
+        .unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        let res = signed_request(
+            Method::DELETE,
+            &format!("/fed/posts/{}", post.id),
+            "one.example",
+            Some("bob"),
+            "",
+        )
+        .await
+        .send_body("")
+        .await
+        .unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[actix_rt::test]
+    async fn same_username_other_host_fail() {
+        let community = create_community("fed_cross_host").await;
+        let post = create_remote_post(&community, "alice", "one.example").await;
+        // make alice@two.example known to the server
+        create_remote_post(&community, "alice", "two.example").await;
+
+        let res = signed_request(
+            Method::PUT,
+            &format!("/fed/posts/{}", post.id),
+            "two.example",
+            Some("alice"),
+            EDIT,
+        )
+        .await
+        .send_body(EDIT)
+        .await
+        .unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        let res = signed_request(
+            Method::DELETE,
+            &format!("/fed/posts/{}", post.id),
+            "two.example",
+            Some("alice"),
+            "",
+        )
+        .await
+        .send_body("")
+        .await
+        .unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[actix_rt::test]
+    async fn remote_moderator_delete_success() {
+        let community = create_community("fed_remote_mod").await;
+        let post = create_remote_post(&community, "alice", "one.example").await;
+        create_remote_post(&community, "carol", "two.example").await;
+
+        make_moderator("carol", "two.example", &community).await;
+
+        let res = signed_request(
+            Method::DELETE,
+            &format!("/fed/posts/{}", post.id),
+            "two.example",
+            Some("carol"),
+            "",
+        )
+        .await
+        .send_body("")
+        .await
+        .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn remote_edit_revision_success() {
+        let (pool, _) = connect().await;
+        let community = create_community("fed_revisions").await;
+        let post = create_remote_post(&community, "alice", "one.example").await;
+        create_remote_post(&community, "carol", "two.example").await;
+
+        make_moderator("carol", "two.example", &community).await;
+
+        let res = signed_request(
+            Method::PUT,
+            &format!("/fed/posts/{}", post.id),
+            "two.example",
+            Some("carol"),
+            EDIT,
+        )
+        .await
+        .send_body(EDIT)
+        .await
+        .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // the previous version is attributed to the remote moderator who replaced it
+        let revision = sqlx::query!(
+            r#"
+                SELECT revision, editor_username, editor_host, title FROM post_revisions
+                WHERE post = $1
+            "#,
+            post.id
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(revision.revision, 1);
+        assert_eq!(revision.editor_username, "carol");
+        assert_eq!(revision.editor_host, "two.example");
+        assert_eq!(revision.title, "Remote post");
+    }
+
+    #[actix_rt::test]
+    async fn missing_post_not_found() {
+        let res = signed_request(
+            Method::DELETE,
+            &format!("/fed/posts/{}", Uuid::new_v4()),
+            "one.example",
+            Some("alice"),
+            "",
+        )
+        .await
+        .send_body("")
+        .await
+        .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn include_sub_children_posts_success() {
+        let community = create_community("fed_sub_children").await;
+        let root = create_remote_post(&community, "alice", "one.example").await;
+        let child = create_remote_reply(&community, Some(root.id), "bob", "one.example").await;
+        let grandchild =
+            create_remote_reply(&community, Some(child.id), "carol", "one.example").await;
+
+        let path = format!(
+            "/fed/posts?parentPost={}&includeSubChildrenPosts=true",
+            root.id
+        );
+        let mut res = signed_request(Method::GET, &path, "one.example", Some("alice"), "")
+            .await
+            .send_body("")
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let posts: Vec<Post> = res.json().await.unwrap();
+        assert_eq!(posts.len(), 2);
+
+        let returned_child = posts.iter().find(|p| p.id == child.id).unwrap();
+        assert_eq!(returned_child.children, vec![grandchild.id]);
+
+        let returned_grandchild = posts.iter().find(|p| p.id == grandchild.id).unwrap();
+        assert_eq!(returned_grandchild.parent_post, Some(child.id));
+        assert!(returned_grandchild.children.is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn remote_vote_success() {
+        let community = create_community("fed_votes").await;
+        let post = create_remote_post(&community, "alice", "one.example").await;
+
+        let vote = |user: &'static str, host: &'static str, body: &'static str| {
+            let path = format!("/fed/posts/{}/vote", post.id);
+            async move {
+                let mut res = signed_request(Method::PUT, &path, host, Some(user), body)
+                    .await
+                    .send_body(body)
+                    .await
+                    .unwrap();
+                assert_eq!(res.status(), StatusCode::OK);
+                res.json::<Score>().await.unwrap().score
+            }
+        };
+
+        assert_eq!(vote("bob", "one.example", r#"{"value": 1}"#).await, 1);
+        // a user has one vote, which they may change
+        assert_eq!(vote("bob", "one.example", r#"{"value": 1}"#).await, 1);
+        assert_eq!(vote("bob", "one.example", r#"{"value": -1}"#).await, -1);
+        // the same username on another host is a different voter
+        assert_eq!(vote("bob", "two.example", r#"{"value": -1}"#).await, -2);
+        assert_eq!(vote("bob", "one.example", r#"{"value": 0}"#).await, -1);
+
+        // the score is served with the post
+        let path = format!("/fed/posts/{}", post.id);
+        let mut res = signed_request(Method::GET, &path, "one.example", Some("alice"), "")
+            .await
+            .send_body("")
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.json::<Post>().await.unwrap().score, -1);
+
+        let res = signed_request(
+            Method::PUT,
+            &format!("/fed/posts/{}/vote", Uuid::new_v4()),
+            "one.example",
+            Some("bob"),
+            r#"{"value": 1}"#,
+        )
+        .await
+        .send_body(r#"{"value": 1}"#)
+        .await
+        .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
 }
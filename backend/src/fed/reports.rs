@@ -0,0 +1,233 @@
+use {
+    crate::{
+        models::{database, fed::Report, internal::REPORT_OPEN},
+        util::{file_post_report, get_client_host, get_user_id, validate_report_reason},
+        AppData, Error,
+    },
+    actix_web::{post, web, HttpRequest, HttpResponse, Responder, Result},
+    anyhow::anyhow,
+    uuid::Uuid,
+};
+
+/// Files a report of a local post, or of a message sent by a local user, forwarded by the server
+/// of the reporting user
+#[post("/fed/reports")]
+pub(crate) async fn create_report(
+    req: HttpRequest,
+    data: web::Data<AppData>,
+    web::Json(body): web::Json<Report>,
+) -> Result<impl Responder, Error> {
+    let username = get_user_id(&req)?;
+    let host = get_client_host(&req)?;
+
+    let filed = match (body.post, body.message) {
+        (Some(post), None) => file_post_report(post, username, host, body.reason, &data.pool)
+            .await?
+            .is_some(),
+        (None, Some(message)) => {
+            let reason = validate_report_reason(body.reason)?;
+
+            // the latest matching message sent to the reporting user is taken to be the one
+            // reported
+            sqlx::query_as!(
+                database::Report,
+                r#"
+                    INSERT INTO reports
+                    SELECT $1, $2, $3, NULL, id, NULL, sender_username, sender_host, $4, $5, $6
+                    FROM messages
+                    WHERE sender_username = $7
+                    AND sender_host = $8
+                    AND receiver_username = $2
+                    AND receiver_host = $3
+                    AND title = $9
+                    AND content = $10
+                    ORDER BY timestamp DESC
+                    LIMIT 1
+                    RETURNING *
+                "#,
+                Uuid::new_v4(),
+                username,
+                host,
+                reason,
+                chrono::Local::now().timestamp(),
+                REPORT_OPEN,
+                message.sender,
+                data.host,
+                message.title,
+                serde_json::to_value(&message.content)?
+            )
+            .fetch_optional(&data.pool)
+            .await?
+            .is_some()
+        }
+        _ => {
+            return Err(Error::BadRequest(anyhow!(
+                "Reports must be of exactly one post or message"
+            )))
+        }
+    };
+
+    if filed {
+        Ok(HttpResponse::Created().finish())
+    } else {
+        Ok(HttpResponse::NotFound().finish())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        crate::{
+            models::internal::{Post, Report},
+            pagination::Page,
+            test::{connect, new_user_login, signed_request, ADDR, FQDN},
+        },
+        actix_web::http::{header::CONTENT_TYPE, Method, StatusCode},
+        serde_json::json,
+        uuid::Uuid,
+    };
+
+    #[actix_rt::test]
+    async fn forwarded_report_success() {
+        let (client, _, cookie) = new_user_login().await;
+
+        let res = client
+            .post(format!("{}/internal/communities", *ADDR))
+            .header(CONTENT_TYPE, "application/json")
+            .cookie(cookie.clone())
+            .send_body(r#"{"id": "fed_reports", "title": "Reports", "description": "Reports"}"#)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let mut res = client
+            .post(format!("{}/internal/posts", *ADDR))
+            .header(CONTENT_TYPE, "application/json")
+            .cookie(cookie.clone())
+            .send_body(r#"{"community": "fed_reports", "title": "Spam", "content": []}"#)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let post: Post = res.json().await.unwrap();
+
+        let body = format!(r#"{{"post": "{}", "reason": "Spam"}}"#, post.id);
+        let res = signed_request(
+            Method::POST,
+            "/fed/reports",
+            "one.example",
+            Some("alice"),
+            &body,
+        )
+        .await
+        .send_body(body)
+        .await
+        .unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+
+        // the report joins the community's moderation queue, attributed to the remote user
+        let mut res = client
+            .get(format!(
+                "{}/internal/communities/fed_reports/reports",
+                *ADDR
+            ))
+            .cookie(cookie)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let queue: Page<Report> = res.json().await.unwrap();
+        assert_eq!(queue.items.len(), 1);
+        assert_eq!(queue.items[0].reporter.username, "alice");
+        assert_eq!(queue.items[0].reporter.host, "one.example");
+        assert_eq!(queue.items[0].reason, "Spam");
+
+        let body = format!(r#"{{"post": "{}", "reason": "Spam"}}"#, Uuid::new_v4());
+        let res = signed_request(
+            Method::POST,
+            "/fed/reports",
+            "one.example",
+            Some("alice"),
+            &body,
+        )
+        .await
+        .send_body(body)
+        .await
+        .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn forwarded_message_report_success() {
+        let (pool, _) = connect().await;
+        let (_, sender, _) = new_user_login().await;
+
+        // message sent by a local user to a remote one, whose server keeps it under its own ID
+        let message = Uuid::new_v4();
+        sqlx::query!(
+            r#"
+                INSERT INTO messages VALUES ($1, $2, $3, 'bob', 'one.example', 'Hello', $4, 1, false)
+            "#,
+            message,
+            sender,
+            *FQDN,
+            json!({"text": {"text": "Abuse"}})
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let report = |content: &str| {
+            json!({
+                "message": {
+                    "sender": sender,
+                    "title": "Hello",
+                    "content": {"text": {"text": content}},
+                },
+                "reason": "Abusive",
+            })
+            .to_string()
+        };
+
+        let body = report("Abuse");
+        let res = signed_request(
+            Method::POST,
+            "/fed/reports",
+            "one.example",
+            Some("bob"),
+            &body,
+        )
+        .await
+        .send_body(body)
+        .await
+        .unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+
+        let reported = sqlx::query!(
+            r#"
+                SELECT reporter_username, reporter_host FROM reports
+                WHERE message = $1
+            "#,
+            message
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(reported.reporter_username, "bob");
+        assert_eq!(reported.reporter_host, "one.example");
+
+        // messages that were not sent to the reporting user cannot be reported
+        let body = report("Something else");
+        let res = signed_request(
+            Method::POST,
+            "/fed/reports",
+            "one.example",
+            Some("bob"),
+            &body,
+        )
+        .await
+        .send_body(body)
+        .await
+        .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,442 @@
+//! HTTP Signatures header parsing and generation
+
+use {
+    actix_web::http::Uri,
+    rsa::{hash::Hash, PaddingScheme, PublicKey, RSAPrivateKey, RSAPublicKey},
+    sha2::{Digest, Sha256, Sha512},
+    std::{fmt, str::FromStr},
+};
+
+/// Values of the `algorithm` parameter that can be verified with an RSA key and SHA-512
+pub const SUPPORTED_ALGORITHMS: &[&str] = &["rsa-sha512", "hs2019"];
+
+/// Headers that must be covered by the signature of every federation request
+pub const REQUIRED_HEADERS: &[&str] =
+    &["(request-target)", "host", "client-host", "date", "digest"];
+
+/// Value of a Signature header
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignatureHeader {
+    /// Identifier of the key used to generate the signature
+    pub key_id: String,
+    /// Signature algorithm, if declared
+    pub algorithm: Option<String>,
+    /// Lowercase names of the headers covered by the signature, in signing order
+    pub headers: Vec<String>,
+    /// Raw signature bytes
+    pub signature: Vec<u8>,
+}
+
+impl SignatureHeader {
+    /// Returns whether the declared algorithm, if any, can be verified by this server
+    pub fn algorithm_supported(&self) -> bool {
+        match &self.algorithm {
+            Some(algorithm) => {
+                SUPPORTED_ALGORITHMS.contains(&algorithm.to_ascii_lowercase().as_str())
+            }
+            None => true,
+        }
+    }
+
+    /// Returns whether the supplied header is covered by the signature
+    pub fn covers<T: AsRef<str>>(&self, header: T) -> bool {
+        self.headers
+            .iter()
+            .any(|h| h.eq_ignore_ascii_case(header.as_ref()))
+    }
+
+    /// Builds the signing string from the declared headers list
+    ///
+    /// `target` is the path and query of the request, as returned by [`request_target`].
+    /// `header` is called with the lowercase name of each declared header and should return its
+    /// value, with multiple values already joined by ", ".
+    pub fn signing_string<F>(
+        &self,
+        method: &str,
+        target: &str,
+        mut header: F,
+    ) -> Result<String, Error>
+    where
+        F: FnMut(&str) -> Option<String>,
+    {
+        self.headers
+            .iter()
+            .map(|name| match name.as_str() {
+                "(request-target)" => Ok(format!(
+                    "(request-target): {} {}",
+                    method.to_ascii_lowercase(),
+                    target
+                )),
+                name if name.starts_with('(') => {
+                    Err(Error::UnsupportedPseudoHeader(name.to_owned()))
+                }
+                name => match header(name) {
+                    Some(value) => Ok(format!("{}: {}", name, value.trim())),
+                    None => Err(Error::MissingHeader(name.to_owned())),
+                },
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(|lines| lines.join("\n"))
+    }
+}
+
+impl FromStr for SignatureHeader {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut key_id = None;
+        let mut algorithm = None;
+        let mut headers = None;
+        let mut signature = None;
+
+        for (name, value) in parse_params(s)? {
+            let slot = match name.to_ascii_lowercase().as_str() {
+                "keyid" => &mut key_id,
+                "algorithm" => &mut algorithm,
+                "headers" => &mut headers,
+                "signature" => &mut signature,
+                // unknown parameters such as "created" and "expires" are ignored
+                _ => continue,
+            };
+
+            if slot.is_some() {
+                return Err(Error::DuplicateParameter(name));
+            }
+            *slot = Some(value);
+        }
+
+        Ok(Self {
+            key_id: key_id.ok_or(Error::MissingParameter("keyId"))?,
+            algorithm,
+            headers: match headers {
+                Some(s) => s
+                    .split_whitespace()
+                    .map(|h| h.to_ascii_lowercase())
+                    .collect(),
+                // the specification defaults to only the Date header when none are declared
+                None => vec!["date".to_owned()],
+            },
+            signature: base64::decode(
+                signature
+                    .ok_or(Error::MissingParameter("signature"))?
+                    .as_bytes(),
+            )
+            .map_err(|e| Error::InvalidSignature(e.to_string()))?,
+        })
+    }
+}
+
+impl fmt::Display for SignatureHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "keyId=\"{}\"", escape(&self.key_id))?;
+        if let Some(algorithm) = &self.algorithm {
+            write!(f, ",algorithm=\"{}\"", escape(algorithm))?;
+        }
+        write!(
+            f,
+            ",headers=\"{}\",signature=\"{}\"",
+            self.headers.join(" "),
+            base64::encode(&self.signature)
+        )
+    }
+}
+
+/// Splits a Signature header value into its name/value parameters
+fn parse_params(s: &str) -> Result<Vec<(String, String)>, Error> {
+    let mut params = vec![];
+    let mut chars = s.chars().peekable();
+
+    loop {
+        while chars.peek().map_or(false, |c| c.is_whitespace()) {
+            chars.next();
+        }
+
+        if chars.peek().is_none() {
+            break;
+        }
+
+        let mut name = String::new();
+        while let Some(&c) = chars.peek() {
+            if c == '=' || c == ',' || c.is_whitespace() {
+                break;
+            }
+            name.push(c);
+            chars.next();
+        }
+        if name.is_empty() {
+            return Err(Error::Malformed("empty parameter name".to_owned()));
+        }
+
+        while chars.peek().map_or(false, |c| c.is_whitespace()) {
+            chars.next();
+        }
+        if chars.next() != Some('=') {
+            return Err(Error::Malformed(format!("missing value for {:?}", name)));
+        }
+        while chars.peek().map_or(false, |c| c.is_whitespace()) {
+            chars.next();
+        }
+
+        let mut value = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some(c) => value.push(c),
+                        None => return Err(Error::Malformed("unterminated escape".to_owned())),
+                    },
+                    Some(c) => value.push(c),
+                    None => {
+                        return Err(Error::Malformed(format!(
+                            "unterminated quoted value for {:?}",
+                            name
+                        )))
+                    }
+                }
+            }
+        } else {
+            while let Some(&c) = chars.peek() {
+                if c == ',' || c.is_whitespace() {
+                    break;
+                }
+                value.push(c);
+                chars.next();
+            }
+        }
+
+        params.push((name, value));
+
+        while chars.peek().map_or(false, |c| c.is_whitespace()) {
+            chars.next();
+        }
+        match chars.next() {
+            None => break,
+            Some(',') => {}
+            Some(c) => return Err(Error::Malformed(format!("unexpected character {:?}", c))),
+        }
+    }
+
+    Ok(params)
+}
+
+/// Escapes a value for use in a quoted string
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Returns the path and query of a URI, which together form the `(request-target)` pseudo-header
+pub fn request_target(uri: &Uri) -> &str {
+    uri.path_and_query().map_or("/", |target| target.as_str())
+}
+
+/// Signs a signing string with the supplied private key using RSA PKCS#1 v1.5 and SHA-512
+pub fn sign(privkey: &RSAPrivateKey, input: &str) -> Result<Vec<u8>, rsa::errors::Error> {
+    privkey.sign(
+        PaddingScheme::PKCS1v15Sign {
+            hash: Some(Hash::SHA2_512),
+        },
+        Sha512::digest(input.as_bytes()).as_slice(),
+    )
+}
+
+/// Verifies the signature of a signing string against the supplied public key
+pub fn verify(
+    pubkey: &RSAPublicKey,
+    input: &str,
+    signature: &[u8],
+) -> Result<(), rsa::errors::Error> {
+    pubkey.verify(
+        PaddingScheme::PKCS1v15Sign {
+            hash: Some(Hash::SHA2_512),
+        },
+        Sha512::digest(input.as_bytes()).as_slice(),
+        signature,
+    )
+}
+
+/// Signs a signing string with RSA and SHA-256, as expected by ActivityPub implementations
+pub fn sign_sha256(privkey: &RSAPrivateKey, input: &str) -> Result<Vec<u8>, rsa::errors::Error> {
+    privkey.sign(
+        PaddingScheme::PKCS1v15Sign {
+            hash: Some(Hash::SHA2_256),
+        },
+        Sha256::digest(input.as_bytes()).as_slice(),
+    )
+}
+
+/// Verifies an RSA and SHA-256 signature of a signing string against the supplied public key
+pub fn verify_sha256(
+    pubkey: &RSAPublicKey,
+    input: &str,
+    signature: &[u8],
+) -> Result<(), rsa::errors::Error> {
+    pubkey.verify(
+        PaddingScheme::PKCS1v15Sign {
+            hash: Some(Hash::SHA2_256),
+        },
+        Sha256::digest(input.as_bytes()).as_slice(),
+        signature,
+    )
+}
+
+/// Decodes the DER contents of a PEM encoded key, ignoring its armour lines
+pub fn decode_pem<T: AsRef<str>>(pem: T) -> Result<Vec<u8>, base64::DecodeError> {
+    let encoded = pem
+        .as_ref()
+        .lines()
+        .filter(|line| !line.starts_with('-'))
+        .flat_map(|line| line.chars())
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>();
+
+    base64::decode(encoded)
+}
+
+/// Signature header error
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum Error {
+    #[error("Malformed Signature header: {0}")]
+    Malformed(String),
+
+    #[error("Missing \"{0}\" parameter in Signature header")]
+    MissingParameter(&'static str),
+
+    #[error("Duplicate \"{0}\" parameter in Signature header")]
+    DuplicateParameter(String),
+
+    #[error("Invalid base64 in \"signature\" parameter: {0}")]
+    InvalidSignature(String),
+
+    #[error("Header \"{0}\" is covered by the signature but missing from the request")]
+    MissingHeader(String),
+
+    #[error("Unsupported pseudo-header \"{0}\" in Signature header")]
+    UnsupportedPseudoHeader(String),
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::{request_target, Error, SignatureHeader},
+        actix_web::http::Uri,
+        proptest::prelude::*,
+    };
+
+    #[test]
+    fn request_target_query_success() {
+        let uri: Uri = "https://one.example/fed/posts?parentPost=1"
+            .parse()
+            .unwrap();
+        assert_eq!(request_target(&uri), "/fed/posts?parentPost=1");
+
+        let uri: Uri = "https://one.example".parse().unwrap();
+        assert_eq!(request_target(&uri), "/");
+    }
+
+    #[test]
+    fn parse_any_order_success() {
+        let header = r#"signature="AQID", headers="(request-target) host date",algorithm=rsa-sha512 , keyId="global""#
+            .parse::<SignatureHeader>()
+            .unwrap();
+
+        assert_eq!(
+            header,
+            SignatureHeader {
+                key_id: "global".to_owned(),
+                algorithm: Some("rsa-sha512".to_owned()),
+                headers: vec![
+                    "(request-target)".to_owned(),
+                    "host".to_owned(),
+                    "date".to_owned()
+                ],
+                signature: vec![1, 2, 3],
+            }
+        );
+    }
+
+    #[test]
+    fn parse_default_headers_success() {
+        let header = r#"keyId="global",signature="AQID""#.parse::<SignatureHeader>().unwrap();
+
+        assert_eq!(header.algorithm, None);
+        assert_eq!(header.headers, vec!["date".to_owned()]);
+    }
+
+    #[test]
+    fn parse_missing_signature_fail() {
+        assert_eq!(
+            r#"keyId="global",headers="date""#.parse::<SignatureHeader>(),
+            Err(Error::MissingParameter("signature"))
+        );
+    }
+
+    #[test]
+    fn parse_duplicate_parameter_fail() {
+        assert_eq!(
+            r#"keyId="a",keyId="b",signature="AQID""#.parse::<SignatureHeader>(),
+            Err(Error::DuplicateParameter("keyId".to_owned()))
+        );
+    }
+
+    #[test]
+    fn signing_string_follows_declared_order() {
+        let header =
+            r#"keyId="global",headers="date (request-target) client-host",signature="AQID""#
+                .parse::<SignatureHeader>()
+                .unwrap();
+
+        let input = header
+            .signing_string("POST", "/fed/posts", |name| match name {
+                "date" => Some("Tue, 07 Jun 2014 20:51:35 GMT".to_owned()),
+                "client-host" => Some("example.org".to_owned()),
+                _ => None,
+            })
+            .unwrap();
+
+        assert_eq!(
+            input,
+            "date: Tue, 07 Jun 2014 20:51:35 GMT\n(request-target): post /fed/posts\nclient-host: example.org"
+        );
+    }
+
+    #[test]
+    fn signing_string_missing_header_fail() {
+        let header = r#"keyId="global",headers="host user-id",signature="AQID""#
+            .parse::<SignatureHeader>()
+            .unwrap();
+
+        assert_eq!(
+            header.signing_string("GET", "/", |name| match name {
+                "host" => Some("example.org".to_owned()),
+                _ => None,
+            }),
+            Err(Error::MissingHeader("user-id".to_owned()))
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn signature_header_round_trip(
+            key_id in "[ -~]{1,32}",
+            algorithm in proptest::option::of("[ -~]{1,16}"),
+            headers in proptest::collection::vec("[a-z0-9-]{1,16}|\\(request-target\\)", 1..8),
+            signature in proptest::collection::vec(any::<u8>(), 1..512),
+        ) {
+            let header = SignatureHeader {
+                key_id,
+                algorithm,
+                headers,
+                signature,
+            };
+
+            assert_eq!(header.to_string().parse::<SignatureHeader>(), Ok(header));
+        }
+
+        #[test]
+        fn parse_arbitrary_input_no_panic(s in "\\PC*") {
+            let _ = s.parse::<SignatureHeader>();
+        }
+    }
+}
@@ -0,0 +1,586 @@
+//! Background synchronisation of remote communities and posts into a local cache
+
+use {
+    crate::{
+        fed::{
+            client::{self, Client},
+            PostFilters,
+        },
+        models::{fed::COMMUNITY_TIMESTAMPS, internal::UserId},
+        util::{federated_remotes, normalise_host},
+        Error,
+    },
+    actix_rt::time::delay_for,
+    actix_web::http::StatusCode,
+    log::{debug, error},
+    rsa::RSAPrivateKey,
+    sqlx::{Pool, Postgres},
+    std::{
+        collections::{HashMap, HashSet},
+        time::Duration,
+    },
+    uuid::Uuid,
+};
+
+/// Default interval between synchronisations of all remotes
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// User-ID sent with synchronisation requests, which are not made on behalf of any user
+const SYNC_USER: &str = "sync";
+
+/// Synchronises the cache with every known remote that is federated with
+pub async fn sync_all(pool: &Pool<Postgres>, client: &Client) -> Result<(), Error> {
+    for remote in federated_remotes(pool).await? {
+        match sync_remote(pool, client, &remote).await {
+            Ok(()) => {}
+            // a remote that no longer lists communities has nothing left to cache
+            Err(e) if is_permanent(&e) => {
+                debug!("sync: dropping cache of {}: {}", remote, e);
+                sqlx::query!(
+                    r#"
+                        DELETE FROM remote_communities
+                        WHERE host = $1
+                    "#,
+                    remote
+                )
+                .execute(pool)
+                .await?;
+            }
+            Err(e) => error!("sync: failed to synchronise {}: {}", remote, e),
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns whether a synchronisation failed because the remote no longer has what was requested,
+/// such as a community that was deleted, which retrying at the next interval will not resolve
+///
+/// Other rejections, such as rate limits or a key the remote has not yet registered, are retried.
+fn is_permanent(e: &Error) -> bool {
+    matches!(
+        e,
+        Error::Client(client::Error::ResponseStatus(StatusCode::NOT_FOUND))
+            | Error::Client(client::Error::ResponseStatus(StatusCode::GONE))
+    )
+}
+
+/// Synchronises the cached communities and posts of a single remote
+pub async fn sync_remote(pool: &Pool<Postgres>, client: &Client, host: &str) -> Result<(), Error> {
+    let ids = client.get_communities(host).await?;
+
+    // forget communities that no longer exist on the remote
+    sqlx::query!(
+        r#"
+            DELETE FROM remote_communities
+            WHERE host = $1
+            AND NOT (id = ANY($2))
+        "#,
+        host,
+        &ids
+    )
+    .execute(pool)
+    .await?;
+
+    for id in ids {
+        match sync_community(pool, client, host, &id).await {
+            Ok(()) => {}
+            // removing the community also removes its cached posts
+            Err(e) if is_permanent(&e) => {
+                debug!("sync: dropping cache of {} on {}: {}", id, host, e);
+                sqlx::query!(
+                    r#"
+                        DELETE FROM remote_communities
+                        WHERE id = $1
+                        AND host = $2
+                    "#,
+                    id,
+                    host
+                )
+                .execute(pool)
+                .await?;
+            }
+            Err(e) => error!("sync: failed to synchronise {} on {}: {}", id, host, e),
+        }
+    }
+
+    Ok(())
+}
+
+/// Synchronises a single remote community, fetching only posts that are new or were modified
+pub async fn sync_community(
+    pool: &Pool<Postgres>,
+    client: &Client,
+    host: &str,
+    id: &str,
+) -> Result<(), Error> {
+    let community = client.get_community(host, id).await?;
+    let moderators = community
+        .admins
+        .into_iter()
+        .map(UserId::from)
+        .collect::<Vec<_>>();
+
+    // the community is cached once whichever port of the remote it is synchronised through,
+    // dropping any copy cached under another one
+    sqlx::query!(
+        r#"
+            DELETE FROM remote_communities
+            WHERE id = $1
+            AND lower(split_part(host, ':', 1)) = $2
+            AND host <> $3
+        "#,
+        id,
+        normalise_host(host)?,
+        host
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query!(
+        r#"
+            INSERT INTO remote_communities VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (id, host) DO UPDATE
+                SET title = $3, description = $4, moderators = $5
+        "#,
+        id,
+        host,
+        community.title,
+        community.description,
+        serde_json::to_value(&moderators)?,
+        // 0 until the first synchronisation of the community's posts completes
+        0i64
+    )
+    .execute(pool)
+    .await?;
+
+    // remotes without the timestamps endpoint are synchronised by fetching every post up front
+    let mut fetched = HashMap::new();
+    let mut scores = HashMap::new();
+    let timestamps = if client.supports(host, COMMUNITY_TIMESTAMPS).await? {
+        client
+            .get_community_timestamps(host, id)
+            .await?
+            .into_iter()
+            .map(|t| {
+                if let Some(score) = t.score {
+                    scores.insert(t.id, score);
+                }
+                (t.id, t.modified)
+            })
+            .collect::<HashMap<Uuid, i64>>()
+    } else {
+        let filters = PostFilters {
+            community: Some(id.to_owned()),
+            ..PostFilters::default()
+        };
+        fetched = client
+            .get_posts(host, filters, SYNC_USER)
+            .await?
+            .into_iter()
+            .map(|p| (p.id, p))
+            .collect();
+        scores = fetched.iter().map(|(id, p)| (*id, p.score)).collect();
+        fetched.iter().map(|(id, p)| (*id, p.modified)).collect()
+    };
+
+    let cached = sqlx::query!(
+        r#"
+            SELECT id, modified FROM remote_posts
+            WHERE host = $1
+            AND community = $2
+        "#,
+        host,
+        id
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| (row.id, row.modified))
+    .collect::<HashMap<Uuid, i64>>();
+
+    // remove posts that were purged on the remote, whereas deleted posts are kept as tombstones
+    // until then
+    let removed = cached
+        .keys()
+        .filter(|id| !timestamps.contains_key(*id))
+        .copied()
+        .collect::<Vec<_>>();
+    sqlx::query!(
+        r#"
+            DELETE FROM remote_posts
+            WHERE host = $1
+            AND id = ANY($2)
+        "#,
+        host,
+        &removed
+    )
+    .execute(pool)
+    .await?;
+
+    // fetch posts that are new or whose modification time differs from the cached copy, which
+    // includes posts that have since been deleted
+    let changed = timestamps
+        .iter()
+        .filter(|(id, modified)| cached.get(*id) != Some(*modified))
+        .map(|(id, _)| *id)
+        .collect::<HashSet<_>>();
+
+    debug!(
+        "sync: {} on {} has {} changed and {} removed posts",
+        id,
+        host,
+        changed.len(),
+        removed.len()
+    );
+
+    let threads_changed = !changed.is_empty() || !removed.is_empty();
+
+    for post_id in changed {
+        let post = match fetched.remove(&post_id) {
+            Some(post) => post,
+            None => client.get_post(host, post_id, SYNC_USER).await?,
+        };
+
+        // the latest reply beneath the post is filled in once every changed post is stored
+        sqlx::query!(
+            r#"
+                INSERT INTO remote_posts
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, NULL, $12)
+                ON CONFLICT (id, host) DO UPDATE
+                    SET parent = $4, title = $7, content = $8, modified = $10, deleted = $11,
+                        score = $12
+            "#,
+            post.id,
+            host,
+            id,
+            post.parent_post,
+            post.author.id,
+            post.author.host,
+            post.title,
+            serde_json::to_value(&post.content)?,
+            post.created,
+            post.modified,
+            post.deleted,
+            post.score
+        )
+        .execute(pool)
+        .await?;
+    }
+
+    // votes do not modify posts, so the scores of unchanged posts are refreshed separately
+    let (scored, scores): (Vec<_>, Vec<_>) = scores.into_iter().unzip();
+    sqlx::query!(
+        r#"
+            UPDATE remote_posts
+            SET score = scores.score
+            FROM UNNEST($2::UUID[], $3::BIGINT[]) AS scores (id, score)
+            WHERE remote_posts.host = $1
+            AND remote_posts.id = scores.id
+        "#,
+        host,
+        &scored,
+        &scores
+    )
+    .execute(pool)
+    .await?;
+
+    // the feed orders cached posts by the latest reply beneath them
+    if threads_changed {
+        sqlx::query!(
+            r#"
+                WITH RECURSIVE tree AS (
+                    SELECT id AS root, id, created, deleted FROM remote_posts
+                    WHERE host = $1
+                    AND community = $2
+                    AND parent IS NULL
+                    UNION ALL
+                    SELECT tree.root, remote_posts.id, remote_posts.created, remote_posts.deleted
+                    FROM remote_posts
+                    INNER JOIN tree
+                    ON remote_posts.parent = tree.id
+                    AND remote_posts.host = $1
+                )
+                UPDATE remote_posts
+                SET last_reply = replies.last_reply
+                FROM (
+                    SELECT root, MAX(created) FILTER (WHERE id <> root AND deleted IS NULL)
+                        AS last_reply
+                    FROM tree
+                    GROUP BY root
+                ) AS replies
+                WHERE remote_posts.host = $1
+                AND remote_posts.id = replies.root
+            "#,
+            host,
+            id
+        )
+        .execute(pool)
+        .await?;
+    }
+
+    sqlx::query!(
+        r#"
+            UPDATE remote_communities
+            SET last_synced = $1
+            WHERE id = $2
+            AND host = $3
+        "#,
+        chrono::Local::now().timestamp(),
+        id,
+        host
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Spawns a task on the current system that synchronises all remotes every interval
+pub fn spawn_worker(
+    host: String,
+    pool: Pool<Postgres>,
+    privkey: RSAPrivateKey,
+    interval: Duration,
+) {
+    actix_rt::spawn(async move {
+        let client = Client::new(&host, &privkey, &pool);
+
+        loop {
+            if let Err(e) = sync_all(&pool, &client).await {
+                error!("sync: failed to synchronise remotes: {}", e);
+            }
+
+            delay_for(interval).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::{is_permanent, sync_remote},
+        crate::{
+            fed::client::{self, Client},
+            models::internal::{Community, Post},
+            test::{add_remote, connect, get_all_pages, new_user_login, stand_in, ADDR, FQDN},
+            Config, Error,
+        },
+        actix_web::{
+            http::{header::CONTENT_TYPE, StatusCode},
+            web, HttpResponse,
+        },
+    };
+
+    #[test]
+    fn is_permanent_success() {
+        let status = |status| Error::Client(client::Error::ResponseStatus(status));
+
+        assert!(is_permanent(&status(StatusCode::NOT_FOUND)));
+        assert!(is_permanent(&status(StatusCode::GONE)));
+
+        // rejections that may resolve themselves are retried
+        assert!(!is_permanent(&status(StatusCode::UNAUTHORIZED)));
+        assert!(!is_permanent(&status(StatusCode::FORBIDDEN)));
+        assert!(!is_permanent(&status(StatusCode::REQUEST_TIMEOUT)));
+        assert!(!is_permanent(&status(StatusCode::TOO_MANY_REQUESTS)));
+    }
+
+    #[actix_rt::test]
+    async fn sync_changed_posts_success() {
+        let (pool, privkey) = connect().await;
+        let (client, _, cookie) = new_user_login().await;
+
+        // the backend synchronises with itself through its listening address, which differs from
+        // its FQDN and is therefore treated as a remote
+        let remote = ADDR.trim_start_matches("http://");
+        add_remote(&envy::from_env::<Config>().unwrap().fqdn).await;
+
+        let res = client
+            .post(&format!("{}/internal/communities", *ADDR))
+            .cookie(cookie.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .send_body(
+                r#"{"id": "synced", "title": "Synced", "description": "Cached by the sync"}"#,
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let mut res = client
+            .post(&format!("{}/internal/posts", *ADDR))
+            .cookie(cookie.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .send_body(
+                r#"{"community": "synced", "parentPost": null, "title": "First", "content": [{"text": {"text": "Cached"}}]}"#,
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let post: Post = res.json().await.unwrap();
+
+        sync_remote(&pool, &Client::new(&*FQDN, &privkey, &pool), remote)
+            .await
+            .unwrap();
+
+        let cached = sqlx::query!(
+            r#"
+                SELECT title, modified FROM remote_posts
+                WHERE id = $1 AND host = $2
+            "#,
+            post.id,
+            remote
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(cached.title, "First");
+
+        // communities are served from the cache along with the time of the last sync
+        let communities: Vec<Community> =
+            get_all_pages(&client, &format!("{}/internal/communities", *ADDR), &cookie).await;
+        let community = communities
+            .iter()
+            .find(|c| c.id == "synced" && c.host == remote)
+            .unwrap();
+        assert!(community.last_synced.unwrap() > 0);
+
+        // a modified post is fetched again
+        sqlx::query!(
+            r#"
+                UPDATE posts
+                SET title = 'Second', modified = modified + 1
+                WHERE id = $1
+            "#,
+            post.id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        sync_remote(&pool, &Client::new(&*FQDN, &privkey, &pool), remote)
+            .await
+            .unwrap();
+
+        let cached = sqlx::query!(
+            r#"
+                SELECT title, modified FROM remote_posts
+                WHERE id = $1 AND host = $2
+            "#,
+            post.id,
+            remote
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(cached.title, "Second");
+        assert_eq!(cached.modified, post.modified + 1);
+
+        // a deleted post is cached as a tombstone
+        sqlx::query!(
+            r#"
+                UPDATE posts
+                SET title = '', content = '[]', modified = modified + 1, deleted = modified + 1
+                WHERE id = $1
+            "#,
+            post.id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        sync_remote(&pool, &Client::new(&*FQDN, &privkey, &pool), remote)
+            .await
+            .unwrap();
+
+        let cached = sqlx::query!(
+            r#"
+                SELECT title, deleted FROM remote_posts
+                WHERE id = $1 AND host = $2
+            "#,
+            post.id,
+            remote
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(cached.title, "");
+        assert_eq!(cached.deleted, Some(post.modified + 2));
+
+        // a purged post is removed from the cache
+        sqlx::query!(
+            r#"
+                DELETE FROM posts
+                WHERE id = $1
+            "#,
+            post.id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        sync_remote(&pool, &Client::new(&*FQDN, &privkey, &pool), remote)
+            .await
+            .unwrap();
+
+        let cached = sqlx::query!(
+            r#"
+                SELECT id FROM remote_posts
+                WHERE id = $1 AND host = $2
+            "#,
+            post.id,
+            remote
+        )
+        .fetch_optional(&pool)
+        .await
+        .unwrap();
+        assert!(cached.is_none());
+    }
+
+    #[actix_rt::test]
+    async fn sync_rejected_community_success() {
+        let (pool, privkey) = connect().await;
+
+        // remote that still lists a community but no longer serves it
+        let remote = stand_in(
+            "127.0.0.1",
+            |cfg: &mut web::ServiceConfig| {
+                cfg.route(
+                    "/fed/communities",
+                    web::get().to(|| async { HttpResponse::Ok().json(vec!["gone"]) }),
+                );
+            },
+            None,
+        )
+        .to_string();
+
+        sqlx::query!(
+            r#"
+                INSERT INTO remote_communities VALUES ('gone', $1, 'Gone', '', '[]', 1)
+            "#,
+            remote
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        sync_remote(&pool, &Client::new(&*FQDN, &privkey, &pool), &remote)
+            .await
+            .unwrap();
+
+        // the community is dropped from the cache rather than retried at every interval
+        let cached = sqlx::query!(
+            r#"
+                SELECT EXISTS(
+                    SELECT 1 FROM remote_communities
+                    WHERE id = 'gone' AND host = $1
+                ) AS "exists!"
+            "#,
+            remote
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert!(!cached.exists);
+    }
+}
@@ -1,7 +1,8 @@
 use {
     crate::{
         models::fed::{Message, PostId, User},
-        util::{get_client_host, get_user_id, user_exists},
+        pagination::Pagination,
+        util::{get_client_host, get_user_id, is_suspended, user_exists},
         AppData, Error,
     },
     actix_web::{get, post, web, HttpRequest, HttpResponse, Responder, Result},
@@ -17,17 +18,26 @@ pub struct UserFilters {
     prefix: Option<String>,
 }
 
+/// Lists the usernames of local users, as a page if one was requested
 #[get("/fed/users")]
 pub(crate) async fn get_users(
     data: web::Data<AppData>,
     web::Query(filters): web::Query<UserFilters>,
+    pagination: Pagination,
 ) -> Result<impl Responder, Error> {
     let users: Vec<String> = sqlx::query!(
         r#"
             SELECT username FROM users
-            WHERE ($1::VARCHAR is null OR username ILIKE $1 || '%')
+            WHERE host = $1
+            AND ($2::VARCHAR is null OR username ILIKE $2 || '%')
+            AND ($3::VARCHAR IS NULL OR username > $3)
+            ORDER BY username
+            LIMIT $4
         "#,
-        filters.prefix
+        data.host,
+        filters.prefix,
+        pagination.after::<String>()?,
+        pagination.requested().then(|| pagination.fetch_limit())
     )
     .fetch_all(&data.pool)
     .await?
@@ -35,7 +45,11 @@ pub(crate) async fn get_users(
     .map(|r| r.username)
     .collect();
 
-    Ok(HttpResponse::Ok().json(users))
+    if pagination.requested() {
+        Ok(HttpResponse::Ok().json(pagination.page(users, |u| u.clone())?))
+    } else {
+        Ok(HttpResponse::Ok().json(users))
+    }
 }
 
 #[get("/fed/users/{id}")]
@@ -43,10 +57,21 @@ pub(crate) async fn get_user_by_id(
     data: web::Data<AppData>,
     web::Path(id): web::Path<String>,
 ) -> Result<impl Responder, Error> {
-    // check that user exists
-    if !user_exists(&id, crate::host!(), &data.pool).await? {
-        return Ok(HttpResponse::NotFound().into());
-    }
+    let profile = match sqlx::query!(
+        r#"
+            SELECT about, display_name, avatar_url FROM local_users
+            WHERE username = $1
+            AND host = $2
+        "#,
+        &id,
+        data.host
+    )
+    .fetch_optional(&data.pool)
+    .await?
+    {
+        Some(profile) => profile,
+        None => return Ok(HttpResponse::NotFound().into()),
+    };
 
     // fetch all post IDs authored by user
     let posts: Vec<PostId> = sqlx::query!(
@@ -54,24 +79,33 @@ pub(crate) async fn get_user_by_id(
             SELECT id FROM posts
             WHERE author_username = $1
             AND author_host = $2
+            AND deleted IS NULL
         "#,
         &id,
-        crate::host!()
+        data.host
     )
     .fetch_all(&data.pool)
     .await?
     .into_iter()
     .map(|x| PostId {
         id: x.id,
-        host: crate::host!(),
+        host: data.host.clone(),
     })
     .collect();
 
     Ok(HttpResponse::Ok().json(User {
         id,
         posts,
-        about: "".to_owned(),
-        avatar_url: None,
+        about: profile.about,
+        // avatars stored on this server are made absolute so that remotes can load them
+        avatar_url: profile.avatar_url.map(|url| {
+            if url.starts_with('/') {
+                format!("https://{}{}", data.host, url)
+            } else {
+                url
+            }
+        }),
+        display_name: profile.display_name,
     }))
 }
 
@@ -85,10 +119,12 @@ pub(crate) async fn send_message(
     let sender_id = get_user_id(&req)?;
     let sender_host = get_client_host(&req)?;
 
-    (&id, crate::host!());
+    if is_suspended(sender_id, sender_host, &data.pool).await? {
+        return Ok(HttpResponse::Forbidden().into());
+    }
 
     // check that receiving user exists
-    if !user_exists(&id, crate::host!(), &data.pool).await? {
+    if !user_exists(&id, &data.host, &data.pool).await? {
         return Ok(HttpResponse::NotFound().into());
     }
 
@@ -121,7 +157,7 @@ pub(crate) async fn send_message(
         sender_id,
         sender_host,
         id,
-        crate::host!(),
+        data.host,
         body.title,
         serde_json::to_value(&body.content)?,
         chrono::Local::now().timestamp(),
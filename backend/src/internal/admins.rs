@@ -26,7 +26,7 @@ pub(crate) async fn get_admin_status(
     data: web::Data<AppData>,
     web::Path(user_id): web::Path<String>,
 ) -> Result<impl Responder, Error> {
-    if is_admin(&data.pool, user_id, crate::host!()).await? {
+    if is_admin(&data.pool, user_id, &data.host).await? {
         return Ok(HttpResponse::Ok());
     } else {
         return Ok(HttpResponse::NotFound());
@@ -47,12 +47,12 @@ pub(crate) async fn add_admin(
     };
 
     // check that requesting user is an admin
-    if !is_admin(&data.pool, requesting_user, crate::host!()).await? {
+    if !is_admin(&data.pool, requesting_user, &data.host).await? {
         return Ok(HttpResponse::Unauthorized());
     }
 
     // no-op if target user is already an admin (covers the case that someone is adding themselves as an admin)
-    if is_admin(&data.pool, &user_id, crate::host!()).await? {
+    if is_admin(&data.pool, &user_id, &data.host).await? {
         return Ok(HttpResponse::Ok());
     }
 
@@ -62,7 +62,7 @@ pub(crate) async fn add_admin(
             INSERT INTO admins VALUES ($1, $2)
         "#,
         user_id,
-        crate::host!()
+        data.host
     )
     .execute(&data.pool)
     .await?;
@@ -85,12 +85,12 @@ pub(crate) async fn remove_admin(
     };
 
     // check that requesting user is an admin
-    if !is_admin(&data.pool, requesting_user, crate::host!()).await? {
+    if !is_admin(&data.pool, requesting_user, &data.host).await? {
         return Ok(HttpResponse::Unauthorized());
     }
 
     // 404 if target user is not an admin
-    if !is_admin(&data.pool, &user_id, crate::host!()).await? {
+    if !is_admin(&data.pool, &user_id, &data.host).await? {
         return Ok(HttpResponse::NotFound());
     }
 
@@ -102,7 +102,7 @@ pub(crate) async fn remove_admin(
             AND host = $2
         "#,
         user_id,
-        crate::host!()
+        data.host
     )
     .execute(&data.pool)
     .await?;
@@ -113,7 +113,7 @@ pub(crate) async fn remove_admin(
 #[cfg(test)]
 mod test {
     use {
-        crate::test::{make_admin, new_user_login, ADDR},
+        crate::test::{make_admin, new_user_login, ADDR, FQDN},
         actix_web::{client::Client, http::StatusCode},
     };
 
@@ -143,7 +143,7 @@ mod test {
     async fn get_admin_success() {
         let (_, username, _) = new_user_login().await;
 
-        make_admin(&username, crate::host!()).await;
+        make_admin(&username, &*FQDN).await;
 
         let res = Client::new()
             .get(&format!("{}/internal/admins/{}", *ADDR, username))
@@ -171,7 +171,7 @@ mod test {
         let (admin_client, admin, admin_cookie) = new_user_login().await;
         let (client, username, _) = new_user_login().await;
 
-        make_admin(admin, crate::host!()).await;
+        make_admin(admin, &*FQDN).await;
 
         // not admin before
         let res = client
@@ -203,7 +203,7 @@ mod test {
     async fn remove_admin_nonexistant_fail() {
         let (admin_client, admin, cookie) = new_user_login().await;
 
-        make_admin(admin, crate::host!()).await;
+        make_admin(admin, &*FQDN).await;
 
         let res = admin_client
             .delete(&format!("{}/internal/admins/thisuserdoesnotexist", *ADDR))
@@ -219,7 +219,7 @@ mod test {
         let (_, admin, _) = new_user_login().await;
         let (client, _, cookie) = new_user_login().await;
 
-        make_admin(&admin, crate::host!()).await;
+        make_admin(&admin, &*FQDN).await;
 
         // admin is admin
         let res = client
@@ -244,7 +244,7 @@ mod test {
         let (admin_client, admin, admin_cookie) = new_user_login().await;
         let (client, user, _) = new_user_login().await;
 
-        make_admin(&admin, crate::host!()).await;
+        make_admin(&admin, &*FQDN).await;
 
         // admin is admin
         let res = client
@@ -276,7 +276,7 @@ mod test {
     async fn remove_self_admin_success() {
         let (admin_client, admin, cookie) = new_user_login().await;
 
-        make_admin(&admin, crate::host!()).await;
+        make_admin(&admin, &*FQDN).await;
 
         // admin is admin
         let res = Client::new()
@@ -309,8 +309,8 @@ mod test {
         let (admin_client, admin, admin_cookie) = new_user_login().await;
         let (_, admin2, _) = new_user_login().await;
 
-        make_admin(&admin, crate::host!()).await;
-        make_admin(&admin2, crate::host!()).await;
+        make_admin(&admin, &*FQDN).await;
+        make_admin(&admin2, &*FQDN).await;
 
         // admin is admin
         let res = Client::new()
@@ -0,0 +1,860 @@
+use {
+    crate::{
+        models::{
+            database,
+            internal::{
+                Ban, ModerationLogEntry, NewBan, UserId, MODERATION_BAN, MODERATION_SUSPEND,
+                MODERATION_UNBAN, MODERATION_UNSUSPEND,
+            },
+        },
+        pagination::Pagination,
+        util::{is_admin, is_moderator, user_exists, validate_ban},
+        AppData, Error,
+    },
+    actix_identity::Identity,
+    actix_web::{delete, get, put, web, HttpResponse, Responder, Result},
+    anyhow::anyhow,
+    sqlx::{Pool, Postgres, Transaction},
+    std::convert::TryFrom,
+    uuid::Uuid,
+};
+
+/// Returns whether the supplied local user may ban users from the supplied community, being one
+/// of its moderators or an admin
+async fn can_ban(
+    community: &str,
+    username: &str,
+    host: &str,
+    pool: &Pool<Postgres>,
+) -> Result<bool, Error> {
+    Ok(is_moderator(username, host, community, pool).await?
+        || is_admin(pool, username, host).await?)
+}
+
+/// Parses the user a ban is issued against, returning None if they are local and do not exist
+///
+/// Remote users need not have interacted with the server yet to be banned.
+async fn banned_user(
+    user_id: &str,
+    local: &str,
+    pool: &Pool<Postgres>,
+) -> Result<Option<UserId>, Error> {
+    let user = UserId::try_from((user_id, local))?;
+
+    if user.host == local && !user_exists(&user.username, &user.host, pool).await? {
+        return Ok(None);
+    }
+
+    Ok(Some(user))
+}
+
+/// Records a moderation action in the log
+async fn log_action(
+    tx: &mut Transaction<'_, Postgres>,
+    action: &str,
+    community: Option<&str>,
+    user: &UserId,
+    moderator: &UserId,
+    reason: &str,
+    expires: Option<i64>,
+) -> Result<(), Error> {
+    sqlx::query!(
+        r#"
+            INSERT INTO moderation_log VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+        "#,
+        Uuid::new_v4(),
+        action,
+        community,
+        user.username,
+        user.host,
+        moderator.username,
+        moderator.host,
+        reason,
+        expires,
+        chrono::Local::now().timestamp()
+    )
+    .execute(tx)
+    .await?;
+
+    Ok(())
+}
+
+/// Bans a local or remote user from posting in a community, replacing any previous ban
+#[put("/internal/communities/{id}/bans/{user_id}")]
+pub(crate) async fn ban_community_user(
+    identity: Identity,
+    data: web::Data<AppData>,
+    web::Path((id, user_id)): web::Path<(String, String)>,
+    web::Json(body): web::Json<NewBan>,
+) -> Result<impl Responder, Error> {
+    let username = match identity.identity() {
+        Some(s) => s,
+        None => return Ok(HttpResponse::Unauthorized().finish()),
+    };
+
+    if !sqlx::query!(
+        r#"
+            SELECT EXISTS(SELECT 1 FROM communities WHERE id = $1) AS "exists!"
+        "#,
+        id
+    )
+    .fetch_one(&data.pool)
+    .await?
+    .exists
+    {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    if !can_ban(&id, &username, &data.host, &data.pool).await? {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+
+    let user = match banned_user(&user_id, &data.host, &data.pool).await? {
+        Some(user) => user,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let moderator = UserId {
+        username,
+        host: data.host.clone(),
+    };
+    if user == moderator {
+        return Err(Error::BadRequest(anyhow!(
+            "Moderators cannot ban themselves"
+        )));
+    }
+
+    let reason = validate_ban(&body)?;
+
+    let mut tx = data.pool.begin().await?;
+
+    let ban = sqlx::query_as!(
+        database::CommunityBan,
+        r#"
+            INSERT INTO community_bans VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            ON CONFLICT (community, username, host) DO UPDATE
+                SET reason = $4, created = $5, expires = $6,
+                    moderator_username = $7, moderator_host = $8
+            RETURNING *
+        "#,
+        id,
+        user.username,
+        user.host,
+        reason,
+        chrono::Local::now().timestamp(),
+        body.expires,
+        moderator.username,
+        moderator.host
+    )
+    .fetch_one(&mut tx)
+    .await?;
+
+    log_action(
+        &mut tx,
+        MODERATION_BAN,
+        Some(&id),
+        &user,
+        &moderator,
+        &reason,
+        body.expires,
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().json(Ban::from(ban)))
+}
+
+/// Lifts the ban of a user from a community before it expires
+#[delete("/internal/communities/{id}/bans/{user_id}")]
+pub(crate) async fn unban_community_user(
+    identity: Identity,
+    data: web::Data<AppData>,
+    web::Path((id, user_id)): web::Path<(String, String)>,
+) -> Result<impl Responder, Error> {
+    let username = match identity.identity() {
+        Some(s) => s,
+        None => return Ok(HttpResponse::Unauthorized().finish()),
+    };
+
+    if !can_ban(&id, &username, &data.host, &data.pool).await? {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+
+    let user = UserId::try_from((user_id.as_str(), data.host.as_str()))?;
+    let moderator = UserId {
+        username,
+        host: data.host.clone(),
+    };
+
+    let mut tx = data.pool.begin().await?;
+
+    let ban = sqlx::query!(
+        r#"
+            DELETE FROM community_bans
+            WHERE community = $1
+            AND username = $2
+            AND host = $3
+            AND (expires IS NULL OR expires > $4)
+            RETURNING reason
+        "#,
+        id,
+        user.username,
+        user.host,
+        chrono::Local::now().timestamp()
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+
+    let reason = match ban {
+        Some(ban) => ban.reason,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    log_action(
+        &mut tx,
+        MODERATION_UNBAN,
+        Some(&id),
+        &user,
+        &moderator,
+        &reason,
+        None,
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Lists the users currently banned from a community
+#[get("/internal/communities/{id}/bans")]
+pub(crate) async fn get_community_bans(
+    identity: Identity,
+    data: web::Data<AppData>,
+    web::Path(id): web::Path<String>,
+    pagination: Pagination,
+) -> Result<impl Responder, Error> {
+    let username = match identity.identity() {
+        Some(s) => s,
+        None => return Ok(HttpResponse::Unauthorized().finish()),
+    };
+
+    if !can_ban(&id, &username, &data.host, &data.pool).await? {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+
+    let (after_host, after_username) = match pagination.after::<(String, String)>()? {
+        Some((host, username)) => (Some(host), Some(username)),
+        None => (None, None),
+    };
+
+    let bans: Vec<Ban> = sqlx::query_as!(
+        database::CommunityBan,
+        r#"
+            SELECT * FROM community_bans
+            WHERE community = $1
+            AND (expires IS NULL OR expires > $2)
+            AND ($3::TEXT IS NULL OR (host, username) > ($3, $4))
+            ORDER BY host, username
+            LIMIT $5
+        "#,
+        id,
+        chrono::Local::now().timestamp(),
+        after_host,
+        after_username,
+        pagination.fetch_limit()
+    )
+    .fetch_all(&data.pool)
+    .await?
+    .into_iter()
+    .map(Ban::from)
+    .collect();
+
+    Ok(HttpResponse::Ok()
+        .json(pagination.page(bans, |b| (b.user.host.clone(), b.user.username.clone()))?))
+}
+
+/// Suspends a local or remote user from the whole server, replacing any previous suspension
+///
+/// Suspended local users cannot log in or use their existing sessions, and suspended remote users
+/// cannot post or send messages to the server.
+#[put("/internal/suspensions/{user_id}")]
+pub(crate) async fn suspend_user(
+    identity: Identity,
+    data: web::Data<AppData>,
+    web::Path(user_id): web::Path<String>,
+    web::Json(body): web::Json<NewBan>,
+) -> Result<impl Responder, Error> {
+    let username = match identity.identity() {
+        Some(s) => s,
+        None => return Ok(HttpResponse::Unauthorized().finish()),
+    };
+
+    if !is_admin(&data.pool, &username, &data.host).await? {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+
+    let user = match banned_user(&user_id, &data.host, &data.pool).await? {
+        Some(user) => user,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let admin = UserId {
+        username,
+        host: data.host.clone(),
+    };
+    if user == admin {
+        return Err(Error::BadRequest(anyhow!(
+            "Admins cannot suspend themselves"
+        )));
+    }
+
+    let reason = validate_ban(&body)?;
+
+    let mut tx = data.pool.begin().await?;
+
+    let suspension = sqlx::query_as!(
+        database::Suspension,
+        r#"
+            INSERT INTO suspensions VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ON CONFLICT (username, host) DO UPDATE
+                SET reason = $3, created = $4, expires = $5,
+                    moderator_username = $6, moderator_host = $7
+            RETURNING *
+        "#,
+        user.username,
+        user.host,
+        reason,
+        chrono::Local::now().timestamp(),
+        body.expires,
+        admin.username,
+        admin.host
+    )
+    .fetch_one(&mut tx)
+    .await?;
+
+    log_action(
+        &mut tx,
+        MODERATION_SUSPEND,
+        None,
+        &user,
+        &admin,
+        &reason,
+        body.expires,
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().json(Ban::from(suspension)))
+}
+
+/// Lifts the suspension of a user before it expires
+#[delete("/internal/suspensions/{user_id}")]
+pub(crate) async fn unsuspend_user(
+    identity: Identity,
+    data: web::Data<AppData>,
+    web::Path(user_id): web::Path<String>,
+) -> Result<impl Responder, Error> {
+    let username = match identity.identity() {
+        Some(s) => s,
+        None => return Ok(HttpResponse::Unauthorized().finish()),
+    };
+
+    if !is_admin(&data.pool, &username, &data.host).await? {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+
+    let user = UserId::try_from((user_id.as_str(), data.host.as_str()))?;
+    let admin = UserId {
+        username,
+        host: data.host.clone(),
+    };
+
+    let mut tx = data.pool.begin().await?;
+
+    let suspension = sqlx::query!(
+        r#"
+            DELETE FROM suspensions
+            WHERE username = $1
+            AND host = $2
+            AND (expires IS NULL OR expires > $3)
+            RETURNING reason
+        "#,
+        user.username,
+        user.host,
+        chrono::Local::now().timestamp()
+    )
+    .fetch_optional(&mut tx)
+    .await?;
+
+    let reason = match suspension {
+        Some(suspension) => suspension.reason,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    log_action(
+        &mut tx,
+        MODERATION_UNSUSPEND,
+        None,
+        &user,
+        &admin,
+        &reason,
+        None,
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Lists the users currently suspended from the server
+#[get("/internal/suspensions")]
+pub(crate) async fn get_suspensions(
+    identity: Identity,
+    data: web::Data<AppData>,
+    pagination: Pagination,
+) -> Result<impl Responder, Error> {
+    let username = match identity.identity() {
+        Some(s) => s,
+        None => return Ok(HttpResponse::Unauthorized().finish()),
+    };
+
+    if !is_admin(&data.pool, &username, &data.host).await? {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+
+    let (after_host, after_username) = match pagination.after::<(String, String)>()? {
+        Some((host, username)) => (Some(host), Some(username)),
+        None => (None, None),
+    };
+
+    let suspensions: Vec<Ban> = sqlx::query_as!(
+        database::Suspension,
+        r#"
+            SELECT * FROM suspensions
+            WHERE (expires IS NULL OR expires > $1)
+            AND ($2::TEXT IS NULL OR (host, username) > ($2, $3))
+            ORDER BY host, username
+            LIMIT $4
+        "#,
+        chrono::Local::now().timestamp(),
+        after_host,
+        after_username,
+        pagination.fetch_limit()
+    )
+    .fetch_all(&data.pool)
+    .await?
+    .into_iter()
+    .map(Ban::from)
+    .collect();
+
+    Ok(HttpResponse::Ok().json(pagination.page(suspensions, |b| {
+        (b.user.host.clone(), b.user.username.clone())
+    })?))
+}
+
+/// Moderation log of a community, listing the bans issued and lifted in it newest first
+///
+/// The log is public so that members can see how their community is moderated.
+#[get("/internal/communities/{id}/modlog")]
+pub(crate) async fn get_community_moderation_log(
+    data: web::Data<AppData>,
+    web::Path(id): web::Path<String>,
+    pagination: Pagination,
+) -> Result<impl Responder, Error> {
+    let (after_created, after_id) = match pagination.after::<(i64, Uuid)>()? {
+        Some((created, id)) => (Some(created), Some(id)),
+        None => (None, None),
+    };
+
+    let entries: Vec<ModerationLogEntry> = sqlx::query_as!(
+        database::ModerationLogEntry,
+        r#"
+            SELECT * FROM moderation_log
+            WHERE community = $1
+            AND ($2::BIGINT IS NULL OR (created, id) < ($2, $3))
+            ORDER BY created DESC, id DESC
+            LIMIT $4
+        "#,
+        id,
+        after_created,
+        after_id,
+        pagination.fetch_limit()
+    )
+    .fetch_all(&data.pool)
+    .await?
+    .into_iter()
+    .map(ModerationLogEntry::from)
+    .collect();
+
+    Ok(HttpResponse::Ok().json(pagination.page(entries, |e| (e.created, e.id))?))
+}
+
+/// Moderation log of the whole server for admins, listing every ban and suspension issued and
+/// lifted newest first
+#[get("/internal/modlog")]
+pub(crate) async fn get_moderation_log(
+    identity: Identity,
+    data: web::Data<AppData>,
+    pagination: Pagination,
+) -> Result<impl Responder, Error> {
+    let username = match identity.identity() {
+        Some(s) => s,
+        None => return Ok(HttpResponse::Unauthorized().finish()),
+    };
+
+    if !is_admin(&data.pool, &username, &data.host).await? {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+
+    let (after_created, after_id) = match pagination.after::<(i64, Uuid)>()? {
+        Some((created, id)) => (Some(created), Some(id)),
+        None => (None, None),
+    };
+
+    let entries: Vec<ModerationLogEntry> = sqlx::query_as!(
+        database::ModerationLogEntry,
+        r#"
+            SELECT * FROM moderation_log
+            WHERE ($1::BIGINT IS NULL OR (created, id) < ($1, $2))
+            ORDER BY created DESC, id DESC
+            LIMIT $3
+        "#,
+        after_created,
+        after_id,
+        pagination.fetch_limit()
+    )
+    .fetch_all(&data.pool)
+    .await?
+    .into_iter()
+    .map(ModerationLogEntry::from)
+    .collect();
+
+    Ok(HttpResponse::Ok().json(pagination.page(entries, |e| (e.created, e.id))?))
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        crate::{
+            models::{
+                fed,
+                internal::{self, Ban, ModerationLogEntry},
+            },
+            pagination::Page,
+            test::{get_all_pages, make_admin, new_user_login, signed_request, ADDR, FQDN},
+        },
+        actix_web::http::{header::CONTENT_TYPE, Method, StatusCode},
+    };
+
+    #[actix_rt::test]
+    async fn community_ban_success() {
+        let (client, moderator, cookie) = new_user_login().await;
+        let (user_client, user, user_cookie) = new_user_login().await;
+
+        let res = client
+            .post(format!("{}/internal/communities", *ADDR))
+            .header(CONTENT_TYPE, "application/json")
+            .cookie(cookie.clone())
+            .send_body(r#"{"id": "banning", "title": "Banning", "description": "Bans"}"#)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let post = r#"{"community": "banning", "title": "Hello", "content": []}"#;
+        let ban_url = format!("{}/internal/communities/banning/bans/{}", *ADDR, user);
+
+        // bans must give a reason and expire in the future
+        for body in &[r#"{"reason": " "}"#, r#"{"reason": "Spam", "expires": 1}"#] {
+            let res = client
+                .put(&ban_url)
+                .header(CONTENT_TYPE, "application/json")
+                .cookie(cookie.clone())
+                .send_body(*body)
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        }
+
+        // only moderators may ban
+        let res = user_client
+            .put(format!(
+                "{}/internal/communities/banning/bans/{}",
+                *ADDR, moderator
+            ))
+            .header(CONTENT_TYPE, "application/json")
+            .cookie(user_cookie.clone())
+            .send_body(r#"{"reason": "Mutiny"}"#)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        // posts made before the bans can no longer be edited or voted on
+        let mut res = user_client
+            .post(format!("{}/internal/posts", *ADDR))
+            .header(CONTENT_TYPE, "application/json")
+            .cookie(user_cookie.clone())
+            .send_body(post)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let user_post: internal::Post = res.json().await.unwrap();
+
+        let mut res = signed_request(
+            Method::POST,
+            "/fed/posts",
+            "one.example",
+            Some("mallory"),
+            post,
+        )
+        .await
+        .send_body(post)
+        .await
+        .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let remote_post: fed::Post = res.json().await.unwrap();
+
+        let mut res = client
+            .put(&ban_url)
+            .header(CONTENT_TYPE, "application/json")
+            .cookie(cookie.clone())
+            .send_body(r#"{"reason": "Spam"}"#)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let ban: Ban = res.json().await.unwrap();
+        assert_eq!(ban.user.username, user);
+        assert_eq!(ban.moderator.username, moderator);
+        assert!(ban.expires.is_none());
+
+        let res = user_client
+            .post(format!("{}/internal/posts", *ADDR))
+            .header(CONTENT_TYPE, "application/json")
+            .cookie(user_cookie.clone())
+            .send_body(post)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        let edit = r#"{"title": "Edited", "content": []}"#;
+        let vote = r#"{"value": 1}"#;
+        let res = user_client
+            .put(format!("{}/internal/posts/{}", *ADDR, user_post.id))
+            .header(CONTENT_TYPE, "application/json")
+            .cookie(user_cookie.clone())
+            .send_body(edit)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        let res = user_client
+            .put(format!("{}/internal/posts/{}/vote", *ADDR, user_post.id))
+            .header(CONTENT_TYPE, "application/json")
+            .cookie(user_cookie.clone())
+            .send_body(vote)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        // remote users are banned from posting through federation
+        let remote_ban_url = format!(
+            "{}/internal/communities/banning/bans/mallory@one.example",
+            *ADDR
+        );
+        let res = client
+            .put(&remote_ban_url)
+            .header(CONTENT_TYPE, "application/json")
+            .cookie(cookie.clone())
+            .send_body(r#"{"reason": "Spam"}"#)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = signed_request(
+            Method::POST,
+            "/fed/posts",
+            "one.example",
+            Some("mallory"),
+            post,
+        )
+        .await
+        .send_body(post)
+        .await
+        .unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        for (path, body) in &[
+            (format!("/fed/posts/{}", remote_post.id), edit),
+            (format!("/fed/posts/{}/vote", remote_post.id), vote),
+        ] {
+            let res = signed_request(Method::PUT, path, "one.example", Some("mallory"), body)
+                .await
+                .send_body(*body)
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        }
+
+        let bans: Vec<Ban> = get_all_pages(
+            &client,
+            &format!("{}/internal/communities/banning/bans", *ADDR),
+            &cookie,
+        )
+        .await;
+        assert_eq!(bans.len(), 2);
+        assert!(bans.contains(&ban));
+
+        let res = client
+            .delete(&ban_url)
+            .cookie(cookie.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = user_client
+            .post(format!("{}/internal/posts", *ADDR))
+            .header(CONTENT_TYPE, "application/json")
+            .cookie(user_cookie)
+            .send_body(post)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = client.delete(&ban_url).cookie(cookie).send().await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        // the public log records each ban and its lifting, which all happened within a second
+        let mut res = user_client
+            .get(format!("{}/internal/communities/banning/modlog", *ADDR))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let log: Page<ModerationLogEntry> = res.json().await.unwrap();
+        let mut actions = log
+            .items
+            .iter()
+            .map(|e| (e.action.as_str(), e.user.username.as_str()))
+            .collect::<Vec<_>>();
+        actions.sort();
+        assert_eq!(
+            actions,
+            vec![
+                ("ban", user.as_str()),
+                ("ban", "mallory"),
+                ("unban", user.as_str())
+            ]
+        );
+        assert!(log.items.iter().all(|e| e.reason == "Spam"));
+    }
+
+    #[actix_rt::test]
+    async fn suspension_success() {
+        let (client, admin, cookie) = new_user_login().await;
+        let (user_client, user, user_cookie) = new_user_login().await;
+        let suspension_url = format!("{}/internal/suspensions/{}", *ADDR, user);
+
+        let res = client
+            .put(&suspension_url)
+            .header(CONTENT_TYPE, "application/json")
+            .cookie(cookie.clone())
+            .send_body(r#"{"reason": "Abuse"}"#)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        make_admin(&admin, &*FQDN).await;
+
+        let mut res = client
+            .put(&suspension_url)
+            .header(CONTENT_TYPE, "application/json")
+            .cookie(cookie.clone())
+            .send_body(r#"{"reason": "Abuse"}"#)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let suspension: Ban = res.json().await.unwrap();
+        assert!(suspension.community.is_none());
+
+        // the existing session of the suspended user is no longer accepted
+        let res = user_client
+            .post(format!("{}/internal/posts", *ADDR))
+            .header(CONTENT_TYPE, "application/json")
+            .cookie(user_cookie)
+            .send_body(r#"{"community": "none", "title": "Hello", "content": []}"#)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        let login = format!(
+            r#"{{"username": "{}", "password": "{}_password"}}"#,
+            user, user
+        );
+        let res = user_client
+            .post(format!("{}/internal/login", *ADDR))
+            .header(CONTENT_TYPE, "application/json")
+            .send_body(login.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        // suspended remote users cannot send messages
+        let res = client
+            .put(format!(
+                "{}/internal/suspensions/mallory@one.example",
+                *ADDR
+            ))
+            .header(CONTENT_TYPE, "application/json")
+            .cookie(cookie.clone())
+            .send_body(r#"{"reason": "Abuse"}"#)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let message = r#"{"title": "Hello", "content": {"text": {"text": "Abuse"}}}"#;
+        let res = signed_request(
+            Method::POST,
+            &format!("/fed/users/{}", admin),
+            "one.example",
+            Some("mallory"),
+            message,
+        )
+        .await
+        .send_body(message)
+        .await
+        .unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        let suspensions: Vec<Ban> =
+            get_all_pages(&client, &format!("{}/internal/suspensions", *ADDR), &cookie).await;
+        assert!(suspensions.contains(&suspension));
+
+        let res = client
+            .delete(&suspension_url)
+            .cookie(cookie.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = user_client
+            .post(format!("{}/internal/login", *ADDR))
+            .header(CONTENT_TYPE, "application/json")
+            .send_body(login)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let log: Vec<ModerationLogEntry> =
+            get_all_pages(&client, &format!("{}/internal/modlog", *ADDR), &cookie).await;
+        let mut actions = log
+            .iter()
+            .filter(|e| e.user.username == user)
+            .map(|e| e.action.as_str())
+            .collect::<Vec<_>>();
+        actions.sort();
+        assert_eq!(actions, vec!["suspend", "unsuspend"]);
+    }
+}
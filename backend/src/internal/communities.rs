@@ -2,15 +2,14 @@ use {
     crate::{
         models::{
             database,
-            internal::{Community, NewCommunity, UserId},
+            internal::{Community, HostQuery, NewCommunity, UserId},
         },
-        util::{is_moderator, user_exists},
+        pagination::Pagination,
+        util::{ensure_federates, is_known_remote, is_moderator, user_exists},
         AppData, Error,
     },
     actix_identity::Identity,
     actix_web::{delete, get, post, web, HttpResponse, Responder, Result},
-    futures::future::join_all,
-    log::error,
 };
 
 /// Create new community
@@ -30,14 +29,15 @@ pub(crate) async fn create_community(
 
     let c = Community {
         id: body.id,
-        host: crate::host!(),
+        host: data.host.clone(),
         title: body.title,
         description: body.description,
         moderators: vec![UserId {
             username: username.clone(),
-            host: crate::host!(),
+            host: data.host.clone(),
         }],
         created: chrono::Local::now().timestamp(),
+        last_synced: None,
     };
 
     // insert community into database
@@ -59,7 +59,7 @@ pub(crate) async fn create_community(
             INSERT INTO moderators VALUES ($1, $2, $3)
         "#,
         username,
-        crate::host!(),
+        data.host,
         c.id,
     )
     .execute(&data.pool)
@@ -71,7 +71,7 @@ pub(crate) async fn create_community(
             INSERT INTO subscriptions VALUES ($1, $2, $3)
         "#,
         username,
-        crate::host!(),
+        data.host,
         c.id,
     )
     .execute(&data.pool)
@@ -80,29 +80,65 @@ pub(crate) async fn create_community(
     Ok(HttpResponse::Ok().json(c))
 }
 
-/// Gets all communities
+/// Gets a page of local communities and remote communities cached by the background sync,
+/// ordered by host and ID
 #[get("/internal/communities")]
-pub(crate) async fn get_communities(data: web::Data<AppData>) -> Result<impl Responder, Error> {
-    let mut communities: Vec<Community> = sqlx::query_as!(
-        database::Community,
+pub(crate) async fn get_communities(
+    data: web::Data<AppData>,
+    pagination: Pagination,
+) -> Result<impl Responder, Error> {
+    let after = pagination.after::<(String, String)>()?;
+    let (after_host, after_id) = match after {
+        Some((host, id)) => (Some(host), Some(id)),
+        None => (None, None),
+    };
+
+    let mut communities = sqlx::query!(
         r#"
-            SELECT * FROM communities
+            SELECT * FROM (
+                SELECT
+                    id,
+                    $1::VARCHAR AS host,
+                    title,
+                    description,
+                    NULL::JSONB AS moderators,
+                    created,
+                    NULL::BIGINT AS last_synced
+                FROM communities
+                UNION ALL
+                SELECT id, host, title, description, moderators, 0, last_synced
+                FROM remote_communities
+            ) AS listing
+            WHERE ($2::VARCHAR IS NULL OR (host, id) > ($2, $3))
+            ORDER BY host, id
+            LIMIT $4
         "#,
+        data.host,
+        after_host,
+        after_id,
+        pagination.fetch_limit()
     )
     .fetch_all(&data.pool)
     .await?
     .into_iter()
-    .map(|r| Community {
-        id: r.id,
-        host: crate::host!(),
-        title: r.title,
-        description: r.description,
-        moderators: vec![],
-        created: r.created,
+    .map(|r| {
+        Ok(Community {
+            id: r.id.unwrap_or_default(),
+            host: r.host.unwrap_or_default(),
+            title: r.title.unwrap_or_default(),
+            description: r.description.unwrap_or_default(),
+            // local moderators are fetched below, remote ones are cached with the community
+            moderators: match r.moderators {
+                Some(moderators) => serde_json::from_value(moderators)?,
+                None => vec![],
+            },
+            created: r.created.unwrap_or_default(),
+            last_synced: r.last_synced,
+        })
     })
-    .collect();
+    .collect::<Result<Vec<_>, Error>>()?;
 
-    for community in &mut communities {
+    for community in communities.iter_mut().filter(|c| c.last_synced.is_none()) {
         community.moderators = sqlx::query!(
             r#"
                 SELECT username, host FROM moderators
@@ -120,71 +156,8 @@ pub(crate) async fn get_communities(data: web::Data<AppData>) -> Result<impl Res
         .collect();
     }
 
-    // get remote communities
-    let mut remote_communities = {
-        let remotes: Vec<String> = sqlx::query!(
-            r#"
-                SELECT host FROM remotes
-            "#,
-        )
-        .fetch_all(&data.pool)
-        .await?
-        .into_iter()
-        .map(|r| r.host)
-        .collect();
-
-        join_all(remotes.into_iter().map(|remote| {
-            let privkey = data.privkey.clone();
-            let client = crate::Client::new(&privkey);
-
-            Box::pin(async move {
-                match client.get_communities(&remote).await {
-                    Ok(ids) => {
-                        join_all(ids.into_iter().map(|community_id| {
-
-                            let remote = remote.clone();
-                            let client =  crate::Client::new(&privkey);
-
-                            Box::pin(async move {
-                                match client.get_community(&remote, &community_id).await {
-                                    Ok(c) => {
-                                        Some(Community {
-                                            id: c.id.clone(),
-                                            host: remote.clone(),
-                                            title: c.title,
-                                            description: c.description,
-                                            moderators: c.admins.into_iter().map(|u| UserId::from(u)).collect(),
-                                            created: 0,
-                                        })
-                                    },
-                                    Err(e) => {
-                                        error!(
-                                            "Error returned while fetching community \"{}\" from {}: {}",
-                                            community_id, &remote, e
-                                        );
-                                        None
-                                    }
-                                }
-                            })
-                        }))
-                        .await
-                    }
-                    Err(e) => {
-                        error!(
-                            "Error returned while fetching community IDs from {}: {}",
-                            &remote, e
-                        );
-                        vec![]
-                    }
-                }
-            })
-        }))
-        .await.into_iter().flatten().filter_map(|x| x).collect()
-    };
-    communities.append(&mut remote_communities);
-
-    // Return a successful response containing the IDs in JSON
-    Ok(HttpResponse::Ok().json(communities))
+    // Return a successful response containing the page of communities in JSON
+    Ok(HttpResponse::Ok().json(pagination.page(communities, |c| (c.host.clone(), c.id.clone()))?))
 }
 
 /// Gets a community by ID
@@ -207,11 +180,12 @@ pub(crate) async fn get_community_by_id(
 
     let mut community = Community {
         id: row.id.clone(),
-        host: crate::host!(),
+        host: data.host.clone(),
         title: row.title,
         description: row.description,
         moderators: vec![],
         created: row.created,
+        last_synced: None,
     };
 
     // fetch moderators
@@ -251,7 +225,7 @@ pub(crate) async fn delete_community(
     };
 
     // must be a moderator to delete a community
-    if !is_moderator(username, crate::host!(), &community, &data.pool).await? {
+    if !is_moderator(username, &data.host, &community, &data.pool).await? {
         return Ok(HttpResponse::Unauthorized());
     }
 
@@ -274,6 +248,7 @@ pub(crate) async fn subscribe_community(
     identity: Identity,
     data: web::Data<AppData>,
     web::Path(community): web::Path<String>,
+    web::Query(query): web::Query<HostQuery>,
 ) -> Result<impl Responder, Error> {
     let username = match identity.identity() {
         Some(s) => s,
@@ -283,13 +258,40 @@ pub(crate) async fn subscribe_community(
         }
     };
 
+    if let Some(remote) = query.remote(&data.host) {
+        if !is_known_remote(remote, &data.pool).await? {
+            return Ok(HttpResponse::NotFound());
+        }
+        ensure_federates(remote, &data.pool).await?;
+
+        // ensure that the community exists on the remote
+        crate::Client::new(&data.host, &data.privkey, &data.pool)
+            .get_community(remote, &community)
+            .await?;
+
+        sqlx::query!(
+            r#"
+                INSERT INTO remote_subscriptions VALUES ($1, $2, $3, $4)
+                ON CONFLICT DO NOTHING
+            "#,
+            username,
+            data.host,
+            community,
+            remote
+        )
+        .execute(&data.pool)
+        .await?;
+
+        return Ok(HttpResponse::Ok());
+    }
+
     sqlx::query!(
         r#"
             INSERT INTO subscriptions VALUES ($1, $2, $3)
             ON CONFLICT DO NOTHING
         "#,
         username,
-        crate::host!(),
+        data.host,
         community
     )
     .execute(&data.pool)
@@ -304,6 +306,7 @@ pub(crate) async fn unsubscribe_community(
     identity: Identity,
     data: web::Data<AppData>,
     web::Path(community): web::Path<String>,
+    web::Query(query): web::Query<HostQuery>,
 ) -> Result<impl Responder, Error> {
     let username = match identity.identity() {
         Some(s) => s,
@@ -313,6 +316,26 @@ pub(crate) async fn unsubscribe_community(
         }
     };
 
+    if let Some(remote) = query.remote(&data.host) {
+        sqlx::query!(
+            r#"
+                DELETE FROM remote_subscriptions
+                WHERE username = $1
+                AND host = $2
+                AND community = $3
+                AND community_host = $4
+            "#,
+            username,
+            data.host,
+            community,
+            remote
+        )
+        .execute(&data.pool)
+        .await?;
+
+        return Ok(HttpResponse::Ok());
+    }
+
     sqlx::query!(
         r#"
             DELETE FROM subscriptions
@@ -321,7 +344,7 @@ pub(crate) async fn unsubscribe_community(
             AND community = $3
         "#,
         username,
-        crate::host!(),
+        data.host,
         community
     )
     .execute(&data.pool)
@@ -330,32 +353,38 @@ pub(crate) async fn unsubscribe_community(
     Ok(HttpResponse::Ok())
 }
 
-/// Fuzzy string search by community title and description
+/// Fuzzy string search by community title and description, paginated by ID
 #[get("/internal/communities/search/{search}")]
 pub(crate) async fn search_communities(
     data: web::Data<AppData>,
     web::Path(search): web::Path<String>,
+    pagination: Pagination,
 ) -> Result<impl Responder, Error> {
     // Execute query
     let mut communities: Vec<Community> = sqlx::query_as!(
         database::Community,
         r#"
             SELECT * FROM communities
-            WHERE title % $1
-            OR description % $1
+            WHERE (title % $1 OR description % $1)
+            AND ($2::VARCHAR IS NULL OR id > $2)
+            ORDER BY id
+            LIMIT $3
         "#,
-        search
+        search,
+        pagination.after::<String>()?,
+        pagination.fetch_limit()
     )
     .fetch_all(&data.pool)
     .await?
     .into_iter()
     .map(|r| Community {
         id: r.id,
-        host: crate::host!(),
+        host: data.host.clone(),
         title: r.title,
         description: r.description,
         moderators: vec![],
         created: r.created,
+        last_synced: None,
     })
     .collect();
 
@@ -377,7 +406,7 @@ pub(crate) async fn search_communities(
         .collect();
     }
 
-    Ok(HttpResponse::Ok().json(communities))
+    Ok(HttpResponse::Ok().json(pagination.page(communities, |c| c.id.clone())?))
 }
 
 /// Adds a moderator to a community
@@ -397,12 +426,12 @@ pub(crate) async fn add_community_moderator(
     };
 
     // requesting user must be a moderator to add new moderator
-    if !is_moderator(requesting_user, crate::host!(), &community, &data.pool).await? {
+    if !is_moderator(requesting_user, &data.host, &community, &data.pool).await? {
         return Ok(HttpResponse::Unauthorized());
     }
 
     // target user must exist
-    if !user_exists(&user, crate::host!(), &data.pool).await? {
+    if !user_exists(&user, &data.host, &data.pool).await? {
         return Ok(HttpResponse::NotFound());
     }
 
@@ -413,7 +442,7 @@ pub(crate) async fn add_community_moderator(
             ON CONFLICT DO NOTHING
         "#,
         user,
-        crate::host!(),
+        data.host,
         community
     )
     .execute(&data.pool)
@@ -439,12 +468,12 @@ pub(crate) async fn remove_community_moderator(
     };
 
     // requesting user must be a moderator to remove a moderator
-    if !is_moderator(requesting_user, crate::host!(), &community, &data.pool).await? {
+    if !is_moderator(requesting_user, &data.host, &community, &data.pool).await? {
         return Ok(HttpResponse::Unauthorized());
     }
 
     // target user must be a moderator
-    if !is_moderator(&user, crate::host!(), &community, &data.pool).await? {
+    if !is_moderator(&user, &data.host, &community, &data.pool).await? {
         return Ok(HttpResponse::NotFound());
     }
 
@@ -457,7 +486,7 @@ pub(crate) async fn remove_community_moderator(
             AND community = $3
         "#,
         user,
-        crate::host!(),
+        data.host,
         community
     )
     .execute(&data.pool)
@@ -470,8 +499,10 @@ pub(crate) async fn remove_community_moderator(
 mod test {
     use {
         crate::{
-            models::internal::{Community, User, UserId},
-            test::{new_user_login, ADDR},
+            models::internal::{Community, Post, User, UserId},
+            pagination::Page,
+            test::{add_remote, new_user_login, ADDR, FQDN},
+            Config,
         },
         actix_web::{
             client::Client,
@@ -494,7 +525,7 @@ mod test {
         let (client, username, cookie) = new_user_login().await;
         let user_ids = vec![UserId {
             username: username,
-            host: crate::host!(),
+            host: FQDN.clone(),
         }];
 
         // Create community successfully
@@ -724,11 +755,11 @@ mod test {
         assert_eq!(moderators.len(), 2);
         assert!(moderators.contains(&UserId {
             username: moderator.clone(),
-            host: crate::host!()
+            host: FQDN.clone()
         }));
         assert!(moderators.contains(&UserId {
             username: user.clone(),
-            host: crate::host!()
+            host: FQDN.clone()
         }));
     }
 
@@ -778,11 +809,11 @@ mod test {
         assert_eq!(moderators.len(), 2);
         assert!(moderators.contains(&UserId {
             username: moderator.clone(),
-            host: crate::host!()
+            host: FQDN.clone()
         }));
         assert!(moderators.contains(&UserId {
             username: moderator2.clone(),
-            host: crate::host!()
+            host: FQDN.clone()
         }));
 
         // add moderator as moderator
@@ -808,11 +839,11 @@ mod test {
         assert_eq!(moderators.len(), 2);
         assert!(moderators.contains(&UserId {
             username: moderator.clone(),
-            host: crate::host!()
+            host: FQDN.clone()
         }));
         assert!(moderators.contains(&UserId {
             username: moderator2.clone(),
-            host: crate::host!()
+            host: FQDN.clone()
         }));
     }
 
@@ -1037,11 +1068,11 @@ mod test {
         assert_eq!(moderators.len(), 2);
         assert!(moderators.contains(&UserId {
             username: moderator.clone(),
-            host: crate::host!()
+            host: FQDN.clone()
         }));
         assert!(moderators.contains(&UserId {
             username: moderator2.clone(),
-            host: crate::host!()
+            host: FQDN.clone()
         }));
 
         // add moderator as moderator
@@ -1070,11 +1101,11 @@ mod test {
         assert_eq!(moderators.len(), 2);
         assert!(moderators.contains(&UserId {
             username: moderator.clone(),
-            host: crate::host!()
+            host: FQDN.clone()
         }));
         assert!(moderators.contains(&UserId {
             username: moderator2.clone(),
-            host: crate::host!()
+            host: FQDN.clone()
         }));
 
         // moderator removes moderator2
@@ -1203,4 +1234,129 @@ mod test {
             .unwrap();
         assert_eq!(res.status(), StatusCode::NOT_FOUND);
     }
+
+    #[actix_rt::test]
+    async fn subscribe_remote_success() {
+        let (client, username, cookie) = new_user_login().await;
+
+        // the backend federates with itself through its listening address, which differs from its
+        // FQDN and is therefore treated as a remote
+        let remote = ADDR.trim_start_matches("http://");
+        add_remote(remote.split(':').next().unwrap()).await;
+        add_remote(&envy::from_env::<Config>().unwrap().fqdn).await;
+
+        let res = client
+            .post(&format!("{}/internal/communities", *ADDR))
+            .cookie(cookie.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .send_body(
+                r#"
+                    {
+                        "id": "remote_subscribed",
+                        "title": "Remote community",
+                        "description": "Subscribed to over federation"
+                    }
+                "#,
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let mut res = client
+            .post(&format!("{}/internal/posts", *ADDR))
+            .cookie(cookie.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .send_body(
+                r#"
+                    {
+                        "community": "remote_subscribed",
+                        "parentPost": null,
+                        "title": "Subscribed post",
+                        "content": [{"text": {"text": "Seen through a remote subscription"}}]
+                    }
+                "#,
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let post: Post = res.json().await.unwrap();
+
+        // Subscribe to it through the remote
+        let res = client
+            .post(&format!(
+                "{}/internal/communities/remote_subscribed/subscribe?host={}",
+                *ADDR, remote
+            ))
+            .cookie(cookie.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // User entity should list the remote subscription
+        let mut res = client
+            .get(&format!("{}/internal/users/{}", *ADDR, username))
+            .cookie(cookie.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let user: User = res.json().await.unwrap();
+        assert_eq!(
+            user.subscribed,
+            vec![format!("remote_subscribed@{}", remote)]
+        );
+
+        // Bulk posts should include posts fetched from the remote
+        let mut res = client
+            .get(&format!("{}/internal/posts", *ADDR))
+            .cookie(cookie.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let posts: Page<Post> = res.json().await.unwrap();
+        assert!(posts
+            .items
+            .iter()
+            .any(|p| p.id == post.id && p.host == remote));
+
+        // Unsubscribe from it
+        let res = client
+            .delete(&format!(
+                "{}/internal/communities/remote_subscribed/subscribe?host={}",
+                *ADDR, remote
+            ))
+            .cookie(cookie.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let mut res = client
+            .get(&format!("{}/internal/users/{}", *ADDR, username))
+            .cookie(cookie.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let user: User = res.json().await.unwrap();
+        assert!(user.subscribed.is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn subscribe_unknown_remote_fail() {
+        let (client, _, cookie) = new_user_login().await;
+
+        let res = client
+            .post(&format!(
+                "{}/internal/communities/anything/subscribe?host=unknown.example",
+                *ADDR
+            ))
+            .cookie(cookie.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
 }
@@ -0,0 +1,436 @@
+use {
+    crate::{
+        fed::{
+            health,
+            outbox::{DEAD, PENDING},
+        },
+        models::internal::{FederationPolicy, NewFederationPolicy},
+        util::{is_admin, normalise_host},
+        AppData, Error,
+    },
+    actix_identity::Identity,
+    actix_web::{delete, get, put, web, HttpResponse, Responder, Result},
+    anyhow::anyhow,
+};
+
+/// Modes a federation policy may have
+const MODES: [&str; 3] = ["block", "silence", "allow"];
+
+/// Lists all federation policies
+#[get("/internal/federation/policies")]
+pub(crate) async fn get_federation_policies(
+    data: web::Data<AppData>,
+    identity: Identity,
+) -> Result<impl Responder, Error> {
+    // exit early if requesting user is not authorised
+    let requesting_user = match identity.identity() {
+        Some(s) => s,
+        None => return Ok(HttpResponse::Unauthorized().finish()),
+    };
+
+    // check that requesting user is an admin
+    if !is_admin(&data.pool, requesting_user, &data.host).await? {
+        return Ok(HttpResponse::Unauthorized().finish());
+    }
+
+    let policies = sqlx::query_as!(
+        FederationPolicy,
+        r#"
+            SELECT * FROM federation_policies
+            ORDER BY created
+        "#,
+    )
+    .fetch_all(&data.pool)
+    .await?;
+
+    Ok(HttpResponse::Ok().json(policies))
+}
+
+/// Creates or replaces the federation policy towards a host
+///
+/// Blocking a host purges everything cached from it and gives up on pending deliveries to it,
+/// silencing a host purges its cached communities.
+#[put("/internal/federation/policies/{host}")]
+pub(crate) async fn set_federation_policy(
+    data: web::Data<AppData>,
+    identity: Identity,
+    web::Path(host): web::Path<String>,
+    web::Json(body): web::Json<NewFederationPolicy>,
+) -> Result<impl Responder, Error> {
+    // exit early if requesting user is not authorised
+    let requesting_user = match identity.identity() {
+        Some(s) => s,
+        None => return Ok(HttpResponse::Unauthorized().finish()),
+    };
+
+    // check that requesting user is an admin
+    if !is_admin(&data.pool, requesting_user, &data.host).await? {
+        return Ok(HttpResponse::Unauthorized().finish());
+    }
+
+    if !MODES.contains(&body.mode.as_str()) {
+        return Err(Error::BadRequest(anyhow!(
+            "Mode must be one of {}",
+            MODES.join(", ")
+        )));
+    }
+
+    // policies apply to every port of a host
+    let host = normalise_host(host)?;
+
+    let mut tx = data.pool.begin().await?;
+
+    let policy = sqlx::query_as!(
+        FederationPolicy,
+        r#"
+            INSERT INTO federation_policies VALUES ($1, $2, $3, $4)
+            ON CONFLICT (host) DO UPDATE
+                SET mode = $2, reason = $3
+            RETURNING *
+        "#,
+        host,
+        body.mode,
+        body.reason,
+        chrono::Local::now().timestamp()
+    )
+    .fetch_one(&mut tx)
+    .await?;
+
+    if policy.mode == "block" || policy.mode == "silence" {
+        // removing communities also removes their cached posts
+        sqlx::query!(
+            r#"
+                DELETE FROM remote_communities
+                WHERE split_part(host, ':', 1) = $1
+            "#,
+            host
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+
+    if policy.mode == "block" {
+        sqlx::query!(
+            r#"
+                DELETE FROM remote_users
+                WHERE split_part(host, ':', 1) = $1
+            "#,
+            host
+        )
+        .execute(&mut tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+                DELETE FROM remote_subscriptions
+                WHERE split_part(community_host, ':', 1) = $1
+            "#,
+            host
+        )
+        .execute(&mut tx)
+        .await?;
+
+        sqlx::query!(
+            r#"
+                UPDATE outbox
+                SET status = $1, last_error = $2
+                WHERE split_part(host, ':', 1) = $3
+                AND status = $4
+            "#,
+            DEAD,
+            format!("Federation with \"{}\" is blocked by policy", host),
+            host,
+            PENDING
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().json(policy))
+}
+
+/// Removes the federation policy towards a host
+#[delete("/internal/federation/policies/{host}")]
+pub(crate) async fn remove_federation_policy(
+    data: web::Data<AppData>,
+    identity: Identity,
+    web::Path(host): web::Path<String>,
+) -> Result<impl Responder, Error> {
+    // exit early if requesting user is not authorised
+    let requesting_user = match identity.identity() {
+        Some(s) => s,
+        None => return Ok(HttpResponse::Unauthorized()),
+    };
+
+    // check that requesting user is an admin
+    if !is_admin(&data.pool, requesting_user, &data.host).await? {
+        return Ok(HttpResponse::Unauthorized());
+    }
+
+    // policies apply to every port of a host
+    let removed = sqlx::query!(
+        r#"
+            DELETE FROM federation_policies
+            WHERE host = $1
+        "#,
+        normalise_host(host)?
+    )
+    .execute(&data.pool)
+    .await?
+    .rows_affected();
+
+    if removed == 0 {
+        return Ok(HttpResponse::NotFound());
+    }
+
+    Ok(HttpResponse::Ok())
+}
+
+/// Lists the health of every remote contacted since the server started, as observed by the
+/// federation client
+#[get("/internal/federation/health")]
+pub(crate) async fn get_remote_health(
+    data: web::Data<AppData>,
+    identity: Identity,
+) -> Result<impl Responder, Error> {
+    // exit early if requesting user is not authorised
+    let requesting_user = match identity.identity() {
+        Some(s) => s,
+        None => return Ok(HttpResponse::Unauthorized().finish()),
+    };
+
+    // check that requesting user is an admin
+    if !is_admin(&data.pool, requesting_user, &data.host).await? {
+        return Ok(HttpResponse::Unauthorized().finish());
+    }
+
+    Ok(HttpResponse::Ok().json(health::snapshot()))
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        crate::{
+            fed::{
+                client::{Client, Error},
+                health::FAILURE_THRESHOLD,
+            },
+            models::internal::{FederationPolicy, RemoteHealth},
+            test::{
+                add_remote, connect, make_admin, new_user_login, signed_request, stand_in, ADDR,
+                FQDN,
+            },
+            util::federated_remotes,
+        },
+        actix_web::{
+            http::{header::CONTENT_TYPE, Method, StatusCode},
+            web, HttpResponse,
+        },
+    };
+
+    #[actix_rt::test]
+    async fn block_host_success() {
+        let (pool, _) = connect().await;
+        let (client, username, cookie) = new_user_login().await;
+        make_admin(&username, &*FQDN).await;
+
+        sqlx::query!(
+            r#"
+                INSERT INTO users VALUES ('someone', 'blocked.example:8080')
+                ON CONFLICT DO NOTHING
+            "#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        // requests from the host are accepted and it is advertised before it is blocked
+        let res = signed_request(Method::GET, "/fed/communities", "blocked.example", None, "")
+            .await
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let mut res = signed_request(Method::GET, "/fed/discover", "example.org", None, "")
+            .await
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let hosts: Vec<String> = res.json().await.unwrap();
+        assert!(hosts.contains(&"blocked.example:8080".to_owned()));
+
+        let mut res = client
+            .put(&format!(
+                "{}/internal/federation/policies/Blocked.example:8080",
+                *ADDR
+            ))
+            .cookie(cookie.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .send_body(r#"{"mode": "block", "reason": "Spam"}"#)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let policy: FederationPolicy = res.json().await.unwrap();
+        assert_eq!(policy.host, "blocked.example");
+
+        let mut res = client
+            .get(&format!("{}/internal/federation/policies", *ADDR))
+            .cookie(cookie.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let policies: Vec<FederationPolicy> = res.json().await.unwrap();
+        assert!(policies.contains(&policy));
+
+        let res = signed_request(Method::GET, "/fed/communities", "blocked.example", None, "")
+            .await
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        // blocked hosts are not advertised
+        let mut res = signed_request(Method::GET, "/fed/discover", "example.org", None, "")
+            .await
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let hosts: Vec<String> = res.json().await.unwrap();
+        assert!(!hosts.contains(&"blocked.example:8080".to_owned()));
+
+        // nor synchronised with, whatever port they are known by
+        add_remote("Blocked.example:8080").await;
+        let remotes = federated_remotes(&pool).await.unwrap();
+        assert!(!remotes.contains(&"Blocked.example:8080".to_owned()));
+
+        // removed by the same host it was set for, whatever the case and port
+        let res = client
+            .delete(&format!(
+                "{}/internal/federation/policies/BLOCKED.example:8080",
+                *ADDR
+            ))
+            .cookie(cookie.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = signed_request(Method::GET, "/fed/communities", "blocked.example", None, "")
+            .await
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn invalid_mode_fail() {
+        let (client, username, cookie) = new_user_login().await;
+        make_admin(&username, &*FQDN).await;
+
+        let res = client
+            .put(&format!(
+                "{}/internal/federation/policies/invalid.example",
+                *ADDR
+            ))
+            .cookie(cookie.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .send_body(r#"{"mode": "ignore", "reason": ""}"#)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        let res = client
+            .delete(&format!(
+                "{}/internal/federation/policies/invalid.example",
+                *ADDR
+            ))
+            .cookie(cookie.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn non_admin_fail() {
+        let (client, _, cookie) = new_user_login().await;
+
+        let res = client
+            .put(&format!(
+                "{}/internal/federation/policies/nonadmin.example",
+                *ADDR
+            ))
+            .cookie(cookie.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .send_body(r#"{"mode": "block", "reason": ""}"#)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_rt::test]
+    async fn circuit_open_success() {
+        let (pool, privkey) = connect().await;
+        let (client, username, cookie) = new_user_login().await;
+        make_admin(&username, &*FQDN).await;
+
+        // stand-in remote failing every federation request
+        let addr = stand_in(
+            "127.0.0.1",
+            |cfg: &mut web::ServiceConfig| {
+                cfg.route(
+                    "/fed/communities",
+                    web::get().to(|| async { HttpResponse::InternalServerError().finish() }),
+                );
+            },
+            None,
+        );
+        let host = addr.to_string();
+
+        let fed_client = Client::new(&*FQDN, &privkey, &pool);
+        for _ in 0..FAILURE_THRESHOLD {
+            match fed_client.get_communities(&host).await {
+                Err(Error::ResponseStatus(status)) => {
+                    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR)
+                }
+                r => panic!("unexpected result {:?}", r),
+            }
+        }
+
+        // further requests are refused without contacting the remote
+        match fed_client.get_communities(&host).await {
+            Err(Error::CircuitOpen(h)) => assert_eq!(h, host),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        let mut res = client
+            .get(&format!("{}/internal/federation/health", *ADDR))
+            .cookie(cookie.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let remotes: Vec<RemoteHealth> = res.json().await.unwrap();
+        let remote = remotes.into_iter().find(|r| r.host == host).unwrap();
+        assert_eq!(remote.circuit, "open");
+        assert_eq!(remote.failures, u64::from(FAILURE_THRESHOLD));
+        assert_eq!(remote.consecutive_failures, FAILURE_THRESHOLD);
+
+        // only admins may see remote health
+        let (client, _, cookie) = new_user_login().await;
+        let res = client
+            .get(&format!("{}/internal/federation/health", *ADDR))
+            .cookie(cookie)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,973 @@
+use {
+    crate::{
+        fed::{client::Client, sync},
+        models::{
+            database,
+            internal::{FeedQuery, FeedSort, Post, UserId},
+        },
+        pagination::{encode_cursor, Page, Pagination},
+        util::{federation_policy, is_known_remote, normalise_host, Policy},
+        AppData, Error,
+    },
+    actix_identity::Identity,
+    actix_web::{get, web, HttpResponse, Responder, Result},
+    anyhow::anyhow,
+    futures::future::join_all,
+    log::error,
+    serde::{Deserialize, Serialize},
+    sqlx::{Pool, Postgres},
+    std::{cmp::Ordering, collections::HashMap, convert::TryFrom},
+    uuid::Uuid,
+};
+
+/// Exponent of the age of a post in hours that its score and replies are divided by in the hot
+/// ranking
+const GRAVITY: f64 = 1.8;
+
+/// Number of the most recent top-level posts of each source that the hot ranking is computed
+/// over, beyond which posts have decayed below all but the quietest recent ones
+const HOT_WINDOW: i64 = 500;
+
+/// Where a top-level post in the feed is stored
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Source {
+    Local,
+    /// Cache of a remote community kept by the background sync
+    Cached,
+}
+
+/// Top-level post considered for the feed, with the activity it is ranked by
+#[derive(Debug, Clone)]
+struct Candidate {
+    id: Uuid,
+    host: String,
+    source: Source,
+    created: i64,
+    /// Sum of the votes on the post
+    score: i64,
+    /// Number of live replies anywhere beneath the post, only counted for the hot ranking
+    replies: i64,
+    /// Time of the latest reply, or of the post itself if there are none
+    active: i64,
+}
+
+/// Position in the feed after which the next page starts
+#[derive(Debug, Serialize, Deserialize)]
+struct Cursor {
+    sort: FeedSort,
+    /// Time the first page was ranked at, so that time-decayed ranks do not change between pages
+    now: i64,
+    rank: f64,
+    created: i64,
+    host: String,
+    id: Uuid,
+}
+
+/// Ranks a post for the supplied sort mode, higher ranks coming first
+fn rank(sort: FeedSort, candidate: &Candidate, now: i64) -> f64 {
+    match sort {
+        FeedSort::New => candidate.created as f64,
+        FeedSort::Active => candidate.active as f64,
+        FeedSort::Top => candidate.score as f64,
+        FeedSort::Hot => {
+            // posts voted below zero overall rank last, ordered among themselves by age
+            let points = (candidate.score + candidate.replies + 1).max(0) as f64;
+            let hours = (now - candidate.created).max(0) as f64 / 3600.0;
+            points / (hours + 2.0).powf(GRAVITY)
+        }
+    }
+}
+
+/// Position of a post in the feed for the supplied sort mode
+fn key(sort: FeedSort, candidate: &Candidate, now: i64) -> (f64, i64, Uuid, &str) {
+    (
+        rank(sort, candidate, now),
+        candidate.created,
+        candidate.id,
+        candidate.host.as_str(),
+    )
+}
+
+/// Orders posts by rank, then by creation time, ID and host so that every post has a distinct
+/// position that a cursor can refer to, the first post being the greatest
+fn compare(a: (f64, i64, Uuid, &str), b: (f64, i64, Uuid, &str)) -> Ordering {
+    a.0.partial_cmp(&b.0)
+        .unwrap_or(Ordering::Equal)
+        .then(a.1.cmp(&b.1))
+        .then(a.2.cmp(&b.2))
+        .then(a.3.cmp(b.3))
+}
+
+/// Sorts the candidates into the order of the feed and returns those of the page following the
+/// cursor, along with the cursor of the next page if there are more posts
+fn paginate(
+    sort: FeedSort,
+    mut candidates: Vec<Candidate>,
+    cursor: Option<Cursor>,
+    now: i64,
+    limit: usize,
+) -> (Vec<Candidate>, Option<Cursor>) {
+    candidates.sort_by(|a, b| compare(key(sort, b, now), key(sort, a, now)));
+
+    let mut page = candidates
+        .into_iter()
+        .filter(|c| match &cursor {
+            Some(cursor) => {
+                compare(
+                    key(sort, c, now),
+                    (cursor.rank, cursor.created, cursor.id, cursor.host.as_str()),
+                ) == Ordering::Less
+            }
+            None => true,
+        })
+        .take(limit + 1)
+        .collect::<Vec<_>>();
+
+    if page.len() <= limit {
+        return (page, None);
+    }
+
+    page.truncate(limit);
+    let next = page.last().map(|last| Cursor {
+        sort,
+        now,
+        rank: rank(sort, last, now),
+        created: last.created,
+        host: last.host.clone(),
+        id: last.id,
+    });
+
+    (page, next)
+}
+
+/// Name of a sort mode that is ranked in the database, as matched by the candidate queries
+fn sort_name(sort: FeedSort) -> &'static str {
+    match sort {
+        FeedSort::New | FeedSort::Hot => "new",
+        FeedSort::Top => "top",
+        FeedSort::Active => "active",
+    }
+}
+
+/// Fetches the top-level local posts after the cursor in the order of the supplied sort,
+/// optionally only those in the supplied communities
+///
+/// Post IDs are not shared between hosts, so the host that orders posts last is left out of the
+/// cursor comparison. Hot candidates are the newest posts, whose replies are counted separately.
+async fn local_candidates(
+    sort: FeedSort,
+    communities: Option<&[String]>,
+    cursor: Option<&Cursor>,
+    limit: i64,
+    host: &str,
+    pool: &Pool<Postgres>,
+) -> Result<Vec<Candidate>, Error> {
+    let rank = cursor.map(|c| c.rank as i64);
+    let created = cursor.map(|c| c.created);
+    let id = cursor.map(|c| c.id);
+
+    // each sort has its own branch so that it is served from its own index, the others being
+    // skipped as their sort does not match
+    let mut candidates = sqlx::query!(
+        r#"
+            SELECT id AS "id!", created AS "created!", score AS "score!", active AS "active!"
+            FROM (
+                (
+                    SELECT id, created, score, COALESCE(last_reply, created) AS active
+                    FROM posts
+                    WHERE $1 = 'new'
+                    AND parent IS NULL
+                    AND deleted IS NULL
+                    AND ($2::TEXT[] IS NULL OR community = ANY($2))
+                    AND ($4::BIGINT IS NULL OR (created, id) < ($4, $5))
+                    ORDER BY created DESC, id DESC
+                    LIMIT $6
+                )
+                UNION ALL
+                (
+                    SELECT id, created, score, COALESCE(last_reply, created) AS active
+                    FROM posts
+                    WHERE $1 = 'top'
+                    AND parent IS NULL
+                    AND deleted IS NULL
+                    AND ($2::TEXT[] IS NULL OR community = ANY($2))
+                    AND ($3::BIGINT IS NULL OR (score, created, id) < ($3, $4, $5))
+                    ORDER BY score DESC, created DESC, id DESC
+                    LIMIT $6
+                )
+                UNION ALL
+                (
+                    SELECT id, created, score, COALESCE(last_reply, created) AS active
+                    FROM posts
+                    WHERE $1 = 'active'
+                    AND parent IS NULL
+                    AND deleted IS NULL
+                    AND ($2::TEXT[] IS NULL OR community = ANY($2))
+                    AND (
+                        $3::BIGINT IS NULL
+                        OR (COALESCE(last_reply, created), created, id) < ($3, $4, $5)
+                    )
+                    ORDER BY COALESCE(last_reply, created) DESC, created DESC, id DESC
+                    LIMIT $6
+                )
+            ) AS candidates
+        "#,
+        sort_name(sort),
+        communities,
+        rank,
+        created,
+        id,
+        limit
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| Candidate {
+        id: row.id,
+        host: host.to_owned(),
+        source: Source::Local,
+        created: row.created,
+        score: row.score,
+        replies: 0,
+        active: row.active,
+    })
+    .collect::<Vec<_>>();
+
+    if sort == FeedSort::Hot {
+        let ids = candidates.iter().map(|c| c.id).collect::<Vec<_>>();
+        let replies = sqlx::query!(
+            r#"
+                WITH RECURSIVE tree AS (
+                    SELECT id AS root, id, deleted FROM posts
+                    WHERE id = ANY($1)
+                    UNION ALL
+                    SELECT tree.root, posts.id, posts.deleted FROM posts
+                    INNER JOIN tree ON posts.parent = tree.id
+                )
+                SELECT
+                    root AS "root!",
+                    COUNT(*) FILTER (WHERE id <> root AND deleted IS NULL) AS "replies!"
+                FROM tree
+                GROUP BY root
+            "#,
+            &ids
+        )
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|row| (row.root, row.replies))
+        .collect::<HashMap<_, _>>();
+
+        for candidate in &mut candidates {
+            candidate.replies = replies.get(&candidate.id).copied().unwrap_or_default();
+        }
+    }
+
+    Ok(candidates)
+}
+
+/// Fetches the cached top-level posts of remote communities after the cursor in the order
+/// of the supplied sort, as `local_candidates` does, optionally only those of the supplied
+/// communities identified by ID and host
+async fn cached_candidates(
+    sort: FeedSort,
+    communities: Option<&[(String, String)]>,
+    cursor: Option<&Cursor>,
+    limit: i64,
+    pool: &Pool<Postgres>,
+) -> Result<Vec<Candidate>, Error> {
+    let (ids, hosts) = match communities {
+        Some(communities) => {
+            let (ids, hosts): (Vec<_>, Vec<_>) = communities.iter().cloned().unzip();
+            (Some(ids), Some(hosts))
+        }
+        None => (None, None),
+    };
+    let rank = cursor.map(|c| c.rank as i64);
+    let created = cursor.map(|c| c.created);
+    let id = cursor.map(|c| c.id);
+
+    let mut candidates = sqlx::query!(
+        r#"
+            SELECT
+                host AS "host!",
+                id AS "id!",
+                created AS "created!",
+                score AS "score!",
+                active AS "active!"
+            FROM (
+                (
+                    SELECT host, id, created, score, COALESCE(last_reply, created) AS active
+                    FROM remote_posts
+                    WHERE $1 = 'new'
+                    AND parent IS NULL
+                    AND deleted IS NULL
+                    AND ($2::TEXT[] IS NULL OR (community, host) IN (
+                        SELECT * FROM UNNEST($2::TEXT[], $3::VARCHAR[])
+                    ))
+                    AND ($5::BIGINT IS NULL OR (created, id) < ($5, $6))
+                    ORDER BY created DESC, id DESC
+                    LIMIT $7
+                )
+                UNION ALL
+                (
+                    SELECT host, id, created, score, COALESCE(last_reply, created) AS active
+                    FROM remote_posts
+                    WHERE $1 = 'top'
+                    AND parent IS NULL
+                    AND deleted IS NULL
+                    AND ($2::TEXT[] IS NULL OR (community, host) IN (
+                        SELECT * FROM UNNEST($2::TEXT[], $3::VARCHAR[])
+                    ))
+                    AND ($4::BIGINT IS NULL OR (score, created, id) < ($4, $5, $6))
+                    ORDER BY score DESC, created DESC, id DESC
+                    LIMIT $7
+                )
+                UNION ALL
+                (
+                    SELECT host, id, created, score, COALESCE(last_reply, created) AS active
+                    FROM remote_posts
+                    WHERE $1 = 'active'
+                    AND parent IS NULL
+                    AND deleted IS NULL
+                    AND ($2::TEXT[] IS NULL OR (community, host) IN (
+                        SELECT * FROM UNNEST($2::TEXT[], $3::VARCHAR[])
+                    ))
+                    AND (
+                        $4::BIGINT IS NULL
+                        OR (COALESCE(last_reply, created), created, id) < ($4, $5, $6)
+                    )
+                    ORDER BY COALESCE(last_reply, created) DESC, created DESC, id DESC
+                    LIMIT $7
+                )
+            ) AS candidates
+        "#,
+        sort_name(sort),
+        ids.as_deref(),
+        hosts.as_deref(),
+        rank,
+        created,
+        id,
+        limit
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| Candidate {
+        id: row.id,
+        host: row.host,
+        source: Source::Cached,
+        created: row.created,
+        score: row.score,
+        replies: 0,
+        active: row.active,
+    })
+    .collect::<Vec<_>>();
+
+    if sort == FeedSort::Hot {
+        let (hosts, ids): (Vec<_>, Vec<_>) =
+            candidates.iter().map(|c| (c.host.clone(), c.id)).unzip();
+        let replies = sqlx::query!(
+            r#"
+                WITH RECURSIVE tree AS (
+                    SELECT host, id AS root, id, deleted FROM remote_posts
+                    WHERE (host, id) IN (SELECT * FROM UNNEST($1::VARCHAR[], $2::UUID[]))
+                    UNION ALL
+                    SELECT tree.host, tree.root, remote_posts.id, remote_posts.deleted
+                    FROM remote_posts
+                    INNER JOIN tree
+                    ON remote_posts.parent = tree.id
+                    AND remote_posts.host = tree.host
+                )
+                SELECT
+                    host AS "host!",
+                    root AS "root!",
+                    COUNT(*) FILTER (WHERE id <> root AND deleted IS NULL) AS "replies!"
+                FROM tree
+                GROUP BY host, root
+            "#,
+            &hosts,
+            &ids
+        )
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|row| ((row.host, row.root), row.replies))
+        .collect::<HashMap<_, _>>();
+
+        for candidate in &mut candidates {
+            candidate.replies = replies
+                .get(&(candidate.host.clone(), candidate.id))
+                .copied()
+                .unwrap_or_default();
+        }
+    }
+
+    Ok(candidates)
+}
+
+/// Fetches the posts of a page of the feed, in the order of the page
+async fn fetch_page(
+    page: &[Candidate],
+    host: &str,
+    pool: &Pool<Postgres>,
+) -> Result<Vec<Post>, Error> {
+    let local = page
+        .iter()
+        .filter(|c| c.source == Source::Local)
+        .map(|c| c.id)
+        .collect::<Vec<_>>();
+    let (cached_hosts, cached_ids): (Vec<_>, Vec<_>) = page
+        .iter()
+        .filter(|c| c.source == Source::Cached)
+        .map(|c| (c.host.clone(), c.id))
+        .unzip();
+
+    let mut posts = HashMap::new();
+
+    for post in sqlx::query_as!(
+        database::Post,
+        r#"
+            SELECT * FROM posts
+            WHERE id = ANY($1)
+        "#,
+        &local
+    )
+    .fetch_all(pool)
+    .await?
+    {
+        posts.insert((host.to_owned(), post.id), Post::try_from((post, host))?);
+    }
+
+    for row in sqlx::query!(
+        r#"
+            SELECT
+                remote_posts.id,
+                remote_posts.host,
+                remote_posts.community,
+                remote_posts.parent,
+                remote_posts.author_username,
+                remote_posts.author_host,
+                remote_posts.title,
+                remote_posts.content,
+                remote_posts.created,
+                remote_posts.modified,
+                remote_posts.score,
+                remote_communities.last_synced
+            FROM remote_posts
+            INNER JOIN remote_communities
+            ON remote_posts.community = remote_communities.id
+            AND remote_posts.host = remote_communities.host
+            WHERE (remote_posts.host, remote_posts.id) IN (
+                SELECT * FROM UNNEST($1::VARCHAR[], $2::UUID[])
+            )
+        "#,
+        &cached_hosts,
+        &cached_ids
+    )
+    .fetch_all(pool)
+    .await?
+    {
+        posts.insert(
+            (row.host.clone(), row.id),
+            Post {
+                id: row.id,
+                host: row.host,
+                community: row.community,
+                parent_post: row.parent,
+                children: vec![],
+                title: row.title,
+                content: serde_json::from_value(row.content)?,
+                author: UserId {
+                    username: row.author_username,
+                    host: row.author_host,
+                },
+                modified: row.modified,
+                created: row.created,
+                last_synced: Some(row.last_synced),
+                deleted: None,
+                score: row.score,
+            },
+        );
+    }
+
+    Ok(page
+        .iter()
+        .filter_map(|c| posts.remove(&(c.host.clone(), c.id)))
+        .collect())
+}
+
+/// Gets a page of top-level posts from the communities the user is subscribed to, or from every
+/// community if they have no subscriptions, merging local and remote posts into one order
+///
+/// A single community may be selected instead, in which case only its posts are shown.
+#[get("/internal/posts")]
+pub(crate) async fn get_bulk_post(
+    identity: Identity,
+    data: web::Data<AppData>,
+    web::Query(query): web::Query<FeedQuery>,
+    pagination: Pagination,
+) -> Result<impl Responder, Error> {
+    let username = match identity.identity() {
+        Some(s) => s,
+        None => return Ok(HttpResponse::Unauthorized().finish()),
+    };
+
+    let cursor = match pagination.after::<Cursor>()? {
+        Some(cursor) if cursor.sort != query.sort => {
+            return Err(Error::BadRequest(anyhow!(
+                "Cursor was issued for a different sort"
+            )));
+        }
+        cursor => cursor,
+    };
+    let now = cursor
+        .as_ref()
+        .map_or_else(|| chrono::Local::now().timestamp(), |c| c.now);
+
+    let (subscriptions, remote_subscriptions) = match query.community {
+        // only hosts added as remotes are fetched from, rather than any host named in the query
+        Some(community) => match query.host.filter(|host| *host != data.host) {
+            Some(host) if is_known_remote(&host, &data.pool).await? => {
+                (vec![], vec![(community, host)])
+            }
+            Some(_) => return Ok(HttpResponse::NotFound().finish()),
+            None => (vec![community], vec![]),
+        },
+        None => (
+            sqlx::query!(
+                r#"
+                    SELECT community FROM subscriptions
+                    WHERE username = $1
+                    AND host = $2
+                "#,
+                username,
+                data.host
+            )
+            .fetch_all(&data.pool)
+            .await?
+            .into_iter()
+            .map(|x| x.community)
+            .collect::<Vec<_>>(),
+            sqlx::query!(
+                r#"
+                    SELECT community, community_host FROM remote_subscriptions
+                    WHERE username = $1
+                    AND host = $2
+                "#,
+                username,
+                data.host
+            )
+            .fetch_all(&data.pool)
+            .await?
+            .into_iter()
+            .map(|x| (x.community, x.community_host))
+            .collect::<Vec<_>>(),
+        ),
+    };
+
+    // hot ranks decay with time, so it is computed over a window of the newest posts while the
+    // other sorts are ranked and paginated by each source's query
+    let (cursor_filter, limit) = match query.sort {
+        FeedSort::Hot => (None, HOT_WINDOW),
+        _ => (cursor.as_ref(), pagination.fetch_limit()),
+    };
+
+    let mut candidates;
+
+    // if user is not subscribed, show content from everywhere
+    if subscriptions.is_empty() && remote_subscriptions.is_empty() {
+        candidates = local_candidates(
+            query.sort,
+            None,
+            cursor_filter,
+            limit,
+            &data.host,
+            &data.pool,
+        )
+        .await?;
+        candidates.append(
+            &mut cached_candidates(query.sort, None, cursor_filter, limit, &data.pool).await?,
+        );
+    } else {
+        candidates = local_candidates(
+            query.sort,
+            Some(&subscriptions),
+            cursor_filter,
+            limit,
+            &data.host,
+            &data.pool,
+        )
+        .await?;
+
+        // only subscriptions keep the port a remote was reached through, so subscribed
+        // communities are matched against the cache by their normalised host
+        let cached = sqlx::query!(
+            r#"
+                SELECT id, host FROM remote_communities
+            "#,
+        )
+        .fetch_all(&data.pool)
+        .await?
+        .into_iter()
+        .map(|row| Ok(((row.id, normalise_host(&row.host)?), row.host)))
+        .collect::<Result<HashMap<_, _>, Error>>()?;
+
+        let mut cached_subscriptions = vec![];
+        let mut uncached_subscriptions = vec![];
+        for (community, community_host) in remote_subscriptions {
+            // hosts left out of the background sync are not synchronised here either
+            if federation_policy(&community_host, &data.pool).await? != Policy::Allowed {
+                continue;
+            }
+
+            match cached.get(&(community.clone(), normalise_host(&community_host)?)) {
+                Some(host) => cached_subscriptions.push((community, host.clone())),
+                None => uncached_subscriptions.push((community, community_host)),
+            }
+        }
+
+        // Synchronise subscribed remote communities that are not cached yet, so that every page
+        // is served from the cache rather than from the remotes
+        if !uncached_subscriptions.is_empty() {
+            let client = Client::new(&data.host, &data.privkey, &data.pool);
+            join_all(
+                uncached_subscriptions
+                    .iter()
+                    .map(|(community, community_host)| {
+                        let client = &client;
+                        let pool = &data.pool;
+                        async move {
+                            if let Err(e) =
+                                sync::sync_community(pool, client, community_host, community).await
+                            {
+                                error!(
+                                    "Error occured while synchronising {} from remote {}: {}",
+                                    community, community_host, e
+                                );
+                            }
+                        }
+                    }),
+            )
+            .await;
+            cached_subscriptions.append(&mut uncached_subscriptions);
+        }
+
+        candidates.append(
+            &mut cached_candidates(
+                query.sort,
+                Some(&cached_subscriptions),
+                cursor_filter,
+                limit,
+                &data.pool,
+            )
+            .await?,
+        );
+    }
+
+    let (page, next) = paginate(
+        query.sort,
+        candidates,
+        cursor,
+        now,
+        pagination.limit() as usize,
+    );
+
+    Ok(HttpResponse::Ok().json(Page {
+        items: fetch_page(&page, &data.host, &data.pool).await?,
+        next_cursor: next.as_ref().map(encode_cursor).transpose()?,
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::{paginate, Candidate, Source},
+        crate::{
+            models::internal::{FeedSort, Post},
+            pagination::Page,
+            test::{connect, new_user_login, ADDR, FQDN},
+        },
+        actix_web::http::{header::CONTENT_TYPE, StatusCode},
+        uuid::Uuid,
+    };
+
+    fn candidate(created: i64, score: i64, replies: i64, active: i64) -> Candidate {
+        Candidate {
+            id: Uuid::new_v4(),
+            host: "example.org".to_owned(),
+            source: Source::Local,
+            created,
+            score,
+            replies,
+            active,
+        }
+    }
+
+    #[test]
+    fn paginate_sort_success() {
+        let now = 100 * 3600;
+        // old but popular, recent and quiet, recently replied to, and recent but downvoted
+        let candidates = vec![
+            candidate(0, 40, 50, 10 * 3600),
+            candidate(99 * 3600, 0, 1, 99 * 3600),
+            candidate(90 * 3600, 5, 2, 100 * 3600),
+            candidate(98 * 3600, -5, 0, 98 * 3600),
+        ];
+        let order = |sort| {
+            paginate(sort, candidates.clone(), None, now, 10)
+                .0
+                .iter()
+                .map(|c| candidates.iter().position(|x| x.id == c.id).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(order(FeedSort::New), vec![1, 3, 2, 0]);
+        assert_eq!(order(FeedSort::Top), vec![0, 2, 1, 3]);
+        assert_eq!(order(FeedSort::Active), vec![2, 1, 3, 0]);
+        assert_eq!(order(FeedSort::Hot), vec![1, 2, 0, 3]);
+    }
+
+    #[test]
+    fn paginate_cursor_success() {
+        // posts with equal ranks are still ordered consistently
+        let candidates = (0..7)
+            .map(|i| candidate(i / 2, 0, 0, 0))
+            .collect::<Vec<_>>();
+
+        let mut seen = vec![];
+        let mut cursor = None;
+        loop {
+            let (page, next) = paginate(FeedSort::New, candidates.clone(), cursor, 0, 3);
+            assert!(page.len() <= 3);
+            seen.extend(page.into_iter().map(|c| c.id));
+
+            match next {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+
+        assert_eq!(seen.len(), candidates.len());
+        for candidate in &candidates {
+            assert!(seen.contains(&candidate.id));
+        }
+    }
+
+    #[actix_rt::test]
+    async fn feed_pages_success() {
+        let (client, _, cookie) = new_user_login().await;
+
+        let res = client
+            .post(format!("{}/internal/communities", *ADDR))
+            .header(CONTENT_TYPE, "application/json")
+            .cookie(cookie.clone())
+            .send_body(r#"{"id": "feed_pages", "title": "Feed", "description": "Paginated"}"#)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let mut created = vec![];
+        for i in 0..5 {
+            let mut res = client
+                .post(format!("{}/internal/posts", *ADDR))
+                .header(CONTENT_TYPE, "application/json")
+                .cookie(cookie.clone())
+                .send_body(format!(
+                    r#"{{"community": "feed_pages", "title": "Post {}", "content": []}}"#,
+                    i
+                ))
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            created.push(res.json::<Post>().await.unwrap().id);
+        }
+
+        // walk the feed two posts at a time in every order
+        for sort in &["new", "top", "active", "hot"] {
+            let mut seen = vec![];
+            let mut url = format!("{}/internal/posts?sort={}&limit=2", *ADDR, sort);
+            loop {
+                let mut res = client
+                    .get(&url)
+                    .cookie(cookie.clone())
+                    .send()
+                    .await
+                    .unwrap();
+                assert_eq!(res.status(), StatusCode::OK);
+                let page: Page<Post> = res.json().await.unwrap();
+                assert!(page.items.len() <= 2);
+                seen.extend(page.items.into_iter().map(|p| p.id));
+
+                match page.next_cursor {
+                    Some(cursor) => {
+                        url = format!(
+                            "{}/internal/posts?sort={}&limit=2&cursor={}",
+                            *ADDR, sort, cursor
+                        )
+                    }
+                    None => break,
+                }
+            }
+
+            // the creator is subscribed to the community, so the feed holds exactly its posts
+            assert_eq!(seen.len(), created.len());
+            for id in &created {
+                assert!(seen.contains(id));
+            }
+        }
+
+        // a single community can be shown to users not subscribed to it
+        let (other, _, other_cookie) = new_user_login().await;
+        let mut res = other
+            .get(format!(
+                "{}/internal/posts?community=feed_pages&limit=10",
+                *ADDR
+            ))
+            .cookie(other_cookie.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let page: Page<Post> = res.json().await.unwrap();
+        assert_eq!(page.items.len(), created.len());
+        assert!(page.items.iter().all(|p| p.community == "feed_pages"));
+
+        // but only from known remotes
+        let res = other
+            .get(format!(
+                "{}/internal/posts?community=feed_pages&host=unknown.example",
+                *ADDR
+            ))
+            .cookie(other_cookie)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        // cursors are tied to their sort
+        let mut res = client
+            .get(format!("{}/internal/posts?sort=new&limit=1", *ADDR))
+            .cookie(cookie.clone())
+            .send()
+            .await
+            .unwrap();
+        let page: Page<Post> = res.json().await.unwrap();
+        let res = client
+            .get(format!(
+                "{}/internal/posts?sort=top&cursor={}",
+                *ADDR,
+                page.next_cursor.unwrap()
+            ))
+            .cookie(cookie)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn feed_cached_port_success() {
+        let (pool, _) = connect().await;
+        let (client, username, cookie) = new_user_login().await;
+        let id = Uuid::new_v4();
+
+        // the cache holds the remote without a port, while the subscription was made through one
+        sqlx::query!(
+            r#"
+                INSERT INTO remote_communities VALUES ('feed_port', 'feedport.example', 'Port', '', '[]', 1)
+            "#
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query!(
+            r#"
+                INSERT INTO remote_posts
+                VALUES ($1, 'feedport.example', 'feed_port', NULL, 'author', 'feedport.example', 'Cached', '[]', 1, 1, NULL)
+            "#,
+            id
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query!(
+            r#"
+                INSERT INTO remote_subscriptions VALUES ($1, $2, 'feed_port', 'FeedPort.example:8080')
+            "#,
+            username,
+            *FQDN
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let mut res = client
+            .get(format!("{}/internal/posts", *ADDR))
+            .cookie(cookie)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let page: Page<Post> = res.json().await.unwrap();
+        assert_eq!(
+            page.items.iter().map(|p| p.id).collect::<Vec<_>>(),
+            vec![id]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn feed_silenced_fail() {
+        let (pool, _) = connect().await;
+        let (client, username, cookie) = new_user_login().await;
+
+        // subscriptions to hosts left out of federation are neither served nor synchronised
+        sqlx::query!(
+            r#"
+                INSERT INTO federation_policies VALUES ('feedsilenced.example', 'silence', 'Spam', 1)
+                ON CONFLICT DO NOTHING
+            "#
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query!(
+            r#"
+                INSERT INTO remote_communities VALUES ('feed_silenced', 'feedsilenced.example', 'Silenced', '', '[]', 1)
+            "#
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query!(
+            r#"
+                INSERT INTO remote_posts
+                VALUES ($1, 'feedsilenced.example', 'feed_silenced', NULL, 'author', 'feedsilenced.example', 'Silenced', '[]', 1, 1, NULL)
+            "#,
+            Uuid::new_v4()
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query!(
+            r#"
+                INSERT INTO remote_subscriptions VALUES ($1, $2, 'feed_silenced', 'feedsilenced.example')
+            "#,
+            username,
+            *FQDN
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let mut res = client
+            .get(format!("{}/internal/posts", *ADDR))
+            .cookie(cookie)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let page: Page<Post> = res.json().await.unwrap();
+        assert!(page.items.is_empty());
+    }
+}
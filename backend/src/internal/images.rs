@@ -80,7 +80,7 @@ pub(crate) async fn add_image(
         id,
         content,
         requesting_user,
-        crate::host!()
+        data.host
     )
     .execute(&data.pool)
     .await?;
@@ -110,7 +110,7 @@ pub(crate) async fn remove_image(
         "#,
         id,
         requesting_user,
-        crate::host!()
+        data.host
     )
     .execute(&data.pool)
     .await?;
@@ -1,10 +1,12 @@
 use {
     crate::{
+        fed::outbox::{self, Delivery},
         models::{
             database, fed,
             internal::{self, UserId},
         },
-        util::user_exists,
+        pagination::Pagination,
+        util::{ensure_federates, user_exists},
         AppData, Error,
     },
     actix_identity::Identity,
@@ -37,7 +39,7 @@ pub(crate) async fn get_unread(
             AND receiver_host = $2
         "#,
         username,
-        crate::host!()
+        data.host
     )
     .fetch_all(&data.pool)
     .await?
@@ -70,7 +72,7 @@ pub(crate) async fn get_all(
             AND receiver_host = $2
         "#,
         username,
-        crate::host!()
+        data.host
     )
     .fetch_all(&data.pool)
     .await?
@@ -87,7 +89,7 @@ pub(crate) async fn get_all(
                 AND sender_host = $2
             "#,
             username,
-            crate::host!()
+            data.host
         )
         .fetch_all(&data.pool)
         .await?
@@ -114,7 +116,7 @@ pub(crate) async fn mark_read(
         None => return Ok(HttpResponse::Unauthorized().into()),
     };
 
-    let sender = UserId::try_from(user_id.as_str())?;
+    let sender = UserId::try_from((user_id.as_str(), data.host.as_str()))?;
 
     sqlx::query!(
         r#"
@@ -128,7 +130,7 @@ pub(crate) async fn mark_read(
         sender.username,
         sender.host,
         username,
-        crate::host!()
+        data.host
     )
     .execute(&data.pool)
     .await?;
@@ -136,12 +138,13 @@ pub(crate) async fn mark_read(
     Ok(HttpResponse::Ok())
 }
 
-/// Get array of message objects belonging to the chat with the supplied user
+/// Get a page of the messages belonging to the chat with the supplied user, oldest first
 #[get("/internal/messages/{user_id}")]
 pub(crate) async fn get_messages_with_user(
     identity: Identity,
     data: web::Data<AppData>,
     web::Path(user_id): web::Path<String>,
+    pagination: Pagination,
 ) -> Result<impl Responder, Error> {
     let username = match identity.identity() {
         Some(s) => s,
@@ -149,14 +152,19 @@ pub(crate) async fn get_messages_with_user(
     };
 
     // parse user_id from path
-    let partner = UserId::try_from(user_id.as_str())?;
+    let partner = UserId::try_from((user_id.as_str(), data.host.as_str()))?;
 
-    let mut messages: Vec<internal::Message> = sqlx::query_as!(
+    let (after_timestamp, after_id) = match pagination.after::<(i64, Uuid)>()? {
+        Some((timestamp, id)) => (Some(timestamp), Some(id)),
+        None => (None, None),
+    };
+
+    let messages: Vec<internal::Message> = sqlx::query_as!(
         database::Message,
         r#"
             SELECT * FROM messages
 
-            WHERE (sender_username = $1
+            WHERE ((sender_username = $1
             AND sender_host = $2
             AND receiver_username = $3
             AND receiver_host = $4)
@@ -164,12 +172,19 @@ pub(crate) async fn get_messages_with_user(
             OR (sender_username = $3
             AND sender_host = $4
             AND receiver_username = $1
-            AND receiver_host = $2)
+            AND receiver_host = $2))
+
+            AND ($5::BIGINT IS NULL OR (timestamp, id) > ($5, $6))
+            ORDER BY timestamp, id
+            LIMIT $7
         "#,
         partner.username,
         partner.host,
         username,
-        crate::host!()
+        data.host,
+        after_timestamp,
+        after_id,
+        pagination.fetch_limit()
     )
     .fetch_all(&data.pool)
     .await?
@@ -177,9 +192,7 @@ pub(crate) async fn get_messages_with_user(
     .map(|m| m.try_into())
     .collect::<Result<_, _>>()?;
 
-    messages.sort_by(|a, b| a.timestamp.partial_cmp(&b.timestamp).unwrap());
-
-    Ok(HttpResponse::Ok().json(messages))
+    Ok(HttpResponse::Ok().json(pagination.page(messages, |m| (m.timestamp, m.id))?))
 }
 
 /// Send a new message to a user
@@ -196,25 +209,29 @@ pub(crate) async fn send_message_to_user(
     };
 
     // parse user_id from path
-    let receiver = UserId::try_from(user_id.as_str())?;
+    let receiver = UserId::try_from((user_id.as_str(), data.host.as_str()))?;
 
-    if receiver.host == crate::host!() {
+    if receiver.host == data.host {
         // message is to local user, check that user exists
         if !user_exists(&receiver.username, &receiver.host, &data.pool).await? {
             return Ok(HttpResponse::NotFound().into());
         }
     } else {
-        // message is to foreign user
-        crate::Client::new(&data.privkey)
-            .send_message(
-                &id,
-                &fed::UserId {
+        // message is to foreign user, queue it for delivery
+        ensure_federates(&receiver.host, &data.pool).await?;
+
+        outbox::enqueue(
+            &data.pool,
+            &Delivery::Message {
+                from: id.clone(),
+                to: fed::UserId {
                     id: receiver.username.clone(),
                     host: receiver.host.clone(),
                 },
-                &body,
-            )
-            .await?;
+                message: body.clone(),
+            },
+        )
+        .await?;
 
         // ensure foreign user is in database
         match sqlx::query!(
@@ -241,7 +258,7 @@ pub(crate) async fn send_message_to_user(
         id: Uuid::new_v4(),
         sender: UserId {
             username: id,
-            host: crate::host!(),
+            host: data.host.clone(),
         },
         receiver,
         title: body.title,
@@ -250,6 +267,8 @@ pub(crate) async fn send_message_to_user(
         read: false,
     };
 
+    let queued = msg.receiver.host != data.host;
+
     match sqlx::query!(
         r#"
             INSERT INTO messages VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
@@ -267,6 +286,8 @@ pub(crate) async fn send_message_to_user(
     .execute(&data.pool)
     .await
     {
+        // messages to remote users are accepted for delivery rather than delivered
+        Ok(_) if queued => Ok(HttpResponse::Accepted().json(msg)),
         Ok(_) => Ok(HttpResponse::Created().json(msg)),
         Err(e) => Err(e.into()),
     }
@@ -277,7 +298,8 @@ mod test {
     use {
         crate::{
             models::internal::UserId,
-            test::{new_user_login, ADDR},
+            pagination::Page,
+            test::{new_user_login, ADDR, FQDN},
         },
         actix_http::http::{header::CONTENT_TYPE, StatusCode},
     };
@@ -314,14 +336,14 @@ mod test {
             res_body.sender,
             UserId {
                 username: sender,
-                host: crate::host!()
+                host: FQDN.clone()
             }
         );
         assert_eq!(
             res_body.receiver,
             UserId {
                 username: receiver,
-                host: crate::host!()
+                host: FQDN.clone()
             }
         );
         assert_eq!(res_body.title, "🐝".to_owned());
@@ -394,7 +416,7 @@ mod test {
             res.json::<Vec<UserId>>().await.unwrap(),
             vec![UserId {
                 username: sender.clone(),
-                host: crate::host!()
+                host: FQDN.clone()
             }]
         );
 
@@ -448,20 +470,24 @@ mod test {
             .await
             .unwrap();
         assert_eq!(res.status(), StatusCode::OK);
-        let res_body: Vec<crate::models::internal::Message> = res.json().await.unwrap();
+        let res_body: Vec<crate::models::internal::Message> = res
+            .json::<Page<crate::models::internal::Message>>()
+            .await
+            .unwrap()
+            .items;
         assert_eq!(res_body.len(), 1);
         assert_eq!(
             res_body[0].sender,
             UserId {
                 username: sender,
-                host: crate::host!()
+                host: FQDN.clone()
             }
         );
         assert_eq!(
             res_body[0].receiver,
             UserId {
                 username: receiver,
-                host: crate::host!()
+                host: FQDN.clone()
             }
         );
         assert_eq!(res_body[0].title, "✨✨✨✨".to_owned());
@@ -1,15 +1,24 @@
 //! Handlers for federated routes
 
 mod admins;
+mod bans;
 mod communities;
+mod federation;
+mod feed;
 mod images;
 mod messages;
+mod outbox;
 mod posts;
 mod remotes;
+mod reports;
+mod revisions;
 mod users;
 pub mod ws;
 
-pub use {admins::*, communities::*, images::*, messages::*, posts::*, remotes::*, users::*};
+pub use {
+    admins::*, bans::*, communities::*, federation::*, feed::*, images::*, messages::*, outbox::*,
+    posts::*, remotes::*, reports::*, revisions::*, users::*,
+};
 
 #[cfg(test)]
 mod test {
@@ -0,0 +1,173 @@
+use {
+    crate::{
+        fed::outbox::{Delivery, PENDING},
+        models::internal::{OutboxFilters, OutboxItem},
+        util::is_admin,
+        AppData, Error,
+    },
+    actix_identity::Identity,
+    actix_web::{get, post, web, HttpResponse, Responder, Result},
+    uuid::Uuid,
+};
+
+/// Lists queued federation deliveries, optionally filtered by status
+#[get("/internal/outbox")]
+pub(crate) async fn get_outbox(
+    data: web::Data<AppData>,
+    identity: Identity,
+    web::Query(filters): web::Query<OutboxFilters>,
+) -> Result<impl Responder, Error> {
+    // exit early if requesting user is not authorised
+    let requesting_user = match identity.identity() {
+        Some(s) => s,
+        None => return Ok(HttpResponse::Unauthorized().finish()),
+    };
+
+    // check that requesting user is an admin
+    if !is_admin(&data.pool, requesting_user, &data.host).await? {
+        return Ok(HttpResponse::Unauthorized().finish());
+    }
+
+    let items = sqlx::query!(
+        r#"
+            SELECT * FROM outbox
+            WHERE ($1::VARCHAR is null OR status = $1)
+            ORDER BY created
+        "#,
+        filters.status
+    )
+    .fetch_all(&data.pool)
+    .await?
+    .into_iter()
+    .map(|row| {
+        Ok(OutboxItem {
+            id: row.id,
+            host: row.host,
+            delivery: serde_json::from_value::<Delivery>(row.delivery)?,
+            status: row.status,
+            attempts: row.attempts,
+            next_attempt: row.next_attempt,
+            last_error: row.last_error,
+            created: row.created,
+        })
+    })
+    .collect::<Result<Vec<_>, Error>>()?;
+
+    Ok(HttpResponse::Ok().json(items))
+}
+
+/// Resets the attempts of a queued delivery and schedules it immediately
+#[post("/internal/outbox/{id}/retry")]
+pub(crate) async fn retry_outbox_item(
+    data: web::Data<AppData>,
+    identity: Identity,
+    web::Path(id): web::Path<Uuid>,
+) -> Result<impl Responder, Error> {
+    // exit early if requesting user is not authorised
+    let requesting_user = match identity.identity() {
+        Some(s) => s,
+        None => return Ok(HttpResponse::Unauthorized()),
+    };
+
+    // check that requesting user is an admin
+    if !is_admin(&data.pool, requesting_user, &data.host).await? {
+        return Ok(HttpResponse::Unauthorized());
+    }
+
+    let updated = sqlx::query!(
+        r#"
+            UPDATE outbox
+            SET status = $1, attempts = 0, next_attempt = $2
+            WHERE id = $3
+        "#,
+        PENDING,
+        chrono::Local::now().timestamp(),
+        id
+    )
+    .execute(&data.pool)
+    .await?
+    .rows_affected();
+
+    if updated == 0 {
+        return Ok(HttpResponse::NotFound());
+    }
+
+    Ok(HttpResponse::Ok())
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        crate::{
+            fed::outbox::PENDING,
+            models::internal::OutboxItem,
+            test::{make_admin, new_user_login, ADDR, FQDN},
+        },
+        actix_web::http::{header::CONTENT_TYPE, StatusCode},
+    };
+
+    #[actix_rt::test]
+    async fn queued_message_listed_and_retried() {
+        let (client, username, cookie) = new_user_login().await;
+        make_admin(&username, &*FQDN).await;
+
+        // messages to remote users are queued rather than sent inline
+        let res = client
+            .post(&format!(
+                "{}/internal/messages/{}",
+                *ADDR, "nobody@unreachable.example"
+            ))
+            .cookie(cookie.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .send_body(r#"{"title": "Queued", "content": {"text": {"text": "Hello"}}}"#)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+
+        let mut res = client
+            .get(&format!("{}/internal/outbox?status={}", *ADDR, PENDING))
+            .cookie(cookie.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let items: Vec<OutboxItem> = res.json().await.unwrap();
+        let item = items
+            .iter()
+            .find(|i| i.host == "unreachable.example")
+            .unwrap();
+
+        let res = client
+            .post(&format!("{}/internal/outbox/{}/retry", *ADDR, item.id))
+            .cookie(cookie.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = client
+            .post(&format!(
+                "{}/internal/outbox/{}/retry",
+                *ADDR,
+                uuid::Uuid::new_v4()
+            ))
+            .cookie(cookie.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn non_admin_fail() {
+        let (client, _, cookie) = new_user_login().await;
+
+        let res = client
+            .get(&format!("{}/internal/outbox", *ADDR))
+            .cookie(cookie.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+}
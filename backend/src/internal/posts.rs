@@ -1,83 +1,42 @@
 use {
     crate::{
-        fed::{client::Client, PostFilters},
+        activitypub,
+        fed::client::Client,
         models::{
             database,
-            fed::PostEdit,
-            internal::{NewPost, Post, UserId},
+            fed::{self, PostEdit, Score, Vote, VOTES},
+            internal::{HostQuery, NewPost, Post, UserId},
+        },
+        pagination::Pagination,
+        tombstones,
+        util::{
+            ensure_federates, fetch_descendants, is_banned, is_banned_from_post, is_known_remote,
+            record_reply, record_revision, record_vote,
         },
         AppData, Error,
     },
     actix_identity::Identity,
     actix_web::{delete, get, post, put, web, HttpResponse, Responder, Result},
-    futures::future::join_all,
+    anyhow::anyhow,
     log::error,
     sqlx::{Pool, Postgres},
-    std::{
-        collections::HashMap,
-        convert::{TryFrom, TryInto},
-    },
+    std::convert::{TryFrom, TryInto},
     uuid::Uuid,
 };
 
-/// Recursively fetches all child posts of the supplied root post
-async fn fetch_children(root: Uuid, executor: &Pool<Postgres>) -> anyhow::Result<Vec<Post>, Error> {
-    let mut map = HashMap::<Uuid, Vec<Post>>::new();
-
-    // Retrieve all posts from the database iteratively
-    {
-        let mut stack = vec![root];
-        while !stack.is_empty() {
-            // safe to unwrap as it is preceded by a check that it is not empty
-            let parent = stack.pop().unwrap();
-
-            let posts: Vec<Post> = sqlx::query_as!(
-                database::Post,
-                r#"
-                    SELECT * FROM posts
-                    WHERE parent = $1
-                "#,
-                parent
-            )
-            .fetch_all(executor)
-            .await?
-            .into_iter()
-            .map(|row| row.try_into())
-            .collect::<Result<_, _>>()?;
-
-            for post in &posts {
-                stack.push(post.id);
-            }
-            map.insert(parent, posts);
-        }
-    }
-
-    // Recursively build post tree
-    let posts = {
-        fn get_children(parent: Uuid, map: &mut HashMap<Uuid, Vec<Post>>) -> Vec<Post> {
-            match map.remove(&parent) {
-                Some(mut posts) => {
-                    for post in &mut posts {
-                        post.children = get_children(post.id, map);
-                    }
-
-                    return posts;
-                }
-                None => return vec![],
-            }
-        }
-
-        let mut posts = vec![];
-
-        posts.append(map.get_mut(&root).unwrap());
-        for post in &mut posts {
-            post.children = get_children(post.id, &mut map);
-        }
-
-        posts
-    };
+/// Fetches all descendants of the supplied root post on the supplied local host, nested by parent
+async fn fetch_children(
+    root: Uuid,
+    host: &str,
+    executor: &Pool<Postgres>,
+) -> anyhow::Result<Vec<Post>, Error> {
+    let posts = fetch_descendants(&[root], executor)
+        .await?
+        .into_iter()
+        .map(|row| (row, host).try_into())
+        .collect::<Result<_, _>>()?;
 
-    Ok(posts)
+    Ok(Post::into_trees(posts))
 }
 
 /// Get post
@@ -87,7 +46,7 @@ pub(crate) async fn get_post(
     web::Path(post_id): web::Path<Uuid>,
 ) -> Result<impl Responder, Error> {
     // fetch post from database
-    let mut post: Post = sqlx::query_as!(
+    let post = sqlx::query_as!(
         database::Post,
         r#"
             SELECT * FROM posts
@@ -96,10 +55,10 @@ pub(crate) async fn get_post(
         post_id
     )
     .fetch_one(&data.pool)
-    .await?
-    .try_into()?;
+    .await?;
+    let mut post = Post::try_from((post, data.host.as_str()))?;
 
-    post.children = match fetch_children(post.id, &data.pool).await {
+    post.children = match fetch_children(post.id, &data.host, &data.pool).await {
         Ok(children) => children,
         Err(e) => {
             error!("Error occured whilst executing query: {}", e);
@@ -111,174 +70,47 @@ pub(crate) async fn get_post(
     Ok(HttpResponse::Ok().json(post))
 }
 
-/// Get bulk posts
-#[get("/internal/posts")]
-pub(crate) async fn get_bulk_post(
+/// Create new post
+#[post("/internal/posts")]
+pub(crate) async fn create_post(
     identity: Identity,
     data: web::Data<AppData>,
+    web::Json(body): web::Json<NewPost>,
 ) -> Result<impl Responder, Error> {
     let username = match identity.identity() {
         Some(s) => s,
         None => return Ok(HttpResponse::Unauthorized().into()),
     };
 
-    let subscriptions = sqlx::query!(
-        r#"
-            SELECT community FROM subscriptions
-            WHERE username = $1
-            AND host = $2
-        "#,
-        username,
-        crate::host!()
-    )
-    .fetch_all(&data.pool)
-    .await?
-    .into_iter()
-    .map(|x| x.community)
-    .collect::<Vec<_>>();
-
-    // if user is not subscribed, show content from everywhere
-    if subscriptions.is_empty() {
-        // Fetch local posts
-        let mut posts: Vec<Post> = sqlx::query_as!(
-            database::Post,
-            r#"
-                SELECT * FROM posts
-                WHERE parent IS NULL
-            "#,
-        )
-        .fetch_all(&data.pool)
-        .await?
-        .into_iter()
-        .map(|x| x.try_into())
-        .collect::<Result<_, _>>()?;
-
-        // Fetch remote posts
-        posts.append(
-            &mut join_all(
-                sqlx::query!(
-                    r#"
-                        SELECT host FROM remotes
-                    "#,
-                )
-                .fetch_all(&data.pool)
-                .await?
-                .into_iter()
-                .map(|remote| {
-                    let client = Client::new(&data.privkey);
-                    let username = username.clone();
-                    Box::pin(async move {
-                        match client
-                            .get_posts(
-                                &remote.host,
-                                PostFilters {
-                                    limit: Some(5),
-                                    ..PostFilters::default()
-                                },
-                                &username,
-                            )
-                            .await
-                        {
-                            Ok(posts) => Some(
-                                posts
-                                    .into_iter()
-                                    .map(|p| Post {
-                                        id: p.id,
-                                        host: remote.host.clone(),
-                                        community: p.community,
-                                        parent_post: p.parent_post,
-                                        children: vec![],
-                                        title: p.title,
-                                        content: p.content,
-                                        author: p.author.into(),
-                                        modified: p.modified,
-                                        created: p.created,
-                                    })
-                                    .collect::<Vec<_>>(),
-                            ),
-                            Err(e) => {
-                                error!(
-                                    "Error occured while fetching posts from remote {}: {}",
-                                    remote.host, e
-                                );
-                                None
-                            }
-                        }
-                    })
-                }),
+    // post to the community's host if it is a remote
+    let query = HostQuery {
+        host: body.host.clone(),
+    };
+    if let Some(host) = query.remote(&data.host) {
+        if !is_known_remote(host, &data.pool).await? {
+            return Ok(HttpResponse::NotFound().finish());
+        }
+        ensure_federates(host, &data.pool).await?;
+
+        let post = Client::new(&data.host, &data.privkey, &data.pool)
+            .create_post(
+                host,
+                &username,
+                &fed::NewPost {
+                    community: body.community,
+                    parent_post: body.parent_post,
+                    title: Some(body.title),
+                    content: body.content,
+                },
             )
-            .await
-            .into_iter()
-            .filter_map(|x| x)
-            .flatten()
-            .collect(),
-        );
+            .await?;
 
-        // Return a successful response containing the IDs in JSON
-        Ok(HttpResponse::Ok().json(posts))
-    } else
-    // if user is subscribed to communities, show only content from those communities
-    {
-        let posts: Vec<Post> = join_all(subscriptions.into_iter().map(|community| {
-            let pool = data.pool.clone();
-            Box::pin(async move {
-                let query = sqlx::query_as!(
-                    database::Post,
-                    r#"
-                            SELECT * FROM posts
-                            WHERE parent IS NULL
-                            AND community = $1
-                        "#,
-                    community
-                )
-                .fetch_all(&pool)
-                .await;
-
-                match query {
-                    Ok(xs) => {
-                        let posts = xs
-                            .into_iter()
-                            .map(|x| Post::try_from(x))
-                            .collect::<Result<Vec<_>, _>>();
-
-                        match posts {
-                            Ok(xs) => {
-                                xs
-                            },
-                            Err(e) => {
-                                error!("Error returned while converting database posts to internal posts: {}", e);
-                                vec![]
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        error!("Error returned while fetching posts database: {}", e);
-                        vec![]
-                    }
-                }
-            })
-        }))
-        .await
-        .into_iter()
-        .flatten()
-        .collect();
-
-        // Return a successful response containing the IDs in JSON
-        Ok(HttpResponse::Ok().json(posts))
+        return Ok(HttpResponse::Ok().json(Post::from_fed(post, host.to_owned())));
     }
-}
 
-/// Create new post
-#[post("/internal/posts")]
-pub(crate) async fn create_post(
-    identity: Identity,
-    data: web::Data<AppData>,
-    web::Json(body): web::Json<NewPost>,
-) -> Result<impl Responder, Error> {
-    let username = match identity.identity() {
-        Some(s) => s,
-        None => return Ok(HttpResponse::Unauthorized().into()),
-    };
+    if is_banned(&username, &data.host, &body.community, &data.pool).await? {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
 
     let now = chrono::Local::now().timestamp();
     let p = database::Post {
@@ -286,11 +118,14 @@ pub(crate) async fn create_post(
         community: body.community,
         parent: body.parent_post,
         author_username: username.clone(),
-        author_host: crate::host!(),
+        author_host: data.host.clone(),
         title: body.title,
         content: serde_json::to_value(&body.content)?,
         created: now,
         modified: now,
+        deleted: None,
+        score: 0,
+        last_reply: None,
     };
 
     sqlx::query!(
@@ -311,7 +146,13 @@ pub(crate) async fn create_post(
     .execute(&data.pool)
     .await?;
 
-    Ok(HttpResponse::Ok().json(Post::try_from(p)?))
+    if let Some(parent) = p.parent {
+        record_reply(parent, p.created, &data.pool).await?;
+    }
+
+    activitypub::publish("Create", p.id, &data.host, &data.pool).await?;
+
+    Ok(HttpResponse::Ok().json(Post::try_from((p, data.host.as_str()))?))
 }
 
 /// Edit a post
@@ -321,12 +162,38 @@ pub(crate) async fn edit_post(
     data: web::Data<AppData>,
     web::Json(body): web::Json<PostEdit>,
     web::Path(id): web::Path<Uuid>,
+    web::Query(query): web::Query<HostQuery>,
 ) -> Result<impl Responder, Error> {
     let username = match identity.identity() {
         Some(s) => s,
         None => return Ok(HttpResponse::Unauthorized().into()),
     };
 
+    // edit the post on its host if it is a remote
+    if let Some(host) = query.remote(&data.host) {
+        if !is_known_remote(host, &data.pool).await? {
+            return Ok(HttpResponse::NotFound().finish());
+        }
+        ensure_federates(host, &data.pool).await?;
+
+        let client = Client::new(&data.host, &data.privkey, &data.pool);
+        client.edit_post(host, id, &username, &body).await?;
+        let post = client.get_post(host, id, &username).await?;
+
+        return Ok(HttpResponse::Ok().json(Post::from_fed(post, host.to_owned())));
+    }
+
+    if is_banned_from_post(&username, &data.host, id, &data.pool).await? {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+
+    let mut tx = data.pool.begin().await?;
+
+    // keep the previous version of the post
+    if !record_revision(id, &username, &data.host, &mut tx).await? {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
     // Execute query
     sqlx::query!(
         r#"
@@ -334,6 +201,7 @@ pub(crate) async fn edit_post(
             SET content = $1, title = $2, modified = $3
             FROM users
             WHERE posts.id = $4
+            AND posts.deleted IS NULL
             AND users.username = $5
             AND users.host = $6
         "#,
@@ -342,13 +210,17 @@ pub(crate) async fn edit_post(
         chrono::Local::now().timestamp(),
         id,
         username,
-        crate::host!()
+        data.host
     )
-    .execute(&data.pool)
+    .execute(&mut tx)
     .await?;
 
+    tx.commit().await?;
+
+    activitypub::publish("Update", id, &data.host, &data.pool).await?;
+
     // fetch post from database
-    let mut post: Post = sqlx::query_as!(
+    let post = sqlx::query_as!(
         database::Post,
         r#"
             SELECT * FROM posts
@@ -357,10 +229,10 @@ pub(crate) async fn edit_post(
         id
     )
     .fetch_one(&data.pool)
-    .await?
-    .try_into()?;
+    .await?;
+    let mut post = Post::try_from((post, data.host.as_str()))?;
 
-    post.children = match fetch_children(post.id, &data.pool).await {
+    post.children = match fetch_children(post.id, &data.host, &data.pool).await {
         Ok(children) => children,
         Err(e) => {
             error!("Error occured whilst executing query: {}", e);
@@ -378,11 +250,12 @@ pub(crate) async fn delete_post(
     identity: Identity,
     data: web::Data<AppData>,
     web::Path(post_id): web::Path<Uuid>,
+    web::Query(query): web::Query<HostQuery>,
 ) -> Result<impl Responder, Error> {
     let user = match identity.identity() {
         Some(s) => UserId {
             username: s,
-            host: crate::host!(),
+            host: data.host.clone(),
         },
         None => {
             // must be logged in to delete a community
@@ -390,11 +263,26 @@ pub(crate) async fn delete_post(
         }
     };
 
+    // delete the post on its host if it is a remote
+    if let Some(host) = query.remote(&data.host) {
+        if !is_known_remote(host, &data.pool).await? {
+            return Ok(HttpResponse::NotFound());
+        }
+        ensure_federates(host, &data.pool).await?;
+
+        Client::new(&data.host, &data.privkey, &data.pool)
+            .delete_post(host, post_id, &user.username)
+            .await?;
+
+        return Ok(HttpResponse::Ok());
+    }
+
     let author = {
         let row = sqlx::query!(
             r#"
                 SELECT author_username, author_host FROM posts
                 WHERE id = $1
+                AND deleted IS NULL
             "#,
             post_id,
         )
@@ -412,41 +300,105 @@ pub(crate) async fn delete_post(
         return Ok(HttpResponse::Unauthorized());
     }
 
-    sqlx::query!(
-        r#"
-            DELETE FROM posts
-            WHERE id = $1
-        "#,
-        post_id
-    )
-    .execute(&data.pool)
-    .await?;
+    activitypub::publish("Delete", post_id, &data.host, &data.pool).await?;
+
+    // replies stay in place beneath the blanked post
+    tombstones::tombstone(post_id, &data.pool).await?;
 
     Ok(HttpResponse::Ok())
 }
 
-/// Fuzzy string search by title and post content
+/// Casts, changes or withdraws the user's vote on a post, responding with its new score
+#[put("/internal/posts/{id}/vote")]
+pub(crate) async fn vote_post(
+    identity: Identity,
+    data: web::Data<AppData>,
+    web::Json(body): web::Json<Vote>,
+    web::Path(id): web::Path<Uuid>,
+    web::Query(query): web::Query<HostQuery>,
+) -> Result<impl Responder, Error> {
+    let username = match identity.identity() {
+        Some(s) => s,
+        None => return Ok(HttpResponse::Unauthorized().finish()),
+    };
+
+    // vote on the post's host if it is a remote, keeping the cached score up to date
+    if let Some(host) = query.remote(&data.host) {
+        if !is_known_remote(host, &data.pool).await? {
+            return Ok(HttpResponse::NotFound().finish());
+        }
+        ensure_federates(host, &data.pool).await?;
+
+        let client = Client::new(&data.host, &data.privkey, &data.pool);
+        if !client.supports(host, VOTES).await? {
+            return Err(Error::BadRequest(anyhow!(
+                "{} does not support votes",
+                host
+            )));
+        }
+        let score = client.vote_post(host, id, &username, body).await?;
+
+        sqlx::query!(
+            r#"
+                UPDATE remote_posts
+                SET score = $1
+                WHERE id = $2
+                AND host = $3
+            "#,
+            score,
+            id,
+            host
+        )
+        .execute(&data.pool)
+        .await?;
+
+        return Ok(HttpResponse::Ok().json(Score { score }));
+    }
+
+    if is_banned_from_post(&username, &data.host, id, &data.pool).await? {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+
+    match record_vote(id, &username, &data.host, body, &data.pool).await? {
+        Some(score) => Ok(HttpResponse::Ok().json(Score { score })),
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
+
+/// Fuzzy string search by title and post content, newest first
 #[get("/internal/posts/search/{search}")]
 pub(crate) async fn search_posts(
     data: web::Data<AppData>,
     web::Path(search): web::Path<String>,
+    pagination: Pagination,
 ) -> Result<impl Responder, Error> {
+    let (after_created, after_id) = match pagination.after::<(i64, Uuid)>()? {
+        Some((created, id)) => (Some(created), Some(id)),
+        None => (None, None),
+    };
+
     let posts: Vec<Post> = sqlx::query_as!(
         database::Post,
         r#"
             SELECT * FROM posts
-            WHERE title % $1
-            OR content::TEXT % $1
+            WHERE (title % $1 OR content::TEXT % $1)
+            AND deleted IS NULL
+            AND ($2::BIGINT IS NULL OR (created, id) < ($2, $3))
+            ORDER BY created DESC, id DESC
+            LIMIT $4
         "#,
-        search
+        search,
+        after_created,
+        after_id,
+        pagination.fetch_limit()
     )
     .fetch_all(&data.pool)
     .await?
     .into_iter()
-    .map(|x| x.try_into())
+    .map(|x| (x, data.host.as_str()).try_into())
     .collect::<Result<_, _>>()?;
 
-    Ok(HttpResponse::Ok().json(posts))
+    Ok(HttpResponse::Ok().json(pagination.page(posts, |p| (p.created, p.id))?))
 }
 
 #[cfg(test)]
@@ -455,9 +407,11 @@ mod test {
         crate::{
             models::{
                 database::{PostContent, TextContent},
+                fed::Score,
                 internal::Post,
             },
-            test::{new_user_login, ADDR},
+            test::{add_remote, new_user_login, ADDR},
+            Config,
         },
         actix_web::http::{header::CONTENT_TYPE, StatusCode},
     };
@@ -563,6 +517,19 @@ mod test {
         let post: Post = res.json().await.unwrap();
         assert_eq!(post.author.username, username);
 
+        let mut res = client
+            .post(&format!("{}/internal/posts", *ADDR))
+            .header(CONTENT_TYPE, "application/json")
+            .cookie(cookie.clone())
+            .send_body(format!(
+                r#"{{"community": "community3", "parentPost": "{}", "title": "", "content": []}}"#,
+                post.id
+            ))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let reply: Post = res.json().await.unwrap();
+
         let res = client
             .delete(&format!("{}/internal/posts/{}", *ADDR, post.id))
             .cookie(cookie.clone())
@@ -570,6 +537,91 @@ mod test {
             .await
             .unwrap();
         assert_eq!(res.status(), StatusCode::OK);
+
+        // the post is blanked but keeps its replies
+        let mut res = client
+            .get(&format!("{}/internal/posts/{}", *ADDR, post.id))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let tombstone: Post = res.json().await.unwrap();
+        assert_eq!(tombstone.title, "");
+        assert!(tombstone.content.is_empty());
+        assert!(tombstone.deleted.is_some());
+        assert_eq!(tombstone.children, vec![reply]);
+
+        // tombstones cannot be deleted again
+        let res = client
+            .delete(&format!("{}/internal/posts/{}", *ADDR, post.id))
+            .cookie(cookie.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn vote_post_success() {
+        let (client, _, cookie) = new_user_login().await;
+        let (other_client, _, other_cookie) = new_user_login().await;
+
+        let res = client
+            .post(&format!("{}/internal/communities", *ADDR))
+            .header(CONTENT_TYPE, "application/json")
+            .cookie(cookie.clone())
+            .send_body(r#"{"id": "votes", "title": "Votes", "description": "Scored posts"}"#)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let mut res = client
+            .post(&format!("{}/internal/posts", *ADDR))
+            .header(CONTENT_TYPE, "application/json")
+            .cookie(cookie.clone())
+            .send_body(r#"{"community": "votes", "title": "Vote on me", "content": []}"#)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let post: Post = res.json().await.unwrap();
+        assert_eq!(post.score, 0);
+
+        let url = format!("{}/internal/posts/{}/vote", *ADDR, post.id);
+        for (client, cookie, body, score) in &[
+            (&client, &cookie, r#"{"value": 1}"#, 1),
+            (&other_client, &other_cookie, r#"{"value": -1}"#, 0),
+            // changing a vote replaces it
+            (&client, &cookie, r#"{"value": -1}"#, -2),
+            // withdrawing a vote removes it
+            (&other_client, &other_cookie, r#"{"value": 0}"#, -1),
+        ] {
+            let mut res = client
+                .put(&url)
+                .header(CONTENT_TYPE, "application/json")
+                .cookie((*cookie).clone())
+                .send_body(*body)
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(res.json::<Score>().await.unwrap().score, *score);
+        }
+
+        let res = client
+            .put(&url)
+            .header(CONTENT_TYPE, "application/json")
+            .cookie(cookie.clone())
+            .send_body(r#"{"value": 2}"#)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        let mut res = client
+            .get(&format!("{}/internal/posts/{}", *ADDR, post.id))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.json::<Post>().await.unwrap().score, -1);
     }
 
     #[actix_rt::test]
@@ -894,4 +946,116 @@ mod test {
             })]
         );
     }
+
+    #[actix_rt::test]
+    async fn remote_create_edit_delete_success() {
+        let (client, _, cookie) = new_user_login().await;
+        let (other_client, _, other_cookie) = new_user_login().await;
+
+        // the backend federates with itself through its listening address, which differs from its
+        // FQDN and is therefore treated as a remote
+        let remote = ADDR.trim_start_matches("http://");
+        add_remote(&envy::from_env::<Config>().unwrap().fqdn).await;
+
+        let res = client
+            .post(format!("{}/internal/communities", *ADDR))
+            .header(CONTENT_TYPE, "application/json")
+            .cookie(cookie.clone())
+            .send_body(
+                r#"
+                    {
+                        "id": "remote_writes",
+                        "title": "Remote writes",
+                        "description": "Written to over federation"
+                    }
+                "#,
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // hosts that were not added as remotes are not written to
+        let res = client
+            .post(&format!("{}/internal/posts", *ADDR))
+            .cookie(cookie.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .send_body(
+                r#"{"community": "remote_writes", "title": "Lost", "content": [], "host": "unknown.example"}"#,
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        add_remote(remote.split(':').next().unwrap()).await;
+
+        // Create post
+        let mut res = client
+            .post(&format!("{}/internal/posts", *ADDR))
+            .cookie(cookie.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .send_body(format!(
+                r#"
+                    {{
+                        "community": "remote_writes",
+                        "parentPost": null,
+                        "title": "Remote post",
+                        "content": [{{"text": {{"text": "Posted remotely"}}}}],
+                        "host": "{}"
+                    }}
+                "#,
+                remote
+            ))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let post: Post = res.json().await.unwrap();
+        assert_eq!(post.host, remote);
+        assert_eq!(post.title, "Remote post");
+
+        // Edit post
+        let mut res = client
+            .put(&format!(
+                "{}/internal/posts/{}?host={}",
+                *ADDR, post.id, remote
+            ))
+            .cookie(cookie.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .send_body(r#"{"title": "Edited remotely", "content": [{"text": {"text": "Edited"}}]}"#)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let edited: Post = res.json().await.unwrap();
+        assert_eq!(edited.id, post.id);
+        assert_eq!(edited.title, "Edited remotely");
+
+        // Other users may not delete the post
+        let res = other_client
+            .delete(&format!(
+                "{}/internal/posts/{}?host={}",
+                *ADDR, post.id, remote
+            ))
+            .cookie(other_cookie.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        // Delete post
+        let res = client
+            .delete(&format!(
+                "{}/internal/posts/{}?host={}",
+                *ADDR, post.id, remote
+            ))
+            .cookie(cookie.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = client
+            .get(&format!("{}/internal/posts/{}", *ADDR, post.id))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
 }
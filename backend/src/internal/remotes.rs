@@ -1,5 +1,8 @@
 use {
-    crate::{util::is_admin, AppData, Error},
+    crate::{
+        fed::client::forget_base_url, models::internal::RemoteOptions, util::is_admin, AppData,
+        Error,
+    },
     actix_identity::Identity,
     actix_web::{delete, get, http::uri::Authority, post, web, HttpResponse, Responder, Result},
 };
@@ -17,7 +20,7 @@ pub(crate) async fn get_remote_servers(
     };
 
     // check that requesting user is an admin
-    if !is_admin(&data.pool, requesting_user, crate::host!()).await? {
+    if !is_admin(&data.pool, requesting_user, &data.host).await? {
         return Ok(HttpResponse::Unauthorized().into());
     }
 
@@ -35,12 +38,13 @@ pub(crate) async fn get_remote_servers(
     Ok(HttpResponse::Ok().json(remotes))
 }
 
-/// Add a remote server
+/// Add a remote server, optionally requiring that it is only reached over HTTPS
 #[post("/internal/remotes/{remote}")]
 pub(crate) async fn add_remote_server(
     data: web::Data<AppData>,
     identity: Identity,
     web::Path(remote): web::Path<String>,
+    web::Query(options): web::Query<RemoteOptions>,
 ) -> Result<impl Responder, Error> {
     // exit early if requesting user is not authorised
     let requesting_user = match identity.identity() {
@@ -49,7 +53,7 @@ pub(crate) async fn add_remote_server(
     };
 
     // check that requesting user is an admin
-    if !is_admin(&data.pool, requesting_user, crate::host!()).await? {
+    if !is_admin(&data.pool, requesting_user, &data.host).await? {
         return Ok(HttpResponse::Unauthorized());
     }
 
@@ -59,21 +63,27 @@ pub(crate) async fn add_remote_server(
         .map_err(|e| Error::BadRequest(e.into()))?;
     let host = authority.host();
 
-    let pubkey = crate::Client::new(&data.privkey).get_key(&remote).await?;
+    let pubkey = crate::Client::new(&data.host, &data.privkey, &data.pool)
+        .get_key(&remote)
+        .await?;
 
     // Execute query
     sqlx::query!(
         r#"
-            INSERT INTO remotes VALUES ($1, $2)
+            INSERT INTO remotes VALUES ($1, $2, $3)
             ON CONFLICT (host) DO UPDATE
-                SET pubkey = $2
+                SET pubkey = $2, allow_http = $3
         "#,
         host,
-        pubkey
+        pubkey,
+        options.allow_http.unwrap_or(true)
     )
     .execute(&data.pool)
     .await?;
 
+    // the base URL may have been resolved under the previous setting
+    forget_base_url(&remote);
+
     Ok(HttpResponse::Ok())
 }
 
@@ -91,7 +101,7 @@ pub(crate) async fn remove_remote_server(
     };
 
     // check that requesting user is an admin
-    if !is_admin(&data.pool, requesting_user, crate::host!()).await? {
+    if !is_admin(&data.pool, requesting_user, &data.host).await? {
         return Ok(HttpResponse::Unauthorized());
     }
 
@@ -117,7 +127,7 @@ pub(crate) async fn remove_remote_server(
 #[cfg(test)]
 mod test {
     use {
-        crate::test::{make_admin, new_user_login, ADDR},
+        crate::test::{make_admin, new_user_login, ADDR, FQDN},
         actix_http::http::StatusCode,
     };
 
@@ -134,7 +144,7 @@ mod test {
             .unwrap();
         assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
 
-        make_admin(username, crate::host!()).await;
+        make_admin(username, &*FQDN).await;
 
         // no remotes
         let mut res = client
@@ -222,7 +232,7 @@ mod test {
     async fn add_remote_bad_format_fail() {
         let (client, username, cookie) = new_user_login().await;
 
-        make_admin(username, crate::host!()).await;
+        make_admin(username, &*FQDN).await;
 
         // scheme not allowed
         let res = client
@@ -253,7 +263,7 @@ mod test {
     async fn add_remote_bad_host_fail() {
         let (client, username, cookie) = new_user_login().await;
 
-        make_admin(username, crate::host!()).await;
+        make_admin(username, &*FQDN).await;
 
         // add example.org:1234
         let res = client
@@ -0,0 +1,554 @@
+use {
+    crate::{
+        fed::{
+            client::Client,
+            outbox::{self, Delivery},
+        },
+        models::{
+            database, fed,
+            internal::{
+                HostQuery, NewReport, Report, REPORT_DISMISSED, REPORT_OPEN, REPORT_RESOLVED,
+            },
+        },
+        pagination::Pagination,
+        util::{
+            ensure_federates, file_post_report, is_admin, is_known_remote, is_moderator,
+            validate_report_reason,
+        },
+        AppData, Error,
+    },
+    actix_identity::Identity,
+    actix_web::{get, post, put, web, HttpResponse, Responder, Result},
+    anyhow::anyhow,
+    sqlx::{Pool, Postgres},
+    uuid::Uuid,
+};
+
+/// Returns whether the supplied local user may handle reports about the supplied community, or
+/// about messages if it is None, being one of its moderators or an admin
+async fn can_handle_reports(
+    community: Option<&str>,
+    username: &str,
+    host: &str,
+    pool: &Pool<Postgres>,
+) -> Result<bool, Error> {
+    if let Some(community) = community {
+        if is_moderator(username, host, community, pool).await? {
+            return Ok(true);
+        }
+    }
+
+    is_admin(pool, username, host).await
+}
+
+/// Report a post, comment or received message
+///
+/// Reports of remote posts and of messages from remote users are forwarded to their host rather
+/// than kept locally.
+#[post("/internal/reports")]
+pub(crate) async fn create_report(
+    identity: Identity,
+    data: web::Data<AppData>,
+    web::Json(body): web::Json<NewReport>,
+) -> Result<impl Responder, Error> {
+    let username = match identity.identity() {
+        Some(s) => s,
+        None => return Ok(HttpResponse::Unauthorized().finish()),
+    };
+
+    match (body.post, body.message) {
+        (Some(post), None) => {
+            let query = HostQuery { host: body.host };
+            if let Some(host) = query.remote(&data.host) {
+                if !is_known_remote(host, &data.pool).await? {
+                    return Ok(HttpResponse::NotFound().finish());
+                }
+                ensure_federates(host, &data.pool).await?;
+
+                let client = Client::new(&data.host, &data.privkey, &data.pool);
+                if !client.supports(host, fed::REPORTS).await? {
+                    return Err(Error::BadRequest(anyhow!(
+                        "{} does not accept reports",
+                        host
+                    )));
+                }
+
+                outbox::enqueue(
+                    &data.pool,
+                    &Delivery::Report {
+                        from: username,
+                        host: host.to_owned(),
+                        report: fed::Report {
+                            post: Some(post),
+                            message: None,
+                            reason: validate_report_reason(body.reason)?,
+                        },
+                    },
+                )
+                .await?;
+
+                return Ok(HttpResponse::Accepted().finish());
+            }
+
+            match file_post_report(post, &username, &data.host, body.reason, &data.pool).await? {
+                Some(report) => Ok(HttpResponse::Created().json(report)),
+                None => Ok(HttpResponse::NotFound().finish()),
+            }
+        }
+        (None, Some(message)) => {
+            let reason = validate_report_reason(body.reason)?;
+
+            // only the receiver of a message may report it
+            let received = match sqlx::query!(
+                r#"
+                    SELECT sender_username, sender_host, title, content FROM messages
+                    WHERE id = $1
+                    AND receiver_username = $2
+                    AND receiver_host = $3
+                "#,
+                message,
+                username,
+                data.host
+            )
+            .fetch_optional(&data.pool)
+            .await?
+            {
+                Some(received) => received,
+                None => return Ok(HttpResponse::NotFound().finish()),
+            };
+
+            // messages from remote users are reported to the admins of the sender's host
+            if received.sender_host != data.host {
+                let host = received.sender_host;
+                ensure_federates(&host, &data.pool).await?;
+
+                let client = Client::new(&data.host, &data.privkey, &data.pool);
+                if !client.supports(&host, fed::MESSAGE_REPORTS).await? {
+                    return Err(Error::BadRequest(anyhow!(
+                        "{} does not accept reports of messages",
+                        host
+                    )));
+                }
+
+                outbox::enqueue(
+                    &data.pool,
+                    &Delivery::Report {
+                        from: username,
+                        host,
+                        report: fed::Report {
+                            post: None,
+                            message: Some(fed::ReportedMessage {
+                                sender: received.sender_username,
+                                title: received.title,
+                                content: serde_json::from_value(received.content)?,
+                            }),
+                            reason,
+                        },
+                    },
+                )
+                .await?;
+
+                return Ok(HttpResponse::Accepted().finish());
+            }
+
+            let report = sqlx::query_as!(
+                database::Report,
+                r#"
+                    INSERT INTO reports
+                    SELECT $1, $2, $3, NULL, id, NULL, sender_username, sender_host, $4, $5, $6
+                    FROM messages
+                    WHERE id = $7
+                    AND receiver_username = $2
+                    AND receiver_host = $3
+                    RETURNING *
+                "#,
+                Uuid::new_v4(),
+                username,
+                data.host,
+                reason,
+                chrono::Local::now().timestamp(),
+                REPORT_OPEN,
+                message
+            )
+            .fetch_optional(&data.pool)
+            .await?;
+
+            match report {
+                Some(report) => Ok(HttpResponse::Created().json(Report::from(report))),
+                None => Ok(HttpResponse::NotFound().finish()),
+            }
+        }
+        _ => Err(Error::BadRequest(anyhow!(
+            "Reports must be of exactly one post or message"
+        ))),
+    }
+}
+
+/// Moderation queue of a community, listing its open reports oldest first
+#[get("/internal/communities/{id}/reports")]
+pub(crate) async fn get_community_reports(
+    identity: Identity,
+    data: web::Data<AppData>,
+    web::Path(id): web::Path<String>,
+    pagination: Pagination,
+) -> Result<impl Responder, Error> {
+    let username = match identity.identity() {
+        Some(s) => s,
+        None => return Ok(HttpResponse::Unauthorized().finish()),
+    };
+
+    if !can_handle_reports(Some(&id), &username, &data.host, &data.pool).await? {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+
+    let (after_created, after_id) = match pagination.after::<(i64, Uuid)>()? {
+        Some((created, id)) => (Some(created), Some(id)),
+        None => (None, None),
+    };
+
+    let reports: Vec<Report> = sqlx::query_as!(
+        database::Report,
+        r#"
+            SELECT * FROM reports
+            WHERE community = $1
+            AND status = $2
+            AND ($3::BIGINT IS NULL OR (created, id) > ($3, $4))
+            ORDER BY created, id
+            LIMIT $5
+        "#,
+        id,
+        REPORT_OPEN,
+        after_created,
+        after_id,
+        pagination.fetch_limit()
+    )
+    .fetch_all(&data.pool)
+    .await?
+    .into_iter()
+    .map(Report::from)
+    .collect();
+
+    Ok(HttpResponse::Ok().json(pagination.page(reports, |r| (r.created, r.id))?))
+}
+
+/// Moderation queue of the whole server for admins, listing every open report oldest first,
+/// including those about messages
+#[get("/internal/reports")]
+pub(crate) async fn get_reports(
+    identity: Identity,
+    data: web::Data<AppData>,
+    pagination: Pagination,
+) -> Result<impl Responder, Error> {
+    let username = match identity.identity() {
+        Some(s) => s,
+        None => return Ok(HttpResponse::Unauthorized().finish()),
+    };
+
+    if !is_admin(&data.pool, &username, &data.host).await? {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+
+    let (after_created, after_id) = match pagination.after::<(i64, Uuid)>()? {
+        Some((created, id)) => (Some(created), Some(id)),
+        None => (None, None),
+    };
+
+    let reports: Vec<Report> = sqlx::query_as!(
+        database::Report,
+        r#"
+            SELECT * FROM reports
+            WHERE status = $1
+            AND ($2::BIGINT IS NULL OR (created, id) > ($2, $3))
+            ORDER BY created, id
+            LIMIT $4
+        "#,
+        REPORT_OPEN,
+        after_created,
+        after_id,
+        pagination.fetch_limit()
+    )
+    .fetch_all(&data.pool)
+    .await?
+    .into_iter()
+    .map(Report::from)
+    .collect();
+
+    Ok(HttpResponse::Ok().json(pagination.page(reports, |r| (r.created, r.id))?))
+}
+
+/// Closes an open report with the supplied status on behalf of a moderator or admin
+async fn close_report(
+    id: Uuid,
+    status: &str,
+    identity: Identity,
+    data: web::Data<AppData>,
+) -> Result<HttpResponse, Error> {
+    let username = match identity.identity() {
+        Some(s) => s,
+        None => return Ok(HttpResponse::Unauthorized().finish()),
+    };
+
+    let community = match sqlx::query!(
+        r#"
+            SELECT community FROM reports
+            WHERE id = $1
+            AND status = $2
+        "#,
+        id,
+        REPORT_OPEN
+    )
+    .fetch_optional(&data.pool)
+    .await?
+    {
+        Some(row) => row.community,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    if !can_handle_reports(community.as_deref(), &username, &data.host, &data.pool).await? {
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+
+    let report = sqlx::query_as!(
+        database::Report,
+        r#"
+            UPDATE reports
+            SET status = $1, handler_username = $2, handler_host = $3, handled = $4
+            WHERE id = $5
+            AND status = $6
+            RETURNING *
+        "#,
+        status,
+        username,
+        data.host,
+        chrono::Local::now().timestamp(),
+        id,
+        REPORT_OPEN
+    )
+    .fetch_optional(&data.pool)
+    .await?;
+
+    // another moderator may have closed the report in the meantime
+    match report {
+        Some(report) => Ok(HttpResponse::Ok().json(Report::from(report))),
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
+
+/// Marks an open report as acted upon
+#[put("/internal/reports/{id}/resolve")]
+pub(crate) async fn resolve_report(
+    identity: Identity,
+    data: web::Data<AppData>,
+    web::Path(id): web::Path<Uuid>,
+) -> Result<impl Responder, Error> {
+    close_report(id, REPORT_RESOLVED, identity, data).await
+}
+
+/// Marks an open report as rejected without action
+#[put("/internal/reports/{id}/dismiss")]
+pub(crate) async fn dismiss_report(
+    identity: Identity,
+    data: web::Data<AppData>,
+    web::Path(id): web::Path<Uuid>,
+) -> Result<impl Responder, Error> {
+    close_report(id, REPORT_DISMISSED, identity, data).await
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        crate::{
+            models::internal::{Message, Post, Report},
+            pagination::Page,
+            test::{get_all_pages, make_admin, new_user_login, ADDR, FQDN},
+        },
+        actix_web::http::{header::CONTENT_TYPE, StatusCode},
+    };
+
+    #[actix_rt::test]
+    async fn moderation_queue_success() {
+        let (client, moderator, cookie) = new_user_login().await;
+        let (reporter_client, reporter, reporter_cookie) = new_user_login().await;
+
+        let res = client
+            .post(format!("{}/internal/communities", *ADDR))
+            .header(CONTENT_TYPE, "application/json")
+            .cookie(cookie.clone())
+            .send_body(r#"{"id": "reported", "title": "Reported", "description": "Reports"}"#)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let mut res = client
+            .post(format!("{}/internal/posts", *ADDR))
+            .header(CONTENT_TYPE, "application/json")
+            .cookie(cookie.clone())
+            .send_body(r#"{"community": "reported", "title": "Spam", "content": []}"#)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let post: Post = res.json().await.unwrap();
+
+        let res = reporter_client
+            .post(format!("{}/internal/reports", *ADDR))
+            .header(CONTENT_TYPE, "application/json")
+            .cookie(reporter_cookie.clone())
+            .send_body(format!(r#"{{"post": "{}", "reason": "   "}}"#, post.id))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        // reports are only forwarded to known remotes
+        let res = reporter_client
+            .post(format!("{}/internal/reports", *ADDR))
+            .header(CONTENT_TYPE, "application/json")
+            .cookie(reporter_cookie.clone())
+            .send_body(format!(
+                r#"{{"post": "{}", "host": "unknown.example", "reason": "Spam"}}"#,
+                post.id
+            ))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        let mut res = reporter_client
+            .post(format!("{}/internal/reports", *ADDR))
+            .header(CONTENT_TYPE, "application/json")
+            .cookie(reporter_cookie.clone())
+            .send_body(format!(r#"{{"post": "{}", "reason": "Spam"}}"#, post.id))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let report: Report = res.json().await.unwrap();
+        assert_eq!(report.reporter.username, reporter);
+        assert_eq!(report.author.username, moderator);
+        assert_eq!(report.community.as_deref(), Some("reported"));
+
+        // the creator of the community moderates it
+        let mut res = client
+            .get(format!("{}/internal/communities/reported/reports", *ADDR))
+            .cookie(cookie.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let queue: Page<Report> = res.json().await.unwrap();
+        assert_eq!(queue.items, vec![report.clone()]);
+
+        // other users cannot see or close the queue
+        let res = reporter_client
+            .get(format!("{}/internal/communities/reported/reports", *ADDR))
+            .cookie(reporter_cookie.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        let res = reporter_client
+            .put(format!("{}/internal/reports/{}/dismiss", *ADDR, report.id))
+            .cookie(reporter_cookie.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        let mut res = client
+            .put(format!("{}/internal/reports/{}/resolve", *ADDR, report.id))
+            .cookie(cookie.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let resolved: Report = res.json().await.unwrap();
+        assert_eq!(resolved.status, "resolved");
+        assert_eq!(resolved.handler.unwrap().username, moderator);
+
+        // closed reports leave the queue and cannot be closed again
+        let mut res = client
+            .get(format!("{}/internal/communities/reported/reports", *ADDR))
+            .cookie(cookie.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.json::<Page<Report>>().await.unwrap().items.is_empty());
+
+        let res = client
+            .put(format!("{}/internal/reports/{}/dismiss", *ADDR, report.id))
+            .cookie(cookie)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn report_message_success() {
+        let (sender_client, sender, sender_cookie) = new_user_login().await;
+        let (client, receiver, cookie) = new_user_login().await;
+
+        let res = sender_client
+            .post(format!("{}/internal/messages/{}", *ADDR, receiver))
+            .header(CONTENT_TYPE, "application/json")
+            .cookie(sender_cookie.clone())
+            .send_body(r#"{"title": "Hello", "content": {"text": {"text": "Abuse"}}}"#)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+
+        let mut res = client
+            .get(format!("{}/internal/messages/{}", *ADDR, sender))
+            .cookie(cookie.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let message = res.json::<Page<Message>>().await.unwrap().items.remove(0);
+
+        let body = format!(r#"{{"message": "{}", "reason": "Abusive"}}"#, message.id);
+
+        // only the receiver may report a message
+        let res = sender_client
+            .post(format!("{}/internal/reports", *ADDR))
+            .header(CONTENT_TYPE, "application/json")
+            .cookie(sender_cookie)
+            .send_body(body.clone())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        let mut res = client
+            .post(format!("{}/internal/reports", *ADDR))
+            .header(CONTENT_TYPE, "application/json")
+            .cookie(cookie.clone())
+            .send_body(body)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let report: Report = res.json().await.unwrap();
+        assert_eq!(report.author.username, sender);
+        assert!(report.community.is_none());
+
+        // message reports are handled by admins
+        let res = client
+            .get(format!("{}/internal/reports", *ADDR))
+            .cookie(cookie.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        make_admin(&receiver, &*FQDN).await;
+        let queue: Vec<Report> =
+            get_all_pages(&client, &format!("{}/internal/reports", *ADDR), &cookie).await;
+        assert!(queue.contains(&report));
+
+        let res = client
+            .put(format!("{}/internal/reports/{}/dismiss", *ADDR, report.id))
+            .cookie(cookie)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,333 @@
+use {
+    crate::{
+        models::{
+            database::PostContent,
+            internal::{DiffChange, DiffLine, PostRevision, RevisionDiff, UserId},
+        },
+        util::{is_admin, is_moderator},
+        AppData, Error,
+    },
+    actix_identity::Identity,
+    actix_web::{get, web, HttpResponse, Responder, Result},
+    similar::{ChangeTag, TextDiff},
+    sqlx::{Pool, Postgres},
+    uuid::Uuid,
+};
+
+/// Returns whether the supplied local user may view the revisions of a post, being its author, a
+/// moderator of its community or an admin, or None if the post does not exist or was deleted
+async fn can_view_revisions(
+    post: Uuid,
+    username: &str,
+    host: &str,
+    pool: &Pool<Postgres>,
+) -> Result<Option<bool>, Error> {
+    let post = match sqlx::query!(
+        r#"
+            SELECT community, author_username, author_host FROM posts
+            WHERE id = $1
+            AND deleted IS NULL
+        "#,
+        post
+    )
+    .fetch_optional(pool)
+    .await?
+    {
+        Some(post) => post,
+        None => return Ok(None),
+    };
+
+    Ok(Some(
+        (post.author_username == username && post.author_host == host)
+            || is_moderator(username, host, &post.community, pool).await?
+            || is_admin(pool, username, host).await?,
+    ))
+}
+
+/// Fetches every revision of a post, oldest first
+async fn fetch_revisions(post: Uuid, pool: &Pool<Postgres>) -> Result<Vec<PostRevision>, Error> {
+    sqlx::query!(
+        r#"
+            SELECT * FROM post_revisions
+            WHERE post = $1
+            ORDER BY revision
+        "#,
+        post
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| {
+        Ok(PostRevision {
+            revision: row.revision,
+            editor: UserId {
+                username: row.editor_username,
+                host: row.editor_host,
+            },
+            edited: row.edited,
+            title: row.title,
+            content: serde_json::from_value(row.content)?,
+        })
+    })
+    .collect()
+}
+
+/// Joins the text of each content block of a post, one block per line
+fn content_text(content: &[PostContent]) -> String {
+    content
+        .iter()
+        .map(|block| match block {
+            PostContent::Text(text) => text.text.as_str(),
+            PostContent::Markdown(markdown) => markdown.text.as_str(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Compares two texts line by line
+fn diff_lines(old: &str, new: &str) -> Vec<DiffLine> {
+    TextDiff::from_lines(old, new)
+        .iter_all_changes()
+        .map(|change| DiffLine {
+            change: match change.tag() {
+                ChangeTag::Equal => DiffChange::Equal,
+                ChangeTag::Insert => DiffChange::Insert,
+                ChangeTag::Delete => DiffChange::Delete,
+            },
+            text: change.value().trim_end_matches('\n').to_owned(),
+        })
+        .collect()
+}
+
+/// Lists the previous versions of a local post
+#[get("/internal/posts/{id}/revisions")]
+pub(crate) async fn get_post_revisions(
+    identity: Identity,
+    data: web::Data<AppData>,
+    web::Path(id): web::Path<Uuid>,
+) -> Result<impl Responder, Error> {
+    let username = match identity.identity() {
+        Some(s) => s,
+        None => return Ok(HttpResponse::Unauthorized().finish()),
+    };
+
+    match can_view_revisions(id, &username, &data.host, &data.pool).await? {
+        Some(true) => {}
+        Some(false) => return Ok(HttpResponse::Forbidden().finish()),
+        None => return Ok(HttpResponse::NotFound().finish()),
+    }
+
+    Ok(HttpResponse::Ok().json(fetch_revisions(id, &data.pool).await?))
+}
+
+/// Compares a revision of a local post with the version that replaced it, which is either the next
+/// revision or the current post
+#[get("/internal/posts/{id}/revisions/{revision}/diff")]
+pub(crate) async fn get_post_revision_diff(
+    identity: Identity,
+    data: web::Data<AppData>,
+    web::Path((id, revision)): web::Path<(Uuid, i32)>,
+) -> Result<impl Responder, Error> {
+    let username = match identity.identity() {
+        Some(s) => s,
+        None => return Ok(HttpResponse::Unauthorized().finish()),
+    };
+
+    match can_view_revisions(id, &username, &data.host, &data.pool).await? {
+        Some(true) => {}
+        Some(false) => return Ok(HttpResponse::Forbidden().finish()),
+        None => return Ok(HttpResponse::NotFound().finish()),
+    }
+
+    let mut revisions = fetch_revisions(id, &data.pool)
+        .await?
+        .into_iter()
+        .skip_while(|r| r.revision != revision);
+
+    let old = match revisions.next() {
+        Some(old) => old,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    let (title, content) = match revisions.next() {
+        Some(next) => (next.title, next.content),
+        None => {
+            let post = sqlx::query!(
+                r#"
+                    SELECT title, content FROM posts
+                    WHERE id = $1
+                "#,
+                id
+            )
+            .fetch_one(&data.pool)
+            .await?;
+
+            (post.title, serde_json::from_value(post.content)?)
+        }
+    };
+
+    Ok(HttpResponse::Ok().json(RevisionDiff {
+        revision: old.revision,
+        editor: old.editor,
+        edited: old.edited,
+        title: diff_lines(&old.title, &title),
+        content: diff_lines(&content_text(&old.content), &content_text(&content)),
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::diff_lines,
+        crate::{
+            models::internal::{DiffChange, DiffLine, Post, PostRevision, RevisionDiff},
+            test::{new_user_login, ADDR, FQDN},
+        },
+        actix_web::http::{header::CONTENT_TYPE, StatusCode},
+    };
+
+    #[test]
+    fn diff_lines_success() {
+        let lines = diff_lines("first\nsecond\nthird", "first\nchanged\nthird");
+
+        assert_eq!(
+            lines
+                .iter()
+                .map(|line| (line.change, line.text.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (DiffChange::Equal, "first"),
+                (DiffChange::Delete, "second"),
+                (DiffChange::Insert, "changed"),
+                (DiffChange::Equal, "third"),
+            ]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn revisions_success() {
+        let (client, username, cookie) = new_user_login().await;
+
+        let res = client
+            .post(format!("{}/internal/communities", *ADDR))
+            .header(CONTENT_TYPE, "application/json")
+            .cookie(cookie.clone())
+            .send_body(
+                r#"{"id": "revisions", "title": "Revisions", "description": "Edited posts"}"#,
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let mut res = client
+            .post(format!("{}/internal/posts", *ADDR))
+            .header(CONTENT_TYPE, "application/json")
+            .cookie(cookie.clone())
+            .send_body(
+                r#"{"community": "revisions", "title": "Original", "content": [{"text": {"text": "Original content"}}]}"#,
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let post: Post = res.json().await.unwrap();
+
+        for title in &["Second", "Third"] {
+            let res = client
+                .put(format!("{}/internal/posts/{}", *ADDR, post.id))
+                .header(CONTENT_TYPE, "application/json")
+                .cookie(cookie.clone())
+                .send_body(format!(
+                    r#"{{"title": "{}", "content": [{{"text": {{"text": "{} content"}}}}]}}"#,
+                    title, title
+                ))
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+
+        // each edit recorded the version it replaced
+        let mut res = client
+            .get(format!("{}/internal/posts/{}/revisions", *ADDR, post.id))
+            .cookie(cookie.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let revisions: Vec<PostRevision> = res.json().await.unwrap();
+        assert_eq!(
+            revisions
+                .iter()
+                .map(|r| (r.revision, r.title.as_str()))
+                .collect::<Vec<_>>(),
+            vec![(1, "Original"), (2, "Second")]
+        );
+        assert_eq!(revisions[0].editor.username, username);
+        assert_eq!(revisions[0].editor.host, *FQDN);
+
+        // the latest revision is compared with the current post
+        let mut res = client
+            .get(format!(
+                "{}/internal/posts/{}/revisions/2/diff",
+                *ADDR, post.id
+            ))
+            .cookie(cookie.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let diff: RevisionDiff = res.json().await.unwrap();
+        assert_eq!(
+            diff.title,
+            vec![
+                DiffLine {
+                    change: DiffChange::Delete,
+                    text: "Second".to_owned()
+                },
+                DiffLine {
+                    change: DiffChange::Insert,
+                    text: "Third".to_owned()
+                },
+            ]
+        );
+
+        let res = client
+            .get(format!(
+                "{}/internal/posts/{}/revisions/3/diff",
+                *ADDR, post.id
+            ))
+            .cookie(cookie.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        // other users may not see the revisions
+        let (other, _, other_cookie) = new_user_login().await;
+        let res = other
+            .get(format!("{}/internal/posts/{}/revisions", *ADDR, post.id))
+            .cookie(other_cookie)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        // deleting the post removes the content of its earlier versions along with its own
+        let res = client
+            .delete(format!("{}/internal/posts/{}", *ADDR, post.id))
+            .cookie(cookie.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        for path in &["revisions", "revisions/1/diff"] {
+            let res = client
+                .get(format!("{}/internal/posts/{}/{}", *ADDR, post.id, path))
+                .cookie(cookie.clone())
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        }
+    }
+}
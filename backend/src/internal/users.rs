@@ -1,14 +1,32 @@
 use {
     crate::{
-        models::internal::{CreatedUser, LoginInfo, NewUser, PasswordChange, User, UserId},
-        util::user_exists,
+        models::{
+            fed::{self, PostId},
+            internal::{
+                CreatedUser, LoginInfo, NewUser, PasswordChange, ProfileUpdate, User, UserId,
+            },
+        },
+        pagination::Pagination,
+        util::{
+            ensure_federates, is_known_remote, is_suspended, sanitise_text, user_exists,
+            validate_avatar_url,
+        },
         AppData, Error,
     },
     actix_identity::Identity,
     actix_web::{delete, get, post, put, web, HttpResponse, Responder, Result},
+    log::warn,
     rand::RngCore,
+    std::convert::TryFrom,
 };
 
+/// Seconds for which a fetched remote user profile is served from the cache
+const REMOTE_USER_TTL: i64 = 5 * 60;
+/// Maximum length of display names in characters, matching the column in local_users
+const DISPLAY_NAME_MAX_LENGTH: usize = 64;
+/// Maximum length of bios in characters
+const ABOUT_MAX_LENGTH: usize = 2048;
+
 fn generate_password_hash<T: AsRef<[u8]>>(password: T) -> anyhow::Result<String, Error> {
     let mut rng = rand::thread_rng();
     let mut salt = [0u8; crate::SALT_LENGTH];
@@ -46,13 +64,18 @@ pub(crate) async fn login(
             AND host = $2
         "#,
         body.username,
-        crate::host!()
+        data.host
     )
     .fetch_one(&data.pool)
     .await?
     .hash;
 
     if argon2::verify_encoded(&hash, body.password.as_bytes())? {
+        // suspended users are told so rather than being shown a wrong password
+        if is_suspended(&body.username, &data.host, &data.pool).await? {
+            return Ok(HttpResponse::Forbidden());
+        }
+
         identity.remember(body.username);
         Ok(HttpResponse::Ok())
     } else {
@@ -84,7 +107,7 @@ pub(crate) async fn create_user(
         return Ok(HttpResponse::Unauthorized().finish());
     }
 
-    if user_exists(&body.username, crate::host!(), &data.pool).await? {
+    if user_exists(&body.username, &data.host, &data.pool).await? {
         // username already in use
         return Ok(HttpResponse::BadRequest().finish());
     }
@@ -107,7 +130,7 @@ pub(crate) async fn create_user(
             VALUES ($1, $2)
         "#,
         body.username,
-        crate::host!()
+        data.host
     )
     .execute(&data.pool)
     .await?;
@@ -118,7 +141,7 @@ pub(crate) async fn create_user(
             VALUES ($1, $2, $3, $4, $5, NULL)
         "#,
         body.username,
-        crate::host!(),
+        data.host,
         password_hash,
         recovery_key_hash,
         now
@@ -136,30 +159,49 @@ pub(crate) async fn create_user(
 }
 
 /// Get user information
+///
+/// Accepts either a local username or a full "username@host" user ID.
 #[get("/internal/users/{id}")]
 pub(crate) async fn get_user(
     data: web::Data<AppData>,
-    web::Path(username): web::Path<String>,
+    web::Path(id): web::Path<String>,
 ) -> Result<impl Responder, Error> {
+    let user_id = UserId::try_from((id.as_str(), data.host.as_str()))?;
+
+    if user_id.host != data.host {
+        // only hosts added as remotes are fetched from, rather than any host named in the path
+        if !is_known_remote(&user_id.host, &data.pool).await? {
+            return Ok(HttpResponse::NotFound().finish());
+        }
+
+        return Ok(HttpResponse::Ok().json(get_remote_user(&data, user_id).await?));
+    }
+
+    let username = user_id.username;
+
     // Execute query
     let row = sqlx::query!(
         r#"
-            SELECT created, avatar_url FROM local_users
+            SELECT created, avatar_url, about, display_name FROM local_users
             WHERE username = $1
             AND host = $2
         "#,
         username,
-        crate::host!()
+        data.host
     )
     .fetch_one(&data.pool)
     .await?;
 
     let mut user = User {
         username: username.clone(),
+        host: data.host.clone(),
         subscribed: vec![],
         moderates: vec![],
         created: row.created,
         avatar_url: row.avatar_url,
+        display_name: row.display_name,
+        about: row.about,
+        posts: vec![],
     };
 
     user.subscribed = sqlx::query!(
@@ -169,7 +211,7 @@ pub(crate) async fn get_user(
             AND host = $2
         "#,
         username,
-        crate::host!()
+        data.host
     )
     .fetch_all(&data.pool)
     .await?
@@ -177,6 +219,23 @@ pub(crate) async fn get_user(
     .map(|row| row.community)
     .collect();
 
+    // subscriptions to remote communities are listed as "community@host"
+    user.subscribed.extend(
+        sqlx::query!(
+            r#"
+                SELECT community, community_host FROM remote_subscriptions
+                WHERE username = $1
+                AND host = $2
+            "#,
+            username,
+            data.host
+        )
+        .fetch_all(&data.pool)
+        .await?
+        .into_iter()
+        .map(|row| format!("{}@{}", row.community, row.community_host)),
+    );
+
     user.moderates = sqlx::query!(
         r#"
             SELECT community FROM moderators
@@ -184,7 +243,7 @@ pub(crate) async fn get_user(
             AND host = $2
         "#,
         username,
-        crate::host!()
+        data.host
     )
     .fetch_all(&data.pool)
     .await?
@@ -192,10 +251,133 @@ pub(crate) async fn get_user(
     .map(|row| row.community)
     .collect();
 
+    user.posts = sqlx::query!(
+        r#"
+            SELECT id FROM posts
+            WHERE author_username = $1
+            AND author_host = $2
+            AND deleted IS NULL
+        "#,
+        username,
+        data.host
+    )
+    .fetch_all(&data.pool)
+    .await?
+    .into_iter()
+    .map(|row| PostId {
+        id: row.id,
+        host: data.host.clone(),
+    })
+    .collect();
+
     // Return a successful response containing the User
     Ok(HttpResponse::Ok().json(user))
 }
 
+/// Holds the profile fields of a remote user to the same rules as local edits, dropping those that
+/// fail them rather than serving them to clients as received
+fn sanitise_remote_profile(user: fed::User) -> fed::User {
+    fed::User {
+        about: sanitise_text(&user.about, ABOUT_MAX_LENGTH).unwrap_or_default(),
+        display_name: user
+            .display_name
+            .and_then(|s| sanitise_text(s, DISPLAY_NAME_MAX_LENGTH).ok())
+            .filter(|s| !s.is_empty()),
+        // paths are only valid for avatars on this server
+        avatar_url: user
+            .avatar_url
+            .and_then(|s| validate_avatar_url(s).ok())
+            .filter(|s| !s.starts_with('/')),
+        ..user
+    }
+}
+
+/// Gets the profile of a remote user, fetching it from its host if the cached copy is missing or
+/// older than REMOTE_USER_TTL
+async fn get_remote_user(data: &AppData, user_id: UserId) -> Result<User, Error> {
+    ensure_federates(&user_id.host, &data.pool).await?;
+
+    let cached = sqlx::query!(
+        r#"
+            SELECT about, display_name, avatar_url, posts, fetched FROM remote_users
+            WHERE username = $1
+            AND host = $2
+        "#,
+        user_id.username,
+        user_id.host
+    )
+    .fetch_optional(&data.pool)
+    .await?;
+
+    let now = chrono::Local::now().timestamp();
+
+    let (about, display_name, avatar_url, posts) = match cached {
+        Some(row) if now - row.fetched < REMOTE_USER_TTL => (
+            row.about,
+            row.display_name,
+            row.avatar_url,
+            serde_json::from_value(row.posts)?,
+        ),
+        cached => {
+            match crate::Client::new(&data.host, &data.privkey, &data.pool)
+                .get_user(&user_id.host, &user_id.username)
+                .await
+            {
+                Ok(user) => {
+                    let user = sanitise_remote_profile(user);
+
+                    sqlx::query!(
+                        r#"
+                            INSERT INTO remote_users
+                                (username, host, about, avatar_url, posts, fetched, display_name)
+                            VALUES ($1, $2, $3, $4, $5, $6, $7)
+                            ON CONFLICT (username, host) DO UPDATE
+                                SET about = $3, avatar_url = $4, posts = $5, fetched = $6,
+                                    display_name = $7
+                        "#,
+                        user_id.username,
+                        user_id.host,
+                        user.about,
+                        user.avatar_url,
+                        serde_json::to_value(&user.posts)?,
+                        now,
+                        user.display_name
+                    )
+                    .execute(&data.pool)
+                    .await?;
+
+                    (user.about, user.display_name, user.avatar_url, user.posts)
+                }
+                // serve a stale copy rather than failing if the remote is unreachable
+                Err(e) => match cached {
+                    Some(row) => {
+                        warn!("Serving stale profile of {:?}: {}", user_id, e);
+                        (
+                            row.about,
+                            row.display_name,
+                            row.avatar_url,
+                            serde_json::from_value(row.posts)?,
+                        )
+                    }
+                    None => return Err(e.into()),
+                },
+            }
+        }
+    };
+
+    Ok(User {
+        username: user_id.username,
+        host: user_id.host,
+        subscribed: vec![],
+        moderates: vec![],
+        created: 0,
+        avatar_url,
+        display_name,
+        about,
+        posts,
+    })
+}
+
 /// Delete a user
 #[delete("/internal/users/{id}")]
 pub(crate) async fn delete_user(
@@ -223,7 +405,7 @@ pub(crate) async fn delete_user(
             AND host = $2
         "#,
         username,
-        crate::host!()
+        data.host
     )
     .execute(&data.pool)
     .await?;
@@ -257,7 +439,7 @@ pub(crate) async fn change_user_password(
                     AND host = $2
                 "#,
                 username,
-                crate::host!()
+                data.host
             )
             .fetch_one(&data.pool)
             .await?
@@ -293,7 +475,7 @@ pub(crate) async fn change_user_password(
             AND host = $2
         "#,
         username,
-        crate::host!(),
+        data.host,
         password_hash,
         new_recovery_hash
     )
@@ -326,6 +508,8 @@ pub(crate) async fn update_avatar_url(
         }
     }
 
+    let url = validate_avatar_url(url)?;
+
     // Update avatar url
     sqlx::query!(
         r#"
@@ -336,7 +520,73 @@ pub(crate) async fn update_avatar_url(
         "#,
         url,
         username,
-        crate::host!(),
+        data.host,
+    )
+    .execute(&data.pool)
+    .await?;
+
+    Ok(HttpResponse::Ok())
+}
+
+/// Updates the display name, bio and avatar of the current user
+#[put("/internal/users/{id}/profile")]
+pub(crate) async fn update_profile(
+    identity: Identity,
+    data: web::Data<AppData>,
+    web::Path(username): web::Path<String>,
+    web::Json(body): web::Json<ProfileUpdate>,
+) -> Result<impl Responder, Error> {
+    match identity.identity() {
+        Some(s) => {
+            if s != username {
+                // must be logged in as user being updated
+                return Ok(HttpResponse::Unauthorized());
+            }
+        }
+        None => {
+            // must be logged in to update profile
+            return Ok(HttpResponse::Unauthorized());
+        }
+    }
+
+    // validate all fields before applying any of them; None leaves a field unchanged and
+    // Some(None) clears it
+    let display_name = body
+        .display_name
+        .map(|s| sanitise_text(s, DISPLAY_NAME_MAX_LENGTH))
+        .transpose()?
+        .map(|s| if s.is_empty() { None } else { Some(s) });
+    let about = body
+        .about
+        .map(|s| sanitise_text(s, ABOUT_MAX_LENGTH))
+        .transpose()?;
+    let avatar_url = body
+        .avatar_url
+        .map(|s| {
+            if s.trim().is_empty() {
+                Ok(None)
+            } else {
+                validate_avatar_url(s).map(Some)
+            }
+        })
+        .transpose()?;
+
+    sqlx::query!(
+        r#"
+            UPDATE local_users
+            SET display_name = CASE WHEN $1 THEN $2 ELSE display_name END,
+                about = COALESCE($3, about),
+                avatar_url = CASE WHEN $4 THEN $5 ELSE avatar_url END
+            WHERE username = $6
+            AND host = $7
+        "#,
+        display_name.is_some(),
+        display_name.flatten(),
+        about,
+        avatar_url.is_some(),
+        avatar_url.flatten(),
+        username,
+        data.host,
     )
     .execute(&data.pool)
     .await?;
@@ -349,32 +599,82 @@ pub(crate) async fn update_avatar_url(
 pub(crate) async fn search_users(
     data: web::Data<AppData>,
     web::Path(search): web::Path<String>,
+    pagination: Pagination,
 ) -> Result<impl Responder, Error> {
+    let (after_host, after_username) = match pagination.after::<(String, String)>()? {
+        Some((host, username)) => (Some(host), Some(username)),
+        None => (None, None),
+    };
+
     let users: Vec<UserId> = sqlx::query_as!(
         UserId,
         r#"
-            SELECT * FROM users
+            SELECT username, host FROM users
             WHERE username % $1
+            AND ($2::VARCHAR IS NULL OR (host, username) > ($2, $3))
+            ORDER BY host, username
+            LIMIT $4
         "#,
-        search
+        search,
+        after_host,
+        after_username,
+        pagination.fetch_limit()
     )
     .fetch_all(&data.pool)
     .await?;
 
-    Ok(HttpResponse::Ok().json(users))
+    Ok(HttpResponse::Ok().json(pagination.page(users, |u| (u.host.clone(), u.username.clone()))?))
 }
 
 #[cfg(test)]
 mod test {
     use {
-        crate::{models::internal::CreatedUser, test::ADDR},
-        actix_web::HttpMessage,
+        super::sanitise_remote_profile,
+        crate::{
+            models::{
+                fed,
+                internal::{CreatedUser, User},
+            },
+            test::{add_remote, new_user_login, signed_request, ADDR, FQDN},
+            Config,
+        },
+        actix_web::{http::Method, HttpMessage},
         awc::{
             http::{header::CONTENT_TYPE, StatusCode},
             Client,
         },
     };
 
+    #[test]
+    fn sanitise_remote_profile_success() {
+        let user = sanitise_remote_profile(fed::User {
+            id: "remote".to_owned(),
+            posts: vec![],
+            about: "  hello\u{1b}[31m ".to_owned(),
+            avatar_url: Some("javascript:alert(1)".to_owned()),
+            display_name: Some("\u{0}".to_owned()),
+        });
+        assert_eq!(user.about, "hello[31m");
+        assert_eq!(user.avatar_url, None);
+        assert_eq!(user.display_name, None);
+
+        // paths would refer to this server rather than the remote
+        let user = sanitise_remote_profile(fed::User {
+            avatar_url: Some("/internal/images/1234".to_owned()),
+            ..user
+        });
+        assert_eq!(user.avatar_url, None);
+
+        let user = sanitise_remote_profile(fed::User {
+            avatar_url: Some("https://remote.example/avatar.png".to_owned()),
+            ..user
+        });
+        assert_eq!(
+            user.avatar_url.as_deref(),
+            Some("https://remote.example/avatar.png")
+        );
+    }
+
     #[actix_rt::test]
     async fn create_user() {
         let client = Client::new();
@@ -873,4 +1173,178 @@ mod test {
         assert_eq!(cookies.len(), 1);
         assert_eq!(cookies[0].name(), "auth");
     }
+
+    #[actix_rt::test]
+    async fn get_remote_user_success() {
+        let (client, username, cookie) = new_user_login().await;
+
+        // the backend federates with itself through its listening address, which differs from its
+        // FQDN and is therefore treated as a remote
+        let remote = ADDR.trim_start_matches("http://");
+        add_remote(&envy::from_env::<Config>().unwrap().fqdn).await;
+
+        // hosts that were not added as remotes are not fetched from
+        let res = client
+            .get(&format!(
+                "{}/internal/users/{}@unknown.example",
+                *ADDR, username
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        add_remote(remote.split(':').next().unwrap()).await;
+
+        let res = client
+            .post(&format!("{}/internal/communities", *ADDR))
+            .cookie(cookie.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .send_body(
+                r#"{"id": "remote_profile", "title": "Profiles", "description": "Profiles"}"#,
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = client
+            .post(&format!("{}/internal/posts", *ADDR))
+            .cookie(cookie.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .send_body(
+                r#"{"community": "remote_profile", "parentPost": null, "title": "Mine", "content": [{"text": {"text": "Authored"}}]}"#,
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let mut res = client
+            .get(&format!("{}/internal/users/{}@{}", *ADDR, username, remote))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let user: User = res.json().await.unwrap();
+        assert_eq!(user.username, username);
+        assert_eq!(user.host, remote);
+        assert_eq!(user.created, 0);
+        assert_eq!(user.posts.len(), 1);
+
+        // unknown remote users are not found
+        let res = client
+            .get(&format!("{}/internal/users/nobody@{}", *ADDR, remote))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn update_profile_success() {
+        let (client, username, cookie) = new_user_login().await;
+
+        let res = client
+            .put(&format!("{}/internal/users/{}/profile", *ADDR, username))
+            .cookie(cookie.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .send_body(
+                r#"
+                    {
+                        "displayName": "  Ferris \u0007",
+                        "about": "Likes crabs\nand lobsters",
+                        "avatarUrl": "/internal/images/ferris"
+                    }
+                "#,
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let mut res = client
+            .get(&format!("{}/internal/users/{}", *ADDR, username))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let user: User = res.json().await.unwrap();
+        assert_eq!(user.display_name, Some("Ferris".to_owned()));
+        assert_eq!(user.about, "Likes crabs\nand lobsters");
+        assert_eq!(user.avatar_url, Some("/internal/images/ferris".to_owned()));
+
+        // profile is exposed over federation with an absolute avatar URL
+        let mut res = signed_request(
+            Method::GET,
+            &format!("/fed/users/{}", username),
+            "one.example",
+            None,
+            "",
+        )
+        .await
+        .send_body("")
+        .await
+        .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let user: fed::User = res.json().await.unwrap();
+        assert_eq!(user.display_name, Some("Ferris".to_owned()));
+        assert_eq!(user.about, "Likes crabs\nand lobsters");
+        assert_eq!(
+            user.avatar_url,
+            Some(format!("https://{}/internal/images/ferris", *FQDN))
+        );
+
+        // empty strings clear the display name and avatar, absent fields are unchanged
+        let res = client
+            .put(&format!("{}/internal/users/{}/profile", *ADDR, username))
+            .cookie(cookie.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .send_body(r#"{"displayName": "", "avatarUrl": ""}"#)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let mut res = client
+            .get(&format!("{}/internal/users/{}", *ADDR, username))
+            .send()
+            .await
+            .unwrap();
+        let user: User = res.json().await.unwrap();
+        assert_eq!(user.display_name, None);
+        assert_eq!(user.avatar_url, None);
+        assert_eq!(user.about, "Likes crabs\nand lobsters");
+    }
+
+    #[actix_rt::test]
+    async fn update_profile_fail() {
+        let (client, username, cookie) = new_user_login().await;
+        let (_, other, _) = new_user_login().await;
+
+        // too long
+        let res = client
+            .put(&format!("{}/internal/users/{}/profile", *ADDR, username))
+            .cookie(cookie.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .send_body(format!(r#"{{"about": "{}"}}"#, "a".repeat(2049)))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        // not a URL
+        let res = client
+            .put(&format!("{}/internal/users/{}/profile", *ADDR, username))
+            .cookie(cookie.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .send_body(r#"{"avatarUrl": "javascript:alert(1)"}"#)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        // another user's profile
+        let res = client
+            .put(&format!("{}/internal/users/{}/profile", *ADDR, other))
+            .cookie(cookie.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .send_body(r#"{"about": "Not mine"}"#)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
 }
@@ -22,8 +22,8 @@ pub(crate) async fn open_ws(
     stream: web::Payload,
     web::Path(auth): web::Path<String>,
 ) -> Result<impl Responder, actix_web::Error> {
-    let user_id = match validate_cookie(&auth, &data.secret, &data.pool).await? {
-        Some(s) => UserId::try_from(s.as_str())?,
+    let user_id = match validate_cookie(&auth, &data.secret, &data.pool, &data.host).await? {
+        Some(s) => UserId::try_from((s.as_str(), data.host.as_str()))?,
         None => {
             // must be logged in
             return Ok(HttpResponse::Unauthorized().into());
@@ -8,30 +8,37 @@ use {
     actix_identity::IdentityService,
     actix_service::{fn_service, Service},
     actix_web::{
-        dev::{BodySize, MessageBody, ServiceRequest, ServiceResponse},
+        dev::{BodySize, HttpServiceFactory, MessageBody, ServiceRequest, ServiceResponse},
         middleware::Logger,
-        App, HttpServer,
+        web, App, HttpServer,
     },
     anyhow::{bail, Result},
     futures_util::{future::ok, FutureExt},
     log::info,
-    middleware::{auth::Authentication, fedsec::Signed},
-    once_cell::sync::OnceCell,
+    middleware::{
+        auth::Authentication,
+        fedsec::{ReplayCache, Signed, DEFAULT_CLOCK_SKEW},
+        vhost,
+    },
+    regex::Regex,
     rsa::RSAPrivateKey,
     sentry::IntoDsn,
     serde::Deserialize,
     sqlx::{postgres::PgPoolOptions, Pool, Postgres},
-    std::env,
+    std::{env, sync::Arc, time::Duration},
 };
 
+mod activitypub;
 mod error;
 mod fed;
 mod internal;
 mod metrics;
 mod middleware;
 mod models;
+mod pagination;
 #[cfg(test)]
 mod test;
+mod tombstones;
 mod util;
 
 pub use error::Error;
@@ -43,9 +50,6 @@ const DB_MAX_SIZE: u32 = 15;
 pub const SALT_LENGTH: usize = 32;
 /// Recovery key wordcount (log2(7530^6) = 77 bits)
 pub const RECOVERY_LENGTH: usize = 6;
-/// "host" value for entities on this server
-pub static HOST: OnceCell<String> = OnceCell::new();
-
 /// Application configuration
 #[derive(Deserialize, Debug, Clone)]
 pub struct Config {
@@ -55,6 +59,8 @@ pub struct Config {
     web_addr: String,
     /// Database address
     database_url: String,
+    /// Database schema to confine the instance to, defaults to the public schema
+    database_schema: Option<String>,
     /// Location of static frontend content to serve
     dist_path: String,
     /// Sentry DSN
@@ -63,11 +69,25 @@ pub struct Config {
     secret: String,
     /// RSA Private Key
     privkey: String,
+    /// Maximum accepted age of federation requests in seconds
+    fed_clock_skew: Option<u64>,
+    /// Seconds between passes over the federation outbox
+    outbox_interval: Option<u64>,
+    /// Seconds between synchronisations of remote communities
+    remote_sync_interval: Option<u64>,
+    /// Seconds deleted posts are kept as tombstones for before they may be purged
+    tombstone_retention: Option<u64>,
+    /// Whether to serve the ActivityPub bridge
+    activitypub: Option<bool>,
+    /// Comma-separated "fqdn=schema=privkey" instances to serve alongside the primary one
+    virtual_hosts: Option<String>,
 }
 
 /// Shared application data
 #[derive(Debug, Clone)]
 struct AppData {
+    /// "host" value for entities on this server
+    host: String,
     /// Database connection pool
     pool: Pool<Postgres>,
     /// RSA private key
@@ -76,14 +96,64 @@ struct AppData {
     ws_server: Addr<internal::ws::server::Server>,
     /// JWT secret
     secret: Vec<u8>,
+    /// Recently received federation request signatures
+    replay_cache: Arc<ReplayCache>,
 }
 
-/// Run main application
-pub async fn run(config: Config) -> Result<()> {
-    if HOST.get_or_init(|| config.fqdn.clone()) != &config.fqdn {
-        bail!("Failed to initialise local hostname");
+impl AppData {
+    /// Creates the application data of an instance, starting its WebSocket server
+    fn new(
+        host: String,
+        pool: Pool<Postgres>,
+        privkey: RSAPrivateKey,
+        secret: Vec<u8>,
+        clock_skew: Duration,
+    ) -> Self {
+        let ws_server = internal::ws::server::Server::new(pool.clone()).start();
+
+        Self {
+            host,
+            pool,
+            privkey,
+            ws_server,
+            secret,
+            replay_cache: Arc::new(ReplayCache::new(clock_skew)),
+        }
+    }
+}
+
+/// Opens a connection pool to the database, confining every connection to `schema` if supplied
+pub(crate) async fn connect_database(
+    url: &str,
+    schema: Option<&str>,
+    max_connections: u32,
+) -> Result<Pool<Postgres>> {
+    let mut options = PgPoolOptions::new().max_connections(max_connections);
+
+    if let Some(schema) = schema {
+        if !Regex::new("^[a-z_][a-z0-9_]{0,62}$")
+            .expect("Failed to build regular expression")
+            .is_match(schema)
+        {
+            bail!("Invalid database schema {:?}", schema);
+        }
+
+        // extensions such as pg_trgm remain in the public schema
+        let search_path = format!("SET search_path TO {}, public", schema);
+        options = options.after_connect(move |conn| {
+            let search_path = search_path.clone();
+            Box::pin(async move {
+                sqlx::query(&search_path).execute(conn).await?;
+                Ok(())
+            })
+        });
     }
 
+    Ok(options.connect(url).await?)
+}
+
+/// Run main application
+pub async fn run(config: Config) -> Result<()> {
     let _sentry_guard = sentry::init(sentry::ClientOptions {
         dsn: config.sentry_dsn.into_dsn().expect("Failed to parse DSN"),
         release: match env::var("HEROKU_RELEASE_VERSION") {
@@ -101,140 +171,135 @@ pub async fn run(config: Config) -> Result<()> {
     };
     let logger = sentry_log::SentryLogger::with_dest(log_builder.build());
 
-    log::set_boxed_logger(Box::new(logger)).expect("Failed to set global logger");
-    if cfg!(test) {
-        // avoid noise in test output
-        log::set_max_level(log::LevelFilter::Off);
-    } else {
-        log::set_max_level(log::LevelFilter::Trace);
+    // only the first instance in a process installs the logger
+    if log::set_boxed_logger(Box::new(logger)).is_ok() {
+        if cfg!(test) {
+            // avoid noise in test output
+            log::set_max_level(log::LevelFilter::Off);
+        } else {
+            log::set_max_level(log::LevelFilter::Trace);
+        }
     }
 
-    let data = {
-        let pool = PgPoolOptions::new()
-            .max_connections(DB_MAX_SIZE)
-            .connect(&config.database_url)
-            .await?;
+    let secret = base64::decode(&config.secret).expect("Failed to decode base64 secret");
+    let clock_skew = config
+        .fed_clock_skew
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_CLOCK_SKEW);
 
-        let privkey = RSAPrivateKey::from_pkcs8(&base64::decode(&config.privkey)?)?;
+    // the primary instance comes first and serves requests for any host that is not a virtual host
+    let mut instances = vec![AppData::new(
+        config.fqdn.clone(),
+        connect_database(
+            &config.database_url,
+            config.database_schema.as_deref(),
+            DB_MAX_SIZE,
+        )
+        .await?,
+        RSAPrivateKey::from_pkcs8(&base64::decode(&config.privkey)?)?,
+        secret.clone(),
+        clock_skew,
+    )];
 
-        let ws_server = internal::ws::server::Server::new(pool.clone()).start();
+    for vhost in vhost::parse(config.virtual_hosts.as_deref().unwrap_or_default())? {
+        if instances
+            .iter()
+            .any(|data| data.host.eq_ignore_ascii_case(&vhost.fqdn))
+        {
+            bail!("Virtual host {} is configured more than once", vhost.fqdn);
+        }
 
-        let secret = base64::decode(&config.secret).expect("Failed to decode base64 secret");
+        let pool = connect_database(&config.database_url, Some(&vhost.schema), DB_MAX_SIZE).await?;
+        vhost::migrate(&pool, &vhost.schema).await?;
 
-        AppData {
+        info!("Serving virtual host {}", vhost.fqdn);
+        instances.push(AppData::new(
+            vhost.fqdn,
             pool,
-            privkey,
-            ws_server,
-            secret,
-        }
-    };
+            vhost.privkey,
+            secret.clone(),
+            clock_skew,
+        ));
+    }
+
+    for data in &instances {
+        // Start delivering queued federation requests
+        fed::outbox::spawn_worker(
+            data.host.clone(),
+            data.pool.clone(),
+            data.privkey.clone(),
+            config
+                .outbox_interval
+                .map(Duration::from_secs)
+                .unwrap_or(fed::outbox::DEFAULT_INTERVAL),
+        );
+
+        // Start synchronising the cache of remote communities
+        fed::sync::spawn_worker(
+            data.host.clone(),
+            data.pool.clone(),
+            data.privkey.clone(),
+            config
+                .remote_sync_interval
+                .map(Duration::from_secs)
+                .unwrap_or(fed::sync::DEFAULT_INTERVAL),
+        );
+
+        // Start purging expired tombstones of deleted posts
+        tombstones::spawn_worker(
+            data.pool.clone(),
+            config
+                .tombstone_retention
+                .map(Duration::from_secs)
+                .unwrap_or(tombstones::DEFAULT_RETENTION),
+        );
+    }
+
+    let activitypub = config.activitypub.unwrap_or(false);
 
     let dist_path = config.dist_path;
-    let index_path = format!("{}/index.html", &dist_path);
 
     // Start HTTP server
     info!("Starting HTTP server at http://{}", config.web_addr);
     HttpServer::new(move || {
-        let index_path = index_path.clone();
-
-        App::new()
-            .data(data.clone())
-            .service(metrics::metrics)
-            .service(fed::get_communities)
-            .service(fed::get_community_by_id)
-            .service(fed::get_community_timestamps)
-            .service(fed::get_post_by_id)
-            .service(fed::get_posts)
-            .service(fed::create_post)
-            .service(fed::edit_post)
-            .service(fed::delete_post)
-            .service(fed::get_users)
-            .service(fed::get_user_by_id)
-            .service(fed::send_message)
-            .service(fed::get_public_key)
-            .service(fed::get_known_hosts)
-            .service(internal::login)
-            .service(internal::logout)
-            .service(internal::create_user)
-            .service(internal::get_user)
-            .service(internal::delete_user)
-            .service(internal::change_user_password)
-            .service(internal::search_users)
-            .service(internal::update_avatar_url)
-            .service(internal::create_community)
-            .service(internal::get_communities)
-            .service(internal::get_community_by_id)
-            .service(internal::delete_community)
-            .service(internal::subscribe_community)
-            .service(internal::unsubscribe_community)
-            .service(internal::search_communities)
-            .service(internal::add_community_moderator)
-            .service(internal::remove_community_moderator)
-            .service(internal::get_post)
-            .service(internal::get_bulk_post)
-            .service(internal::create_post)
-            .service(internal::edit_post)
-            .service(internal::delete_post)
-            .service(internal::search_posts)
-            .service(internal::get_admins)
-            .service(internal::get_admin_status)
-            .service(internal::add_admin)
-            .service(internal::remove_admin)
-            .service(internal::get_unread)
-            .service(internal::get_all)
-            .service(internal::mark_read)
-            .service(internal::get_messages_with_user)
-            .service(internal::send_message_to_user)
-            .service(internal::get_remote_servers)
-            .service(internal::add_remote_server)
-            .service(internal::remove_remote_server)
-            .service(internal::get_image)
-            .service(internal::add_image)
-            .service(internal::remove_image)
-            .service(internal::ws::open_ws)
-            .service(
-                Files::new("/", &dist_path)
-                    .show_files_listing()
-                    .index_file("index.html")
-                    .default_handler(fn_service(move |req: ServiceRequest| {
-                        let file = match actix_files::NamedFile::open(&index_path) {
-                            Ok(f) => f,
-                            Err(e) => return ok(req.error_response(e)),
-                        };
-
-                        let (req, _) = req.into_parts();
-
-                        match file.into_response(&req) {
-                            Ok(item) => ok(ServiceResponse::new(req.clone(), item)),
-                            Err(e) => ok(ServiceResponse::from_err(e, req)),
-                        }
-                    })),
-            )
-            .wrap(sentry_actix::Sentry::new())
-            .wrap(Logger::default())
-            .wrap(Signed)
-            .wrap(IdentityService::new(Authentication::new(&data.secret)))
-            .wrap_fn(|req, srv| {
-                metrics::HTTP_COUNTER.inc();
-                let timer = metrics::HTTP_REQ_HISTOGRAM
-                    .with_label_values(&["all"])
-                    .start_timer();
-                srv.call(req).map(|res| {
-                    timer.observe_duration();
-
-                    match &res {
-                        Ok(res) => match res.response().body().size() {
-                            BodySize::Sized(s) => {
-                                metrics::HTTP_RESP_SIZE_HISTOGRAM.observe(s as f64)
-                            }
-                            _ => {}
-                        },
+        let mut app = App::new();
+
+        // virtual hosts are matched by the Host header before falling back to the primary instance
+        for data in &instances[1..] {
+            app = app.service(
+                web::scope("")
+                    .guard(vhost::guard(data.host.clone()))
+                    .data(data.clone())
+                    .service(routes(data, activitypub, &dist_path)),
+            );
+        }
+
+        app.service(web::scope("").data(instances[0].clone()).service(routes(
+            &instances[0],
+            activitypub,
+            &dist_path,
+        )))
+        .wrap(sentry_actix::Sentry::new())
+        .wrap(Logger::default())
+        .wrap_fn(|req, srv| {
+            metrics::HTTP_COUNTER.inc();
+            let timer = metrics::HTTP_REQ_HISTOGRAM
+                .with_label_values(&["all"])
+                .start_timer();
+            srv.call(req).map(|res| {
+                timer.observe_duration();
+
+                match &res {
+                    Ok(res) => match res.response().body().size() {
+                        BodySize::Sized(s) => metrics::HTTP_RESP_SIZE_HISTOGRAM.observe(s as f64),
                         _ => {}
-                    }
+                    },
+                    _ => {}
+                }
 
-                    res
-                })
+                res
             })
+        })
     })
     .bind(config.web_addr)?
     .run()
@@ -242,3 +307,114 @@ pub async fn run(config: Config) -> Result<()> {
 
     Ok(())
 }
+
+/// Builds the routes of an instance, wrapped in the middleware that depends on its AppData
+fn routes(data: &AppData, activitypub: bool, dist_path: &str) -> impl HttpServiceFactory {
+    let index_path = format!("{}/index.html", dist_path);
+
+    web::scope("")
+        .service(metrics::metrics)
+        .service(fed::get_communities)
+        .service(fed::get_community_by_id)
+        .service(fed::get_community_timestamps)
+        .service(fed::get_post_by_id)
+        .service(fed::get_posts)
+        .service(fed::create_post)
+        .service(fed::edit_post)
+        .service(fed::delete_post)
+        .service(fed::vote_post)
+        .service(fed::create_report)
+        .service(fed::get_users)
+        .service(fed::get_user_by_id)
+        .service(fed::send_message)
+        .service(fed::get_public_key)
+        .service(fed::get_known_hosts)
+        .service(fed::get_node_info)
+        .service(internal::login)
+        .service(internal::logout)
+        .service(internal::create_user)
+        .service(internal::get_user)
+        .service(internal::delete_user)
+        .service(internal::change_user_password)
+        .service(internal::search_users)
+        .service(internal::update_avatar_url)
+        .service(internal::update_profile)
+        .service(internal::create_community)
+        .service(internal::get_communities)
+        .service(internal::get_community_by_id)
+        .service(internal::delete_community)
+        .service(internal::subscribe_community)
+        .service(internal::unsubscribe_community)
+        .service(internal::search_communities)
+        .service(internal::add_community_moderator)
+        .service(internal::remove_community_moderator)
+        .service(internal::get_post)
+        .service(internal::get_bulk_post)
+        .service(internal::create_post)
+        .service(internal::edit_post)
+        .service(internal::delete_post)
+        .service(internal::vote_post)
+        .service(internal::search_posts)
+        .service(internal::get_post_revisions)
+        .service(internal::get_post_revision_diff)
+        .service(internal::create_report)
+        .service(internal::get_reports)
+        .service(internal::get_community_reports)
+        .service(internal::resolve_report)
+        .service(internal::dismiss_report)
+        .service(internal::get_community_bans)
+        .service(internal::ban_community_user)
+        .service(internal::unban_community_user)
+        .service(internal::get_community_moderation_log)
+        .service(internal::get_suspensions)
+        .service(internal::suspend_user)
+        .service(internal::unsuspend_user)
+        .service(internal::get_moderation_log)
+        .service(internal::get_admins)
+        .service(internal::get_admin_status)
+        .service(internal::add_admin)
+        .service(internal::remove_admin)
+        .service(internal::get_unread)
+        .service(internal::get_all)
+        .service(internal::mark_read)
+        .service(internal::get_messages_with_user)
+        .service(internal::send_message_to_user)
+        .service(internal::get_remote_servers)
+        .service(internal::add_remote_server)
+        .service(internal::remove_remote_server)
+        .service(internal::get_outbox)
+        .service(internal::retry_outbox_item)
+        .service(internal::get_federation_policies)
+        .service(internal::set_federation_policy)
+        .service(internal::remove_federation_policy)
+        .service(internal::get_remote_health)
+        .service(internal::get_image)
+        .service(internal::add_image)
+        .service(internal::remove_image)
+        .service(internal::ws::open_ws)
+        .configure(|cfg| {
+            if activitypub {
+                activitypub::configure(cfg)
+            }
+        })
+        .service(
+            Files::new("/", dist_path)
+                .show_files_listing()
+                .index_file("index.html")
+                .default_handler(fn_service(move |req: ServiceRequest| {
+                    let file = match actix_files::NamedFile::open(&index_path) {
+                        Ok(f) => f,
+                        Err(e) => return ok(req.error_response(e)),
+                    };
+
+                    let (req, _) = req.into_parts();
+
+                    match file.into_response(&req) {
+                        Ok(item) => ok(ServiceResponse::new(req.clone(), item)),
+                        Err(e) => ok(ServiceResponse::from_err(e, req)),
+                    }
+                })),
+        )
+        .wrap(Signed)
+        .wrap(IdentityService::new(Authentication::new(&data.secret)))
+}
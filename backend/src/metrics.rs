@@ -3,8 +3,9 @@ use {
     actix_web::{get, web, Responder, Result},
     once_cell::sync::Lazy,
     prometheus::{
-        labels, opts, register_counter, register_gauge, register_histogram, register_histogram_vec,
-        Counter, Gauge, Histogram, HistogramVec,
+        labels, opts, register_counter, register_counter_vec, register_gauge_vec,
+        register_histogram, register_histogram_vec, Counter, CounterVec, GaugeVec, Histogram,
+        HistogramVec,
     },
     prometheus::{Encoder, TextEncoder},
 };
@@ -12,10 +13,11 @@ use {
 /// Prometheus metrics scrape endpoint
 #[get("/metrics")]
 pub(crate) async fn metrics(data: web::Data<AppData>) -> Result<impl Responder, Error> {
-    let metric_families = prometheus::gather();
+    // virtual hosts share the registry, so the counts of each are labelled with its host
+    let host = [data.host.as_str()];
 
     // get number of users
-    NUM_USERS.set(
+    NUM_USERS.with_label_values(&host).set(
         sqlx::query!("SELECT COUNT(*) FROM users")
             .fetch_one(&data.pool)
             .await?
@@ -24,8 +26,8 @@ pub(crate) async fn metrics(data: web::Data<AppData>) -> Result<impl Responder,
     );
 
     // get number of posts
-    NUM_POSTS.set(
-        sqlx::query!("SELECT COUNT(*) FROM posts")
+    NUM_POSTS.with_label_values(&host).set(
+        sqlx::query!("SELECT COUNT(*) FROM posts WHERE deleted IS NULL")
             .fetch_one(&data.pool)
             .await?
             .count
@@ -33,7 +35,7 @@ pub(crate) async fn metrics(data: web::Data<AppData>) -> Result<impl Responder,
     );
 
     // get number of communities
-    NUM_COMMUNITIES.set(
+    NUM_COMMUNITIES.with_label_values(&host).set(
         sqlx::query!("SELECT COUNT(*) FROM communities")
             .fetch_one(&data.pool)
             .await?
@@ -42,7 +44,7 @@ pub(crate) async fn metrics(data: web::Data<AppData>) -> Result<impl Responder,
     );
 
     // get number of remotes
-    NUM_REMOTES.set(
+    NUM_REMOTES.with_label_values(&host).set(
         sqlx::query!("SELECT COUNT(*) FROM remotes")
             .fetch_one(&data.pool)
             .await?
@@ -51,7 +53,7 @@ pub(crate) async fn metrics(data: web::Data<AppData>) -> Result<impl Responder,
     );
 
     // get number of images
-    NUM_IMAGES.set(
+    NUM_IMAGES.with_label_values(&host).set(
         sqlx::query!("SELECT COUNT(*) FROM images")
             .fetch_one(&data.pool)
             .await?
@@ -61,7 +63,7 @@ pub(crate) async fn metrics(data: web::Data<AppData>) -> Result<impl Responder,
 
     let mut buffer = vec![];
     TextEncoder::new()
-        .encode(&metric_families, &mut buffer)
+        .encode(&prometheus::gather(), &mut buffer)
         .unwrap();
 
     Ok(String::from_utf8(buffer).unwrap())
@@ -93,47 +95,89 @@ pub static HTTP_RESP_SIZE_HISTOGRAM: Lazy<Histogram> = Lazy::new(|| {
     .unwrap()
 });
 
-pub static NUM_USERS: Lazy<Gauge> = Lazy::new(|| {
-    register_gauge!(opts!(
-        "num_users",
-        "Number of users.",
-        labels! {"handler" => "all",}
-    ))
+pub static NUM_USERS: Lazy<GaugeVec> = Lazy::new(|| {
+    register_gauge_vec!(
+        opts!(
+            "num_users",
+            "Number of users.",
+            labels! {"handler" => "all",}
+        ),
+        &["host"]
+    )
     .unwrap()
 });
 
-pub static NUM_POSTS: Lazy<Gauge> = Lazy::new(|| {
-    register_gauge!(opts!(
-        "num_posts",
-        "Number of posts.",
-        labels! {"handler" => "all",}
-    ))
+pub static NUM_POSTS: Lazy<GaugeVec> = Lazy::new(|| {
+    register_gauge_vec!(
+        opts!(
+            "num_posts",
+            "Number of posts.",
+            labels! {"handler" => "all",}
+        ),
+        &["host"]
+    )
     .unwrap()
 });
 
-pub static NUM_COMMUNITIES: Lazy<Gauge> = Lazy::new(|| {
-    register_gauge!(opts!(
-        "num_communities",
-        "Number of users.",
-        labels! {"handler" => "all",}
-    ))
+pub static NUM_COMMUNITIES: Lazy<GaugeVec> = Lazy::new(|| {
+    register_gauge_vec!(
+        opts!(
+            "num_communities",
+            "Number of users.",
+            labels! {"handler" => "all",}
+        ),
+        &["host"]
+    )
     .unwrap()
 });
 
-pub static NUM_REMOTES: Lazy<Gauge> = Lazy::new(|| {
-    register_gauge!(opts!(
-        "num_remotes",
-        "Number of remote servers.",
-        labels! {"handler" => "all",}
-    ))
+pub static NUM_REMOTES: Lazy<GaugeVec> = Lazy::new(|| {
+    register_gauge_vec!(
+        opts!(
+            "num_remotes",
+            "Number of remote servers.",
+            labels! {"handler" => "all",}
+        ),
+        &["host"]
+    )
     .unwrap()
 });
 
-pub static NUM_IMAGES: Lazy<Gauge> = Lazy::new(|| {
-    register_gauge!(opts!(
-        "num_images",
-        "Number of images.",
-        labels! {"handler" => "all",}
-    ))
+pub static NUM_IMAGES: Lazy<GaugeVec> = Lazy::new(|| {
+    register_gauge_vec!(
+        opts!(
+            "num_images",
+            "Number of images.",
+            labels! {"handler" => "all",}
+        ),
+        &["host"]
+    )
+    .unwrap()
+});
+
+pub static FED_REQUESTS: Lazy<CounterVec> = Lazy::new(|| {
+    register_counter_vec!(
+        "federation_requests_total",
+        "Number of federation requests by remote and outcome.",
+        &["host", "outcome"]
+    )
+    .unwrap()
+});
+
+pub static FED_REQUEST_HISTOGRAM: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "federation_request_duration_seconds",
+        "The federation request latencies in seconds.",
+        &["host"]
+    )
+    .unwrap()
+});
+
+pub static FED_CIRCUIT_OPEN: Lazy<GaugeVec> = Lazy::new(|| {
+    register_gauge_vec!(
+        "federation_circuit_open",
+        "Whether requests to a remote are being refused.",
+        &["host"]
+    )
     .unwrap()
 });
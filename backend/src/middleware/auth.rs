@@ -75,6 +75,7 @@ pub(crate) async fn validate_cookie(
     value: &str,
     secret: &[u8],
     pool: &Pool<Postgres>,
+    host: &str,
 ) -> Result<Option<String>, Error> {
     let token_data = jsonwebtoken::decode::<Token>(
         value,
@@ -90,12 +91,19 @@ pub(crate) async fn validate_cookie(
                 WHERE username = $1
                 AND host = $2
                 AND session = $3
+                AND NOT EXISTS (
+                    SELECT 1 FROM suspensions
+                    WHERE suspensions.username = local_users.username
+                    AND suspensions.host = local_users.host
+                    AND (expires IS NULL OR expires > $4)
+                )
                 LIMIT 1
             );
         "#,
         token_data.claims.username,
-        crate::host!(),
-        Some(&token_data.claims.session[..])
+        host,
+        Some(&token_data.claims.session[..]),
+        chrono::Local::now().timestamp()
     )
     .fetch_one(pool)
     .await
@@ -116,17 +124,19 @@ impl IdentityPolicy for Authentication {
     type ResponseFuture = Pin<Box<dyn Future<Output = Result<(), Error>>>>;
 
     fn from_request(&self, request: &mut ServiceRequest) -> Self::Future {
-        // get database connection pool
-        let pool = request
+        // get database connection pool and local host
+        let data = request
             .app_data::<web::Data<AppData>>()
-            .expect("Failed to get AppData from request")
-            .pool
-            .clone();
+            .expect("Failed to get AppData from request");
+        let pool = data.pool.clone();
+        let host = data.host.clone();
 
         // get cookie from request
         if let Some(cookie) = request.cookie("auth") {
             let secret = self.secret.clone();
-            return Box::pin(async move { validate_cookie(cookie.value(), &secret, &pool).await });
+            return Box::pin(async move {
+                validate_cookie(cookie.value(), &secret, &pool, &host).await
+            });
         }
 
         return Box::pin(ok(None));
@@ -156,13 +166,13 @@ impl IdentityPolicy for Authentication {
                         .expect("token should always be a valid HeaderValue"),
                 );
 
-                // get database connection pool
-                let pool = response
+                // get database connection pool and local host
+                let data = response
                     .request()
                     .app_data::<web::Data<AppData>>()
-                    .expect("Failed to get AppData from request")
-                    .pool
-                    .clone();
+                    .expect("Failed to get AppData from request");
+                let pool = data.pool.clone();
+                let host = data.host.clone();
 
                 return Box::pin(async move {
                     // insert into db
@@ -174,7 +184,7 @@ impl IdentityPolicy for Authentication {
                             AND host = $2
                         "#,
                         identity,
-                        crate::host!(),
+                        host,
                         &session[..]
                     )
                     .execute(&pool)
@@ -1,32 +1,112 @@
 //! Federation Security Middleware
 
 use {
-    crate::AppData,
+    crate::{
+        fed::signature::{request_target, verify, SignatureHeader, REQUIRED_HEADERS},
+        util::{federation_policy, Defederated, Policy},
+        AppData,
+    },
     actix_http::error::PayloadError,
     actix_service::{Service, Transform},
     actix_web::{
         dev::{ServiceRequest, ServiceResponse},
-        http::uri::Authority,
+        http::{header::HttpDate, uri::Authority, Method},
         web::{BytesMut, Data},
         Error, HttpMessage,
     },
-    anyhow::{anyhow, Context as AnyhowContext, Result},
+    anyhow::{anyhow, bail, Context as AnyhowContext, Result},
     async_stream::stream,
     futures_util::{
         future::{ok, Future, Ready},
         stream::StreamExt,
     },
     log::debug,
-    rsa::{hash::Hash, padding::PaddingScheme, PublicKey, RSAPublicKey},
+    rsa::RSAPublicKey,
     sha2::{Digest, Sha512},
     std::{
         cell::RefCell,
+        collections::HashMap,
         pin::Pin,
         rc::Rc,
+        sync::Mutex,
         task::{Context, Poll},
+        time::{Duration, Instant, SystemTime},
     },
 };
 
+/// Default maximum difference between the Date header of a federation request and the local clock
+pub const DEFAULT_CLOCK_SKEW: Duration = Duration::from_secs(5 * 60);
+
+/// Reasons a correctly signed federation request may still be rejected
+#[derive(thiserror::Error, Debug, PartialEq, Clone, Copy)]
+pub enum ReplayError {
+    #[error("Date header is older than the accepted clock skew window")]
+    Expired,
+
+    #[error("Date header is further in the future than the accepted clock skew window")]
+    FutureDated,
+
+    #[error("Request with an identical signature has already been received")]
+    Replayed,
+}
+
+impl ReplayError {
+    /// Machine-readable code returned in error responses
+    pub fn code(&self) -> &'static str {
+        match self {
+            ReplayError::Expired => "request_expired",
+            ReplayError::FutureDated => "request_future_dated",
+            ReplayError::Replayed => "request_replayed",
+        }
+    }
+}
+
+/// Short-lived cache of the signatures of recently received state-changing federation requests
+#[derive(Debug)]
+pub struct ReplayCache {
+    window: Duration,
+    seen: Mutex<HashMap<Vec<u8>, Instant>>,
+}
+
+impl ReplayCache {
+    /// Creates a new cache accepting requests dated within `window` of the local clock
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Checks that the supplied Date header value is within the clock skew window
+    pub fn check_date(&self, date: SystemTime, now: SystemTime) -> Result<(), ReplayError> {
+        match now.duration_since(date) {
+            Ok(age) if age > self.window => Err(ReplayError::Expired),
+            Ok(_) => Ok(()),
+            Err(e) if e.duration() > self.window => Err(ReplayError::FutureDated),
+            Err(_) => Ok(()),
+        }
+    }
+
+    /// Records the digest of a signature, failing if it was already seen
+    pub fn check_signature(&self, digest: Vec<u8>) -> Result<(), ReplayError> {
+        let now = Instant::now();
+        // a request may be dated up to one window in the future and is accepted until one
+        // window after its date, so digests must be remembered for twice the window
+        let ttl = self.window * 2;
+
+        let mut seen = self.seen.lock().expect("Replay cache lock poisoned");
+        seen.retain(|_, received| now.duration_since(*received) < ttl);
+
+        if seen.contains_key(&digest) {
+            return Err(ReplayError::Replayed);
+        }
+
+        seen.insert(digest, now);
+
+        Ok(())
+    }
+}
+
 pub struct Signed;
 
 impl<S: 'static, B> Transform<S> for Signed
@@ -73,15 +153,26 @@ where
         let mut svc = self.service.clone();
 
         Box::pin(async move {
-            // only validate signatures on non-key federation requests
-            if req.uri().path().starts_with("/fed") && !req.uri().path().starts_with("/fed/key") {
+            // only validate signatures on federation requests other than the public key and
+            // nodeinfo, which must be readable before a remote is known
+            let path = req.uri().path();
+            if path.starts_with("/fed")
+                && !path.starts_with("/fed/key")
+                && !path.starts_with("/fed/nodeinfo")
+            {
                 debug!(
                     "received federation request, validating signature: {:#?}",
                     req
                 );
-                validate_signature(&mut req)
-                    .await
-                    .map_err(|e| Error::from(crate::Error::BadRequest(e)))?;
+                validate_signature(&mut req).await.map_err(|e| {
+                    Error::from(match e.downcast::<ReplayError>() {
+                        Ok(e) => crate::Error::Replay(e),
+                        Err(e) => match e.downcast::<Defederated>() {
+                            Ok(e) => crate::Error::Defederated(e),
+                            Err(e) => crate::Error::BadRequest(e),
+                        },
+                    })
+                })?;
                 debug!("validated signature!");
             }
 
@@ -91,38 +182,66 @@ where
 }
 
 async fn validate_signature(req: &mut ServiceRequest) -> Result<()> {
-    let input = gen_signature_input(req).await?;
-    debug!("signature input: {}", &input);
+    let data = req
+        .app_data::<Data<AppData>>()
+        .expect("Failed to get AppData")
+        .clone();
 
-    let pubkey = {
-        let pool = req
-            .app_data::<Data<AppData>>()
-            .expect("Failed to get AppData")
-            .pool
-            .clone();
-
-        let client_host = req
-            .headers()
-            .get("Client-Host")
-            .ok_or(anyhow!("Missing Client-Host header"))?
-            .to_str()?
-            .parse::<Authority>()?
-            .host()
-            .to_owned();
+    // reject stale or future-dated requests before doing any expensive work
+    let date: SystemTime = req
+        .headers()
+        .get("Date")
+        .ok_or(anyhow!("Missing Date header"))?
+        .to_str()?
+        .parse::<HttpDate>()
+        .context("Parsing Date header")?
+        .into();
+    data.replay_cache.check_date(date, SystemTime::now())?;
+
+    let signature = get_signature(req)?;
+    debug!("got signature from request: {}", signature);
+
+    let client_host = req
+        .headers()
+        .get("Client-Host")
+        .ok_or(anyhow!("Missing Client-Host header"))?
+        .to_str()?
+        .parse::<Authority>()?;
 
+    // refuse defederated hosts before doing any expensive work
+    let policy = federation_policy(client_host.as_str(), &data.pool)
+        .await
+        .map_err(|e| anyhow!("Checking federation policy: {}", e))?;
+    if policy == Policy::Blocked {
+        bail!(Defederated(client_host.host().to_owned()));
+    }
+
+    // each remote has a single key, so the key ID is either the protocol's "global" or the host
+    if signature.key_id != "global"
+        && signature.key_id != client_host.as_str()
+        && signature.key_id != client_host.host()
+    {
+        bail!(
+            "Unknown keyId \"{}\" for host \"{}\"",
+            signature.key_id,
+            client_host
+        );
+    }
+
+    let pubkey = {
         let der = sqlx::query!(
             r#"
                 SELECT pubkey FROM remotes
                 WHERE host = $1
             "#,
-            client_host
+            client_host.host()
         )
-        .fetch_one(&pool)
+        .fetch_one(&data.pool)
         .await
         .map_err(|e| match e {
             sqlx::Error::RowNotFound => anyhow!(
                 "Public key for host \"{}\" not found in database",
-                client_host
+                client_host.host()
             ),
             _ => e.into(),
         })?
@@ -133,80 +252,100 @@ async fn validate_signature(req: &mut ServiceRequest) -> Result<()> {
         RSAPublicKey::from_pkcs8(&der).expect("Keys in database should always be valid")
     };
 
-    let signature = get_signature(req)?;
+    let digest = validate_digest(req).await?;
 
-    debug!("got signature from request: {}", signature);
+    let input = gen_signature_input(req, &signature, &digest)?;
+    debug!("signature input: {}", &input);
 
-    pubkey
-        .verify(
-            PaddingScheme::PKCS1v15Sign {
-                hash: Some(Hash::SHA2_512),
-            },
-            Sha512::digest(input.as_bytes()).as_slice(),
-            &base64::decode(signature)?,
-        )
-        .context("Verifying signature")?;
+    if verify(&pubkey, &input, &signature.signature).is_err() {
+        // some implementations sign the digest with the algorithm name in uppercase regardless of
+        // the Digest header sent, so also accept that form
+        let (algorithm, value) = split_digest(&digest)?;
+        let normalised = format!("{}={}", algorithm.to_ascii_uppercase(), value);
+
+        let input = gen_signature_input(req, &signature, &normalised)?;
+        debug!("retrying with signature input: {}", &input);
+
+        verify(&pubkey, &input, &signature.signature).context("Verifying signature")?;
+    }
+
+    // only state-changing requests are worth replaying
+    if *req.method() != Method::GET && *req.method() != Method::HEAD {
+        data.replay_cache
+            .check_signature(Sha512::digest(&signature.signature).to_vec())?;
+    }
 
     Ok(())
 }
 
-fn get_signature(req: &mut ServiceRequest) -> Result<String> {
-    let value = req
+fn get_signature(req: &ServiceRequest) -> Result<SignatureHeader> {
+    let signature = req
         .headers()
         .get("Signature")
         .ok_or(anyhow!("Missing Signature header"))?
         .to_str()?
-        .to_owned();
+        .parse::<SignatureHeader>()?;
 
-    Ok(value
-        .split(',')
-        .skip(3)
-        .next()
-        .ok_or(anyhow!(
-            "Missing \"signature\" key in Signature header value"
-        ))?
-        .strip_prefix("signature=\"")
-        .ok_or(anyhow!(
-            "Missing \"signature\" value in Signature header value"
-        ))?
-        .strip_suffix('"')
-        .ok_or(anyhow!("Failed to strip trailing doublequotes"))?
-        .to_owned())
+    if !signature.algorithm_supported() {
+        bail!(
+            "Unsupported signature algorithm {:?}",
+            signature.algorithm.unwrap_or_default()
+        );
+    }
+
+    for header in REQUIRED_HEADERS {
+        if !signature.covers(header) {
+            bail!("Signature does not cover required header \"{}\"", header);
+        }
+    }
+
+    // the user a request acts on behalf of must not be spoofable
+    if req.headers().contains_key("User-ID") && !signature.covers("user-id") {
+        bail!("Signature does not cover the User-ID header");
+    }
+
+    Ok(signature)
 }
 
-async fn gen_signature_input(req: &mut ServiceRequest) -> Result<String> {
-    let mut digest = validate_digest(req).await?;
-    // convert sha-512 to SHA-512
-    digest.replace_range(..3, "SHA");
+/// Rebuilds the signing string from the headers declared in the Signature header
+fn gen_signature_input(
+    req: &ServiceRequest,
+    signature: &SignatureHeader,
+    digest: &str,
+) -> Result<String> {
+    Ok(
+        signature.signing_string(req.method().as_str(), request_target(req.uri()), |name| {
+            if name == "digest" {
+                return Some(digest.to_owned());
+            }
 
-    let method = req.method().as_str().to_ascii_lowercase();
-    let path = req.uri().path();
-    let host = req
-        .headers()
-        .get("Host")
-        .ok_or(anyhow!("Missing Host header"))?
-        .to_str()?;
-    let client_host = req
-        .headers()
-        .get("Client-Host")
-        .ok_or(anyhow!("Missing Client-Host header"))?
-        .to_str()?;
-    let user_id = match req.headers().get("User-ID") {
-        Some(value) => format!("user-id: {}\n", value.to_str()?),
-        None => "".to_owned(),
-    };
-    let date = req
-        .headers()
-        .get("Date")
-        .ok_or(anyhow!("Missing Date header"))?
-        .to_str()?;
+            let values = req
+                .headers()
+                .get_all(name)
+                .map(|value| value.to_str().map(str::to_owned))
+                .collect::<Result<Vec<_>, _>>()
+                .ok()?;
+
+            if values.is_empty() {
+                None
+            } else {
+                Some(values.join(", "))
+            }
+        })?,
+    )
+}
+
+/// Splits a digest into its algorithm and base64 encoded value
+fn split_digest(digest: &str) -> Result<(&str, &str)> {
+    let mut parts = digest.trim().splitn(2, '=');
 
-    Ok(format!(
-        "(request-target): {} {}\nhost: {}\nclient-host: {}\n{}date: {}\ndigest: {}",
-        method, path, host, client_host, user_id, date, digest
-    ))
+    match (parts.next(), parts.next()) {
+        (Some(algorithm), Some(value)) => Ok((algorithm, value)),
+        _ => Err(anyhow!("Digest header value badly formatted")),
+    }
 }
 
+/// Validates the SHA-512 digest of the body and returns the Digest header entry as sent
 async fn validate_digest(req: &mut ServiceRequest) -> Result<String> {
     let mut body = BytesMut::new();
     let mut stream = req.take_payload();
@@ -214,32 +353,35 @@ async fn validate_digest(req: &mut ServiceRequest) -> Result<String> {
         body.extend_from_slice(&chunk?);
     }
 
-    let digest = match req.headers().get("Digest") {
-        Some(value) => {
-            let digest = value.to_str()?.split("=").skip(1).next();
-
-            let digest = match digest {
-                Some(s) => s,
-                _ => return Err(anyhow!("Digest header value badly formatted")),
-            };
-
-            let digest = match base64::decode(digest) {
-                Ok(b) => b,
-                Err(e) => return Err(anyhow!("Digest header value invalid base64: {:?}", e)),
-            };
+    let header = req
+        .headers()
+        .get("Digest")
+        .ok_or(anyhow!("Missing Digest header"))?
+        .to_str()?;
 
-            if digest != Sha512::digest(&body).as_slice() {
-                return Err(anyhow!(
-                    "Invalid Digest header value: expected \"{:?}\" found \"{:?}\"",
-                    &digest,
-                    Sha512::digest(&body).as_slice()
-                ));
-            }
+    // the header may contain several comma-separated digests
+    let digest = header
+        .split(',')
+        .find(|entry| match split_digest(entry) {
+            Ok((algorithm, _)) => algorithm.eq_ignore_ascii_case("sha-512"),
+            Err(_) => false,
+        })
+        .ok_or(anyhow!("Digest header does not contain a SHA-512 digest"))?
+        .trim()
+        .to_owned();
 
-            base64::encode(Sha512::digest(&body))
-        }
-        None => return Err(anyhow!("Missing Digest header")),
+    let expected = match base64::decode(split_digest(&digest)?.1) {
+        Ok(b) => b,
+        Err(e) => return Err(anyhow!("Digest header value invalid base64: {:?}", e)),
     };
+
+    if expected != Sha512::digest(&body).as_slice() {
+        return Err(anyhow!(
+            "Invalid Digest header value: expected \"{:?}\" found \"{:?}\"",
+            &expected,
+            Sha512::digest(&body).as_slice()
+        ));
+    }
     debug!("digest validated: {:?}", digest);
 
     let stream = stream! {
@@ -250,5 +392,65 @@ async fn validate_digest(req: &mut ServiceRequest) -> Result<String> {
     };
     req.set_payload(actix_http::Payload::Stream(Box::pin(stream)));
 
-    Ok(format!("sha-512={}", digest))
+    Ok(digest)
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::{ReplayCache, ReplayError},
+        std::time::{Duration, SystemTime},
+    };
+
+    #[test]
+    fn date_within_window_success() {
+        let cache = ReplayCache::new(Duration::from_secs(300));
+        let now = SystemTime::now();
+
+        assert_eq!(cache.check_date(now, now), Ok(()));
+        assert_eq!(
+            cache.check_date(now - Duration::from_secs(299), now),
+            Ok(())
+        );
+        assert_eq!(
+            cache.check_date(now + Duration::from_secs(299), now),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn date_outside_window_fail() {
+        let cache = ReplayCache::new(Duration::from_secs(300));
+        let now = SystemTime::now();
+
+        assert_eq!(
+            cache.check_date(now - Duration::from_secs(301), now),
+            Err(ReplayError::Expired)
+        );
+        assert_eq!(
+            cache.check_date(now + Duration::from_secs(301), now),
+            Err(ReplayError::FutureDated)
+        );
+    }
+
+    #[test]
+    fn repeated_signature_fail() {
+        let cache = ReplayCache::new(Duration::from_secs(300));
+
+        assert_eq!(cache.check_signature(vec![1, 2, 3]), Ok(()));
+        assert_eq!(cache.check_signature(vec![4, 5, 6]), Ok(()));
+        assert_eq!(
+            cache.check_signature(vec![1, 2, 3]),
+            Err(ReplayError::Replayed)
+        );
+    }
+
+    #[test]
+    fn expired_signature_forgotten() {
+        let cache = ReplayCache::new(Duration::from_millis(1));
+
+        assert_eq!(cache.check_signature(vec![1, 2, 3]), Ok(()));
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(cache.check_signature(vec![1, 2, 3]), Ok(()));
+    }
 }
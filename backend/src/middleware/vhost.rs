@@ -0,0 +1,235 @@
+//! Virtual hosting of several instances ("tenants") from one process, selected by the Host header
+
+use {
+    actix_web::{
+        dev::RequestHead,
+        guard::{self, Guard},
+        http::header::HOST,
+    },
+    anyhow::{anyhow, bail, Context, Result},
+    rsa::RSAPrivateKey,
+    sqlx::{Pool, Postgres},
+    std::str::FromStr,
+};
+
+/// Instance served alongside the primary one, with its own FQDN, RSA key and database schema
+#[derive(Debug, Clone)]
+pub struct VirtualHost {
+    /// Fully-qualified domain name requests for the instance are addressed to
+    pub fqdn: String,
+    /// Schema the instance's tables are kept in
+    pub schema: String,
+    /// RSA private key
+    pub privkey: RSAPrivateKey,
+}
+
+impl FromStr for VirtualHost {
+    type Err = anyhow::Error;
+
+    /// Parses a virtual host from "fqdn=schema=privkey", where privkey is a base64 encoded PKCS#8
+    /// RSA private key
+    fn from_str(s: &str) -> Result<Self> {
+        // the key may end in base64 padding, so only the first two separators are significant
+        let mut parts = s.trim().splitn(3, '=');
+
+        let (fqdn, schema, privkey) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(fqdn), Some(schema), Some(privkey)) if !fqdn.is_empty() => {
+                (fqdn, schema, privkey)
+            }
+            _ => bail!("Expected virtual host of the form \"fqdn=schema=privkey\""),
+        };
+
+        let privkey = RSAPrivateKey::from_pkcs8(
+            &base64::decode(privkey).context("Decoding virtual host private key")?,
+        )
+        .map_err(|e| anyhow!("Parsing private key of virtual host {}: {}", fqdn, e))?;
+
+        Ok(Self {
+            fqdn: fqdn.to_owned(),
+            schema: schema.to_owned(),
+            privkey,
+        })
+    }
+}
+
+/// Parses a comma-separated list of virtual hosts
+pub fn parse(s: &str) -> Result<Vec<VirtualHost>> {
+    s.split(',')
+        .filter(|vhost| !vhost.trim().is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+/// Creates the schema of a virtual host if it does not exist and applies any outstanding
+/// migrations to it
+///
+/// `pool` must be confined to the schema.
+pub async fn migrate(pool: &Pool<Postgres>, schema: &str) -> Result<()> {
+    sqlx::query(&format!("CREATE SCHEMA IF NOT EXISTS {}", schema))
+        .execute(pool)
+        .await?;
+
+    sqlx::migrate!("./migrations")
+        .run(&mut *pool.acquire().await?)
+        .await
+        .with_context(|| format!("Migrating schema {}", schema))?;
+
+    Ok(())
+}
+
+/// Returns whether a request addressed to `host` is for the instance at `fqdn`, ignoring the port
+/// of the request if the FQDN does not include one
+fn matches(fqdn: &str, host: &str) -> bool {
+    let host = if fqdn.contains(':') {
+        host
+    } else {
+        host.split(':').next().unwrap_or(host)
+    };
+
+    host.eq_ignore_ascii_case(fqdn)
+}
+
+/// Guard matching requests addressed to the instance at `fqdn`
+pub fn guard(fqdn: String) -> impl Guard {
+    guard::fn_guard(move |req: &RequestHead| {
+        req.headers
+            .get(HOST)
+            .and_then(|host| host.to_str().ok())
+            .or_else(|| req.uri.authority().map(|authority| authority.as_str()))
+            .map_or(false, |host| matches(&fqdn, host))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::{matches, parse},
+        crate::test::spawn_instance_with,
+        actix_web::{
+            client::Client,
+            http::{
+                header::{CONTENT_TYPE, HOST},
+                StatusCode,
+            },
+            HttpMessage,
+        },
+        rsa::{PrivateKeyEncoding, RSAPrivateKey},
+    };
+
+    #[test]
+    fn matches_success() {
+        assert!(matches("example.org", "example.org"));
+        assert!(matches("example.org", "Example.ORG:8080"));
+        assert!(matches("127.0.0.1:8080", "127.0.0.1:8080"));
+
+        assert!(!matches("127.0.0.1:8080", "127.0.0.1:8081"));
+        assert!(!matches("example.org", "other.example.org"));
+    }
+
+    #[test]
+    fn parse_success() {
+        let key = RSAPrivateKey::new(&mut rand07::rngs::OsRng, 512).unwrap();
+        let encoded = base64::encode(key.to_pkcs8().unwrap());
+
+        let vhosts = parse(&format!(
+            "a.example=tenant_a={}, b.example:8080=tenant_b={}",
+            encoded, encoded
+        ))
+        .unwrap();
+        assert_eq!(vhosts.len(), 2);
+        assert_eq!(vhosts[0].fqdn, "a.example");
+        assert_eq!(vhosts[0].schema, "tenant_a");
+        assert_eq!(vhosts[0].privkey, key);
+        assert_eq!(vhosts[1].fqdn, "b.example:8080");
+
+        assert!(parse("").unwrap().is_empty());
+        assert!(parse("a.example=tenant_a").is_err());
+        assert!(parse("a.example=tenant_a=notakey").is_err());
+    }
+
+    #[actix_rt::test]
+    async fn virtual_host_isolated_success() {
+        let instance = spawn_instance_with(&["tenant.example"]).await;
+        let client = Client::new();
+
+        // each host serves its own key
+        let primary_key = client
+            .get(format!("{}/fed/key", instance.addr))
+            .send()
+            .await
+            .unwrap()
+            .body()
+            .await
+            .unwrap();
+        let mut res = client
+            .get(format!("{}/fed/key", instance.addr))
+            .header(HOST, "tenant.example")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_ne!(res.body().await.unwrap(), primary_key);
+
+        // communities created on the virtual host are not visible on the primary host
+        let res = client
+            .post(format!("{}/internal/users", instance.addr))
+            .header(HOST, "tenant.example")
+            .header(CONTENT_TYPE, "application/json")
+            .send_body(r#"{"username": "tenantuser", "password": "tenantpassword"}"#)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = client
+            .post(format!("{}/internal/login", instance.addr))
+            .header(HOST, "tenant.example")
+            .header(CONTENT_TYPE, "application/json")
+            .send_body(r#"{"username": "tenantuser", "password": "tenantpassword"}"#)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let cookie = res.cookies().unwrap()[0].clone();
+
+        let res = client
+            .post(format!("{}/internal/communities", instance.addr))
+            .header(HOST, "tenant.example")
+            .header(CONTENT_TYPE, "application/json")
+            .cookie(cookie.clone())
+            .send_body(r#"{"id": "tenantcommunity", "title": "Tenant", "description": "Tenant"}"#)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = client
+            .get(format!(
+                "{}/internal/communities/tenantcommunity",
+                instance.addr
+            ))
+            .header(HOST, "tenant.example")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = client
+            .get(format!(
+                "{}/internal/communities/tenantcommunity",
+                instance.addr
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        // sessions of the virtual host are not valid on the primary host
+        let res = client
+            .post(format!("{}/internal/communities", instance.addr))
+            .header(CONTENT_TYPE, "application/json")
+            .cookie(cookie)
+            .send_body(
+                r#"{"id": "primarycommunity", "title": "Primary", "description": "Primary"}"#,
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+}
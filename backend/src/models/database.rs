@@ -42,8 +42,17 @@ pub struct Post {
     pub content: Value, // JSON representation of Vec<PostContent>
     pub created: i64,
     pub modified: i64,
+    /// Time the post was deleted, leaving a tombstone with blank title and content
+    pub deleted: Option<i64>,
+    /// Sum of the votes on the post
+    pub score: i64,
+    /// Time of the latest reply anywhere beneath a top-level post, if it has any
+    pub last_reply: Option<i64>,
 }
 
+/// Names of the supported PostContent variants as they appear in JSON
+pub const POST_CONTENT_TYPES: [&str; 2] = ["text", "markdown"];
+
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 #[serde(rename_all = "camelCase")]
 pub enum PostContent {
@@ -75,3 +84,58 @@ pub struct Message {
     pub timestamp: i64,
     pub read: bool,
 }
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct Report {
+    pub id: Uuid,
+    pub reporter_username: String,
+    pub reporter_host: String,
+    pub post: Option<Uuid>,
+    pub message: Option<Uuid>,
+    pub community: Option<String>,
+    pub author_username: String,
+    pub author_host: String,
+    pub reason: String,
+    pub created: i64,
+    pub status: String,
+    pub handler_username: Option<String>,
+    pub handler_host: Option<String>,
+    pub handled: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct CommunityBan {
+    pub community: String,
+    pub username: String,
+    pub host: String,
+    pub reason: String,
+    pub created: i64,
+    pub expires: Option<i64>,
+    pub moderator_username: String,
+    pub moderator_host: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct Suspension {
+    pub username: String,
+    pub host: String,
+    pub reason: String,
+    pub created: i64,
+    pub expires: Option<i64>,
+    pub moderator_username: String,
+    pub moderator_host: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct ModerationLogEntry {
+    pub id: Uuid,
+    pub action: String,
+    pub community: Option<String>,
+    pub username: String,
+    pub host: String,
+    pub moderator_username: String,
+    pub moderator_host: String,
+    pub reason: String,
+    pub expires: Option<i64>,
+    pub created: i64,
+}
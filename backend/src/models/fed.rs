@@ -17,10 +17,11 @@ pub struct UserId {
     pub host: String,
 }
 
-impl TryFrom<&str> for UserId {
+/// Parses a "username[@host]" user ID, defaulting to the local host supplied alongside it
+impl TryFrom<(&str, &str)> for UserId {
     type Error = Error;
 
-    fn try_from(value: &str) -> Result<Self, Self::Error> {
+    fn try_from(value: (&str, &str)) -> Result<Self, Self::Error> {
         let user_id = internal::UserId::try_from(value)?;
         Ok(Self {
             id: user_id.username,
@@ -43,6 +44,12 @@ pub struct Community {
 pub struct PostTimestamp {
     pub id: Uuid,
     pub modified: i64,
+    /// Time the post was deleted if it is a tombstone
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted: Option<i64>,
+    /// Sum of the votes on the post, absent from servers without votes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub score: Option<i64>,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
@@ -57,6 +64,12 @@ pub struct Post {
     pub author: UserId,
     pub modified: i64,
     pub created: i64,
+    /// Time the post was deleted if it is a tombstone
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted: Option<i64>,
+    /// Sum of the votes on the post
+    #[serde(default)]
+    pub score: i64,
 }
 
 impl TryFrom<database::Post> for Post {
@@ -76,6 +89,8 @@ impl TryFrom<database::Post> for Post {
             },
             created: db.created,
             modified: db.modified,
+            deleted: db.deleted,
+            score: db.score,
         })
     }
 }
@@ -96,25 +111,111 @@ pub struct PostEdit {
     pub content: Vec<database::PostContent>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct User {
     pub id: String,
     pub posts: Vec<PostId>,
     pub about: String,
     pub avatar_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct PostId {
     pub id: Uuid,
     pub host: String,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Message {
     pub title: String,
     pub content: database::PostContent,
 }
+
+/// Feature flag advertised by servers supporting the includeSubChildrenPosts filter
+pub const INCLUDE_SUB_CHILDREN_POSTS: &str = "includeSubChildrenPosts";
+/// Feature flag advertised by servers supporting GET /fed/communities/{id}/timestamps
+pub const COMMUNITY_TIMESTAMPS: &str = "communityTimestamps";
+/// A user's vote on a post: 1 for an upvote, -1 for a downvote or 0 to withdraw a previous vote
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct Vote {
+    pub value: i16,
+}
+
+/// Score of a post after a vote
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct Score {
+    pub score: i64,
+}
+
+/// Report of a post or message forwarded to its host by the server of the reporting user
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Report {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post: Option<Uuid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<ReportedMessage>,
+    pub reason: String,
+}
+
+/// Message sent by a user of the receiving server to the reporting user, identified by its sender
+/// and contents as servers do not share message IDs
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ReportedMessage {
+    pub sender: String,
+    pub title: String,
+    pub content: database::PostContent,
+}
+
+/// Feature flag advertised by servers that page list endpoints when sent a cursor or limit
+pub const PAGINATION: &str = "pagination";
+/// Feature flag advertised by servers supporting PUT /fed/posts/{id}/vote and post scores
+pub const VOTES: &str = "votes";
+/// Feature flag advertised by servers accepting forwarded reports at POST /fed/reports
+pub const REPORTS: &str = "reports";
+/// Feature flag advertised by servers also accepting forwarded reports of messages
+pub const MESSAGE_REPORTS: &str = "messageReports";
+
+/// Description of a server's software, capabilities and usage
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeInfo {
+    pub software: Software,
+    /// Federation endpoints served, with path parameters in braces
+    pub endpoints: Vec<String>,
+    /// Optional federation features supported
+    pub features: Vec<String>,
+    /// Supported PostContent types
+    pub post_content_types: Vec<String>,
+    pub usage: Usage,
+}
+
+impl NodeInfo {
+    /// Returns whether the server advertises the supplied feature
+    pub fn supports<F: AsRef<str>>(&self, feature: F) -> bool {
+        self.features.iter().any(|f| f == feature.as_ref())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Software {
+    pub name: String,
+    pub version: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Usage {
+    pub users: i64,
+    pub communities: i64,
+    pub posts: i64,
+}
@@ -2,6 +2,7 @@
 
 use {
     crate::{
+        fed::outbox::Delivery,
         models::{
             database::{self, PostContent},
             fed,
@@ -10,7 +11,10 @@ use {
     },
     regex::Regex,
     serde::{Deserialize, Serialize},
-    std::convert::TryFrom,
+    std::{
+        collections::{HashMap, HashSet},
+        convert::TryFrom,
+    },
     uuid::Uuid,
 };
 
@@ -30,10 +34,11 @@ impl From<fed::UserId> for UserId {
     }
 }
 
-impl TryFrom<&str> for UserId {
+/// Parses a "username[@host]" user ID, defaulting to the local host supplied alongside it
+impl TryFrom<(&str, &str)> for UserId {
     type Error = Error;
 
-    fn try_from(value: &str) -> Result<Self, Self::Error> {
+    fn try_from((value, local): (&str, &str)) -> Result<Self, Self::Error> {
         let re = Regex::new("^([a-zA-Z0-9-_]{1,24})(@[a-zA-Z0-9-_.]{3,253}(:[0-9]{1,5})?)?$")
             .expect("Failed to build regular expression");
 
@@ -53,7 +58,7 @@ impl TryFrom<&str> for UserId {
 
         let host = match caps.get(2) {
             Some(s) => s.as_str()[1..].to_owned(),
-            None => crate::host!(),
+            None => local.to_owned(),
         };
 
         Ok(UserId { username, host })
@@ -78,10 +83,25 @@ pub struct NewUser {
 #[serde(rename_all = "camelCase")]
 pub struct User {
     pub username: String,
+    pub host: String,
     pub subscribed: Vec<String>,
     pub moderates: Vec<String>,
+    /// Creation time of local users, 0 for remote users
     pub created: i64,
     pub avatar_url: Option<String>,
+    pub display_name: Option<String>,
+    pub about: String,
+    pub posts: Vec<fed::PostId>,
+}
+
+/// Changes to a user's profile, absent fields are left unchanged and empty strings clear the
+/// display name and avatar
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfileUpdate {
+    pub display_name: Option<String>,
+    pub about: Option<String>,
+    pub avatar_url: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -111,6 +131,9 @@ pub struct Community {
     pub description: String,
     pub moderators: Vec<UserId>,
     pub created: i64,
+    /// Time remote communities were last synchronised, 0 if the first synchronisation is pending
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_synced: Option<i64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -120,6 +143,26 @@ pub struct NewPost {
     pub parent_post: Option<Uuid>,
     pub title: String,
     pub content: Vec<PostContent>,
+    /// Host of the community, defaults to the local host
+    #[serde(default)]
+    pub host: Option<String>,
+}
+
+/// Query parameter selecting the host that owns a resource, defaults to the local host
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct HostQuery {
+    pub host: Option<String>,
+}
+
+impl HostQuery {
+    /// Returns the host if it refers to a remote of the supplied local host
+    pub fn remote(&self, local: &str) -> Option<&str> {
+        match &self.host {
+            Some(host) if host != local => Some(host),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -142,15 +185,25 @@ pub struct Post {
     pub author: UserId,
     pub modified: i64,
     pub created: i64,
+    /// Time posts served from the remote cache were last synchronised
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_synced: Option<i64>,
+    /// Time the post was deleted if it is a tombstone
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted: Option<i64>,
+    /// Sum of the up and down votes on the post
+    #[serde(default)]
+    pub score: i64,
 }
 
-impl TryFrom<database::Post> for Post {
+/// Converts a post stored on the local host supplied alongside it
+impl TryFrom<(database::Post, &str)> for Post {
     type Error = Error;
 
-    fn try_from(db: database::Post) -> Result<Self, self::Error> {
+    fn try_from((db, host): (database::Post, &str)) -> Result<Self, self::Error> {
         Ok(Self {
             id: db.id,
-            host: crate::host!(),
+            host: host.to_owned(),
             community: db.community,
             parent_post: db.parent,
             children: vec![],
@@ -162,10 +215,137 @@ impl TryFrom<database::Post> for Post {
             },
             created: db.created,
             modified: db.modified,
+            last_synced: None,
+            deleted: db.deleted,
+            score: db.score,
         })
     }
 }
 
+impl Post {
+    /// Converts a post received from the supplied remote host, without children
+    pub fn from_fed(post: fed::Post, host: String) -> Self {
+        Self {
+            id: post.id,
+            host,
+            community: post.community,
+            parent_post: post.parent_post,
+            children: vec![],
+            title: post.title,
+            content: post.content,
+            author: post.author.into(),
+            modified: post.modified,
+            created: post.created,
+            last_synced: None,
+            deleted: post.deleted,
+            score: post.score,
+        }
+    }
+
+    /// Nests a flat list of posts into trees, returning the posts whose parent is not in the list
+    pub fn into_trees(posts: Vec<Post>) -> Vec<Post> {
+        fn attach(post: &mut Post, map: &mut HashMap<Uuid, Vec<Post>>) {
+            if let Some(mut children) = map.remove(&post.id) {
+                for child in &mut children {
+                    attach(child, map);
+                }
+                post.children = children;
+            }
+        }
+
+        let ids = posts.iter().map(|p| p.id).collect::<HashSet<_>>();
+
+        let mut roots = vec![];
+        let mut map = HashMap::<Uuid, Vec<Post>>::new();
+        for post in posts {
+            match post.parent_post {
+                Some(parent) if ids.contains(&parent) => map.entry(parent).or_default().push(post),
+                _ => roots.push(post),
+            }
+        }
+
+        for root in &mut roots {
+            attach(root, &mut map);
+        }
+
+        roots
+    }
+}
+
+/// Order of the posts in the home feed
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum FeedSort {
+    /// Most recently created first
+    New,
+    /// Highest score first
+    Top,
+    /// Most recently replied to first
+    Active,
+    /// Highest score and most replies relative to age first
+    Hot,
+}
+
+impl Default for FeedSort {
+    fn default() -> Self {
+        Self::New
+    }
+}
+
+/// Query parameters of the home feed besides its pagination
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct FeedQuery {
+    #[serde(default)]
+    pub sort: FeedSort,
+    /// Community to show the posts of instead of the user's subscriptions
+    pub community: Option<String>,
+    /// Host of the community, defaults to the local host
+    pub host: Option<String>,
+}
+
+/// Version of a post before it was edited
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PostRevision {
+    /// Number of the revision, starting from 1 for the original post
+    pub revision: i32,
+    /// User whose edit replaced this revision
+    pub editor: UserId,
+    /// Time this revision was replaced
+    pub edited: i64,
+    pub title: String,
+    pub content: Vec<PostContent>,
+}
+
+/// Kind of change made to a line
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum DiffChange {
+    Equal,
+    Insert,
+    Delete,
+}
+
+/// Line of a diff between two revisions
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DiffLine {
+    pub change: DiffChange,
+    pub text: String,
+}
+
+/// Changes made to a post by the edit that replaced a revision
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RevisionDiff {
+    pub revision: i32,
+    pub editor: UserId,
+    pub edited: i64,
+    pub title: Vec<DiffLine>,
+    pub content: Vec<DiffLine>,
+}
+
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct Message {
@@ -200,6 +380,255 @@ impl TryFrom<database::Message> for Message {
     }
 }
 
+/// Queued outbound federation delivery
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct OutboxItem {
+    pub id: Uuid,
+    pub host: String,
+    pub delivery: Delivery,
+    pub status: String,
+    pub attempts: i32,
+    pub next_attempt: i64,
+    pub last_error: Option<String>,
+    pub created: i64,
+}
+
+/// Filters for GET /internal/outbox requests
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct OutboxFilters {
+    pub status: Option<String>,
+}
+
+/// Options for POST /internal/remotes/{remote} requests
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct RemoteOptions {
+    /// Whether the remote may be reached over plain HTTP if HTTPS fails, defaults to true
+    pub allow_http: Option<bool>,
+}
+
+/// Federation policy towards a remote host
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FederationPolicy {
+    pub host: String,
+    pub mode: String,
+    pub reason: String,
+    pub created: i64,
+}
+
+/// Body of PUT /internal/federation/policies/{host} requests
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct NewFederationPolicy {
+    pub mode: String,
+    #[serde(default)]
+    pub reason: String,
+}
+
+/// Health of a remote as observed by the federation client
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RemoteHealth {
+    pub host: String,
+    /// State of the circuit breaker, one of "closed", "open" or "halfOpen"
+    pub circuit: String,
+    pub requests: u64,
+    pub failures: u64,
+    pub consecutive_failures: u32,
+    /// Moving average of request latency in milliseconds
+    pub average_latency_ms: Option<u64>,
+    pub last_error: Option<String>,
+    pub last_success: Option<i64>,
+    pub last_failure: Option<i64>,
+}
+
+/// Body of POST /internal/reports requests, reporting either a post or a message
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct NewReport {
+    /// Reported post or comment
+    pub post: Option<Uuid>,
+    /// Host of the reported post, defaults to the local host
+    pub host: Option<String>,
+    /// Reported message, which must have been received by the reporter
+    pub message: Option<Uuid>,
+    pub reason: String,
+}
+
+/// Status of a report awaiting a moderator or admin
+pub const REPORT_OPEN: &str = "open";
+/// Status of a report that was acted upon
+pub const REPORT_RESOLVED: &str = "resolved";
+/// Status of a report that was rejected without action
+pub const REPORT_DISMISSED: &str = "dismissed";
+
+/// Report of a post or message awaiting or having received the attention of a moderator or admin
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Report {
+    pub id: Uuid,
+    pub reporter: UserId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post: Option<Uuid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<Uuid>,
+    /// Community of the reported post, whose moderators handle the report
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub community: Option<String>,
+    /// Author of the reported post or sender of the reported message
+    pub author: UserId,
+    pub reason: String,
+    pub created: i64,
+    /// One of "open", "resolved" or "dismissed"
+    pub status: String,
+    /// Moderator or admin that resolved or dismissed the report
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub handler: Option<UserId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub handled: Option<i64>,
+}
+
+impl From<database::Report> for Report {
+    fn from(db: database::Report) -> Self {
+        Self {
+            id: db.id,
+            reporter: UserId {
+                username: db.reporter_username,
+                host: db.reporter_host,
+            },
+            post: db.post,
+            message: db.message,
+            community: db.community,
+            author: UserId {
+                username: db.author_username,
+                host: db.author_host,
+            },
+            reason: db.reason,
+            created: db.created,
+            status: db.status,
+            handler: match (db.handler_username, db.handler_host) {
+                (Some(username), Some(host)) => Some(UserId { username, host }),
+                _ => None,
+            },
+            handled: db.handled,
+        }
+    }
+}
+
+/// Body of requests banning a user from a community or suspending them from the server
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct NewBan {
+    pub reason: String,
+    /// Time the ban is lifted at, or never if absent
+    pub expires: Option<i64>,
+}
+
+/// Ban of a user from posting in a community, or suspension from the whole server if it has no
+/// community
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Ban {
+    pub user: UserId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub community: Option<String>,
+    pub reason: String,
+    pub created: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<i64>,
+    /// Moderator or admin that issued the ban
+    pub moderator: UserId,
+}
+
+impl From<database::CommunityBan> for Ban {
+    fn from(db: database::CommunityBan) -> Self {
+        Self {
+            user: UserId {
+                username: db.username,
+                host: db.host,
+            },
+            community: Some(db.community),
+            reason: db.reason,
+            created: db.created,
+            expires: db.expires,
+            moderator: UserId {
+                username: db.moderator_username,
+                host: db.moderator_host,
+            },
+        }
+    }
+}
+
+impl From<database::Suspension> for Ban {
+    fn from(db: database::Suspension) -> Self {
+        Self {
+            user: UserId {
+                username: db.username,
+                host: db.host,
+            },
+            community: None,
+            reason: db.reason,
+            created: db.created,
+            expires: db.expires,
+            moderator: UserId {
+                username: db.moderator_username,
+                host: db.moderator_host,
+            },
+        }
+    }
+}
+
+/// Moderation log action of banning a user from a community
+pub const MODERATION_BAN: &str = "ban";
+/// Moderation log action of lifting a ban before it expired
+pub const MODERATION_UNBAN: &str = "unban";
+/// Moderation log action of suspending a user from the server
+pub const MODERATION_SUSPEND: &str = "suspend";
+/// Moderation log action of lifting a suspension before it expired
+pub const MODERATION_UNSUSPEND: &str = "unsuspend";
+
+/// Entry of the moderation log, recording a ban or suspension being issued or lifted
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ModerationLogEntry {
+    pub id: Uuid,
+    /// One of "ban", "unban", "suspend" or "unsuspend"
+    pub action: String,
+    /// Community of bans, absent for suspensions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub community: Option<String>,
+    pub user: UserId,
+    pub moderator: UserId,
+    pub reason: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<i64>,
+    pub created: i64,
+}
+
+impl From<database::ModerationLogEntry> for ModerationLogEntry {
+    fn from(db: database::ModerationLogEntry) -> Self {
+        Self {
+            id: db.id,
+            action: db.action,
+            community: db.community,
+            user: UserId {
+                username: db.username,
+                host: db.host,
+            },
+            moderator: UserId {
+                username: db.moderator_username,
+                host: db.moderator_host,
+            },
+            reason: db.reason,
+            expires: db.expires,
+            created: db.created,
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use {super::UserId, proptest::prelude::*, std::convert::TryFrom};
@@ -207,30 +636,21 @@ mod test {
     proptest! {
         #[test]
         fn userid_username_parse(username in "[a-zA-Z0-9-_]{1,24}") {
-            // set in case these tests run before server initialisation
-            crate::HOST.set("example.org".to_owned()).ok();
-
-            let id = UserId::try_from(username.as_str()).unwrap();
+            let id = UserId::try_from((username.as_str(), "example.org")).unwrap();
             assert_eq!(id.username, username);
-            assert_eq!(Some(&id.host), crate::HOST.get());
+            assert_eq!(id.host, "example.org");
         }
 
         #[test]
         fn userid_hostname_parse(username in "[a-zA-Z0-9-_]{1,24}", domain in "[a-zA-Z0-9-_.]{3,253}") {
-            // set in case these tests run before server initialisation
-            crate::HOST.set("example.org".to_owned()).ok();
-
-            let id = UserId::try_from(format!("{}@{}", username, domain).as_str()).unwrap();
+            let id = UserId::try_from((format!("{}@{}", username, domain).as_str(), "example.org")).unwrap();
             assert_eq!(id.username, username);
             assert_eq!(id.host, domain);
         }
 
         #[test]
         fn userid_hostname_with_port_parse(username in "[a-zA-Z0-9-_]{1,24}", domain in "[a-zA-Z0-9-_.]{3,253}", port in 0u16..65535) {
-            // set in case these tests run before server initialisation
-            crate::HOST.set("example.org".to_owned()).ok();
-
-            let id = UserId::try_from(format!("{}@{}:{}", username, domain, port).as_str()).unwrap();
+            let id = UserId::try_from((format!("{}@{}:{}", username, domain, port).as_str(), "example.org")).unwrap();
             assert_eq!(id.username, username);
             assert_eq!(id.host, format!("{}:{}", domain, port));
         }
@@ -1,5 +1,6 @@
 //! Database, request and response structures
 
+pub mod activitypub;
 pub mod database;
 pub mod fed;
 pub mod internal;
@@ -0,0 +1,167 @@
+//! Cursor pagination of list endpoints
+//!
+//! Lists are ordered by a unique key, and a cursor encodes the key of the last item of a page so
+//! that the next page starts after it even if items are added or removed in the meantime.
+
+use {
+    crate::Error,
+    actix_web::{dev::Payload, FromRequest, HttpRequest},
+    anyhow::anyhow,
+    futures::future::{ready, Ready},
+    serde::{de::DeserializeOwned, Deserialize, Serialize},
+};
+
+/// Number of items in a page if no limit is requested
+pub const DEFAULT_LIMIT: i64 = 25;
+/// Largest number of items that may be requested in one page
+pub const MAX_LIMIT: i64 = 100;
+
+/// Page of a list, along with the cursor to request the following page with if there is one
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+}
+
+/// Query parameters of a paginated request
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PageQuery {
+    /// Cursor returned with the previous page
+    pub cursor: Option<String>,
+    /// Maximum number of items to return
+    pub limit: Option<i64>,
+}
+
+/// Pagination parameters of a list request, extracted from its query string alongside any other
+/// query parameters
+#[derive(Debug, Clone)]
+pub struct Pagination {
+    cursor: Option<String>,
+    limit: i64,
+    requested: bool,
+}
+
+impl FromRequest for Pagination {
+    type Error = Error;
+    type Future = Ready<Result<Self, Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(
+            serde_urlencoded::from_str::<PageQuery>(req.query_string())
+                .map_err(|e| Error::BadRequest(e.into()))
+                .map(|query| Self {
+                    requested: query.cursor.is_some() || query.limit.is_some(),
+                    cursor: query.cursor,
+                    limit: query.limit.unwrap_or(DEFAULT_LIMIT).max(1).min(MAX_LIMIT),
+                }),
+        )
+    }
+}
+
+impl Pagination {
+    /// Returns whether the request asked for a page
+    ///
+    /// Federation routes return the whole list to servers that do not, as the protocol predates
+    /// pagination.
+    pub fn requested(&self) -> bool {
+        self.requested
+    }
+
+    /// Maximum number of items in the page
+    pub fn limit(&self) -> i64 {
+        self.limit
+    }
+
+    /// Number of items to fetch to fill the page, one more than it holds so that `page` can tell
+    /// whether another page follows
+    pub fn fetch_limit(&self) -> i64 {
+        self.limit + 1
+    }
+
+    /// Decodes the key of the last item of the previous page, if any
+    pub fn after<K: DeserializeOwned>(&self) -> Result<Option<K>, Error> {
+        self.cursor.as_deref().map(decode_cursor).transpose()
+    }
+
+    /// Builds a page from the items following the cursor in list order, fetched with
+    /// `fetch_limit`, using the supplied key of the last item as the cursor of the next page
+    pub fn page<T, K: Serialize, F: Fn(&T) -> K>(
+        &self,
+        mut items: Vec<T>,
+        key: F,
+    ) -> Result<Page<T>, Error> {
+        if items.len() as i64 <= self.limit {
+            return Ok(Page {
+                items,
+                next_cursor: None,
+            });
+        }
+
+        items.truncate(self.limit as usize);
+        let next_cursor = items
+            .last()
+            .map(|last| encode_cursor(&key(last)))
+            .transpose()?;
+
+        Ok(Page { items, next_cursor })
+    }
+}
+
+/// Encodes the position of an item in a list as an opaque cursor
+pub fn encode_cursor<T: Serialize>(position: &T) -> Result<String, Error> {
+    Ok(base64::encode_config(
+        serde_json::to_vec(position)?,
+        base64::URL_SAFE_NO_PAD,
+    ))
+}
+
+/// Decodes a cursor produced by `encode_cursor`
+pub fn decode_cursor<T: DeserializeOwned>(cursor: &str) -> Result<T, Error> {
+    let bytes = base64::decode_config(cursor, base64::URL_SAFE_NO_PAD)
+        .map_err(|e| Error::BadRequest(anyhow!("Invalid cursor: {}", e)))?;
+
+    serde_json::from_slice(&bytes).map_err(|e| Error::BadRequest(anyhow!("Invalid cursor: {}", e)))
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::{Pagination, MAX_LIMIT},
+        actix_web::{test::TestRequest, FromRequest},
+    };
+
+    async fn extract(query: &str) -> Pagination {
+        let (req, mut payload) = TestRequest::with_uri(&format!("/list?{}", query)).to_http_parts();
+        Pagination::from_request(&req, &mut payload).await.unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn page_success() {
+        let pagination = extract("limit=2&prefix=ignored").await;
+        assert!(pagination.requested());
+
+        let page = pagination.page(vec![1, 2, 3], |i| *i).unwrap();
+        assert_eq!(page.items, vec![1, 2]);
+
+        let next = extract(&format!("cursor={}", page.next_cursor.unwrap())).await;
+        assert_eq!(next.after::<i32>().unwrap(), Some(2));
+
+        let page = pagination.page(vec![3], |i| *i).unwrap();
+        assert_eq!(page.items, vec![3]);
+        assert!(page.next_cursor.is_none());
+    }
+
+    #[actix_rt::test]
+    async fn limit_bounds_success() {
+        let pagination = extract("").await;
+        assert!(!pagination.requested());
+        assert!(pagination.after::<i32>().unwrap().is_none());
+
+        assert_eq!(extract("limit=0").await.limit(), 1);
+        assert_eq!(extract("limit=100000").await.limit(), MAX_LIMIT);
+        assert!(extract("cursor=notacursor").await.after::<i32>().is_err());
+    }
+}
@@ -1,22 +1,43 @@
 use {
-    crate::Config,
+    crate::{
+        fed::signature::{self, SignatureHeader},
+        pagination::Page,
+        Config,
+    },
     actix_rt::time::delay_for,
     actix_web::{
-        client::Client,
-        http::{header::CONTENT_TYPE, Cookie, StatusCode},
-        HttpMessage,
+        client::{Client, ClientRequest},
+        http::{
+            header::{Date, CONTENT_TYPE},
+            Cookie, Method, StatusCode,
+        },
+        web, App, HttpMessage, HttpServer,
     },
     once_cell::sync::Lazy,
-    sqlx::Connection,
+    rsa::{PrivateKeyEncoding, PublicKeyEncoding, RSAPrivateKey, RSAPublicKey},
+    rustls::ServerConfig,
+    serde::de::DeserializeOwned,
+    sha2::{Digest, Sha512},
+    sqlx::{postgres::PgPoolOptions, Connection, Pool, Postgres},
     std::{
+        net::{SocketAddr, TcpListener},
+        sync::atomic::{AtomicU8, Ordering},
         thread,
-        time::{Duration, Instant},
+        time::{Duration, Instant, SystemTime},
     },
 };
 
 /// Seconds to wait for backend to start
 const TIMEOUT: u64 = 5;
 
+/// FQDN of instance of backend at ADDR
+pub static FQDN: Lazy<String> = Lazy::new(|| {
+    // load environment variables from .env file, failing silently
+    dotenv::dotenv().ok();
+
+    envy::from_env::<Config>().unwrap().fqdn
+});
+
 /// Address of instance of backend
 ///
 /// First dereference will destroy the database and start a new instance of the backend, will
@@ -101,6 +122,11 @@ async fn drop_tables(addr: &str) {
 
 /// Creates a new user and logs in
 pub async fn new_user_login() -> (Client, String, Cookie<'static>) {
+    new_user_login_at(&ADDR).await
+}
+
+/// Creates a new user on the backend at the supplied address and logs in
+pub async fn new_user_login_at(addr: &str) -> (Client, String, Cookie<'static>) {
     let client = Client::new();
 
     let username = format!("{}", rand::random::<u16>());
@@ -108,7 +134,7 @@ pub async fn new_user_login() -> (Client, String, Cookie<'static>) {
 
     // Create a user
     let res = client
-        .post(format!("{}/internal/users", *ADDR))
+        .post(format!("{}/internal/users", addr))
         .header(CONTENT_TYPE, "application/json")
         .send_body(format!(
             "{{\"username\":\"{}\",\"password\":\"{}\"}}",
@@ -120,7 +146,7 @@ pub async fn new_user_login() -> (Client, String, Cookie<'static>) {
 
     // Login succesfully
     let res = client
-        .post(format!("{}/internal/login", *ADDR))
+        .post(format!("{}/internal/login", addr))
         .header(CONTENT_TYPE, "application/json")
         .send_body(format!(
             "{{\"username\":\"{}\",\"password\":\"{}\"}}",
@@ -138,6 +164,50 @@ pub async fn new_user_login() -> (Client, String, Cookie<'static>) {
     (client, username, cookie)
 }
 
+/// Requests every page of a paginated list, starting from the supplied URL
+pub async fn get_all_pages<T: DeserializeOwned>(
+    client: &Client,
+    url: &str,
+    cookie: &Cookie<'static>,
+) -> Vec<T> {
+    let separator = if url.contains('?') { '&' } else { '?' };
+    let mut items = vec![];
+    let mut next = url.to_owned();
+
+    loop {
+        let mut res = client
+            .get(&next)
+            .cookie(cookie.clone())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let page = res.json::<Page<T>>().limit(1 << 20).await.unwrap();
+        items.extend(page.items);
+
+        match page.next_cursor {
+            Some(cursor) => next = format!("{}{}cursor={}", url, separator, cursor),
+            None => return items,
+        }
+    }
+}
+
+/// Opens a connection pool to the database of the running backend and loads its private key
+pub async fn connect() -> (Pool<Postgres>, RSAPrivateKey) {
+    // ensure that the backend is running and migrations are applied
+    Lazy::force(&ADDR);
+
+    let config = envy::from_env::<Config>().unwrap();
+    let pool = PgPoolOptions::new()
+        .max_connections(1)
+        .connect(&config.database_url)
+        .await
+        .unwrap();
+    let privkey = RSAPrivateKey::from_pkcs8(&base64::decode(&config.privkey).unwrap()).unwrap();
+
+    (pool, privkey)
+}
+
 /// Gives the supplied user admin privileges
 pub async fn make_admin<A: AsRef<str>, B: AsRef<str>>(username: A, host: B) {
     let mut conn = sqlx::PgConnection::connect(&envy::from_env::<Config>().unwrap().database_url)
@@ -155,3 +225,296 @@ pub async fn make_admin<A: AsRef<str>, B: AsRef<str>>(username: A, host: B) {
     .await
     .unwrap();
 }
+
+/// Makes the supplied user a moderator of the supplied community
+pub async fn make_moderator<A: AsRef<str>, B: AsRef<str>, C: AsRef<str>>(
+    username: A,
+    host: B,
+    community: C,
+) {
+    let mut conn = sqlx::PgConnection::connect(&envy::from_env::<Config>().unwrap().database_url)
+        .await
+        .unwrap();
+
+    sqlx::query!(
+        r#"
+            INSERT INTO moderators VALUES ($1, $2, $3)
+        "#,
+        username.as_ref(),
+        host.as_ref(),
+        community.as_ref(),
+    )
+    .execute(&mut conn)
+    .await
+    .unwrap();
+}
+
+/// Trusts the backend's own key for the supplied remote host, returning the key
+pub async fn add_remote(host: &str) -> RSAPrivateKey {
+    let config = envy::from_env::<Config>().unwrap();
+    let privkey = RSAPrivateKey::from_pkcs8(&base64::decode(&config.privkey).unwrap()).unwrap();
+
+    let mut conn = sqlx::PgConnection::connect(&config.database_url)
+        .await
+        .unwrap();
+
+    sqlx::query!(
+        r#"
+            INSERT INTO remotes VALUES ($1, $2)
+            ON CONFLICT DO NOTHING
+        "#,
+        host,
+        RSAPublicKey::from(&privkey).to_pkcs8().unwrap()
+    )
+    .execute(&mut conn)
+    .await
+    .unwrap();
+
+    privkey
+}
+
+/// Builds a federation request signed with the backend's own key as though it were sent by
+/// `client_host`, trusting that key for `client_host` if it is not already a remote
+pub async fn signed_request(
+    method: Method,
+    path: &str,
+    client_host: &str,
+    user_id: Option<&str>,
+    body: &str,
+) -> ClientRequest {
+    let privkey = add_remote(client_host).await;
+
+    let mut req = Client::new()
+        .request(method, format!("{}{}", *ADDR, path))
+        .set(Date(SystemTime::now().into()))
+        .header("Client-Host", client_host)
+        .header(
+            "Digest",
+            format!(
+                "SHA-512={}",
+                base64::encode(Sha512::digest(body.as_bytes()))
+            ),
+        )
+        .header(CONTENT_TYPE, "application/json");
+    if let Some(user_id) = user_id {
+        req = req.header("User-ID", user_id);
+    }
+
+    let mut header = SignatureHeader {
+        key_id: "global".to_owned(),
+        algorithm: Some("rsa-sha512".to_owned()),
+        headers: vec![
+            "(request-target)",
+            "host",
+            "client-host",
+            "user-id",
+            "date",
+            "digest",
+        ]
+        .into_iter()
+        .filter(|h| *h != "user-id" || user_id.is_some())
+        .map(str::to_owned)
+        .collect(),
+        signature: vec![],
+    };
+
+    let host = req.get_uri().authority().unwrap().to_string();
+    let input = header
+        .signing_string(
+            req.get_method().as_str(),
+            signature::request_target(req.get_uri()),
+            |name| match name {
+                "host" => Some(host.clone()),
+                _ => req
+                    .headers()
+                    .get(name)
+                    .map(|v| v.to_str().unwrap().to_owned()),
+            },
+        )
+        .unwrap();
+    header.signature = signature::sign(&privkey, &input).unwrap();
+
+    req.header("Signature", header.to_string())
+}
+
+/// Starts a stand-in remote on the supplied IP serving the supplied routes, over TLS if a server
+/// configuration is supplied
+pub fn stand_in<F>(ip: &str, routes: F, tls: Option<ServerConfig>) -> SocketAddr
+where
+    F: Fn(&mut web::ServiceConfig) + Send + Clone + 'static,
+{
+    let server = HttpServer::new(move || App::new().configure(routes.clone())).workers(1);
+    let server = match tls {
+        Some(config) => server.bind_rustls((ip, 0), config),
+        None => server.bind((ip, 0)),
+    }
+    .unwrap();
+    let addr = server.addrs()[0];
+    server.run();
+
+    addr
+}
+
+/// Number of instances started by spawn_instance
+static INSTANCES: AtomicU8 = AtomicU8::new(0);
+
+/// Backend instance started alongside the one at ADDR, with its own FQDN, RSA key and database
+/// schema
+pub struct Instance {
+    /// FQDN of the instance, which is also the address it listens on
+    pub fqdn: String,
+    /// Base URL of the instance
+    pub addr: String,
+    /// Connection pool confined to the instance's schema
+    pub pool: Pool<Postgres>,
+    /// RSA private key of the instance
+    pub privkey: RSAPrivateKey,
+}
+
+impl Instance {
+    /// Creates a new user on the instance and logs in
+    pub async fn new_user_login(&self) -> (Client, String, Cookie<'static>) {
+        new_user_login_at(&self.addr).await
+    }
+
+    /// Gives the supplied user of the instance admin privileges
+    pub async fn make_admin<U: AsRef<str>>(&self, username: U) {
+        sqlx::query!(
+            r#"
+                INSERT INTO admins VALUES ($1, $2)
+            "#,
+            username.as_ref(),
+            self.fqdn,
+        )
+        .execute(&self.pool)
+        .await
+        .unwrap();
+    }
+
+    /// Adds another instance as a remote of this one through the internal API, which fetches its
+    /// key
+    pub async fn add_remote(&self, other: &Instance) {
+        let (client, username, cookie) = self.new_user_login().await;
+        self.make_admin(&username).await;
+
+        let res = client
+            .post(format!("{}/internal/remotes/{}", self.addr, other.fqdn))
+            .cookie(cookie)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+}
+
+/// Starts a new backend instance on its own loopback address, isolated from every other instance
+/// in its own database schema, and waits for it to respond
+pub async fn spawn_instance() -> Instance {
+    spawn_instance_with(&[]).await
+}
+
+/// Starts a new backend instance as spawn_instance does, that also serves the supplied virtual
+/// hosts from fresh schemas of their own
+pub async fn spawn_instance_with(virtual_hosts: &[&str]) -> Instance {
+    // the instance at ADDR recreates the public schema, which must not happen after other
+    // instances have started using the extensions in it
+    Lazy::force(&ADDR);
+
+    let n = INSTANCES.fetch_add(1, Ordering::SeqCst);
+    let ip = format!("127.0.0.{}", 10 + n);
+    let schema = format!("instance_{}", n);
+
+    // reserve a free port by binding to it, releasing it for the instance to bind
+    let port = TcpListener::bind((ip.as_str(), 0))
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let fqdn = format!("{}:{}", ip, port);
+
+    // small keys keep key generation fast in debug builds
+    let privkey = RSAPrivateKey::new(&mut rand07::rngs::OsRng, 1024).unwrap();
+
+    let virtual_hosts = virtual_hosts
+        .iter()
+        .enumerate()
+        .map(|(i, vhost)| {
+            let key = RSAPrivateKey::new(&mut rand07::rngs::OsRng, 1024).unwrap();
+            (
+                vhost.to_string(),
+                format!("{}_vhost_{}", schema, i),
+                base64::encode(key.to_pkcs8().unwrap()),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let config = Config {
+        virtual_hosts: Some(
+            virtual_hosts
+                .iter()
+                .map(|(fqdn, schema, privkey)| format!("{}={}={}", fqdn, schema, privkey))
+                .collect::<Vec<_>>()
+                .join(","),
+        ),
+        fqdn: fqdn.clone(),
+        web_addr: fqdn.clone(),
+        database_schema: Some(schema.clone()),
+        privkey: base64::encode(privkey.to_pkcs8().unwrap()),
+        outbox_interval: Some(1),
+        // tests synchronise explicitly
+        remote_sync_interval: Some(60 * 60),
+        ..envy::from_env::<Config>().unwrap()
+    };
+
+    let pool = crate::connect_database(&config.database_url, Some(&schema), 2)
+        .await
+        .unwrap();
+    for statement in &[
+        format!("DROP SCHEMA IF EXISTS {} CASCADE", schema),
+        format!("CREATE SCHEMA {}", schema),
+    ] {
+        sqlx::query(statement).execute(&pool).await.unwrap();
+    }
+    // the schemas of virtual hosts are created and migrated by the instance
+    for (_, schema, _) in &virtual_hosts {
+        sqlx::query(&format!("DROP SCHEMA IF EXISTS {} CASCADE", schema))
+            .execute(&pool)
+            .await
+            .unwrap();
+    }
+    sqlx::migrate!("./migrations")
+        .run(&mut *pool.acquire().await.unwrap())
+        .await
+        .unwrap();
+
+    thread::spawn(move || {
+        actix::run(async move { crate::run(config).await.unwrap() }).unwrap();
+    });
+
+    let addr = format!("http://{}", fqdn);
+    let client = Client::new();
+    let start = Instant::now();
+    loop {
+        if start.elapsed().as_secs() >= TIMEOUT {
+            panic!(
+                "Instance {} failed to start within {} seconds",
+                fqdn, TIMEOUT
+            );
+        }
+
+        if let Ok(res) = client.get(format!("{}/fed/nodeinfo", addr)).send().await {
+            if res.status().is_success() {
+                break;
+            }
+        }
+
+        delay_for(Duration::from_millis(250)).await;
+    }
+
+    Instance {
+        fqdn,
+        addr,
+        pool,
+        privkey,
+    }
+}
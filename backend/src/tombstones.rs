@@ -0,0 +1,201 @@
+//! Deleted posts are kept as tombstones so replies to them stay attached to the tree, and purged
+//! once they are old enough that remotes have seen them and no replies remain
+
+use {
+    crate::Error,
+    actix_rt::time::delay_for,
+    log::{debug, error},
+    sqlx::{Pool, Postgres},
+    std::time::Duration,
+    uuid::Uuid,
+};
+
+/// Default time tombstones are kept for before they may be purged
+pub const DEFAULT_RETENTION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Interval between purges of expired tombstones
+const INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Replaces a post with a tombstone, blanking its title and content and forgetting its revisions
+/// but keeping its place in the tree, returning whether a live post was deleted
+pub(crate) async fn tombstone(id: Uuid, pool: &Pool<Postgres>) -> Result<bool, Error> {
+    let now = chrono::Local::now().timestamp();
+    let mut tx = pool.begin().await?;
+
+    let result = sqlx::query!(
+        r#"
+            UPDATE posts
+            SET title = '', content = '[]'::JSONB, modified = $1, deleted = $1
+            WHERE id = $2
+            AND deleted IS NULL
+        "#,
+        now,
+        id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    // earlier versions would otherwise keep the content that was blanked
+    sqlx::query!(
+        r#"
+            DELETE FROM post_revisions
+            WHERE post = $1
+        "#,
+        id
+    )
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Removes tombstones deleted longer than `retention` ago that have no remaining replies,
+/// returning the number of posts removed
+///
+/// A tombstone whose only replies are themselves expired tombstones is removed along with them.
+pub async fn purge(retention: Duration, pool: &Pool<Postgres>) -> Result<u64, Error> {
+    let cutoff = chrono::Local::now().timestamp() - retention.as_secs() as i64;
+    let mut purged = 0;
+
+    // remove the leaves of expired tombstone chains until none are left
+    loop {
+        let removed = sqlx::query!(
+            r#"
+                DELETE FROM posts
+                WHERE deleted < $1
+                AND NOT EXISTS (SELECT 1 FROM posts AS children WHERE children.parent = posts.id)
+            "#,
+            cutoff
+        )
+        .execute(pool)
+        .await?
+        .rows_affected();
+
+        if removed == 0 {
+            return Ok(purged);
+        }
+        purged += removed;
+    }
+}
+
+/// Spawns a task on the current system that purges expired tombstones every hour
+pub fn spawn_worker(pool: Pool<Postgres>, retention: Duration) {
+    actix_rt::spawn(async move {
+        loop {
+            match purge(retention, &pool).await {
+                Ok(0) => {}
+                Ok(n) => debug!("tombstones: purged {} posts", n),
+                Err(e) => error!("tombstones: failed to purge expired tombstones: {}", e),
+            }
+
+            delay_for(INTERVAL).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::{purge, tombstone},
+        crate::test::connect,
+        std::time::Duration,
+        uuid::Uuid,
+    };
+
+    #[actix_rt::test]
+    async fn purge_success() {
+        let (pool, _) = connect().await;
+
+        sqlx::query!(
+            r#"
+                INSERT INTO users VALUES ('tombstoner', 'tombstones.example')
+                ON CONFLICT DO NOTHING
+            "#
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        sqlx::query!(
+            r#"
+                INSERT INTO communities VALUES ('tombstones', 'Tombstones', 'Purged posts', 0)
+                ON CONFLICT DO NOTHING
+            "#
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        // root <- reply <- nested, and root <- live
+        let (root, reply, nested, live) = (
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+        );
+        for (id, parent) in &[
+            (root, None),
+            (reply, Some(root)),
+            (nested, Some(reply)),
+            (live, Some(root)),
+        ] {
+            sqlx::query!(
+                r#"
+                    INSERT INTO posts
+                    VALUES ($1, 'tombstones', $2, 'tombstoner', 'tombstones.example', 'Title',
+                        '[]'::JSONB, 0, 0)
+                "#,
+                id,
+                *parent
+            )
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        assert!(tombstone(root, &pool).await.unwrap());
+        assert!(tombstone(reply, &pool).await.unwrap());
+        assert!(tombstone(nested, &pool).await.unwrap());
+        // deleting a tombstone again has no effect
+        assert!(!tombstone(nested, &pool).await.unwrap());
+
+        let post = sqlx::query!("SELECT title, deleted FROM posts WHERE id = $1", root)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(post.title, "");
+        assert!(post.deleted.is_some());
+
+        // fresh tombstones are kept
+        purge(Duration::from_secs(60), &pool).await.unwrap();
+        let count = sqlx::query!(
+            r#"SELECT COUNT(*) AS "count!" FROM posts WHERE community = 'tombstones'"#
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap()
+        .count;
+        assert_eq!(count, 4);
+
+        // expired tombstones are removed leaf first, but the root still has a live reply
+        sqlx::query!(
+            "UPDATE posts SET deleted = deleted - 120 WHERE community = 'tombstones' AND deleted IS NOT NULL"
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        purge(Duration::from_secs(60), &pool).await.unwrap();
+
+        let remaining = sqlx::query!("SELECT id FROM posts WHERE community = 'tombstones'")
+            .fetch_all(&pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|row| row.id)
+            .collect::<Vec<_>>();
+        assert_eq!(remaining.len(), 2);
+        assert!(remaining.contains(&root));
+        assert!(remaining.contains(&live));
+    }
+}
@@ -1,9 +1,21 @@
 use {
-    crate::Error,
-    actix_web::HttpRequest,
+    crate::{
+        models::{
+            database,
+            fed::Vote,
+            internal::{self, NewBan, REPORT_OPEN},
+        },
+        Error,
+    },
+    actix_web::{
+        http::uri::{Authority, Scheme, Uri},
+        HttpRequest,
+    },
     anyhow::{anyhow, Result},
     regex::Regex,
-    sqlx::{Pool, Postgres},
+    sqlx::{Pool, Postgres, Transaction},
+    std::collections::HashMap,
+    uuid::Uuid,
 };
 
 /// Returns whether the supplied user exists
@@ -31,6 +43,382 @@ pub(crate) async fn user_exists<U: AsRef<str>, H: AsRef<str>>(
     }
 }
 
+/// Normalises an authority to the lowercased host it refers to, without any port, as hosts are
+/// identified by in federation policies
+pub(crate) fn normalise_host<H: AsRef<str>>(authority: H) -> Result<String, Error> {
+    Ok(authority
+        .as_ref()
+        .parse::<Authority>()
+        .map_err(|e| Error::BadRequest(e.into()))?
+        .host()
+        .to_ascii_lowercase())
+}
+
+/// Returns whether the supplied authority refers to a known remote, ignoring any port
+pub(crate) async fn is_known_remote<H: AsRef<str>>(
+    remote: H,
+    pool: &Pool<Postgres>,
+) -> Result<bool, Error> {
+    let authority = remote
+        .as_ref()
+        .parse::<Authority>()
+        .map_err(|e| Error::BadRequest(e.into()))?;
+
+    match sqlx::query!(
+        r#"
+            SELECT EXISTS(
+                SELECT 1 FROM remotes
+                WHERE host = $1
+            )
+        "#,
+        authority.host()
+    )
+    .fetch_one(pool)
+    .await?
+    .exists
+    {
+        Some(exists) => Ok(exists),
+        None => Err(sqlx::error::Error::RowNotFound.into()),
+    }
+}
+
+/// Effective federation policy towards a host
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) enum Policy {
+    /// Federate normally
+    Allowed,
+    /// Accept requests, but leave the host out of fan-outs and discovery
+    Silenced,
+    /// Refuse all federation with the host
+    Blocked,
+}
+
+/// Returned when federating with a host is refused by policy
+#[derive(thiserror::Error, Debug, PartialEq, Clone)]
+#[error("Federation with \"{0}\" is blocked by policy")]
+pub struct Defederated(pub String);
+
+/// Returns the effective federation policy towards the supplied host, ignoring any port
+///
+/// Hosts without a policy are allowed unless any host is explicitly allowed, in which case only
+/// allowed hosts are federated with.
+pub(crate) async fn federation_policy<H: AsRef<str>>(
+    host: H,
+    pool: &Pool<Postgres>,
+) -> Result<Policy, Error> {
+    let host = normalise_host(host)?;
+
+    let row = sqlx::query!(
+        r#"
+            SELECT
+                (SELECT mode FROM federation_policies WHERE host = $1) AS mode,
+                EXISTS(SELECT 1 FROM federation_policies WHERE mode = 'allow') AS "allowlist!"
+        "#,
+        host
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(match row.mode.as_deref() {
+        Some("block") => Policy::Blocked,
+        Some("silence") => Policy::Silenced,
+        Some(_) => Policy::Allowed,
+        None if row.allowlist => Policy::Blocked,
+        None => Policy::Allowed,
+    })
+}
+
+/// Fails with Defederated if federating with the supplied host is blocked by policy
+pub(crate) async fn ensure_federates<H: AsRef<str>>(
+    host: H,
+    pool: &Pool<Postgres>,
+) -> Result<(), Error> {
+    match federation_policy(host.as_ref(), pool).await? {
+        Policy::Blocked => Err(Error::Defederated(Defederated(host.as_ref().to_owned()))),
+        _ => Ok(()),
+    }
+}
+
+/// Returns the known remotes that should be included in fan-outs, excluding blocked and silenced
+/// hosts whatever port they are known by
+pub(crate) async fn federated_remotes(pool: &Pool<Postgres>) -> Result<Vec<String>, Error> {
+    Ok(sqlx::query!(
+        r#"
+            SELECT host FROM remotes
+            WHERE lower(split_part(host, ':', 1)) NOT IN (
+                SELECT host FROM federation_policies
+                WHERE mode IN ('block', 'silence')
+            )
+            AND (
+                NOT EXISTS(SELECT 1 FROM federation_policies WHERE mode = 'allow')
+                OR lower(split_part(host, ':', 1)) IN (
+                    SELECT host FROM federation_policies
+                    WHERE mode = 'allow'
+                )
+            )
+        "#,
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|row| row.host)
+    .collect())
+}
+
+/// Fetches every descendant of the supplied posts in a single query, excluding the posts themselves
+pub(crate) async fn fetch_descendants(
+    roots: &[Uuid],
+    pool: &Pool<Postgres>,
+) -> Result<Vec<database::Post>, Error> {
+    Ok(sqlx::query_as!(
+        database::Post,
+        r#"
+            WITH RECURSIVE descendants AS (
+                SELECT * FROM posts
+                WHERE parent = ANY($1)
+                UNION ALL
+                SELECT posts.* FROM posts
+                INNER JOIN descendants ON posts.parent = descendants.id
+            )
+            SELECT
+                id AS "id!",
+                community AS "community!",
+                parent,
+                author_username AS "author_username!",
+                author_host AS "author_host!",
+                title AS "title!",
+                content AS "content!",
+                created AS "created!",
+                modified AS "modified!",
+                deleted,
+                score AS "score!"
+            FROM descendants
+        "#,
+        roots
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+/// Fetches the IDs of the direct children of each of the supplied posts
+pub(crate) async fn fetch_child_ids(
+    parents: &[Uuid],
+    pool: &Pool<Postgres>,
+) -> Result<HashMap<Uuid, Vec<Uuid>>, Error> {
+    let mut map = HashMap::<Uuid, Vec<Uuid>>::new();
+
+    for row in sqlx::query!(
+        r#"
+            SELECT id, parent AS "parent!" FROM posts
+            WHERE parent = ANY($1)
+        "#,
+        parents
+    )
+    .fetch_all(pool)
+    .await?
+    {
+        map.entry(row.parent).or_default().push(row.id);
+    }
+
+    Ok(map)
+}
+
+/// Records the current title and content of a post as its next revision before it is edited by the
+/// supplied user, returning false if the post does not exist or has been deleted
+pub(crate) async fn record_revision<U: AsRef<str>, H: AsRef<str>>(
+    post: Uuid,
+    editor_username: U,
+    editor_host: H,
+    tx: &mut Transaction<'_, Postgres>,
+) -> Result<bool, Error> {
+    let recorded = sqlx::query!(
+        r#"
+            INSERT INTO post_revisions
+            SELECT
+                id,
+                COALESCE((SELECT MAX(revision) FROM post_revisions WHERE post = $1), 0) + 1,
+                $2,
+                $3,
+                $4,
+                title,
+                content
+            FROM posts
+            WHERE id = $1
+            AND deleted IS NULL
+        "#,
+        post,
+        editor_username.as_ref(),
+        editor_host.as_ref(),
+        chrono::Local::now().timestamp()
+    )
+    .execute(tx)
+    .await?
+    .rows_affected();
+
+    Ok(recorded > 0)
+}
+
+/// Records a reply made at the supplied time beneath a local post as the latest activity of the
+/// top-level post it belongs to, which orders the feed by activity
+pub(crate) async fn record_reply(
+    parent: Uuid,
+    created: i64,
+    pool: &Pool<Postgres>,
+) -> Result<(), Error> {
+    sqlx::query!(
+        r#"
+            WITH RECURSIVE ancestors AS (
+                SELECT id, parent FROM posts
+                WHERE id = $1
+                UNION ALL
+                SELECT posts.id, posts.parent FROM posts
+                INNER JOIN ancestors ON posts.id = ancestors.parent
+            )
+            UPDATE posts
+            SET last_reply = GREATEST(last_reply, $2)
+            WHERE id = (SELECT id FROM ancestors WHERE parent IS NULL)
+        "#,
+        parent,
+        created
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Records the supplied user's vote on a local post, replacing any previous vote, or withdraws
+/// their vote if its value is 0, returning the new score of the post or None if it does not exist
+/// or has been deleted
+pub(crate) async fn record_vote<U: AsRef<str>, H: AsRef<str>>(
+    post: Uuid,
+    voter_username: U,
+    voter_host: H,
+    vote: Vote,
+    pool: &Pool<Postgres>,
+) -> Result<Option<i64>, Error> {
+    if !(-1..=1).contains(&vote.value) {
+        return Err(Error::BadRequest(anyhow!(
+            "Vote must be 1, -1 or 0, got {}",
+            vote.value
+        )));
+    }
+
+    let mut tx = pool.begin().await?;
+
+    // lock the post so that concurrent votes each see the other when recounting the score
+    if sqlx::query!(
+        r#"
+            SELECT id FROM posts
+            WHERE id = $1
+            AND deleted IS NULL
+            FOR UPDATE
+        "#,
+        post
+    )
+    .fetch_optional(&mut tx)
+    .await?
+    .is_none()
+    {
+        return Ok(None);
+    }
+
+    if vote.value == 0 {
+        sqlx::query!(
+            r#"
+                DELETE FROM votes
+                WHERE post = $1
+                AND voter_username = $2
+                AND voter_host = $3
+            "#,
+            post,
+            voter_username.as_ref(),
+            voter_host.as_ref()
+        )
+        .execute(&mut tx)
+        .await?;
+    } else {
+        sqlx::query!(
+            r#"
+                INSERT INTO votes VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT (post, voter_username, voter_host) DO UPDATE
+                    SET value = $4, created = $5
+            "#,
+            post,
+            voter_username.as_ref(),
+            voter_host.as_ref(),
+            vote.value,
+            chrono::Local::now().timestamp()
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+
+    let score = sqlx::query!(
+        r#"
+            UPDATE posts
+            SET score = (SELECT COALESCE(SUM(value), 0) FROM votes WHERE post = $1)
+            WHERE id = $1
+            RETURNING score
+        "#,
+        post
+    )
+    .fetch_one(&mut tx)
+    .await?
+    .score;
+
+    tx.commit().await?;
+
+    Ok(Some(score))
+}
+
+/// Maximum length of the reason given for a report in characters
+pub const REPORT_REASON_MAX_LENGTH: usize = 1024;
+
+/// Validates the reason given for a report, which must not be blank
+pub fn validate_report_reason<T: AsRef<str>>(reason: T) -> Result<String, Error> {
+    let reason = sanitise_text(reason, REPORT_REASON_MAX_LENGTH)?;
+    if reason.is_empty() {
+        return Err(Error::BadRequest(anyhow!("Reports must give a reason")));
+    }
+
+    Ok(reason)
+}
+
+/// Files a report of a local post by the supplied user for the moderators of its community,
+/// returning None if the post does not exist or has been deleted
+pub(crate) async fn file_post_report<U: AsRef<str>, H: AsRef<str>, R: AsRef<str>>(
+    post: Uuid,
+    reporter_username: U,
+    reporter_host: H,
+    reason: R,
+    pool: &Pool<Postgres>,
+) -> Result<Option<internal::Report>, Error> {
+    let reason = validate_report_reason(reason)?;
+
+    Ok(sqlx::query_as!(
+        database::Report,
+        r#"
+            INSERT INTO reports
+            SELECT $1, $2, $3, id, NULL, community, author_username, author_host, $4, $5, $6
+            FROM posts
+            WHERE id = $7
+            AND deleted IS NULL
+            RETURNING *
+        "#,
+        Uuid::new_v4(),
+        reporter_username.as_ref(),
+        reporter_host.as_ref(),
+        reason,
+        chrono::Local::now().timestamp(),
+        REPORT_OPEN,
+        post
+    )
+    .fetch_optional(pool)
+    .await?
+    .map(internal::Report::from))
+}
+
 /// Returns whether the supplied user is a moderator of the supplied community
 pub(crate) async fn is_moderator<U: AsRef<str>, H: AsRef<str>, C: AsRef<str>>(
     username: U,
@@ -58,6 +446,113 @@ pub(crate) async fn is_moderator<U: AsRef<str>, H: AsRef<str>, C: AsRef<str>>(
     }
 }
 
+/// Maximum length of the reason given for a ban or suspension in characters
+pub const BAN_REASON_MAX_LENGTH: usize = 1024;
+
+/// Validates a ban or suspension, which must give a reason and expire in the future if it expires
+/// at all, returning its sanitised reason
+pub fn validate_ban(ban: &NewBan) -> Result<String, Error> {
+    let reason = sanitise_text(&ban.reason, BAN_REASON_MAX_LENGTH)?;
+    if reason.is_empty() {
+        return Err(Error::BadRequest(anyhow!("Bans must give a reason")));
+    }
+
+    if let Some(expires) = ban.expires {
+        if expires <= chrono::Local::now().timestamp() {
+            return Err(Error::BadRequest(anyhow!("Bans must expire in the future")));
+        }
+    }
+
+    Ok(reason)
+}
+
+/// Returns whether the supplied user is currently banned from posting in the supplied community
+pub(crate) async fn is_banned<U: AsRef<str>, H: AsRef<str>, C: AsRef<str>>(
+    username: U,
+    host: H,
+    community: C,
+    pool: &Pool<Postgres>,
+) -> Result<bool, Error> {
+    match sqlx::query!(
+        r#"
+            SELECT EXISTS(
+                SELECT 1 FROM community_bans
+                WHERE username = $1 AND host = $2 AND community = $3
+                AND (expires IS NULL OR expires > $4)
+            )
+        "#,
+        username.as_ref(),
+        host.as_ref(),
+        community.as_ref(),
+        chrono::Local::now().timestamp()
+    )
+    .fetch_one(pool)
+    .await?
+    .exists
+    {
+        Some(b) => Ok(b),
+        None => Err(sqlx::error::Error::RowNotFound.into()),
+    }
+}
+
+/// Returns whether the supplied user is currently banned from the community of a local post
+pub(crate) async fn is_banned_from_post<U: AsRef<str>, H: AsRef<str>>(
+    username: U,
+    host: H,
+    post: Uuid,
+    pool: &Pool<Postgres>,
+) -> Result<bool, Error> {
+    match sqlx::query!(
+        r#"
+            SELECT EXISTS(
+                SELECT 1 FROM community_bans
+                INNER JOIN posts ON posts.community = community_bans.community
+                WHERE community_bans.username = $1 AND community_bans.host = $2
+                AND posts.id = $3
+                AND (community_bans.expires IS NULL OR community_bans.expires > $4)
+            )
+        "#,
+        username.as_ref(),
+        host.as_ref(),
+        post,
+        chrono::Local::now().timestamp()
+    )
+    .fetch_one(pool)
+    .await?
+    .exists
+    {
+        Some(b) => Ok(b),
+        None => Err(sqlx::error::Error::RowNotFound.into()),
+    }
+}
+
+/// Returns whether the supplied user is currently suspended from the server
+pub(crate) async fn is_suspended<U: AsRef<str>, H: AsRef<str>>(
+    username: U,
+    host: H,
+    pool: &Pool<Postgres>,
+) -> Result<bool, Error> {
+    match sqlx::query!(
+        r#"
+            SELECT EXISTS(
+                SELECT 1 FROM suspensions
+                WHERE username = $1 AND host = $2
+                AND (expires IS NULL OR expires > $3)
+            )
+        "#,
+        username.as_ref(),
+        host.as_ref(),
+        chrono::Local::now().timestamp()
+    )
+    .fetch_one(pool)
+    .await?
+    .exists
+    {
+        Some(b) => Ok(b),
+        None => Err(sqlx::error::Error::RowNotFound.into()),
+    }
+}
+
 /// Returns whether the supplied user is an admin or not
 pub(crate) async fn is_admin<A: AsRef<str>, B: AsRef<str>>(
     db: &Pool<Postgres>,
@@ -109,13 +604,87 @@ pub fn get_user_id<'a>(req: &'a HttpRequest) -> actix_web::Result<&'a str, Error
     )))
 }
 
-#[macro_export]
-/// Gets hostname of local server
-macro_rules! host {
-    () => {
-        crate::HOST
-            .get()
-            .expect("Should always be initialised before use")
-            .clone()
-    };
+/// Removes control characters other than newlines and tabs, trims surrounding whitespace and
+/// ensures that the result is at most `max_length` characters long
+pub fn sanitise_text<T: AsRef<str>>(text: T, max_length: usize) -> Result<String, Error> {
+    let text = text
+        .as_ref()
+        .chars()
+        .filter(|c| !c.is_control() || *c == '\n' || *c == '\t')
+        .collect::<String>()
+        .trim()
+        .to_owned();
+
+    if text.chars().count() > max_length {
+        return Err(Error::BadRequest(anyhow!(
+            "Text must be at most {} characters long",
+            max_length
+        )));
+    }
+
+    Ok(text)
+}
+
+/// Validates an avatar URL, which must either be an absolute HTTP(S) URL or a path on this server
+pub fn validate_avatar_url<T: AsRef<str>>(url: T) -> Result<String, Error> {
+    let url = sanitise_text(url, AVATAR_URL_MAX_LENGTH)?;
+
+    if url.chars().any(char::is_whitespace) {
+        return Err(Error::BadRequest(anyhow!(
+            "Avatar URL must not contain whitespace"
+        )));
+    }
+
+    if url.starts_with('/') && !url.starts_with("//") {
+        return Ok(url);
+    }
+
+    match url.parse::<Uri>() {
+        Ok(uri)
+            if uri.authority().is_some()
+                && (uri.scheme() == Some(&Scheme::HTTP)
+                    || uri.scheme() == Some(&Scheme::HTTPS)) =>
+        {
+            Ok(url)
+        }
+        _ => Err(Error::BadRequest(anyhow!(
+            "Avatar URL must be an absolute HTTP(S) URL or a path"
+        ))),
+    }
+}
+
+/// Maximum length of avatar URLs, matching the column in local_users
+pub const AVATAR_URL_MAX_LENGTH: usize = 256;
+
+#[cfg(test)]
+mod test {
+    use super::{sanitise_text, validate_avatar_url};
+
+    #[test]
+    fn sanitise_text_strips_control_characters() {
+        assert_eq!(
+            sanitise_text("  hello\u{0}\u{1b}[31m\nworld\t ", 32).unwrap(),
+            "hello[31m\nworld"
+        );
+    }
+
+    #[test]
+    fn sanitise_text_too_long_fail() {
+        assert!(sanitise_text("🦀".repeat(5), 5).is_ok());
+        assert!(sanitise_text("🦀".repeat(6), 5).is_err());
+    }
+
+    #[test]
+    fn validate_avatar_url_success() {
+        assert!(validate_avatar_url("https://example.org/avatar.png").is_ok());
+        assert!(validate_avatar_url("/internal/images/1234").is_ok());
+    }
+
+    #[test]
+    fn validate_avatar_url_fail() {
+        assert!(validate_avatar_url("javascript:alert(1)").is_err());
+        assert!(validate_avatar_url("//evil.example/avatar.png").is_err());
+        assert!(validate_avatar_url("https://example.org/a b.png").is_err());
+        assert!(validate_avatar_url(format!("https://example.org/{}", "a".repeat(256))).is_err());
+    }
 }